@@ -1,9 +1,10 @@
+use base64::{engine::general_purpose, Engine as _};
 use rand::RngCore;
 
 fn main() {
     let mut key = [0u8; 32];
     rand::thread_rng().fill_bytes(&mut key);
-    let encoded_key = base64::encode(&key);
+    let encoded_key = general_purpose::STANDARD.encode(key);
     
     println!("Generated 256-bit encryption key:");
     println!("{}", encoded_key);
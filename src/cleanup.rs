@@ -0,0 +1,208 @@
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    models::{unix_timestamp, Backend},
+    services::{
+        audit::{AuditAction, AuditEvent, AuditLog},
+        cache::ImageCache,
+        cdn::{self, CdnService},
+        discord::DiscordService,
+        index::{ImageIndex, IndexEntry},
+        manifest,
+        storage::Storage,
+        telegram::TelegramService,
+        tenants::TenantStore,
+        transcode,
+        usage::UsageStore,
+    },
+};
+
+// Upper bound on deletions per run, so one pass never floods the Telegram API
+const CLEANUP_BATCH_SIZE: usize = 50;
+
+// Usage subject charged for deletes made by the cleanup worker
+const CLEANUP_SUBJECT: &str = "system:cleanup";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupReason {
+    Expired,
+    OverQuota,
+}
+
+// An index entry the cleanup worker would delete, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupCandidate {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    #[serde(skip_serializing_if = "Backend::is_telegram")]
+    pub backend: Backend,
+    pub message_id: i64,
+    pub size: usize,
+    pub created_at: u64,
+    pub reason: CleanupReason,
+}
+
+impl CleanupCandidate {
+    fn new(entry: &IndexEntry, reason: CleanupReason) -> Self {
+        Self {
+            id: entry.id.clone(),
+            tenant: entry.reference.tenant.clone(),
+            chat_id: entry.reference.chat_id,
+            backend: entry.reference.backend,
+            message_id: entry.reference.message_id,
+            size: entry.reference.size,
+            created_at: entry.created_at,
+            reason,
+        }
+    }
+}
+
+/// Pick expired entries, then the oldest remaining ones until total storage fits the quota
+pub fn plan_cleanup(entries: &[IndexEntry], quota_bytes: Option<u64>, now: u64) -> Vec<CleanupCandidate> {
+    let (expired, mut live): (Vec<&IndexEntry>, Vec<&IndexEntry>) = entries
+        .iter()
+        .partition(|entry| entry.reference.expires_at.is_some_and(|at| at <= now));
+
+    let mut candidates: Vec<CleanupCandidate> = expired
+        .into_iter()
+        .map(|entry| CleanupCandidate::new(entry, CleanupReason::Expired))
+        .collect();
+
+    if let Some(quota) = quota_bytes {
+        live.sort_by_key(|entry| entry.created_at);
+        let mut total: u64 = live.iter().map(|entry| entry.reference.size as u64).sum();
+
+        for entry in live {
+            if total <= quota {
+                break;
+            }
+            total -= entry.reference.size as u64;
+            candidates.push(CleanupCandidate::new(entry, CleanupReason::OverQuota));
+        }
+    }
+
+    candidates
+}
+
+/// One cleanup pass: delete up to a batch of expired or over-quota images
+#[allow(clippy::too_many_arguments)]
+pub async fn run_cleanup(
+    index: Arc<ImageIndex>,
+    usage: Arc<UsageStore>,
+    tenants: Arc<TenantStore>,
+    cache: Arc<ImageCache>,
+    cdn: Arc<CdnService>,
+    telegram_service: Arc<TelegramService>,
+    discord: Option<Arc<DiscordService>>,
+    audit: Arc<AuditLog>,
+    config: Arc<Config>,
+) {
+    let entries = match index.list() {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("Cleanup failed to read index: {}", e);
+            return;
+        }
+    };
+
+    let candidates = plan_cleanup(&entries, config.storage_quota_bytes, unix_timestamp());
+    if candidates.is_empty() {
+        return;
+    }
+
+    tracing::info!("Cleanup removing {} of {} candidates", candidates.len().min(CLEANUP_BATCH_SIZE), candidates.len());
+
+    for candidate in candidates.into_iter().take(CLEANUP_BATCH_SIZE) {
+        // A deleted tenant's bot may be unknown; fall back to the default one
+        let storage = match tenants.owner(candidate.tenant.as_deref()) {
+            Ok(Some(tenant)) => tenant.storage(&telegram_service),
+            _ => telegram_service.clone(),
+        };
+
+        let storage = match Storage::for_backend(candidate.backend, storage, discord.as_ref()) {
+            Ok(storage) => storage,
+            Err(e) => {
+                tracing::error!("Cleanup cannot delete {}: {}", candidate.id, e);
+                continue;
+            }
+        };
+        let chat_id = Some(candidate.chat_id.unwrap_or(config.telegram_chat_id));
+        if let Ok(Some(entry)) = index.get(&candidate.id)
+            && entry.reference.chunked
+            && let Ok(key) = config.get_encryption_key_bytes()
+        {
+            manifest::delete_file_chunks(&storage, &key, chat_id, &entry.reference).await;
+        }
+
+        // Imports referenced by a bare file_id have no message of ours to delete
+        if candidate.message_id != 0
+            && let Err(e) = storage.delete(chat_id, candidate.message_id).await
+        {
+            tracing::error!("Cleanup failed to delete message {}: {}", candidate.message_id, e);
+            continue;
+        }
+
+        match index.remove(&candidate.id) {
+            Ok(Some(entry)) => {
+                if let Ok(key) = config.get_encryption_key_bytes() {
+                    transcode::delete_transcodes(&storage, &key, config.telegram_chat_id, &entry).await;
+                }
+                cache.remove(&entry.reference.file_id);
+                cdn.purge_in_background(
+                    config.clone(),
+                    cdn::image_paths(&entry.id, entry.reference.tenant.as_deref()),
+                );
+                usage.record_delete(&[CLEANUP_SUBJECT.to_string()], &entry.uploader, entry.reference.size);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("Cleanup failed to update index: {}", e),
+        }
+
+        let event = AuditEvent::new(AuditAction::CleanupDelete)
+            .id(&candidate.id)
+            .detail(format!("{:?}, {} bytes", candidate.reason, candidate.size));
+        audit.record(event);
+
+        // Pace deletions the same way the upload worker paces uploads
+        tokio::time::sleep(Duration::from_secs(config.upload_delay_secs)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileReference;
+
+    fn entry(id: &str, size: usize, created_at: u64, expires_at: Option<u64>) -> IndexEntry {
+        let mut reference = FileReference::new("file".to_string(), 1, size, "image/png".to_string());
+        reference.expires_at = expires_at;
+        IndexEntry { id: id.to_string(), reference, created_at, uploader: Vec::new(), reuploaders: Vec::new(), content_hash: None, perceptual_hash: None, palette: Vec::new(), filename: None, text: None, previous_file_ids: Vec::new(), broken: false, unavailable: None, tags: Vec::new(), transcodes: Default::default() }
+    }
+
+    #[test]
+    fn test_plan_cleanup_expired_and_quota() {
+        let entries = vec![
+            entry("old", 100, 1, None),
+            entry("expired", 100, 2, Some(50)),
+            entry("mid", 100, 3, None),
+            entry("new", 100, 4, Some(500)),
+        ];
+
+        let plan = plan_cleanup(&entries, Some(200), 100);
+        let planned: Vec<(&str, CleanupReason)> =
+            plan.iter().map(|c| (c.id.as_str(), c.reason)).collect();
+
+        assert_eq!(
+            planned,
+            vec![("expired", CleanupReason::Expired), ("old", CleanupReason::OverQuota)]
+        );
+        assert!(plan_cleanup(&entries, None, 10).is_empty());
+    }
+}
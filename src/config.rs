@@ -1,61 +1,514 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose, Engine as _};
 
+use crate::{
+    error::AppError,
+    middleware::rate_limit::IpNetwork,
+    models::{Backend, STORED_IMAGE_TYPES},
+    scheduler::Schedule,
+    services::optimize::{self, OptimizeStep},
+};
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub telegram_bot_token: String,
     pub telegram_chat_id: i64,
+    // Chat upload, download and delete notices are posted to; logging is off when unset
+    pub telegram_log_chat_id: Option<i64>,
+    // Bot API server, for a self-hosted telegram-bot-api instance
+    pub telegram_api_url: String,
+    // Where new uploads are stored: Telegram, or Discord attachments in DISCORD_CHANNEL_ID
+    pub storage_backend: Backend,
+    pub discord_bot_token: Option<String>,
+    pub discord_channel_id: Option<u64>,
+    pub discord_api_url: String,
+    // Files above this are split into chunks on Discord, whose attachment limit is much lower
+    pub discord_chunk_bytes: usize,
+    // Store an upload on Discord when Telegram refuses it
+    pub discord_overflow: bool,
     pub encryption_key: String,
     pub max_file_size: usize,
     pub rate_limit_per_minute: u32,
+    // Clients never rate limited, e.g. health checkers, internal services or a CDN's origin pulls
+    pub rate_limit_exempt_ips: Vec<IpNetwork>,
+    pub rate_limit_exempt_keys: Vec<String>,
+    // Requests served at once before new ones get a 503 (0 for no limit)
+    pub max_in_flight_requests: usize,
+    // How long a client tripping an abuse threshold is turned away (0 disables the heuristics)
+    pub abuse_ban_secs: u64,
+    // Per-minute thresholds: 4xx responses, invalid or unknown image IDs, and min(uploads, deletes)
+    pub abuse_max_errors: u32,
+    pub abuse_max_id_probes: u32,
+    pub abuse_max_churn: u32,
+    // Time budgets for producing a response: most routes, and those transferring image data
+    pub request_timeout_secs: u64,
+    pub transfer_timeout_secs: u64,
+    // Longest accepted image edge in pixels, and largest accepted canvas in megapixels
+    pub max_image_dimension: Option<u32>,
+    pub max_megapixels: Option<f64>,
+    // Simultaneous image downloads allowed per client IP (0 for no limit)
+    pub max_concurrent_downloads_per_ip: usize,
+    // Memory budget for decrypted images kept in the download cache (0 disables it)
+    pub image_cache_bytes: usize,
+    // Large Telegram downloads are split into ranges of this size, fetched this many at a time
+    pub download_chunk_bytes: u64,
+    pub download_parallelism: usize,
+    // Decoding, resizing and encryption jobs allowed to run at once on blocking threads
+    pub cpu_pool_size: usize,
+    // Encrypted files larger than this are stored as several documents plus a manifest (0 never splits)
+    pub storage_chunk_bytes: usize,
+    // Upload bodies and queued uploads larger than this wait on disk instead of in memory (0 keeps them all in memory)
+    pub spill_threshold_bytes: usize,
+    pub spill_dir: Option<String>,
     pub bind_address: String,
     pub allowed_image_types: Vec<String>,
     #[serde(default)]
     pub admin_secret: String,
+    // Pause after each upload (and each cleanup deletion) to stay under Telegram's rate limits.
+    // A single worker sends uploads one at a time, so this caps them at 60 / delay per minute; 0 disables pacing.
     #[serde(default = "default_upload_delay")]
     pub upload_delay_secs: u64,
+    // Prefix for URLs returned to clients, e.g. https://img.example.com (empty for relative URLs)
+    #[serde(default)]
+    pub public_base_url: String,
+    // JSON file the image index is persisted to (in-memory only when unset)
+    pub index_path: Option<String>,
+    // JSON file tenants and their hashed API keys are persisted to (in-memory only when unset)
+    pub tenants_path: Option<String>,
+    // JSON file albums are persisted to (in-memory only when unset)
+    pub albums_path: Option<String>,
+    // JSON file usage rollups are persisted to (in-memory only when unset)
+    pub usage_path: Option<String>,
+    // JSON file group -> supergroup chat upgrades are persisted to (in-memory only when unset)
+    pub chat_migrations_path: Option<String>,
+    // "off", "truncate" or "hash": how client IPs are masked before anything records them
+    pub privacy_mode: String,
+    // LINK_MODE=viewer: share /view/:id pages instead of raw /image/:id URLs, and send browsers there
+    pub link_to_viewer: bool,
+    // Reject uploads without an API key; reads stay public
+    pub upload_requires_auth: bool,
+    // Start with uploads refused (503) while downloads keep working; admins can toggle it at runtime
+    pub maintenance_mode: bool,
+    // Retry-After sent with those refusals
+    pub maintenance_retry_after_secs: u64,
+    // Start with every mutating endpoint refused while images keep being served; admins can toggle it
+    pub read_only: bool,
+    // Round-trip a tiny encrypted file through the storage chat at startup and refuse to start if it fails
+    pub startup_self_test: bool,
+    // Share images under an ID derived from their content hash instead of the encrypted reference
+    pub content_addressed_ids: bool,
+    // Sign /image bodies with Ed25519, by RESPONSE_SIGNING_KEY or a key derived from the master key
+    pub response_signatures: bool,
+    pub response_signing_key: Option<String>,
+    // Resilience testing: delay and fail this share of Telegram calls and cache lookups at random
+    pub chaos_mode: bool,
+    pub chaos_failure_percent: u8,
+    pub chaos_delay_percent: u8,
+    pub chaos_delay_ms: u64,
+    // "turnstile" or "hcaptcha": anonymous uploads must carry a token the provider accepts
+    pub captcha_provider: Option<String>,
+    pub captcha_secret: Option<String>,
+    // MaxMind-format country database; the GEOIP_* country lists need it
+    pub geoip_db_path: Option<String>,
+    // MaxMind-format ASN database; audit events and stats gain the client's network when set
+    pub geoip_asn_db_path: Option<String>,
+    // ISO country codes allowed or denied on upload and download routes (empty for no restriction)
+    pub geoip_upload_allow: Vec<String>,
+    pub geoip_upload_deny: Vec<String>,
+    pub geoip_download_allow: Vec<String>,
+    pub geoip_download_deny: Vec<String>,
+    // How long a /preview link stays usable
+    pub preview_ttl_secs: u64,
+    // How long a signed link to a private image stays valid
+    pub signed_link_ttl_secs: u64,
+    // Chat audit events are posted to, instead of the log chat
+    pub audit_chat_id: Option<i64>,
+    // Encrypt audit events with the master key before posting them
+    pub audit_encrypt: bool,
+    // JSON-lines file audit events are appended to (in-memory only when unset)
+    pub audit_log_path: Option<String>,
+    // Number of recent audit events kept for GET /admin/audit
+    pub audit_log_capacity: usize,
+    // action -> N, posting only 1 in N audit events of that action to the chat
+    pub log_sample_rates: HashMap<String, u64>,
+    // Log lines queued within this long of each other are posted as one Telegram message
+    pub log_batch_window_ms: u64,
+    // Daily usage rollups older than this are dropped
+    pub usage_retention_days: u64,
+    // Endpoint receiving periodic signed usage reports for billing
+    pub billing_webhook_url: Option<String>,
+    pub billing_webhook_secret: Option<String>,
+    // "cloudflare" or "bunny"; picks the purge API and URL signing format
+    pub cdn_provider: Option<String>,
+    // Image and thumbnail URLs are returned on this host when set, e.g. https://cdn.example.com
+    pub cdn_base_url: Option<String>,
+    pub cdn_api_token: Option<String>,
+    // Cloudflare zone to purge
+    pub cdn_zone_id: Option<String>,
+    // Signs returned CDN URLs when set, valid for cdn_token_ttl_secs
+    pub cdn_token_key: Option<String>,
+    pub cdn_token_ttl_secs: u64,
+    // Reject uploads whose exact content is already stored, returning the existing ID
+    pub strict_dedup: bool,
+    // OCR service receiving each upload as a raw POST and answering {"text": "..."}; OCR is off when unset
+    pub ocr_service_url: Option<String>,
+    // ffmpeg binary serving animated GIFs as video with ?format=mp4 or webm; off when unset
+    pub ffmpeg_path: Option<String>,
+    // PNG composited over served images when requested with ?wm=1 or required by the tenant
+    pub watermark_path: Option<String>,
+    // top-left, top-right, bottom-left, bottom-right or center
+    pub watermark_position: String,
+    pub watermark_opacity: f32,
+    // Steps run over JPEG and PNG uploads before encryption, in order; none by default
+    pub optimize_steps: Vec<OptimizeStep>,
+    // Longest edge uploads are shrunk to by the downscale step
+    pub optimize_max_dimension: Option<u32>,
+    // MIME types always served as attachments rather than displayed inline
+    pub attachment_types: Vec<String>,
+    // Chats ("-100..." IDs or "@username") anyone may import from by message link; admins and
+    // tenants may import from any chat the bot is in
+    pub import_source_chats: Vec<String>,
+    // Error bodies link to <url>#<code> when set
+    pub error_docs_url: Option<String>,
+    // Per-task overrides from SCHEDULE_<TASK> variables: seconds, a cron expression, or "off"
+    #[serde(default)]
+    pub task_schedules: HashMap<String, String>,
+    // Total stored bytes above which the oldest images are removed
+    pub storage_quota_bytes: Option<u64>,
 }
 
 fn default_upload_delay() -> u64 {
     2
 }
 
+// Longer pauses would leave the upload queue stalled for minutes per job
+const MAX_UPLOAD_DELAY_SECS: u64 = 300;
+
+// The Bot API's getFile refuses files over 20 MB
+const MAX_STORAGE_CHUNK_BYTES: usize = 20 * 1024 * 1024;
+
+// Comma-separated ISO country codes, e.g. "TH,US"
+fn country_list(value: Option<String>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| !code.is_empty())
+        .collect()
+}
+
 impl Config {
     pub fn from_env() -> Result<Self> {
         dotenvy::dotenv().ok();
+        Self::from_vars(&env::vars().collect())
+    }
+
+    /// Configuration from `vars` as if they were the environment
+    pub fn from_vars(vars: &HashMap<String, String>) -> Result<Self> {
+        let var = |key: &str| vars.get(key).cloned().ok_or(env::VarError::NotPresent);
 
         let config = Self {
-            telegram_bot_token: env::var("TELEGRAM_BOT_TOKEN")
+            telegram_bot_token: var("TELEGRAM_BOT_TOKEN")
                 .context("TELEGRAM_BOT_TOKEN environment variable is required")?,
-            telegram_chat_id: env::var("TELEGRAM_CHAT_ID")
+            telegram_chat_id: var("TELEGRAM_CHAT_ID")
                 .context("TELEGRAM_CHAT_ID environment variable is required")?
                 .parse()
                 .context("TELEGRAM_CHAT_ID must be a valid integer")?,
-            encryption_key: env::var("ENCRYPTION_KEY")
+            telegram_log_chat_id: var("TELEGRAM_LOG_CHAT_ID")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| v.parse())
+                .transpose()
+                .context("TELEGRAM_LOG_CHAT_ID must be a valid integer")?,
+            telegram_api_url: var("TELEGRAM_API_URL")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "https://api.telegram.org".to_string()),
+            storage_backend: match var("STORAGE_BACKEND").unwrap_or_default().to_lowercase().as_str() {
+                "" | "telegram" => Backend::Telegram,
+                "discord" => Backend::Discord,
+                other => anyhow::bail!("STORAGE_BACKEND must be telegram or discord, got '{}'", other),
+            },
+            discord_bot_token: var("DISCORD_BOT_TOKEN").ok().filter(|v| !v.is_empty()),
+            discord_channel_id: var("DISCORD_CHANNEL_ID")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| v.parse())
+                .transpose()
+                .context("DISCORD_CHANNEL_ID must be a valid integer")?,
+            discord_api_url: var("DISCORD_API_URL")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "https://discord.com/api/v10".to_string()),
+            discord_chunk_bytes: var("DISCORD_CHUNK_BYTES")
+                .unwrap_or_else(|_| "8388608".to_string())
+                .parse()
+                .context("DISCORD_CHUNK_BYTES must be a valid integer")?,
+            discord_overflow: var("DISCORD_OVERFLOW")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            encryption_key: var("ENCRYPTION_KEY")
                 .context("ENCRYPTION_KEY environment variable is required")?,
-            max_file_size: env::var("MAX_FILE_SIZE")
+            max_file_size: var("MAX_FILE_SIZE")
                 .unwrap_or_else(|_| "10485760".to_string()) // 10MB default
                 .parse()
                 .context("MAX_FILE_SIZE must be a valid integer")?,
-            rate_limit_per_minute: env::var("RATE_LIMIT_PER_MINUTE")
+            rate_limit_per_minute: var("RATE_LIMIT_PER_MINUTE")
                 .unwrap_or_else(|_| "60".to_string())
                 .parse()
                 .context("RATE_LIMIT_PER_MINUTE must be a valid integer")?,
-            bind_address: env::var("BIND_ADDRESS")
+            rate_limit_exempt_ips: var("RATE_LIMIT_EXEMPT_IPS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|spec| !spec.is_empty())
+                .map(IpNetwork::parse)
+                .collect::<Result<_>>()
+                .context("RATE_LIMIT_EXEMPT_IPS must be comma-separated IPs or CIDR blocks")?,
+            rate_limit_exempt_keys: var("RATE_LIMIT_EXEMPT_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+            max_in_flight_requests: var("MAX_IN_FLIGHT_REQUESTS")
+                .unwrap_or_else(|_| "512".to_string())
+                .parse()
+                .context("MAX_IN_FLIGHT_REQUESTS must be a valid integer")?,
+            abuse_ban_secs: var("ABUSE_BAN_SECS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("ABUSE_BAN_SECS must be a valid integer")?,
+            abuse_max_errors: var("ABUSE_MAX_ERRORS")
+                .unwrap_or_else(|_| "120".to_string())
+                .parse()
+                .context("ABUSE_MAX_ERRORS must be a valid integer")?,
+            abuse_max_id_probes: var("ABUSE_MAX_ID_PROBES")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .context("ABUSE_MAX_ID_PROBES must be a valid integer")?,
+            abuse_max_churn: var("ABUSE_MAX_CHURN")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()
+                .context("ABUSE_MAX_CHURN must be a valid integer")?,
+            request_timeout_secs: var("REQUEST_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .context("REQUEST_TIMEOUT_SECS must be a valid integer")?,
+            transfer_timeout_secs: var("TRANSFER_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "120".to_string())
+                .parse()
+                .context("TRANSFER_TIMEOUT_SECS must be a valid integer")?,
+            max_image_dimension: var("MAX_IMAGE_DIMENSION")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("MAX_IMAGE_DIMENSION must be a valid integer")?,
+            max_megapixels: var("MAX_MEGAPIXELS")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("MAX_MEGAPIXELS must be a valid number")?,
+            max_concurrent_downloads_per_ip: var("MAX_CONCURRENT_DOWNLOADS_PER_IP")
+                .unwrap_or_else(|_| "4".to_string())
+                .parse()
+                .context("MAX_CONCURRENT_DOWNLOADS_PER_IP must be a valid integer")?,
+            image_cache_bytes: var("IMAGE_CACHE_BYTES")
+                .unwrap_or_else(|_| "134217728".to_string())
+                .parse()
+                .context("IMAGE_CACHE_BYTES must be a valid integer")?,
+            download_chunk_bytes: var("DOWNLOAD_CHUNK_BYTES")
+                .unwrap_or_else(|_| "4194304".to_string())
+                .parse()
+                .context("DOWNLOAD_CHUNK_BYTES must be a valid integer")?,
+            download_parallelism: var("DOWNLOAD_PARALLELISM")
+                .unwrap_or_else(|_| "4".to_string())
+                .parse()
+                .context("DOWNLOAD_PARALLELISM must be a valid integer")?,
+            cpu_pool_size: match var("CPU_POOL_SIZE") {
+                Ok(value) => value.parse().context("CPU_POOL_SIZE must be a valid integer")?,
+                Err(_) => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            },
+            storage_chunk_bytes: var("STORAGE_CHUNK_BYTES")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("STORAGE_CHUNK_BYTES must be a valid integer")?,
+            spill_threshold_bytes: var("SPILL_THRESHOLD_BYTES")
+                .unwrap_or_else(|_| "8388608".to_string())
+                .parse()
+                .context("SPILL_THRESHOLD_BYTES must be a valid integer")?,
+            spill_dir: var("SPILL_DIR").ok(),
+            bind_address: var("BIND_ADDRESS")
                 .unwrap_or_else(|_| "0.0.0.0:3000".to_string()),
-            allowed_image_types: vec![
-                "image/jpeg".to_string(),
-                "image/png".to_string(),
-                "image/gif".to_string(),
-                "image/webp".to_string(),
-            ],
-            admin_secret: env::var("ADMIN_SECRET").unwrap_or_else(|_| "".to_string()),
-            upload_delay_secs: env::var("UPLOAD_DELAY_SECS")
-                .unwrap_or_else(|_| "2".to_string())
-                .parse()
-                .context("UPLOAD_DELAY_SECS must be a valid integer")?,
+            allowed_image_types: STORED_IMAGE_TYPES.iter().map(|mime| mime.to_string()).collect(),
+            admin_secret: var("ADMIN_SECRET").unwrap_or_else(|_| "".to_string()),
+            upload_delay_secs: var("UPLOAD_DELAY_SECS")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("UPLOAD_DELAY_SECS must be a valid integer")?
+                .unwrap_or_else(default_upload_delay),
+            public_base_url: var("PUBLIC_BASE_URL")
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            index_path: var("INDEX_PATH").ok(),
+            chat_migrations_path: var("CHAT_MIGRATIONS_PATH").ok(),
+            privacy_mode: var("PRIVACY_MODE")
+                .unwrap_or_else(|_| "off".to_string())
+                .to_lowercase(),
+            link_to_viewer: match var("LINK_MODE").unwrap_or_default().to_lowercase().as_str() {
+                "" | "direct" => false,
+                "viewer" => true,
+                other => anyhow::bail!("LINK_MODE must be direct or viewer, got '{}'", other),
+            },
+            upload_requires_auth: var("UPLOAD_REQUIRES_AUTH")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            maintenance_mode: var("MAINTENANCE_MODE")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            maintenance_retry_after_secs: var("MAINTENANCE_RETRY_AFTER_SECS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .context("MAINTENANCE_RETRY_AFTER_SECS must be a valid integer")?,
+            read_only: var("READ_ONLY")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            startup_self_test: var("STARTUP_SELF_TEST")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            content_addressed_ids: var("CONTENT_ADDRESSED_IDS")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            response_signatures: var("RESPONSE_SIGNATURES")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            response_signing_key: var("RESPONSE_SIGNING_KEY").ok().filter(|v| !v.is_empty()),
+            chaos_mode: var("CHAOS_MODE")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            chaos_failure_percent: var("CHAOS_FAILURE_PERCENT")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .context("CHAOS_FAILURE_PERCENT must be a valid integer")?,
+            chaos_delay_percent: var("CHAOS_DELAY_PERCENT")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .context("CHAOS_DELAY_PERCENT must be a valid integer")?,
+            chaos_delay_ms: var("CHAOS_DELAY_MS")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()
+                .context("CHAOS_DELAY_MS must be a valid integer")?,
+            captcha_provider: var("CAPTCHA_PROVIDER").ok().map(|v| v.to_lowercase()),
+            captcha_secret: var("CAPTCHA_SECRET").ok().filter(|v| !v.is_empty()),
+            geoip_db_path: var("GEOIP_DB_PATH").ok().filter(|v| !v.is_empty()),
+            geoip_asn_db_path: var("GEOIP_ASN_DB_PATH").ok().filter(|v| !v.is_empty()),
+            geoip_upload_allow: country_list(var("GEOIP_UPLOAD_ALLOW").ok()),
+            geoip_upload_deny: country_list(var("GEOIP_UPLOAD_DENY").ok()),
+            geoip_download_allow: country_list(var("GEOIP_DOWNLOAD_ALLOW").ok()),
+            geoip_download_deny: country_list(var("GEOIP_DOWNLOAD_DENY").ok()),
+            preview_ttl_secs: var("PREVIEW_TTL_SECS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .context("PREVIEW_TTL_SECS must be a valid integer")?,
+            signed_link_ttl_secs: var("SIGNED_LINK_TTL_SECS")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()
+                .context("SIGNED_LINK_TTL_SECS must be a valid integer")?,
+            audit_chat_id: var("AUDIT_CHAT_ID")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("AUDIT_CHAT_ID must be a valid integer")?,
+            audit_encrypt: var("AUDIT_ENCRYPT")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(true),
+            audit_log_path: var("AUDIT_LOG_PATH").ok(),
+            audit_log_capacity: var("AUDIT_LOG_CAPACITY")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()
+                .context("AUDIT_LOG_CAPACITY must be a valid integer")?,
+            log_sample_rates: var("LOG_SAMPLE_RATES")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|pair| !pair.trim().is_empty())
+                .map(|pair| {
+                    let (action, rate) = pair
+                        .split_once('=')
+                        .context("LOG_SAMPLE_RATES entries must look like action=N")?;
+                    let rate = rate.trim().parse().context("LOG_SAMPLE_RATES rates must be valid integers")?;
+                    Ok((action.trim().to_lowercase(), rate))
+                })
+                .collect::<Result<_>>()?,
+            log_batch_window_ms: var("LOG_BATCH_WINDOW_MS")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()
+                .context("LOG_BATCH_WINDOW_MS must be a valid integer")?,
+            tenants_path: var("TENANTS_PATH").ok(),
+            albums_path: var("ALBUMS_PATH").ok(),
+            usage_path: var("USAGE_PATH").ok(),
+            usage_retention_days: var("USAGE_RETENTION_DAYS")
+                .unwrap_or_else(|_| "90".to_string())
+                .parse()
+                .context("USAGE_RETENTION_DAYS must be a valid integer")?,
+            billing_webhook_url: var("BILLING_WEBHOOK_URL").ok(),
+            billing_webhook_secret: var("BILLING_WEBHOOK_SECRET").ok(),
+            cdn_provider: var("CDN_PROVIDER").ok().map(|v| v.to_lowercase()),
+            cdn_base_url: var("CDN_BASE_URL").ok().map(|v| v.trim_end_matches('/').to_string()),
+            cdn_api_token: var("CDN_API_TOKEN").ok(),
+            cdn_zone_id: var("CDN_ZONE_ID").ok(),
+            cdn_token_key: var("CDN_TOKEN_KEY").ok(),
+            cdn_token_ttl_secs: var("CDN_TOKEN_TTL_SECS")
+                .unwrap_or_else(|_| "86400".to_string())
+                .parse()
+                .context("CDN_TOKEN_TTL_SECS must be a valid integer")?,
+            strict_dedup: var("STRICT_DEDUP")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            ocr_service_url: var("OCR_SERVICE_URL").ok(),
+            ffmpeg_path: var("FFMPEG_PATH").ok(),
+            watermark_path: var("WATERMARK_PATH").ok(),
+            watermark_position: var("WATERMARK_POSITION")
+                .unwrap_or_else(|_| "bottom-right".to_string())
+                .to_lowercase(),
+            watermark_opacity: var("WATERMARK_OPACITY")
+                .unwrap_or_else(|_| "0.5".to_string())
+                .parse()
+                .context("WATERMARK_OPACITY must be a valid number")?,
+            optimize_steps: optimize::parse_steps(&var("OPTIMIZE_STEPS").unwrap_or_default())?,
+            optimize_max_dimension: var("OPTIMIZE_MAX_DIMENSION")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("OPTIMIZE_MAX_DIMENSION must be a valid integer")?,
+            attachment_types: var("ATTACHMENT_TYPES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|t| t.trim().to_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            import_source_chats: var("IMPORT_SOURCE_CHATS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|chat| chat.trim().to_lowercase())
+                .filter(|chat| !chat.is_empty())
+                .collect(),
+            error_docs_url: var("ERROR_DOCS_URL").ok(),
+            task_schedules: vars
+                .iter()
+                .filter_map(|(key, value)| {
+                    key.strip_prefix("SCHEDULE_").map(|task| (task.to_lowercase(), value.clone()))
+                })
+                .collect(),
+            storage_quota_bytes: var("STORAGE_QUOTA_BYTES")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("STORAGE_QUOTA_BYTES must be a valid integer")?,
         };
 
         // Validate encryption key length
@@ -64,10 +517,83 @@ impl Config {
         if key_bytes.len() != 32 {
             return Err(anyhow::anyhow!("ENCRYPTION_KEY must be 32 bytes (256 bits) when decoded"));
         }
+        if config.upload_delay_secs > MAX_UPLOAD_DELAY_SECS {
+            anyhow::bail!("UPLOAD_DELAY_SECS must be at most {}, got {}", MAX_UPLOAD_DELAY_SECS, config.upload_delay_secs);
+        }
+        let discord_used = config.storage_backend == Backend::Discord || config.discord_overflow;
+        if discord_used && (config.discord_bot_token.is_none() || config.discord_channel_id.is_none()) {
+            anyhow::bail!("Discord storage requires DISCORD_BOT_TOKEN and DISCORD_CHANNEL_ID");
+        }
+        if config.discord_chunk_bytes == 0 {
+            anyhow::bail!("DISCORD_CHUNK_BYTES must be greater than 0");
+        }
+        // Every chunk has to stay within what getFile lets a bot download
+        if config.storage_chunk_bytes > MAX_STORAGE_CHUNK_BYTES {
+            anyhow::bail!("STORAGE_CHUNK_BYTES must be at most {}, got {}", MAX_STORAGE_CHUNK_BYTES, config.storage_chunk_bytes);
+        }
+        // Content-addressed IDs only resolve through the index, so it has to outlive restarts
+        if config.content_addressed_ids && config.index_path.is_none() {
+            anyhow::bail!("CONTENT_ADDRESSED_IDS requires INDEX_PATH");
+        }
+        if config.optimize_steps.contains(&OptimizeStep::Downscale) && config.optimize_max_dimension.is_none_or(|max| max == 0) {
+            anyhow::bail!("OPTIMIZE_STEPS downscale requires a positive OPTIMIZE_MAX_DIMENSION");
+        }
+        if config.chaos_failure_percent > 100 || config.chaos_delay_percent > 100 {
+            anyhow::bail!("CHAOS_FAILURE_PERCENT and CHAOS_DELAY_PERCENT must be between 0 and 100");
+        }
 
         Ok(config)
     }
 
+    /// Schedule for a periodic task, falling back to `default`; `None` when disabled
+    pub fn task_schedule(&self, task: &str, default: &str) -> Result<Option<Schedule>> {
+        let spec = self.task_schedules.get(task).map(String::as_str).unwrap_or(default);
+        if spec.eq_ignore_ascii_case("off") {
+            return Ok(None);
+        }
+        Schedule::parse(spec)
+            .map(Some)
+            .with_context(|| format!("SCHEDULE_{} is not a valid schedule", task.to_uppercase()))
+    }
+
+    /// Directory spilled uploads are written to, the system temp dir unless SPILL_DIR is set
+    pub fn spill_dir(&self) -> std::path::PathBuf {
+        self.spill_dir
+            .as_ref()
+            .map(Into::into)
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Largest request body accepted by upload routes: a max-size file sent as base64,
+    /// plus room for multipart or JSON framing
+    pub fn upload_body_limit(&self) -> usize {
+        self.max_file_size.div_ceil(3) * 4 + 64 * 1024
+    }
+
+    /// Reject canvases beyond MAX_IMAGE_DIMENSION or MAX_MEGAPIXELS, whatever their byte size
+    pub fn check_dimensions(&self, width: u32, height: u32) -> crate::error::Result<()> {
+        if let Some(max) = self.max_image_dimension
+            && (width > max || height > max)
+        {
+            return Err(AppError::DimensionsTooLarge(format!(
+                "Image is {}x{} pixels. Maximum dimension: {} pixels",
+                width, height, max
+            )));
+        }
+
+        let megapixels = width as f64 * height as f64 / 1_000_000.0;
+        if let Some(max) = self.max_megapixels
+            && megapixels > max
+        {
+            return Err(AppError::DimensionsTooLarge(format!(
+                "Image is {:.1} megapixels. Maximum: {} megapixels",
+                megapixels, max
+            )));
+        }
+
+        Ok(())
+    }
+
     pub fn get_encryption_key_bytes(&self) -> Result<[u8; 32]> {
         let key_bytes = general_purpose::STANDARD.decode(&self.encryption_key)
             .context("Failed to decode encryption key")?;
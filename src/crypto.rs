@@ -1,11 +1,33 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, AeadInPlace, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
 use rand::RngCore;
+use sha2::Sha256;
 use crate::{error::{AppError, Result}, models::FileReference};
 
+// Nonce plus GCM tag around the smallest JSON a reference serializes to
+const MIN_REFERENCE_BYTES: usize = 12 + 16 + 64;
+// Well past the longest reference issued (every optional field set), so anything longer is garbage
+const MAX_ENCRYPTED_ID_LEN: usize = 1024;
+
+// Leads every encrypted ID issued now. The bytes after it are padded to a multiple of 3, so the
+// ID is one character longer than a multiple of 4, a length unpadded base64 never has; IDs from
+// before the version byte keep being accepted by their length.
+const ID_VERSION: u8 = b'1';
+
+// Content-addressed IDs are this prefix and a truncated MAC, far shorter than any encrypted ID
+const CONTENT_ID_PREFIX: char = 'c';
+const CONTENT_ID_MAC_BYTES: usize = 18;
+const CONTENT_ID_LEN: usize = 1 + CONTENT_ID_MAC_BYTES * 4 / 3;
+
+/// Whether `id` has the shape of a content-addressed ID rather than an encrypted reference
+pub fn is_content_id(id: &str) -> bool {
+    id.len() == CONTENT_ID_LEN && id.starts_with(CONTENT_ID_PREFIX)
+}
+
 pub struct CryptoService {
     cipher: Aes256Gcm,
 }
@@ -16,21 +38,22 @@ impl CryptoService {
         Self { cipher }
     }
 
-    /// Encrypt image data
+    /// Encrypt image data as nonce || ciphertext || tag, in a single allocation
     pub fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>> {
         let mut nonce_bytes = [0u8; 12];
         OsRng.fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
-        let mut ciphertext = self
+        let mut result = Vec::with_capacity(nonce_bytes.len() + data.len() + 16);
+        result.extend_from_slice(&nonce_bytes);
+        result.extend_from_slice(data);
+
+        let tag = self
             .cipher
-            .encrypt(nonce, data)
+            .encrypt_in_place_detached(nonce, b"", &mut result[nonce_bytes.len()..])
             .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+        result.extend_from_slice(&tag);
 
-        // Prepend nonce to ciphertext
-        let mut result = nonce_bytes.to_vec();
-        result.append(&mut ciphertext);
-        
         Ok(result)
     }
 
@@ -51,15 +74,20 @@ impl CryptoService {
         Ok(plaintext)
     }
 
-    /// Encrypt file reference for URL-safe ID
+    /// Encrypt file reference for URL-safe ID: the version byte, then nonce || ciphertext in
+    /// base64. The version is authenticated along with the reference.
     pub fn encrypt_file_reference(&self, file_ref: &FileReference) -> Result<String> {
-        let json_data = serde_json::to_vec(file_ref)
+        let mut json_data = serde_json::to_vec(file_ref)
             .map_err(|e| AppError::InternalError(e.to_string()))?;
+        // Trailing whitespace is ignored when the JSON is parsed back
+        while (12 + json_data.len() + 16) % 3 != 0 {
+            json_data.push(b' ');
+        }
 
         let nonce = Nonce::from_slice(&file_ref.nonce);
         let ciphertext = self
             .cipher
-            .encrypt(nonce, json_data.as_slice())
+            .encrypt(nonce, Payload { msg: &json_data, aad: &[ID_VERSION] })
             .map_err(|e| AppError::EncryptionError(e.to_string()))?;
 
         // Combine nonce and ciphertext
@@ -67,15 +95,47 @@ impl CryptoService {
         combined.extend_from_slice(&ciphertext);
 
         // Base64 URL-safe encoding
-        Ok(general_purpose::URL_SAFE_NO_PAD.encode(&combined))
+        Ok(format!("{}{}", ID_VERSION as char, general_purpose::URL_SAFE_NO_PAD.encode(&combined)))
+    }
+
+    /// The ID an image is shared under: its content-addressed ID when it has one, otherwise the
+    /// encrypted reference itself
+    pub fn public_id(&self, file_ref: &FileReference) -> Result<String> {
+        match &file_ref.content_id {
+            Some(id) => Ok(id.clone()),
+            None => self.encrypt_file_reference(file_ref),
+        }
+    }
+
+    /// ID derived from the content hash and tenant, keyed so IDs can't be guessed from content
+    /// someone merely suspects is stored
+    pub fn content_id(key: &[u8; 32], tenant: Option<&str>, content_hash: &str) -> String {
+        let mac = Self::hmac_sha256(key, format!("content-id:{}:{}", tenant.unwrap_or(""), content_hash).as_bytes());
+        format!("{}{}", CONTENT_ID_PREFIX, general_purpose::URL_SAFE_NO_PAD.encode(&mac[..CONTENT_ID_MAC_BYTES]))
     }
 
-    /// Decrypt file reference from URL-safe ID
+    /// Decrypt file reference from URL-safe ID. Malformed IDs are turned away on their length and
+    /// alphabet before any decoding, and references that decrypt to anything the server could not
+    /// have issued are rejected like undecryptable ones.
     pub fn decrypt_file_reference(&self, encrypted_id: &str) -> Result<FileReference> {
-        let combined = general_purpose::URL_SAFE_NO_PAD.decode(encrypted_id)
+        if encrypted_id.len() > MAX_ENCRYPTED_ID_LEN
+            || !encrypted_id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(AppError::InvalidImageId);
+        }
+        // Versioned IDs are the only ones one character past a multiple of 4
+        let (aad, encoded): (&[u8], &str) = if encrypted_id.len() % 4 == 1 {
+            match encrypted_id.split_at(1) {
+                (version, rest) if version.as_bytes() == [ID_VERSION] => (&[ID_VERSION], rest),
+                _ => return Err(AppError::InvalidImageId),
+            }
+        } else {
+            (b"", encrypted_id)
+        };
+        let combined = general_purpose::URL_SAFE_NO_PAD.decode(encoded)
             .map_err(|_| AppError::InvalidImageId)?;
 
-        if combined.len() < 12 {
+        if combined.len() < MIN_REFERENCE_BYTES {
             return Err(AppError::InvalidImageId);
         }
 
@@ -84,16 +144,62 @@ impl CryptoService {
 
         let plaintext = self
             .cipher
-            .decrypt(nonce, ciphertext)
+            .decrypt(nonce, Payload { msg: ciphertext, aad })
             .map_err(|_| AppError::InvalidImageId)?;
 
         let file_ref: FileReference = serde_json::from_slice(&plaintext)
             .map_err(|_| AppError::InvalidImageId)?;
+        if !file_ref.is_well_formed() {
+            return Err(AppError::InvalidImageId);
+        }
 
         Ok(file_ref)
     }
 
+    /// Token authorizing deletion of an image ID without the admin secret
+    pub fn delete_token(key: &[u8; 32], encrypted_id: &str) -> String {
+        let mac = Self::delete_mac(key, encrypted_id);
+        hex::encode(&mac.finalize().into_bytes()[..16])
+    }
+
+    /// Check a deletion token in constant time
+    pub fn verify_delete_token(key: &[u8; 32], encrypted_id: &str, token: &str) -> bool {
+        let Ok(token_bytes) = hex::decode(token) else {
+            return false;
+        };
+        if token_bytes.len() != 16 {
+            return false;
+        }
+        let mac = Self::delete_mac(key, encrypted_id);
+        mac.verify_truncated_left(&token_bytes).is_ok()
+    }
+
+    fn delete_mac(key: &[u8; 32], encrypted_id: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"delete:");
+        mac.update(encrypted_id.as_bytes());
+        mac
+    }
+
+    /// HMAC-SHA256 of `data` under an arbitrary-length key
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Check a full HMAC-SHA256 `tag` of `data` in constant time
+    pub fn verify_hmac_sha256(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(data);
+        mac.verify_slice(tag).is_ok()
+    }
+
     /// Generate a secure random key
+    #[allow(dead_code)]
     pub fn generate_key() -> [u8; 32] {
         let mut key = [0u8; 32];
         OsRng.fill_bytes(&mut key);
@@ -102,7 +208,7 @@ impl CryptoService {
 
     /// Hash data using SHA-256
     pub fn hash_data(data: &[u8]) -> [u8; 32] {
-        use sha2::{Digest, Sha256};
+        use sha2::Digest;
         let mut hasher = Sha256::new();
         hasher.update(data);
         hasher.finalize().into()
@@ -141,7 +247,69 @@ mod tests {
         
         assert_eq!(file_ref.file_id, decrypted_ref.file_id);
         assert_eq!(file_ref.message_id, decrypted_ref.message_id);
-        assert_eq!(file_ref.file_size, decrypted_ref.file_size);
+        assert_eq!(file_ref.size, decrypted_ref.size);
         assert_eq!(file_ref.mime_type, decrypted_ref.mime_type);
     }
+
+    #[test]
+    fn test_id_versions() {
+        let crypto = CryptoService::new(&CryptoService::generate_key());
+        let file_ref = FileReference::new("file_id".to_string(), 1, 10, "image/png".to_string());
+        let id = crypto.encrypt_file_reference(&file_ref).unwrap();
+        assert!(id.starts_with('1') && id.len() % 4 == 1);
+
+        // Unknown versions are refused, and the version can't be stripped or swapped
+        for tampered in [format!("2{}", &id[1..]), id[1..].to_string(), format!("A{}", &id[1..])] {
+            assert!(matches!(crypto.decrypt_file_reference(&tampered), Err(AppError::InvalidImageId)));
+        }
+
+        // IDs issued before the version byte still resolve
+        let json = serde_json::to_vec(&file_ref).unwrap();
+        let ciphertext = crypto.cipher.encrypt(Nonce::from_slice(&file_ref.nonce), json.as_slice()).unwrap();
+        let legacy = general_purpose::URL_SAFE_NO_PAD.encode([file_ref.nonce.as_slice(), &ciphertext].concat());
+        assert_eq!(crypto.decrypt_file_reference(&legacy).unwrap().file_id, "file_id");
+    }
+
+    #[test]
+    fn test_rejects_malformed_references() {
+        let crypto = CryptoService::new(&CryptoService::generate_key());
+        let too_long = "A".repeat(MAX_ENCRYPTED_ID_LEN + 1);
+        for garbage in ["", "abc", "../../etc/passwd", "AAAA====", too_long.as_str()] {
+            assert!(matches!(crypto.decrypt_file_reference(garbage), Err(AppError::InvalidImageId)));
+        }
+
+        // Authentic but outside what the server issues
+        let mut file_ref = FileReference::new("file_id".to_string(), 1, 1024, "text/html".to_string());
+        let id = crypto.encrypt_file_reference(&file_ref).unwrap();
+        assert!(matches!(crypto.decrypt_file_reference(&id), Err(AppError::InvalidImageId)));
+        file_ref.mime_type = "image/png".to_string();
+        file_ref.width = Some(0);
+        let id = crypto.encrypt_file_reference(&file_ref).unwrap();
+        assert!(matches!(crypto.decrypt_file_reference(&id), Err(AppError::InvalidImageId)));
+    }
+
+    #[test]
+    fn test_content_id() {
+        let key = CryptoService::generate_key();
+        let id = CryptoService::content_id(&key, None, "ab12");
+        assert!(is_content_id(&id));
+        assert_eq!(id, CryptoService::content_id(&key, None, "ab12"));
+        assert_ne!(id, CryptoService::content_id(&key, Some("acme"), "ab12"));
+        assert_ne!(id, CryptoService::content_id(&CryptoService::generate_key(), None, "ab12"));
+
+        let crypto = CryptoService::new(&key);
+        let file_ref = FileReference::new("file_id".to_string(), 1, 10, "image/png".to_string());
+        assert!(!is_content_id(&crypto.public_id(&file_ref).unwrap()));
+    }
+
+    #[test]
+    fn test_delete_token() {
+        let key = CryptoService::generate_key();
+        let token = CryptoService::delete_token(&key, "some_id");
+
+        assert!(CryptoService::verify_delete_token(&key, "some_id", &token));
+        assert!(!CryptoService::verify_delete_token(&key, "other_id", &token));
+        assert!(!CryptoService::verify_delete_token(&key, "some_id", &token[..8]));
+        assert!(!CryptoService::verify_delete_token(&key, "some_id", "not hex"));
+    }
 }
@@ -1,13 +1,17 @@
 use axum::{
     extract::multipart::MultipartError,
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Serialize;
 use serde_json::json;
+use std::sync::OnceLock;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+use crate::middleware::request_id::current_request_id;
+
+#[derive(Error, Debug, Clone)]
 pub enum AppError {
     #[error("Telegram API error: {0}")]
     TelegramError(String),
@@ -26,6 +30,15 @@ pub enum AppError {
     
     #[error("Image not found")]
     NotFound,
+
+    // Indexed, but Telegram no longer has the file and no copy was left to restore it from
+    #[error("Image no longer available")]
+    Gone,
+
+    // Telegram answered "wrong file_id or the file is temporarily unavailable", which outages produce
+    // as well as purged files, so it is retried rather than taken as final
+    #[error("File temporarily unavailable")]
+    FileUnavailable,
     
     #[error("Invalid image ID")]
     InvalidImageId,
@@ -44,60 +57,442 @@ pub enum AppError {
 
     #[error("Invalid ID format")]
     InvalidId,
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+
+    // `first_uploaded_at` is when the existing copy was stored
+    #[error("Duplicate of existing image {id}")]
+    Duplicate { id: String, first_uploaded_at: u64 },
+
+    #[error("Image dimensions too large: {0}")]
+    DimensionsTooLarge(String),
+
+    #[error("Invalid fields: {0:?}")]
+    InvalidFields(Vec<FieldError>),
+
+    #[error("Server overloaded")]
+    Overloaded { retry_after_secs: u64 },
+
+    // Anonymous upload without a CAPTCHA token the provider accepted
+    #[error("CAPTCHA verification failed")]
+    CaptchaFailed,
+
+    // The client's country is not allowed on this route by the GeoIP policy
+    #[error("Not available in your region")]
+    RegionBlocked,
+
+    // Uploads are switched off by maintenance mode; downloads keep working
+    #[error("Uploads paused for maintenance")]
+    Maintenance { retry_after_secs: u64 },
+
+    // Every mutating endpoint is switched off by read-only mode
+    #[error("Server is read-only")]
+    ReadOnly,
+
+    // The body received doesn't match a checksum the client sent with it
+    #[error("{algorithm} checksum mismatch")]
+    ChecksumMismatch { algorithm: &'static str },
+}
+
+// One problem with one request field
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+/// Collects every field-level problem of a request so they are reported together
+#[derive(Debug, Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &str, reason: impl Into<String>) {
+        self.0.push(FieldError { field: field.to_string(), reason: reason.into() });
+    }
+
+    /// Fail with all collected problems, if there are any
+    pub fn into_result(self) -> Result<()> {
+        if self.0.is_empty() { Ok(()) } else { Err(AppError::InvalidFields(self.0)) }
+    }
+}
+
+/// Stable, machine-readable identifier of each kind of error, sent as `code` in error bodies.
+/// Codes are never renamed; clients branch on them instead of the human-readable message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    TelegramUnavailable,
+    EncryptionFailed,
+    InvalidFileFormat,
+    FileTooLarge,
+    RateLimited,
+    NotFound,
+    ImageGone,
+    InvalidImageId,
+    InternalError,
+    ValidationFailed,
+    ConfigurationError,
+    Unauthorized,
+    InvalidId,
+    Timeout,
+    QuotaExceeded,
+    DuplicateImage,
+    DimensionsTooLarge,
+    InvalidFields,
+    Overloaded,
+    CaptchaFailed,
+    RegionBlocked,
+    Maintenance,
+    ReadOnly,
+    ChecksumMismatch,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 24] = [
+        ErrorCode::TelegramUnavailable,
+        ErrorCode::EncryptionFailed,
+        ErrorCode::InvalidFileFormat,
+        ErrorCode::FileTooLarge,
+        ErrorCode::RateLimited,
+        ErrorCode::NotFound,
+        ErrorCode::ImageGone,
+        ErrorCode::InvalidImageId,
+        ErrorCode::InternalError,
+        ErrorCode::ValidationFailed,
+        ErrorCode::ConfigurationError,
+        ErrorCode::Unauthorized,
+        ErrorCode::InvalidId,
+        ErrorCode::Timeout,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::DuplicateImage,
+        ErrorCode::DimensionsTooLarge,
+        ErrorCode::InvalidFields,
+        ErrorCode::Overloaded,
+        ErrorCode::CaptchaFailed,
+        ErrorCode::RegionBlocked,
+        ErrorCode::Maintenance,
+        ErrorCode::ReadOnly,
+        ErrorCode::ChecksumMismatch,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::TelegramUnavailable => "telegram_unavailable",
+            ErrorCode::EncryptionFailed => "encryption_failed",
+            ErrorCode::InvalidFileFormat => "invalid_file_format",
+            ErrorCode::FileTooLarge => "file_too_large",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::ImageGone => "image_gone",
+            ErrorCode::InvalidImageId => "invalid_image_id",
+            ErrorCode::InternalError => "internal_error",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::ConfigurationError => "configuration_error",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::InvalidId => "invalid_id",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::DuplicateImage => "duplicate_image",
+            ErrorCode::DimensionsTooLarge => "dimensions_too_large",
+            ErrorCode::InvalidFields => "invalid_fields",
+            ErrorCode::Overloaded => "overloaded",
+            ErrorCode::CaptchaFailed => "captcha_failed",
+            ErrorCode::RegionBlocked => "region_blocked",
+            ErrorCode::Maintenance => "maintenance",
+            ErrorCode::ReadOnly => "read_only",
+            ErrorCode::ChecksumMismatch => "checksum_mismatch",
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::TelegramUnavailable | ErrorCode::Overloaded | ErrorCode::Maintenance | ErrorCode::ReadOnly => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorCode::EncryptionFailed | ErrorCode::InternalError | ErrorCode::ConfigurationError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ErrorCode::InvalidFileFormat
+            | ErrorCode::InvalidImageId
+            | ErrorCode::ValidationFailed
+            | ErrorCode::InvalidId
+            | ErrorCode::InvalidFields
+            | ErrorCode::ChecksumMismatch => StatusCode::BAD_REQUEST,
+            ErrorCode::FileTooLarge | ErrorCode::DimensionsTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::ImageGone => StatusCode::GONE,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::QuotaExceeded | ErrorCode::CaptchaFailed => StatusCode::FORBIDDEN,
+            ErrorCode::DuplicateImage => StatusCode::CONFLICT,
+            ErrorCode::RegionBlocked => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+        }
+    }
+
+    /// What the code means, for the catalog served at /errors
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorCode::TelegramUnavailable => "Telegram could not be reached or rejected the request; retry later",
+            ErrorCode::EncryptionFailed => "Stored data could not be encrypted or decrypted",
+            ErrorCode::InvalidFileFormat => "The file is not an accepted image type or could not be decoded",
+            ErrorCode::FileTooLarge => "The file exceeds the maximum size; details.max_size has the limit",
+            ErrorCode::RateLimited => "Too many requests from this client; retry later",
+            ErrorCode::NotFound => "No image exists under this ID",
+            ErrorCode::ImageGone => "The image was stored but its file is gone from Telegram and could not be restored",
+            ErrorCode::InvalidImageId => "The image ID is malformed",
+            ErrorCode::InternalError => "Unexpected server error",
+            ErrorCode::ValidationFailed => "A request parameter is missing or invalid",
+            ErrorCode::ConfigurationError => "The server is missing configuration needed for this request",
+            ErrorCode::Unauthorized => "Missing or invalid API key or admin credentials",
+            ErrorCode::InvalidId => "An ID in the request is malformed",
+            ErrorCode::Timeout => "The operation did not finish in time",
+            ErrorCode::QuotaExceeded => "The storage quota would be exceeded",
+            ErrorCode::DuplicateImage => {
+                "An identical image is already stored; details.id holds its ID and details.first_uploaded_at when it was stored"
+            }
+            ErrorCode::DimensionsTooLarge => "The image's pixel dimensions exceed the configured limits",
+            ErrorCode::InvalidFields => "One or more fields are invalid; details.fields lists each field and reason",
+            ErrorCode::Overloaded => "Too many requests are in flight; retry after the Retry-After header's delay",
+            ErrorCode::CaptchaFailed => "Anonymous uploads need a valid CAPTCHA token in the X-Captcha-Token header",
+            ErrorCode::RegionBlocked => "The route is not available from the client's country",
+            ErrorCode::Maintenance => "Uploads are paused for maintenance; retry after the Retry-After header's delay",
+            ErrorCode::ReadOnly => "The server is read-only: images are served, but nothing can be uploaded, changed or deleted",
+            ErrorCode::ChecksumMismatch => {
+                "The body doesn't match the CRC32C or SHA-256 sent with it; details.algorithm names the one that failed"
+            }
+        }
+    }
+}
+
+/// Languages error messages are translated into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    En,
+    Th,
+}
+
+tokio::task_local! {
+    // Language of the request being handled, set by the locale middleware
+    static LANGUAGE: Language;
+}
+
+impl Language {
+    /// Best supported match for an Accept-Language header, honouring q-values; English otherwise
+    pub fn negotiate(accept_language: &str) -> Self {
+        accept_language
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.trim().split(';');
+                let tag = parts.next()?.trim().to_ascii_lowercase();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                let language = match tag.split('-').next()? {
+                    "th" => Language::Th,
+                    "en" => Language::En,
+                    _ => return None,
+                };
+                (quality > 0.0).then_some((language, quality))
+            })
+            .fold(None, |best: Option<(Language, f32)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            })
+            .map_or(Language::En, |(language, _)| language)
+    }
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Th => "th",
+        }
+    }
+
+    fn pick(self, en: &'static str, th: &'static str) -> &'static str {
+        match self {
+            Language::En => en,
+            Language::Th => th,
+        }
+    }
+
+    /// Run `future` with this as the language of any error it produces
+    pub async fn scope<F: std::future::Future>(self, future: F) -> F::Output {
+        LANGUAGE.scope(self, future).await
+    }
+}
+
+fn current_language() -> Language {
+    LANGUAGE.try_with(|language| *language).unwrap_or_default()
+}
+
+// Base URL of the error documentation; each body links to <base>#<code> when set
+static DOCS_BASE_URL: OnceLock<String> = OnceLock::new();
+
+/// Link error bodies to documentation at `base_url`, set once at startup
+pub fn set_docs_base_url(base_url: String) {
+    let _ = DOCS_BASE_URL.set(base_url);
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::TelegramError(_) => ErrorCode::TelegramUnavailable,
+            AppError::EncryptionError(_) => ErrorCode::EncryptionFailed,
+            AppError::InvalidFileFormat(_) => ErrorCode::InvalidFileFormat,
+            AppError::FileTooLarge { .. } => ErrorCode::FileTooLarge,
+            AppError::RateLimitExceeded => ErrorCode::RateLimited,
+            AppError::NotFound => ErrorCode::NotFound,
+            AppError::Gone => ErrorCode::ImageGone,
+            AppError::FileUnavailable => ErrorCode::TelegramUnavailable,
+            AppError::InvalidImageId => ErrorCode::InvalidImageId,
+            AppError::InternalError(_) => ErrorCode::InternalError,
+            AppError::ValidationError(_) => ErrorCode::ValidationFailed,
+            AppError::ConfigError(_) => ErrorCode::ConfigurationError,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::InvalidId => ErrorCode::InvalidId,
+            AppError::Timeout(_) => ErrorCode::Timeout,
+            AppError::QuotaExceeded => ErrorCode::QuotaExceeded,
+            AppError::Duplicate { .. } => ErrorCode::DuplicateImage,
+            AppError::DimensionsTooLarge(_) => ErrorCode::DimensionsTooLarge,
+            AppError::InvalidFields(_) => ErrorCode::InvalidFields,
+            AppError::Overloaded { .. } => ErrorCode::Overloaded,
+            AppError::CaptchaFailed => ErrorCode::CaptchaFailed,
+            AppError::RegionBlocked => ErrorCode::RegionBlocked,
+            AppError::Maintenance { .. } => ErrorCode::Maintenance,
+            AppError::ReadOnly => ErrorCode::ReadOnly,
+            AppError::ChecksumMismatch { .. } => ErrorCode::ChecksumMismatch,
+        }
+    }
+
+    /// Structured context clients can act on, beyond the message
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::FileTooLarge { max_size } => Some(json!({ "max_size": max_size })),
+            AppError::Duplicate { id, first_uploaded_at } => {
+                Some(json!({ "id": id, "existing": true, "first_uploaded_at": first_uploaded_at }))
+            }
+            AppError::InvalidFields(fields) => Some(json!({ "fields": fields })),
+            AppError::ChecksumMismatch { algorithm } => Some(json!({ "algorithm": algorithm })),
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+        let code = self.code();
+        let status = code.status();
+        let details = self.details();
+        let existing_id = match &self {
+            AppError::Duplicate { id, .. } => Some(id.clone()),
+            _ => None,
+        };
+        let retry_after = match &self {
+            AppError::Overloaded { retry_after_secs } | AppError::Maintenance { retry_after_secs } => {
+                Some(retry_after_secs.to_string())
+            }
+            _ => None,
+        };
+
+        // Human-readable text follows the client's language; codes and details never change
+        let lang = current_language();
+        let error_message = match self {
             AppError::TelegramError(msg) => {
                 tracing::error!("Telegram error: {}", msg);
-                (StatusCode::SERVICE_UNAVAILABLE, "External service error".to_string())
+                lang.pick("External service error", "บริการภายนอกขัดข้อง").to_string()
             }
             AppError::EncryptionError(msg) => {
                 tracing::error!("Encryption error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Encryption error".to_string())
+                lang.pick("Encryption error", "การเข้ารหัสผิดพลาด").to_string()
             }
-            AppError::InvalidFileFormat(msg) => {
-                (StatusCode::BAD_REQUEST, msg)
+            AppError::InvalidFileFormat(msg) => msg,
+            AppError::FileTooLarge { max_size } => match lang {
+                Language::En => format!("File too large. Maximum size: {} bytes", max_size),
+                Language::Th => format!("ไฟล์มีขนาดใหญ่เกินไป ขนาดสูงสุด: {} ไบต์", max_size),
+            },
+            AppError::RateLimitExceeded => lang.pick("Rate limit exceeded", "ส่งคำขอถี่เกินไป").to_string(),
+            AppError::NotFound => lang.pick("Image not found", "ไม่พบรูปภาพ").to_string(),
+            AppError::Gone => lang.pick("Image is no longer available", "รูปภาพนี้ไม่สามารถใช้งานได้แล้ว").to_string(),
+            AppError::FileUnavailable => {
+                lang.pick("Image is temporarily unavailable", "รูปภาพไม่พร้อมใช้งานชั่วคราว").to_string()
             }
-            AppError::FileTooLarge { max_size } => {
-                (StatusCode::PAYLOAD_TOO_LARGE, 
-                 format!("File too large. Maximum size: {} bytes", max_size))
+            AppError::InvalidImageId => lang.pick("Invalid image ID", "รหัสรูปภาพไม่ถูกต้อง").to_string(),
+            AppError::InternalError(msg) => {
+                tracing::error!("Internal error: {}", msg);
+                lang.pick("Internal server error", "เกิดข้อผิดพลาดภายในเซิร์ฟเวอร์").to_string()
             }
-            AppError::RateLimitExceeded => {
-                (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded".to_string())
+            AppError::ValidationError(msg) => msg,
+            AppError::ConfigError(msg) => {
+                tracing::error!("Configuration error: {}", msg);
+                lang.pick("Configuration error", "การตั้งค่าเซิร์ฟเวอร์ผิดพลาด").to_string()
             }
-            AppError::NotFound => {
-                (StatusCode::NOT_FOUND, "Image not found".to_string())
+            AppError::Unauthorized => lang.pick("Unauthorized", "ไม่ได้รับอนุญาต").to_string(),
+            AppError::InvalidId => lang.pick("Invalid ID format", "รูปแบบรหัสไม่ถูกต้อง").to_string(),
+            AppError::Timeout(msg) => msg,
+            AppError::QuotaExceeded => lang.pick("Storage quota exceeded", "พื้นที่จัดเก็บเกินโควตา").to_string(),
+            AppError::Duplicate { .. } => {
+                lang.pick("An identical image is already stored", "มีรูปภาพเดียวกันนี้จัดเก็บอยู่แล้ว").to_string()
             }
-            AppError::InvalidImageId => {
-                (StatusCode::BAD_REQUEST, "Invalid image ID".to_string())
+            AppError::DimensionsTooLarge(msg) => msg,
+            AppError::InvalidFields(fields) => {
+                let fields: Vec<&str> = fields.iter().map(|error| error.field.as_str()).collect();
+                format!("{}: {}", lang.pick("Invalid fields", "ข้อมูลไม่ถูกต้อง"), fields.join(", "))
             }
-            AppError::InternalError(msg) => {
-                tracing::error!("Internal error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
+            AppError::Overloaded { .. } => {
+                lang.pick("Server is busy, please retry shortly", "เซิร์ฟเวอร์มีภาระงานสูง โปรดลองใหม่อีกครั้ง").to_string()
             }
-            AppError::ValidationError(msg) => {
-                (StatusCode::BAD_REQUEST, msg)
+            AppError::CaptchaFailed => {
+                lang.pick("CAPTCHA verification failed", "การยืนยัน CAPTCHA ไม่ผ่าน").to_string()
             }
-            AppError::ConfigError(msg) => {
-                tracing::error!("Configuration error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Configuration error".to_string())
+            AppError::RegionBlocked => {
+                lang.pick("Not available in your region", "ไม่เปิดให้บริการในภูมิภาคของคุณ").to_string()
             }
-            AppError::Unauthorized => {
-                (StatusCode::UNAUTHORIZED, "Unauthorized".to_string())
+            AppError::Maintenance { .. } => {
+                lang.pick("Uploads are paused for maintenance, please retry later", "ระบบปิดรับอัปโหลดชั่วคราวเพื่อบำรุงรักษา โปรดลองใหม่ภายหลัง").to_string()
             }
-            AppError::InvalidId => {
-                (StatusCode::BAD_REQUEST, "Invalid ID format".to_string())
+            AppError::ReadOnly => {
+                lang.pick("The server is read-only", "เซิร์ฟเวอร์อยู่ในโหมดอ่านอย่างเดียว").to_string()
             }
+            AppError::ChecksumMismatch { .. } => lang
+                .pick("Upload corrupted: checksum mismatch", "ข้อมูลที่อัปโหลดเสียหาย: checksum ไม่ตรงกัน")
+                .to_string(),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
+            "code": code,
             "status": status.as_u16()
-        }));
+        });
+        if let Some(details) = details {
+            body["details"] = details;
+        }
+        if let Some(base_url) = DOCS_BASE_URL.get() {
+            body["docs_url"] = json!(format!("{}#{}", base_url, code.as_str()));
+        }
+        if let Some(request_id) = current_request_id() {
+            body["request_id"] = json!(request_id);
+        }
+        // Kept at the top level for clients written before `details` existed
+        if let Some(id) = existing_id {
+            body["id"] = json!(id);
+        }
+        let body = Json(body);
 
-        (status, body).into_response()
+        let mut response = (status, [(header::CONTENT_LANGUAGE, lang.tag())], body).into_response();
+        if let Some(retry_after) = retry_after
+            && let Ok(value) = retry_after.parse()
+        {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+        // Lets middleware tell error kinds apart without parsing the body
+        response.extensions_mut().insert(code);
+        response
     }
 }
 
@@ -140,3 +535,49 @@ impl From<anyhow::Error> for AppError {
         AppError::ConfigError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_are_unique_and_stable() {
+        let codes: std::collections::HashSet<&str> = ErrorCode::ALL.iter().map(|code| code.as_str()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+        // Serialized form matches as_str
+        for code in ErrorCode::ALL {
+            assert_eq!(serde_json::to_value(code).unwrap(), json!(code.as_str()));
+        }
+        assert_eq!(AppError::FileTooLarge { max_size: 1 }.code().as_str(), "file_too_large");
+        assert_eq!(AppError::QuotaExceeded.code().status(), StatusCode::FORBIDDEN);
+
+        let duplicate = AppError::Duplicate { id: "abc".into(), first_uploaded_at: 1700000000 };
+        assert_eq!(
+            duplicate.details(),
+            Some(json!({ "id": "abc", "existing": true, "first_uploaded_at": 1700000000 }))
+        );
+    }
+
+    #[test]
+    fn test_field_errors_are_collected() {
+        assert!(FieldErrors::default().into_result().is_ok());
+
+        let mut errors = FieldErrors::default();
+        errors.add("image", "missing");
+        errors.add("expires_in", "must be positive");
+        match errors.into_result() {
+            Err(AppError::InvalidFields(fields)) => assert_eq!(fields.len(), 2),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_language() {
+        assert_eq!(Language::negotiate("th-TH,th;q=0.9,en;q=0.8"), Language::Th);
+        assert_eq!(Language::negotiate("en-US,th;q=0.5"), Language::En);
+        assert_eq!(Language::negotiate("fr, th;q=0.3"), Language::Th);
+        assert_eq!(Language::negotiate("de"), Language::En);
+        assert_eq!(Language::negotiate("th;q=0"), Language::En);
+    }
+}
@@ -1,19 +1,124 @@
 use axum::{
-    extract::{Path, State, ConnectInfo},
-    http::StatusCode,
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State, ConnectInfo},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
-use serde::Deserialize;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
 use crate::{
+    cleanup::{plan_cleanup, run_cleanup, CleanupCandidate},
+    orphans::{run_orphan_scan, OrphanScanReport, OrphanScanStatus},
+    crypto::CryptoService,
     error::AppError,
+    handlers::{
+        delete::delete_stored_image,
+        image::load_image_data,
+        pagination::{paginate, Page, PageQuery},
+    },
+    models::{unix_timestamp, Visibility},
+    recovery::content_key_and_storage,
+    services::{
+        audit::{AuditAction, AuditEvent},
+        cdn,
+        geoip::OriginStats,
+        index::{ImageFilter, IndexEntry},
+        manifest,
+        storage::Storage,
+        tenants::{TenantSettings, TenantSummary},
+        transcode,
+        usage::UsageSummary,
+    },
+    worker::QueueSnapshot,
     AppState,
 };
 
+// Usage subject charged for deletes performed with the admin secret
+pub(crate) const ADMIN_SUBJECT: &str = "admin";
+
+// Cookie set by the dashboard login, scoped to /admin
+pub(crate) const SESSION_COOKIE: &str = "rustgram_admin";
+
+// Dashboard sessions last half a day
+pub(crate) const SESSION_MAX_AGE_SECS: u64 = 12 * 3600;
+
+/// Extractor guarding admin endpoints; the admin secret is sent in the `X-Admin-Key` header,
+/// or a dashboard session cookie is presented instead
+pub struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AdminAuth {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, AppError> {
+        let provided = parts
+            .headers
+            .get("x-admin-key")
+            .and_then(|value| value.to_str().ok());
+
+        match provided {
+            Some(key) if is_admin_key(&state.admin_secret, key) => Ok(AdminAuth),
+            _ if has_admin_session(&parts.headers, &state.admin_secret) => Ok(AdminAuth),
+            _ => Err(AppError::Unauthorized),
+        }
+    }
+}
+
+/// Whether `key` is the admin secret, compared in constant time; nothing matches an unset secret
+pub(crate) fn is_admin_key(admin_secret: &str, key: &str) -> bool {
+    !admin_secret.is_empty()
+        && CryptoService::verify_hmac_sha256(
+            key.as_bytes(),
+            b"admin-key",
+            &CryptoService::hmac_sha256(admin_secret.as_bytes(), b"admin-key"),
+        )
+}
+
+/// Session value stored in the dashboard cookie: when it was issued and a MAC over that, so the
+/// secret itself never sits in a cookie and a leaked cookie stops working once it expires
+pub(crate) fn session_token(admin_secret: &str, issued_at: u64) -> String {
+    let mac = CryptoService::hmac_sha256(admin_secret.as_bytes(), session_message(issued_at).as_bytes());
+    format!("{}.{}", issued_at, hex::encode(mac))
+}
+
+fn session_message(issued_at: u64) -> String {
+    format!("admin-session:{}", issued_at)
+}
+
+/// Check a session token's MAC in constant time, and that it was issued within the last
+/// SESSION_MAX_AGE_SECS
+fn verify_session_token(admin_secret: &str, token: &str, now: u64) -> bool {
+    let Some((issued_at, mac)) = token.split_once('.') else {
+        return false;
+    };
+    let (Ok(issued_at), Ok(mac)) = (issued_at.parse::<u64>(), hex::decode(mac)) else {
+        return false;
+    };
+    issued_at <= now
+        && now - issued_at < SESSION_MAX_AGE_SECS
+        && CryptoService::verify_hmac_sha256(admin_secret.as_bytes(), session_message(issued_at).as_bytes(), &mac)
+}
+
+pub(crate) fn has_admin_session(headers: &HeaderMap, admin_secret: &str) -> bool {
+    if admin_secret.is_empty() {
+        return false;
+    }
+
+    let now = unix_timestamp();
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .any(|(name, value)| name == SESSION_COOKIE && verify_session_token(admin_secret, value, now))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AdminDeleteRequest {
     api_key: String,
@@ -26,9 +131,9 @@ pub async fn delete_image(
     Json(payload): Json<AdminDeleteRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     // Basic API Key authentication
-    if payload.api_key != state.admin_secret {
+    if !is_admin_key(&state.admin_secret, &payload.api_key) {
         info!("Unauthorized attempt to delete image: {} from IP: {}", id, addr);
-        state.telegram_service.send_log_message(&format!("Unauthorized delete attempt for image ID: {} from IP: {}", id, addr)).await?;
+        state.audit.record(AuditEvent::new(AuditAction::DeleteDenied).id(&id).ip(addr.ip()));
         return Err(AppError::Unauthorized);
     }
 
@@ -38,23 +143,550 @@ pub async fn delete_image(
     let parts: Vec<&str> = id.split('_').collect();
     if parts.len() != 2 {
         info!("Invalid image ID format for deletion: {} from IP: {}", id, addr);
-        state.telegram_service.send_log_message(&format!("Invalid image ID format for deletion: {} from IP: {}", id, addr)).await?;
+        let event = AuditEvent::new(AuditAction::DeleteFailed).id(&id).ip(addr.ip()).detail("invalid ID format");
+        state.audit.record(event);
         return Err(AppError::InvalidId);
     }
 
     let chat_id = parts[0].parse::<i64>().map_err(|_| AppError::InvalidId)?;
     let message_id = parts[1].parse::<i64>().map_err(|_| AppError::InvalidId)?;
 
+    // A chunked file's manifest is read before its message goes
+    let indexed = state.index.find_by_message(chat_id, message_id, state.config.telegram_chat_id)?;
+    if let Some(entry) = &indexed
+        && entry.reference.backend.is_telegram()
+    {
+        let master_key = state.config.get_encryption_key_bytes()?;
+        let storage = Storage::Telegram(state.telegram_service.clone());
+        manifest::delete_file_chunks(&storage, &master_key, Some(chat_id), &entry.reference).await;
+    }
+
     match state.telegram_service.delete_message(chat_id, message_id).await {
         Ok(_) => {
             info!("Successfully deleted image with ID: {} from IP: {}", id, addr);
-            state.telegram_service.send_log_message(&format!("Image deleted: {} by IP: {}", id, addr)).await?;
+            if let Some(entry) = indexed {
+                if entry.reference.backend.is_telegram() {
+                    let master_key = state.config.get_encryption_key_bytes()?;
+                    let storage = Storage::Telegram(state.telegram_service.clone());
+                    transcode::delete_transcodes(&storage, &master_key, chat_id, &entry).await;
+                }
+                state.index.remove(&entry.id)?;
+                state.cache.remove(&entry.reference.file_id);
+                state.cdn.purge_in_background(
+                    state.config.clone(),
+                    cdn::image_paths(&entry.id, entry.reference.tenant.as_deref()),
+                );
+                state.usage.record_delete(&[ADMIN_SUBJECT.to_string()], &entry.uploader, entry.reference.size);
+            }
+            state.audit.record(AuditEvent::new(AuditAction::Delete).id(&id).ip(addr.ip()).detail("admin"));
             Ok(StatusCode::OK)
         }
         Err(e) => {
             info!("Failed to delete image with ID {}: {:?} from IP: {}", id, e, addr);
-            state.telegram_service.send_log_message(&format!("Failed to delete image {}: {:?} by IP: {}", id, e, addr)).await?;
+            let event = AuditEvent::new(AuditAction::DeleteFailed).id(&id).ip(addr.ip()).detail(e.to_string());
+            state.audit.record(event);
             Err(e)
         }
     }
 }
+
+// An indexed image as listed to admins, without its Telegram internals
+#[derive(Debug, Serialize)]
+pub struct AdminImage {
+    pub id: String,
+    pub size: usize,
+    pub mime_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub tenant: Option<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    // Usage subjects of the uploader, the values the `owner` filter takes
+    pub uploader: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub broken: bool,
+    pub visibility: Visibility,
+}
+
+/// Most recently uploaded images, newest first, narrowed down by any `ImageFilter` parameters
+pub async fn list_images(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+    Query(filter): Query<ImageFilter>,
+) -> Result<Json<Page<AdminImage>>, AppError> {
+    // One more than the page holds, so paginate can tell whether another follows
+    let newest = state.index.page(&filter, query.after()?.as_ref(), query.limit() + 1)?;
+    let images = paginate(newest, &query, IndexEntry::sort_key)?
+        .map(|entry| AdminImage {
+            id: entry.id,
+            size: entry.reference.size,
+            mime_type: entry.reference.mime_type,
+            width: entry.reference.width,
+            height: entry.reference.height,
+            tenant: entry.reference.tenant,
+            created_at: entry.created_at,
+            expires_at: entry.reference.expires_at,
+            uploader: entry.uploader,
+            tags: entry.tags,
+            broken: entry.broken,
+            visibility: entry.reference.visibility,
+        });
+
+    Ok(Json(images))
+}
+
+/// Delete an indexed image by its public ID
+pub async fn delete_indexed_image(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    delete_stored_image(&state, &id, &[ADMIN_SUBJECT.to_string()]).await?;
+    info!("Admin deleted image {}", id);
+    state.audit.record(AuditEvent::new(AuditAction::Delete).id(&id).detail("admin"));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminStats {
+    pub images: usize,
+    pub stored_bytes: u64,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub tenants: usize,
+    // Worker pacing: the pause after each upload, and the resulting ceiling (absent when unpaced)
+    pub upload_delay_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uploads_per_minute: Option<u64>,
+    // Traffic by country and ASN since startup, when GeoIP databases are configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origins: Option<OriginStats>,
+}
+
+/// Storage totals and current upload queue depth
+pub async fn get_stats(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AdminStats>, AppError> {
+    let entries = state.index.list()?;
+    let queue_capacity = state.upload_queue.max_capacity();
+
+    Ok(Json(AdminStats {
+        images: entries.len(),
+        stored_bytes: entries.iter().map(|entry| entry.reference.size as u64).sum(),
+        queue_depth: queue_capacity - state.upload_queue.capacity(),
+        queue_capacity,
+        tenants: state.tenants.list()?.len(),
+        upload_delay_secs: state.config.upload_delay_secs,
+        max_uploads_per_minute: (state.config.upload_delay_secs > 0).then(|| 60 / state.config.upload_delay_secs),
+        origins: state.geo.stats(),
+    }))
+}
+
+/// Jobs waiting in the upload queue and the one the worker is on, for diagnosing stuck uploads
+pub async fn get_queue(_admin: AdminAuth, State(state): State<Arc<AppState>>) -> Json<QueueSnapshot> {
+    Json(state.worker.snapshot())
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerState {
+    pub paused: bool,
+}
+
+/// Stop the worker taking jobs off the queue, halting Telegram uploads; the job in progress finishes
+pub async fn pause_worker(_admin: AdminAuth, State(state): State<Arc<AppState>>) -> Json<WorkerState> {
+    if state.worker.set_paused(true) {
+        tracing::warn!("Upload worker paused by admin");
+    }
+    Json(WorkerState { paused: true })
+}
+
+/// Let the worker take jobs again, in the order they were queued
+pub async fn resume_worker(_admin: AdminAuth, State(state): State<Arc<AppState>>) -> Json<WorkerState> {
+    if state.worker.set_paused(false) {
+        tracing::info!("Upload worker resumed by admin");
+    }
+    Json(WorkerState { paused: false })
+}
+
+// Whether a runtime mode is on, read and written by the mode endpoints
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModeState {
+    pub enabled: bool,
+}
+
+pub async fn get_maintenance(_admin: AdminAuth, State(state): State<Arc<AppState>>) -> Json<ModeState> {
+    Json(ModeState { enabled: state.modes.maintenance() })
+}
+
+/// Switch maintenance mode: uploads get 503 with Retry-After while downloads keep working
+pub async fn set_maintenance(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ModeState>,
+) -> Json<ModeState> {
+    if state.modes.set_maintenance(request.enabled) {
+        tracing::warn!("Maintenance mode {} by admin", if request.enabled { "enabled" } else { "disabled" });
+    }
+    Json(ModeState { enabled: request.enabled })
+}
+
+pub async fn get_read_only(_admin: AdminAuth, State(state): State<Arc<AppState>>) -> Json<ModeState> {
+    Json(ModeState { enabled: state.modes.read_only() })
+}
+
+/// Switch read-only mode: uploads, changes and deletes get 503 while images keep being served
+pub async fn set_read_only(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ModeState>,
+) -> Json<ModeState> {
+    if state.modes.set_read_only(request.enabled) {
+        tracing::warn!("Read-only mode {} by admin", if request.enabled { "enabled" } else { "disabled" });
+    }
+    Json(ModeState { enabled: request.enabled })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub action: Option<AuditAction>,
+    pub limit: Option<usize>,
+}
+
+/// Most recent audit events from the local store, newest first
+pub async fn get_audit_events(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Vec<AuditEvent>> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    Json(state.audit.recent(query.action, limit))
+}
+
+/// Start a cleanup pass now instead of waiting for its schedule
+pub async fn trigger_cleanup(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> StatusCode {
+    tokio::spawn(run_cleanup(
+        state.index.clone(),
+        state.usage.clone(),
+        state.tenants.clone(),
+        state.cache.clone(),
+        state.cdn.clone(),
+        state.telegram_service.clone(),
+        state.discord.clone(),
+        state.audit.clone(),
+        state.config.clone(),
+    ));
+    StatusCode::ACCEPTED
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrewarmRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrewarmResponse {
+    pub queued: usize,
+    // IDs that could not be decrypted and were skipped
+    pub invalid: Vec<String>,
+}
+
+// Upper bound on IDs per pre-warm request
+const MAX_PREWARM_IDS: usize = 1000;
+
+/// Fetch images into the download cache in the background, ahead of expected traffic
+pub async fn prewarm_cache(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PrewarmRequest>,
+) -> Result<(StatusCode, Json<PrewarmResponse>), AppError> {
+    if !state.cache.is_enabled() {
+        return Err(AppError::ValidationError("The image cache is disabled (IMAGE_CACHE_BYTES=0)".to_string()));
+    }
+    if payload.ids.len() > MAX_PREWARM_IDS {
+        return Err(AppError::ValidationError(format!("At most {} IDs per request", MAX_PREWARM_IDS)));
+    }
+
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let crypto = CryptoService::new(&encryption_key);
+
+    let mut file_refs = Vec::new();
+    let mut invalid = Vec::new();
+    for id in payload.ids {
+        match state.index.resolve(&crypto, &id) {
+            Ok(file_ref) => file_refs.push(file_ref),
+            Err(_) => invalid.push(id),
+        }
+    }
+
+    let queued = file_refs.len();
+    tokio::spawn(async move {
+        let mut warmed = 0;
+        for file_ref in file_refs {
+            match load_image_data(&state, &encryption_key, &file_ref).await {
+                Ok(_) => warmed += 1,
+                Err(e) => tracing::warn!("Pre-warm failed for file {}: {}", file_ref.file_id, e),
+            }
+        }
+        info!("Pre-warmed {} of {} images", warmed, queued);
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(PrewarmResponse { queued, invalid })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrphanScanRequest {
+    // Storage chat to scan, TELEGRAM_CHAT_ID by default
+    pub chat_id: Option<i64>,
+    // Skip older messages, e.g. those from before the service used the chat
+    pub from_message_id: Option<i64>,
+    // Delete the orphans reported by the dry run `dry_run_id` instead of only reporting them
+    #[serde(default)]
+    pub delete: bool,
+    pub dry_run_id: Option<String>,
+}
+
+/// Start looking for messages in a storage chat that no image references. Returns the scan's
+/// initial report; poll GET /admin/orphans/:id for the outcome. Orphans are only deleted after a
+/// report-only scan listed them, and only with a persistent index: an in-memory one, or one set up
+/// after the scanned messages were sent, doesn't know about images whose IDs still work.
+pub async fn start_orphan_scan(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<OrphanScanRequest>,
+) -> Result<(StatusCode, Json<OrphanScanReport>), AppError> {
+    let chat_id = payload.chat_id.unwrap_or(state.config.telegram_chat_id);
+    // Log and audit messages are ours too, yet never referenced by an image
+    if [state.config.telegram_log_chat_id, state.config.audit_chat_id].contains(&Some(chat_id)) {
+        return Err(AppError::ValidationError(
+            "Chat also receives log or audit messages, which would be taken for orphans".to_string(),
+        ));
+    }
+    // Their copies are sent before the index points at them
+    if state.orphan_scans.running() || state.migrations.running() {
+        return Err(AppError::ValidationError("An orphan scan or migration is already running".to_string()));
+    }
+
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let report = if payload.delete {
+        if state.config.index_path.is_none() {
+            return Err(AppError::ValidationError("Deleting orphans needs a persistent index (INDEX_PATH)".to_string()));
+        }
+        let dry_run = payload
+            .dry_run_id
+            .as_deref()
+            .and_then(|dry_run_id| state.orphan_scans.get(dry_run_id))
+            .filter(|dry_run| !dry_run.delete && dry_run.status == OrphanScanStatus::Completed)
+            .ok_or_else(|| {
+                AppError::ValidationError("Deleting orphans needs the ID of a completed report-only scan".to_string())
+            })?;
+        if payload.chat_id.is_some_and(|chat_id| chat_id != dry_run.chat_id) {
+            return Err(AppError::ValidationError("Dry run scanned another chat".to_string()));
+        }
+        OrphanScanReport::deleting(job_id, &dry_run)
+    } else {
+        OrphanScanReport::new(job_id, chat_id, payload.from_message_id.unwrap_or(1).max(1))
+    };
+    state.orphan_scans.update(&report);
+    tokio::spawn(run_orphan_scan(state.clone(), report.clone()));
+
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+pub async fn get_orphan_scan(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<OrphanScanReport>, AppError> {
+    state.orphan_scans.get(&job_id).map(Json).ok_or(AppError::NotFound)
+}
+
+/// Dry run of the cleanup worker: what would be deleted if it ran now
+pub async fn preview_cleanup(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<CleanupCandidate>>, AppError> {
+    let entries = state.index.list()?;
+    let candidates = plan_cleanup(&entries, state.config.storage_quota_bytes, unix_timestamp());
+
+    Ok(Json(candidates))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    // e.g. "ip:203.0.113.7" or "key:1a2b3c4d5e6f7a8b"; all subjects when omitted
+    pub subject: Option<String>,
+    pub days: Option<u64>,
+}
+
+/// Usage rollups per API key and IP
+pub async fn get_usage(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<Vec<UsageSummary>>, AppError> {
+    let days = query.days.unwrap_or(30).clamp(1, state.config.usage_retention_days.max(1));
+    let summaries = state.usage.summaries(query.subject.as_deref(), days)?;
+
+    Ok(Json(summaries))
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTenantRequest {
+    pub id: String,
+    #[serde(flatten)]
+    pub settings: TenantSettings,
+    // Base64 32-byte content key to use instead of a generated one
+    pub encryption_key: Option<String>,
+}
+
+// A newly issued API key; this is the only time the raw key is returned
+#[derive(Debug, Serialize)]
+pub struct TenantKeyResponse {
+    pub tenant: TenantSummary,
+    pub api_key: String,
+}
+
+pub async fn list_tenants(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TenantSummary>>, AppError> {
+    let tenants = state.tenants.list()?;
+    Ok(Json(tenants.iter().map(TenantSummary::from).collect()))
+}
+
+pub async fn create_tenant(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateTenantRequest>,
+) -> Result<(StatusCode, Json<TenantKeyResponse>), AppError> {
+    let content_key = payload
+        .encryption_key
+        .map(|key| {
+            general_purpose::STANDARD
+                .decode(key)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| AppError::ValidationError("encryption_key must be 32 bytes of base64".to_string()))
+        })
+        .transpose()?;
+
+    let (tenant, api_key) = state.tenants.create(
+        &payload.id,
+        payload.settings,
+        &state.config.get_encryption_key_bytes()?,
+        content_key,
+    )?;
+    info!("Created tenant {}", tenant.id);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(TenantKeyResponse { tenant: TenantSummary::from(&tenant), api_key }),
+    ))
+}
+
+/// Issue an additional API key for an existing tenant
+pub async fn create_tenant_key(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<TenantKeyResponse>), AppError> {
+    let api_key = state.tenants.add_key(&id)?;
+    let tenant = state.tenants.get(&id)?.ok_or(AppError::NotFound)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(TenantKeyResponse { tenant: TenantSummary::from(&tenant), api_key }),
+    ))
+}
+
+/// Remove a tenant, revoking its API keys and destroying its content key, so its images become unreadable
+pub async fn delete_tenant(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.tenants.remove(&id)?.ok_or(AppError::NotFound)?;
+    info!("Deleted tenant {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReferenceCheck {
+    pub id: String,
+    pub file_id: String,
+    pub message_id: i64,
+    // Whether getFile still resolves the file, i.e. /image can download it
+    pub retrievable: bool,
+    // Size Telegram reports for the stored (encrypted) file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_size: Option<i64>,
+    // Size of the original image recorded at upload
+    pub expected_size: usize,
+    pub broken: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Whether the Telegram file behind an image ID can still be fetched, without downloading it
+pub async fn check_reference(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ReferenceCheck>, AppError> {
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let file_ref = state.index.resolve(&CryptoService::new(&encryption_key), &id)?;
+
+    // The index knows where the file moved if it was re-uploaded since the ID was minted
+    let indexed = state.index.find_by_file_id(&file_ref.file_id)?;
+    let broken = indexed.as_ref().is_some_and(|entry| entry.broken);
+    let file_ref = indexed.map(|entry| entry.reference).unwrap_or(file_ref);
+
+    let (_, storage) = content_key_and_storage(&state, &encryption_key, file_ref.tenant.as_deref())?;
+    let (telegram_size, error) = match storage.get_file_info(&file_ref.file_id).await {
+        Ok(file) => (file.file_size, None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    Ok(Json(ReferenceCheck {
+        id,
+        file_id: file_ref.file_id,
+        message_id: file_ref.message_id,
+        retrievable: error.is_none(),
+        telegram_size,
+        expected_size: file_ref.size,
+        broken,
+        error,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_key() {
+        assert!(is_admin_key("secret", "secret"));
+        assert!(!is_admin_key("secret", "secret2"));
+        assert!(!is_admin_key("", ""));
+    }
+
+    #[test]
+    fn test_session_token_expires() {
+        let token = session_token("secret", 1000);
+        assert!(verify_session_token("secret", &token, 1000));
+        assert!(verify_session_token("secret", &token, 1000 + SESSION_MAX_AGE_SECS - 1));
+        assert!(!verify_session_token("secret", &token, 1000 + SESSION_MAX_AGE_SECS));
+        assert!(!verify_session_token("other", &token, 1000));
+        // Moving the issue time forward invalidates the MAC
+        let forged = token.replacen("1000", "2000", 1);
+        assert!(!verify_session_token("secret", &forged, 2000));
+    }
+}
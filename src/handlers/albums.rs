@@ -0,0 +1,94 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken},
+        me::owner,
+        pagination::{paginate, Page, PageQuery},
+    },
+    services::{
+        albums::{Album, AlbumSettings},
+        cdn,
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct AlbumSummary {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub image_count: usize,
+    pub protected: bool,
+    pub created_at: u64,
+}
+
+impl AlbumSummary {
+    fn new(state: &AppState, album: Album) -> Self {
+        Self {
+            url: cdn::public_url(&state.config, &format!("/gallery/{}", album.id)),
+            protected: album.is_protected(),
+            image_count: album.image_ids.len(),
+            id: album.id,
+            title: album.title,
+            created_at: album.created_at,
+        }
+    }
+}
+
+/// Create an album of the caller's own images, shared at /gallery/:id
+pub async fn create_album(
+    State(state): State<Arc<AppState>>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Json(settings): Json<AlbumSettings>,
+) -> Result<(StatusCode, Json<AlbumSummary>)> {
+    let owner = owner(&api_key, &owner_token)?;
+    for id in &settings.image_ids {
+        let owned = state.index.get(id)?.is_some_and(|entry| entry.uploader.contains(&owner));
+        if !owned {
+            return Err(AppError::ValidationError(format!("Image {} is not one of your uploads", id)));
+        }
+    }
+
+    let album = state.albums.create(&owner, settings, &state.config.get_encryption_key_bytes()?)?;
+    tracing::info!("Created album {} with {} images", album.id, album.image_ids.len());
+
+    Ok((StatusCode::CREATED, Json(AlbumSummary::new(&state, album))))
+}
+
+/// The caller's albums, newest first
+pub async fn list_my_albums(
+    State(state): State<Arc<AppState>>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<AlbumSummary>>> {
+    let albums = state.albums.list_owned(&owner(&api_key, &owner_token)?)?;
+    let page = paginate(albums, &query, |album| (album.created_at, album.id.clone()))?;
+    Ok(Json(page.map(|album| AlbumSummary::new(&state, album))))
+}
+
+/// Delete one of the caller's albums; its images are kept
+pub async fn delete_my_album(
+    State(state): State<Arc<AppState>>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    let owner = owner(&api_key, &owner_token)?;
+    match state.albums.get(&id)? {
+        Some(album) if album.owner == owner => {
+            state.albums.remove(&id)?;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        _ => Err(AppError::NotFound),
+    }
+}
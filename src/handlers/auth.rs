@@ -0,0 +1,110 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use base64::{engine::general_purpose, Engine as _};
+use std::{convert::Infallible, sync::Arc};
+
+use crate::{error::AppError, handlers::admin::is_admin_key, services::geoip::ClientLocation, AppState};
+
+// Basic auth username marking the password as an owner token rather than an API key
+const OWNER_TOKEN_USER: &str = "owner";
+
+/// Username and password of `Authorization: Basic`, the only scheme WebDAV clients offer
+fn basic_credentials(parts: &Parts) -> Option<(String, String)> {
+    let encoded = parts.headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(general_purpose::STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// The client's API key, if one was presented via `X-API-Key`, `Authorization: Bearer`, or as the
+/// password of Basic auth under any username but "owner"
+pub struct ApiKey(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiKey {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        let from_header = parts
+            .headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let from_bearer = || {
+            parts
+                .headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::to_string)
+        };
+
+        let from_basic = || {
+            basic_credentials(parts)
+                .filter(|(user, _)| user != OWNER_TOKEN_USER)
+                .map(|(_, password)| password)
+        };
+
+        Ok(ApiKey(from_header.or_else(from_bearer).or_else(from_basic).filter(|key| !key.is_empty())))
+    }
+}
+
+/// The anonymous owner token handed out at a client's first upload without an API key,
+/// presented again via `X-Owner-Token` or as the Basic auth password of user "owner"
+pub struct OwnerToken(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for OwnerToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        let from_header = parts
+            .headers
+            .get("x-owner-token")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let from_basic = || {
+            basic_credentials(parts)
+                .filter(|(user, _)| user == OWNER_TOKEN_USER)
+                .map(|(_, password)| password)
+        };
+
+        Ok(OwnerToken(from_header.or_else(from_basic).filter(|token| !token.is_empty())))
+    }
+}
+
+/// The API key of an upload request. With UPLOAD_REQUIRES_AUTH, requests without the key of a
+/// tenant are rejected here. With CAPTCHA_PROVIDER set, requests without a tenant's key or the
+/// admin secret have to carry a solved `X-Captcha-Token` instead.
+pub struct UploadAuth(pub ApiKey);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for UploadAuth {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, AppError> {
+        state.modes.check_uploads()?;
+        let Ok(api_key) = ApiKey::from_request_parts(parts, state).await;
+        let country = parts.extensions.get::<ClientLocation>().and_then(|location| location.country.as_deref());
+        state.geo.upload.check(country)?;
+        let tenant = state.tenants.resolve_key(api_key.0.as_deref())?;
+        if state.config.upload_requires_auth {
+            api_key.0.as_ref().and(tenant.as_ref()).ok_or(AppError::Unauthorized)?;
+        }
+        // Without tenants any string resolves to no one, so only a tenant's key or the admin secret
+        // stands in for a CAPTCHA
+        let trusted = tenant.is_some()
+            || api_key.0.as_deref().is_some_and(|key| is_admin_key(&state.admin_secret, key));
+        if let Some(captcha) = &state.captcha
+            && !trusted
+        {
+            let token = parts.headers.get("x-captcha-token").and_then(|value| value.to_str().ok());
+            captcha.verify(token).await?;
+        }
+        Ok(UploadAuth(api_key))
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    body::Bytes,
+    extract::{State, ConnectInfo},
+    http::StatusCode,
+    response::Json,
+};
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, FieldErrors, Result},
+    handlers::{
+        auth::{OwnerToken, UploadAuth},
+        upload::{check_image_field, check_upload_fields, enqueue_job, prepare_upload, upload_options, validate_image},
+    },
+    models::{QueuedResponse, Visibility},
+    services::index::normalize_tags,
+    worker::JobPayload,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct Base64UploadPayload {
+    // Either a data URI (data:image/png;base64,...) or bare base64
+    pub data: String,
+    // Required for bare base64 unless it can be guessed from the filename
+    pub mime_type: Option<String>,
+    pub filename: Option<String>,
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+pub async fn upload_base64(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    Json(payload): Json<Base64UploadPayload>,
+) -> Result<(StatusCode, Json<QueuedResponse>)> {
+    let mut errors = FieldErrors::default();
+
+    let (uri_mime_type, image_data) = match split_data_uri(&payload.data) {
+        Ok((uri_mime_type, encoded)) => match general_purpose::STANDARD.decode(encoded.trim()) {
+            Ok(image_data) => (uri_mime_type, Some(image_data)),
+            Err(e) => {
+                errors.add("data", format!("is not valid base64: {}", e));
+                (uri_mime_type, None)
+            }
+        },
+        Err(AppError::ValidationError(reason)) => {
+            errors.add("data", reason);
+            (None, None)
+        }
+        Err(e) => return Err(e),
+    };
+
+    let final_mime_type = uri_mime_type
+        .or(payload.mime_type)
+        .unwrap_or_else(|| {
+            mime_guess::from_path(payload.filename.as_deref().unwrap_or(""))
+                .first_or_octet_stream()
+                .to_string()
+        });
+
+    if let Some(image_data) = &image_data {
+        check_image_field(&state.config, &mut errors, "data", image_data, &final_mime_type);
+    }
+    check_upload_fields(&mut errors, payload.filename.as_deref(), payload.expires_in);
+    errors.into_result()?;
+
+    let image_data = Bytes::from(image_data.unwrap_or_default());
+    validate_image(&state.cpu, &state.config, image_data.clone(), &final_mime_type).await?;
+
+    let mut options = upload_options(&state, payload.expires_in, api_key, owner_token)?;
+    options.tags = normalize_tags(&payload.tags)?;
+    options.visibility = payload.visibility;
+    let prepared = prepare_upload(
+        &state.cpu,
+        &state.config,
+        options.tenant.as_ref(),
+        image_data,
+        None,
+        payload.filename.as_deref().unwrap_or("image.bin"),
+        final_mime_type,
+    )
+    .await?;
+
+    let response = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+    // Respond to the client immediately
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+/// Split a data URI into its MIME type and base64 payload; bare base64 is passed through
+fn split_data_uri(data: &str) -> Result<(Option<String>, &str)> {
+    let Some(rest) = data.strip_prefix("data:") else {
+        return Ok((None, data));
+    };
+
+    let (header, encoded) = rest
+        .split_once(',')
+        .ok_or_else(|| AppError::ValidationError("Malformed data URI".to_string()))?;
+
+    let mime_type = header
+        .strip_suffix(";base64")
+        .ok_or_else(|| AppError::ValidationError("Data URI must be base64 encoded".to_string()))?;
+
+    let mime_type = (!mime_type.is_empty()).then(|| mime_type.to_string());
+    Ok((mime_type, encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_data_uri() {
+        let (mime, data) = split_data_uri("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(mime.as_deref(), Some("image/png"));
+        assert_eq!(data, "iVBORw0KGgo=");
+
+        let (mime, data) = split_data_uri("iVBORw0KGgo=").unwrap();
+        assert_eq!(mime, None);
+        assert_eq!(data, "iVBORw0KGgo=");
+
+        assert!(split_data_uri("data:image/png,raw").is_err());
+        assert!(split_data_uri("data:image/png;base64").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use axum::{
+    extract::{Form, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    handlers::admin::{has_admin_session, is_admin_key, session_token, SESSION_COOKIE, SESSION_MAX_AGE_SECS},
+    models::unix_timestamp,
+    AppState,
+};
+
+const DASHBOARD_HTML: &str = include_str!("../../static/admin.html");
+const LOGIN_HTML: &str = include_str!("../../static/admin_login.html");
+
+/// The embedded admin dashboard, or its login form without a valid session
+pub async fn dashboard(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Html<&'static str> {
+    if has_admin_session(&headers, &state.admin_secret) {
+        Html(DASHBOARD_HTML)
+    } else {
+        Html(LOGIN_HTML)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    pub key: String,
+}
+
+/// Exchange the admin secret for a session cookie scoped to /admin
+pub async fn login(State(state): State<Arc<AppState>>, Form(form): Form<LoginForm>) -> Response {
+    if !is_admin_key(&state.admin_secret, &form.key) {
+        tracing::info!("Failed admin dashboard login");
+        return (StatusCode::UNAUTHORIZED, Html(LOGIN_HTML)).into_response();
+    }
+
+    let secure = if state.config.public_base_url.starts_with("https://") { "; Secure" } else { "" };
+    let cookie = format!(
+        "{}={}; Path=/admin; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        SESSION_COOKIE,
+        session_token(&state.admin_secret, unix_timestamp()),
+        SESSION_MAX_AGE_SECS,
+        secure
+    );
+
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/admin")).into_response()
+}
+
+pub async fn logout() -> Response {
+    let cookie = format!("{}=; Path=/admin; HttpOnly; SameSite=Strict; Max-Age=0", SESSION_COOKIE);
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/admin")).into_response()
+}
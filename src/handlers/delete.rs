@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, State, ConnectInfo},
+    response::Json,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::auth::ApiKey,
+    services::{
+        audit::{AuditAction, AuditEvent},
+        cdn, manifest,
+        storage::Storage,
+        transcode,
+        usage::usage_subjects,
+    },
+    AppState,
+};
+
+/// Delete an image using the token handed out at upload time (e.g. ShareX deletion URLs)
+pub async fn delete_with_token(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    api_key: ApiKey,
+    Path((id, token)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>> {
+    delete_by_token(&state, &id, &token, addr, api_key).await?;
+
+    Ok(Json(serde_json::json!({ "deleted": true })))
+}
+
+/// Verify a deletion token and remove the Telegram message backing the image
+pub(crate) async fn delete_by_token(
+    state: &AppState,
+    id: &str,
+    token: &str,
+    addr: SocketAddr,
+    api_key: ApiKey,
+) -> Result<()> {
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+
+    if !CryptoService::verify_delete_token(&encryption_key, id, token) {
+        info!("Invalid deletion token for image: {} from IP: {}", id, addr);
+        return Err(AppError::Unauthorized);
+    }
+
+    let deleter = usage_subjects(addr.ip(), api_key.0.as_deref());
+    delete_stored_image(state, id, &deleter).await?;
+
+    info!("Deleted image via token: {} from IP: {}", id, addr);
+    let event = AuditEvent::new(AuditAction::Delete)
+        .id(id)
+        .ip(addr.ip())
+        .key(api_key.0.as_deref())
+        .detail("delete token");
+    state.audit.record(event);
+
+    Ok(())
+}
+
+/// Remove the Telegram message backing an image and drop it from the index, charging `deleter`
+pub(crate) async fn delete_stored_image(state: &AppState, id: &str, deleter: &[String]) -> Result<()> {
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let crypto = CryptoService::new(&encryption_key);
+    let file_ref = state.index.resolve(&crypto, id)?;
+
+    // Files referenced by a bare file_id have no message of ours to delete
+    if file_ref.message_id == 0 {
+        return Err(AppError::ValidationError(
+            "This image is not stored in a message owned by this service".to_string(),
+        ));
+    }
+
+    let storage = match state.tenants.owner(file_ref.tenant.as_deref())? {
+        Some(tenant) => tenant.storage(&state.telegram_service),
+        None => state.telegram_service.clone(),
+    };
+    let storage = Storage::for_backend(file_ref.backend, storage, state.discord.as_ref())?;
+    let chat_id = Some(file_ref.chat_id.unwrap_or(state.config.telegram_chat_id));
+    manifest::delete_file_chunks(&storage, &encryption_key, chat_id, &file_ref).await;
+    storage.delete(chat_id, file_ref.message_id).await?;
+    if let Some(entry) = state.index.find_by_file_id(&file_ref.file_id)? {
+        transcode::delete_transcodes(&storage, &encryption_key, state.config.telegram_chat_id, &entry).await;
+    }
+    state.cache.remove(&file_ref.file_id);
+    state
+        .cdn
+        .purge_in_background(state.config.clone(), cdn::image_paths(id, file_ref.tenant.as_deref()));
+    if let Some(entry) = state.index.remove(id)? {
+        state.usage.record_delete(deleter, &entry.uploader, entry.reference.size);
+    }
+
+    Ok(())
+}
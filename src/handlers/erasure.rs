@@ -0,0 +1,117 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::{net::IpAddr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{
+        admin::{AdminAuth, ADMIN_SUBJECT},
+        delete::delete_stored_image,
+    },
+    services::{
+        audit::{AuditAction, AuditEvent},
+        erasure::{ErasureFailure, ErasureReport, ErasureStatus},
+        usage::{key_subject, owner_subject},
+    },
+    AppState,
+};
+
+/// Whose data to erase; every given identifier is erased
+#[derive(Debug, Deserialize)]
+pub struct ErasureRequest {
+    pub api_key: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub owner_token: Option<String>,
+}
+
+/// Start deleting every image, audit event and usage rollup tied to an API key, owner token or IP.
+/// Returns the job's initial report; poll GET /admin/erasure/:id for the outcome.
+pub async fn start_erasure(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ErasureRequest>,
+) -> Result<(StatusCode, Json<ErasureReport>)> {
+    let mut subjects = Vec::new();
+    subjects.extend(payload.api_key.as_deref().map(key_subject));
+    subjects.extend(payload.owner_token.as_deref().map(owner_subject));
+    // Stored IPs went through PRIVACY_MODE, so the given one has to as well
+    subjects.extend(payload.ip.map(|ip| format!("ip:{}", state.privacy.mask(ip))));
+    if subjects.is_empty() {
+        return Err(AppError::ValidationError("Give an api_key, owner_token or ip to erase".to_string()));
+    }
+
+    let report = ErasureReport::new(Uuid::new_v4().to_string());
+    state.erasures.update(&report);
+    tokio::spawn(run_erasure(state.clone(), subjects, report.clone()));
+
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+pub async fn get_erasure(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<ErasureReport>> {
+    state.erasures.get(&job_id).map(Json).ok_or(AppError::NotFound)
+}
+
+async fn run_erasure(state: Arc<AppState>, subjects: Vec<String>, mut report: ErasureReport) {
+    let owned = match state.index.list() {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|entry| entry.uploader.iter().any(|subject| subjects.contains(subject)))
+            .collect(),
+        Err(e) => {
+            tracing::error!("Erasure {} could not read the index: {}", report.job_id, e);
+            Vec::new()
+        }
+    };
+    report.images_found = owned.len();
+    state.erasures.update(&report);
+
+    for entry in owned {
+        match delete_stored_image(&state, &entry.id, &[ADMIN_SUBJECT.to_string()]).await {
+            Ok(()) => report.images_deleted += 1,
+            Err(e) => report.failed.push(ErasureFailure { id: entry.id, error: e.to_string() }),
+        }
+        state.erasures.update(&report);
+    }
+
+    let audit_result = state.audit.purge(|event| {
+        let key = event.key.as_ref().map(|key| format!("key:{}", key));
+        let ip = event.ip.as_ref().map(|ip| format!("ip:{}", ip));
+        [key, ip].into_iter().flatten().any(|subject| subjects.contains(&subject))
+    });
+    match audit_result {
+        Ok(removed) => report.audit_events_removed = removed,
+        Err(e) => tracing::error!("Erasure {} could not purge audit events: {}", report.job_id, e),
+    }
+
+    // Flushed right away rather than on the next usage_flush run
+    let usage_result = state
+        .usage
+        .purge_subjects(&subjects)
+        .and_then(|removed| state.usage.flush(state.config.usage_retention_days).map(|_| removed));
+    match usage_result {
+        Ok(removed) => report.usage_records_removed = removed,
+        Err(e) => tracing::error!("Erasure {} could not purge usage rollups: {}", report.job_id, e),
+    }
+
+    report.status = ErasureStatus::Completed;
+    report.finished_at = Some(crate::models::unix_timestamp());
+    state.erasures.update(&report);
+    state.audit.record(AuditEvent::new(AuditAction::Erasure).detail(format!(
+        "job {}: {} of {} images deleted, {} audit events, {} usage records",
+        report.job_id,
+        report.images_deleted,
+        report.images_found,
+        report.audit_events_removed,
+        report.usage_records_removed
+    )));
+    tracing::info!("Erasure {} finished", report.job_id);
+}
@@ -0,0 +1,25 @@
+use axum::Json;
+use serde::Serialize;
+
+use crate::error::ErrorCode;
+
+#[derive(Debug, Serialize)]
+pub struct ErrorCatalogEntry {
+    pub code: ErrorCode,
+    pub status: u16,
+    pub description: &'static str,
+}
+
+/// Every error code the API can return, so SDKs can be generated from or checked against it
+pub async fn error_catalog() -> Json<Vec<ErrorCatalogEntry>> {
+    Json(
+        ErrorCode::ALL
+            .into_iter()
+            .map(|code| ErrorCatalogEntry {
+                code,
+                status: code.status().as_u16(),
+                description: code.description(),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,161 @@
+use axum::{
+    extract::{Form, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{error::AppError, handlers::html::escape_html, services::albums::Album, AppState};
+
+// Unlocked galleries stay open for half a day
+const GALLERY_SESSION_MAX_AGE_SECS: u64 = 12 * 3600;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2rem auto;max-width:72rem;padding:0 1rem;color:#222}\
+h1{font-weight:600}\
+.grid{display:grid;grid-template-columns:repeat(auto-fill,minmax(12rem,1fr));gap:.75rem}\
+.grid a{display:block;aspect-ratio:1;background:#f2f2f2;border-radius:6px;overflow:hidden}\
+.grid img{width:100%;height:100%;object-fit:cover}\
+form{display:flex;gap:.5rem}.error{color:#b00}";
+
+/// An album as a grid of thumbnails linking to the full images, behind a password form when
+/// the album has one
+pub async fn gallery(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> Result<Response, AppError> {
+    let album = state.albums.get(&album_id)?.ok_or(AppError::NotFound)?;
+    let key = state.config.get_encryption_key_bytes()?;
+
+    if album.is_protected()
+        && !cookie_values(&headers, &cookie_name(&album)).any(|value| album.verify_session_token(&key, value))
+    {
+        return Ok(Html(password_page(&album, false)).into_response());
+    }
+
+    Ok(Html(gallery_page(&state, &album)?).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockForm {
+    pub password: String,
+}
+
+/// Check a gallery password and remember it in a cookie scoped to the gallery
+pub async fn unlock_gallery(
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<String>,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, AppError> {
+    let album = state.albums.get(&album_id)?.ok_or(AppError::NotFound)?;
+    let key = state.config.get_encryption_key_bytes()?;
+
+    let Some(token) = album.session_token(&key) else {
+        return Ok(Redirect::to(&format!("/gallery/{}", album.id)).into_response());
+    };
+    if !album.verify_password(&form.password, &key) {
+        tracing::info!("Wrong password for gallery {}", album.id);
+        return Ok((StatusCode::UNAUTHORIZED, Html(password_page(&album, true))).into_response());
+    }
+
+    let secure = if state.config.public_base_url.starts_with("https://") { "; Secure" } else { "" };
+    let cookie = format!(
+        "{}={}; Path=/gallery/{}; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        cookie_name(&album),
+        token,
+        album.id,
+        GALLERY_SESSION_MAX_AGE_SECS,
+        secure
+    );
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&format!("/gallery/{}", album.id))).into_response())
+}
+
+fn gallery_page(state: &AppState, album: &Album) -> Result<String, AppError> {
+    let mut tiles = String::new();
+    for id in &album.image_ids {
+        // Images deleted since the album was made, or since hidden from listings, are left out
+        let Some(entry) = state.index.get(id)? else {
+            continue;
+        };
+        if !entry.reference.visibility.is_public() {
+            continue;
+        }
+        let prefix = entry.reference.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+        let alt = entry.filename.as_deref().unwrap_or("");
+        tiles.push_str(&format!(
+            "<a href=\"{prefix}/image/{id}\"><img src=\"{prefix}/thumb/{id}\" alt=\"{alt}\" loading=\"lazy\"></a>\n",
+            prefix = escape_html(&prefix),
+            id = escape_html(id),
+            alt = escape_html(alt),
+        ));
+    }
+
+    Ok(page(
+        &album.title,
+        &format!("<h1>{}</h1>\n<div class=\"grid\">\n{}</div>", escape_html(&album.title), tiles),
+    ))
+}
+
+fn password_page(album: &Album, failed: bool) -> String {
+    let error = if failed { "<p class=\"error\">Wrong password.</p>\n" } else { "" };
+    page(
+        &album.title,
+        &format!(
+            "<h1>{}</h1>\n<p>This gallery is password protected.</p>\n{}\
+<form method=\"post\" action=\"/gallery/{}\"><input type=\"password\" name=\"password\" autofocus required>\
+<button type=\"submit\">View</button></form>",
+            escape_html(&album.title),
+            error,
+            escape_html(&album.id),
+        ),
+    )
+}
+
+fn page(title: &str, body: &str) -> String {
+    let title = if title.is_empty() { "Gallery".to_string() } else { escape_html(title) };
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+<meta name=\"robots\" content=\"noindex\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        title, STYLE, body
+    )
+}
+
+fn cookie_name(album: &Album) -> String {
+    // Album IDs are URL-safe base64, which is valid in cookie names
+    format!("rustgram_gallery_{}", album.id)
+}
+
+/// Values of every cookie called `name`
+fn cookie_values<'a>(headers: &'a HeaderMap, name: &'a str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(move |(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_page_escapes_title() {
+        let album = Album {
+            id: "abc".into(),
+            title: "<script>".into(),
+            owner: "key:x".into(),
+            image_ids: vec![],
+            password_hash: Some("h".into()),
+            created_at: 0,
+        };
+        let html = password_page(&album, true);
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("Wrong password"));
+    }
+}
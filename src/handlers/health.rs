@@ -1,24 +1,38 @@
 use axum::{extract::State, http::StatusCode, response::Json};
-use std::{sync::Arc, time::SystemTime};
+use std::sync::Arc;
 
-use crate::{models::HealthResponse, AppState};
+use crate::{
+    models::{unix_timestamp, HealthResponse, QueueHealth, WorkerHealth},
+    AppState,
+};
 
+/// Readiness: Telegram must be reachable and the upload worker running. Queue and worker
+/// details are reported either way so a stuck worker shows up even while HTTP responds.
 pub async fn health_check(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<HealthResponse>, StatusCode> {
-    // Test Telegram connection
-    match state.telegram_service.test_connection().await {
-        Ok(_) => {
-            let response = HealthResponse {
-                status: "healthy".to_string(),
-                timestamp: SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-                version: env!("CARGO_PKG_VERSION").to_string(),
-            };
-            Ok(Json(response))
-        }
-        Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE),
-    }
-}
\ No newline at end of file
+) -> (StatusCode, Json<HealthResponse>) {
+    let telegram_ok = state.telegram_service.test_connection().await.is_ok();
+    let worker_alive = state.worker.is_alive();
+    let capacity = state.upload_queue.max_capacity();
+
+    let healthy = telegram_ok && worker_alive;
+    let response = HealthResponse {
+        status: if healthy { "healthy" } else { "unhealthy" }.to_string(),
+        timestamp: unix_timestamp(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        queue: QueueHealth {
+            depth: capacity - state.upload_queue.capacity(),
+            capacity,
+            oldest_job_age_secs: state.worker.oldest_job_age(),
+        },
+        worker: WorkerHealth {
+            alive: worker_alive,
+            paused: state.worker.is_paused(),
+            current_job_secs: state.worker.current_job_age(),
+        },
+        last_telegram_success: state.telegram_service.last_success(),
+    };
+
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(response))
+}
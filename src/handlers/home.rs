@@ -0,0 +1,8 @@
+use axum::response::Html;
+
+const UPLOAD_PAGE_HTML: &str = include_str!("../../static/index.html");
+
+/// Minimal drag-and-drop upload page, so a fresh deployment is usable from a browser
+pub async fn upload_page() -> Html<&'static str> {
+    Html(UPLOAD_PAGE_HTML)
+}
@@ -1,21 +1,196 @@
 use axum::{
-    extract::{Path, State, ConnectInfo},
-    http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
+    body::Bytes,
+    extract::{Path, Query, State, ConnectInfo},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
 };
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use std::io::Cursor;
 use std::sync::Arc;
 use std::net::SocketAddr;
 
 use crate::{
+    config::Config,
     crypto::CryptoService,
     error::{AppError, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken},
+        me,
+        upload::header_dimensions,
+    },
+    models::{unix_timestamp, FileReference, Visibility},
+    recovery::{content_key_and_storage, file_unavailable, recover_stale_file},
+    services::{
+        audit::{AuditAction, AuditEvent},
+        cache::ImageCache,
+        index::ImageIndex,
+        manifest,
+        storage::Storage,
+        transcode::{self, VideoFormat},
+        transform::{smart_square_crop, Transform},
+        usage::usage_subjects,
+        watermark::Watermark,
+    },
+    worker::store_upload,
     AppState,
 };
 
+// Longest edge of generated thumbnails, in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+
+// Bump whenever variant rendering changes, so new output never reuses an immutable URL
+const VARIANT_VERSION: u32 = 1;
+
+// Variants are cached for a year and never revalidated; their URL changes when their content would
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+// Path of /v/:id/:variant, or /t/:tenant/v/:id/:variant for tenant namespaces
+#[derive(Debug, Deserialize)]
+pub struct VariantPath {
+    pub tenant: Option<String>,
+    pub id: String,
+    pub variant: String,
+}
+
+/// Render parameters of a named variant; they feed the variant's URL digest
+fn variant_spec(name: &str) -> Option<String> {
+    match name {
+        "thumb" => Some(format!("thumb:{}:v{}", THUMBNAIL_SIZE, VARIANT_VERSION)),
+        "square" => Some(format!("square:{}:smart:v{}", THUMBNAIL_SIZE, VARIANT_VERSION)),
+        _ => None,
+    }
+}
+
+/// Last URL segment of a variant, e.g. "thumb.1a2b3c4d5e6f7a8b", derived from the image ID and render spec
+pub(crate) fn variant_segment(encrypted_id: &str, name: &str) -> Option<String> {
+    let spec = variant_spec(name)?;
+    let digest = CryptoService::hash_data(format!("{}|{}", encrypted_id, spec).as_bytes());
+    Some(format!("{}.{}", name, hex::encode(&digest[..8])))
+}
+
+// Path of /image/:id, or /t/:tenant/image/:id for tenant namespaces
+#[derive(Debug, Deserialize)]
+pub struct ImagePath {
+    pub tenant: Option<String>,
+    pub id: String,
+}
+
+impl ImagePath {
+    /// Decrypt the referenced file, hiding it unless it belongs to the namespace in the path
+    pub(crate) fn file_reference(&self, index: &ImageIndex, crypto: &CryptoService) -> Result<FileReference> {
+        let file_ref = index.resolve(crypto, &self.id)?;
+        if file_ref.tenant != self.tenant {
+            return Err(AppError::NotFound);
+        }
+        Ok(file_ref)
+    }
+}
+
+/// `?exp=&sig=` of a signed link to a private image
+#[derive(Debug, Default, Deserialize)]
+pub struct SignedLink {
+    pub exp: Option<u64>,
+    pub sig: Option<String>,
+}
+
+/// Signature letting anyone holding it fetch private image `id` until `expires_at`
+pub(crate) fn sign_link(master_key: &[u8; 32], id: &str, expires_at: u64) -> String {
+    hex::encode(CryptoService::hmac_sha256(master_key, signed_link_message(id, expires_at).as_bytes()))
+}
+
+/// Check a signed link's signature in constant time
+fn verify_link(master_key: &[u8; 32], id: &str, expires_at: u64, sig: &str) -> bool {
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    CryptoService::verify_hmac_sha256(master_key, signed_link_message(id, expires_at).as_bytes(), &sig)
+}
+
+fn signed_link_message(id: &str, expires_at: u64) -> String {
+    format!("signed-link:{}:{}", id, expires_at)
+}
+
+/// Private images are only served to their owner or through a signed link, and are reported
+/// as missing to everyone else
+pub(crate) fn ensure_visible(
+    state: &AppState,
+    master_key: &[u8; 32],
+    file_ref: &FileReference,
+    id: &str,
+    caller: (&ApiKey, &OwnerToken),
+    link: &SignedLink,
+) -> Result<()> {
+    if file_ref.visibility != Visibility::Private {
+        return Ok(());
+    }
+
+    if let (Some(expires_at), Some(sig)) = (link.exp, &link.sig)
+        && expires_at > unix_timestamp()
+        && verify_link(master_key, id, expires_at, sig)
+    {
+        return Ok(());
+    }
+
+    if let Ok(owner) = me::owner(caller.0, caller.1)
+        && state.index.get(id)?.is_some_and(|entry| entry.uploader.contains(&owner))
+    {
+        return Ok(());
+    }
+
+    Err(AppError::NotFound)
+}
+
+/// Whether the image's tenant requires the watermark on everything it serves, thumbnails included
+fn forced_watermark(state: &AppState, file_ref: &FileReference) -> Result<bool> {
+    Ok(state.watermark.is_some()
+        && state.tenants.owner(file_ref.tenant.as_deref())?.is_some_and(|tenant| tenant.watermark))
+}
+
+/// Shared caches must not hand a private image to someone else
+fn cache_control(file_ref: &FileReference, public: &'static str) -> &'static str {
+    if file_ref.visibility == Visibility::Private { "private, max-age=3600" } else { public }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImageQuery {
+    // Serve with the configured watermark
+    #[serde(default)]
+    pub wm: Option<String>,
+    // x,y,w,h region to serve
+    pub crop: Option<String>,
+    // Clockwise rotation in degrees
+    pub rotate: Option<String>,
+    // "first" for a still of an animated image
+    pub frame: Option<String>,
+    // Send as an attachment, named `filename` or the name the image was uploaded under
+    pub download: Option<String>,
+    pub filename: Option<String>,
+    // "mp4" or "webm" to serve a GIF as video, when FFMPEG_PATH is set
+    pub format: Option<String>,
+}
+
+impl ImageQuery {
+    fn wants_watermark(&self) -> bool {
+        matches!(self.wm.as_deref(), Some("1" | "true"))
+    }
+
+    fn wants_download(&self) -> bool {
+        matches!(self.download.as_deref(), Some("1" | "true"))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn get_image(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Path(encrypted_id): Path<String>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    request_headers: HeaderMap,
+    uri: Uri,
+    Path(path): Path<ImagePath>,
+    Query(query): Query<ImageQuery>,
+    Query(link): Query<SignedLink>,
 ) -> Result<Response> {
     // Initialize crypto service
     let encryption_key = state.config.get_encryption_key_bytes()
@@ -23,31 +198,75 @@ pub async fn get_image(
     let crypto = CryptoService::new(&encryption_key);
 
     // Decrypt file reference
-    let file_ref = crypto.decrypt_file_reference(&encrypted_id)?;
+    let file_ref = path.file_reference(&state.index, &crypto)?;
+    let encrypted_id = path.id;
+    ensure_visible(&state, &encryption_key, &file_ref, &encrypted_id, (&api_key, &owner_token), &link)?;
 
-    // Download encrypted file from Telegram
-    let encrypted_data = state
-        .telegram_service
-        .download_file_by_id(&file_ref.file_id)
-        .await?;
+    // Browsers opening the bare link get the viewer page; <img> tags and any query keep the raw image
+    if state.config.link_to_viewer && uri.query().is_none() && accepts_html(&request_headers) {
+        let prefix = path.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+        let redirect = Redirect::to(&format!("{}/view/{}", prefix, encrypted_id));
+        return Ok(([(header::VARY, "Accept")], redirect).into_response());
+    }
+
+    // Validated before downloading anything
+    let dimensions = file_ref.width.zip(file_ref.height);
+    let mut transform = Transform::parse(query.crop.as_deref(), query.rotate.as_deref(), dimensions)?;
+
+    // Only formats that can be animated need a still; others are already one
+    let animatable = matches!(file_ref.mime_type.as_str(), "image/gif" | "image/webp");
+    transform.first_frame = match query.frame.as_deref() {
+        None => false,
+        Some("first") => animatable,
+        Some(_) => return Err(AppError::ValidationError("frame must be first".to_string())),
+    };
 
-    // Decrypt image data
-    let image_data = crypto.decrypt_data(&encrypted_data)?;
+    // GIFs are often many times larger than the same animation as video
+    let video = query.format.as_deref().map(VideoFormat::parse).transpose()?;
 
-    // Validate decrypted data size matches expected size
-    if image_data.len() != file_ref.size {
-        return Err(AppError::InternalError(
-            "Decrypted file size mismatch".to_string(),
-        ));
+    // Watermarks are drawn on stills only, so a tenant's is left off GIFs and WebPs served
+    // animated rather than flattening them to their first frame
+    let still = !animatable || !transform.is_identity();
+    transform.watermark = (state.watermark.is_some() && query.wants_watermark())
+        || (video.is_none() && still && forced_watermark(&state, &file_ref)?);
+
+    if video.is_some() {
+        if state.config.ffmpeg_path.is_none() {
+            return Err(AppError::ValidationError("Video formats are not enabled on this server".to_string()));
+        }
+        if file_ref.mime_type != "image/gif" {
+            return Err(AppError::ValidationError("format only applies to GIFs".to_string()));
+        }
+        // Edits and watermarks are rendered as still images only
+        if !transform.is_identity() {
+            return Err(AppError::ValidationError("format can't be combined with edits or a watermark".to_string()));
+        }
     }
 
+    // Held until the image has been downloaded and decrypted
+    let _slot = state.download_limiter.acquire(addr.ip())?;
+    let (image_data, mime_type) = match video {
+        Some(format) => {
+            let video = transcoded_video(&state, &encryption_key, &file_ref, format).await?;
+            (video, format.mime_type().to_string())
+        }
+        None if transform.is_identity() => {
+            (load_image_data(&state, &encryption_key, &file_ref).await?, file_ref.mime_type.clone())
+        }
+        None => {
+            let image_data = load_image_data(&state, &encryption_key, &file_ref).await?;
+            transformed(&state, &transform, &file_ref, &image_data).await?
+        }
+    };
+    let reencoded = !transform.is_identity() || video.is_some();
+
     // Create response headers
     let mut headers = HeaderMap::new();
     
     // Set content type
     headers.insert(
         header::CONTENT_TYPE,
-        file_ref.mime_type.parse()
+        mime_type.parse()
             .map_err(|_| AppError::InternalError("Invalid MIME type".to_string()))?,
     );
 
@@ -58,44 +277,483 @@ pub async fn get_image(
             .map_err(|_| AppError::InternalError("Invalid content length".to_string()))?,
     );
 
-    // Set cache headers (optional - cache for 1 hour)
-    headers.insert(
-        header::CACHE_CONTROL,
-        "public, max-age=3600".parse()
-            .map_err(|_| AppError::InternalError("Invalid cache control".to_string()))?,
-    );
+    // Set cache headers (optional - cache for 1 hour). A content-addressed ID always names the
+    // same bytes, so the untouched original can be cached for good.
+    let public = if file_ref.content_id.is_some() && !reencoded {
+        IMMUTABLE_CACHE_CONTROL
+    } else {
+        "public, max-age=3600"
+    };
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static(cache_control(&file_ref, public)));
+    if state.config.link_to_viewer {
+        headers.insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    }
 
     // Add ETag for caching
-    let etag = format!("\"{}\"", hex::encode(&crate::crypto::CryptoService::hash_data(&image_data)[..8]));
+    let digest = crate::crypto::CryptoService::hash_data(&image_data);
+    let etag = format!("\"{}\"", hex::encode(&digest[..8]));
     headers.insert(
         header::ETAG,
         etag.parse()
             .map_err(|_| AppError::InternalError("Invalid ETag".to_string()))?,
     );
 
+    // Lets clients and proxies holding the /pubkey key check nothing altered the body on the way
+    if let Some(signer) = &state.signer {
+        let content_sha256 = header::HeaderValue::from_str(&hex::encode(digest));
+        let signature = header::HeaderValue::from_str(&format!("ed25519={}", signer.sign(&digest)));
+        if let (Ok(content_sha256), Ok(signature)) = (content_sha256, signature) {
+            headers.insert("x-content-sha256", content_sha256);
+            headers.insert("x-signature", signature);
+        }
+    }
+
+    // Forced downloads, either requested or configured for risky types
+    if query.wants_download() || state.config.attachment_types.contains(&mime_type) {
+        let stored_name = state.index.get(&encrypted_id)?.and_then(|entry| entry.filename);
+        let filename = download_filename(query.filename.as_deref().or(stored_name.as_deref()), &mime_type);
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            content_disposition(&filename)
+                .parse()
+                .map_err(|_| AppError::InternalError("Invalid content disposition".to_string()))?,
+        );
+    }
+
+    state
+        .usage
+        .record_download(&usage_subjects(addr.ip(), api_key.0.as_deref()), image_data.len());
+
     tracing::info!(
         "Image served successfully: {} bytes, type: {}",
         image_data.len(),
         file_ref.mime_type
     );
 
-    state.telegram_service.send_log_message(&format!(
-        "Image retrieved: ID={}, Size={}, Type={}, IP={}",
-        encrypted_id,
-        image_data.len(),
-        file_ref.mime_type,
-        addr
-    )).await?;
+    let event = AuditEvent::new(AuditAction::View)
+        .id(&encrypted_id)
+        .ip(addr.ip())
+        .key(api_key.0.as_deref())
+        .detail(format!("{} bytes, {}", image_data.len(), file_ref.mime_type));
+    state.audit.record(event);
 
     // Return image data with headers
     Ok((StatusCode::OK, headers, image_data).into_response())
 }
 
+/// Whether the request is a page navigation rather than an embedded image load
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| media_type.split(';').next().unwrap_or_default().trim() == "text/html")
+}
+
+/// Name offered for a download: the given name with anything unsafe in a header or path
+/// removed, and its extension replaced by the served type's so it can't pass for another kind
+/// of file
+fn download_filename(name: Option<&str>, mime_type: &str) -> String {
+    let extension = match mime_type {
+        "image/jpeg" => "jpg",
+        other => mime_guess::get_mime_extensions_str(other)
+            .and_then(|extensions| extensions.first())
+            .copied()
+            .unwrap_or("bin"),
+    };
+
+    let name: String = name
+        .unwrap_or("")
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '"' | ';'))
+        .take(200)
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ if name.is_empty() => "image",
+        _ => name,
+    };
+    format!("{}.{}", stem, extension)
+}
+
+/// Attachment header with an ASCII fallback name plus the exact UTF-8 name (RFC 6266)
+fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, encoded)
+}
+
+/// The image with `transform` applied, rendered once and then kept in the cache
+async fn transformed(
+    state: &AppState,
+    transform: &Transform,
+    file_ref: &FileReference,
+    image_data: &Bytes,
+) -> Result<(Bytes, String)> {
+    let key = ImageCache::variant_key(&file_ref.file_id, &transform.variant_name());
+    let rendered = match state.cache.get(&key) {
+        Some(cached) => cached,
+        None => {
+            let (config, watermark) = (state.config.clone(), state.watermark.clone());
+            let (transform, image_data) = (transform.clone(), image_data.clone());
+            let data = state
+                .cpu
+                .run(move || {
+                    let decoded = image::load_from_memory(&image_data)?;
+                    config.check_dimensions(decoded.width(), decoded.height())?;
+                    let edited = transform.apply(decoded, watermark.as_deref())?;
+                    let (data, _) = encode_rendition(edited)?;
+                    Ok(Bytes::from(data))
+                })
+                .await?;
+            state.cache.insert(&key, data.clone());
+            data
+        }
+    };
+
+    let mime_type = rendition_mime_type(&rendered);
+    Ok((rendered, mime_type.to_string()))
+}
+
+/// A thumbnail, rendered on first request and then served from the image cache like other
+/// renditions
+async fn cached_thumbnail(
+    state: &AppState,
+    master_key: &[u8; 32],
+    file_ref: &FileReference,
+    smart: bool,
+    watermark: Option<Arc<Watermark>>,
+) -> Result<(Bytes, &'static str)> {
+    let fit = if smart { "smart" } else { "contain" };
+    let variant = format!("thumb={}{}", fit, if watermark.is_some() { ";wm" } else { "" });
+    let key = ImageCache::variant_key(&file_ref.file_id, &variant);
+    let rendered = match state.cache.get(&key) {
+        Some(cached) => cached,
+        None => {
+            let image_data = load_image_data(state, master_key, file_ref).await?;
+            let config = state.config.clone();
+            let (data, _) = state
+                .cpu
+                .run(move || render_thumbnail(&config, &image_data, smart, watermark.as_deref()))
+                .await?;
+            let data = Bytes::from(data);
+            state.cache.insert(&key, data.clone());
+            data
+        }
+    };
+
+    let mime_type = rendition_mime_type(&rendered);
+    Ok((rendered, mime_type))
+}
+
+/// Renditions are encoded as PNG when they have an alpha channel, JPEG otherwise
+fn rendition_mime_type(rendered: &[u8]) -> &'static str {
+    match image::guess_format(rendered) {
+        Ok(ImageFormat::Png) => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+/// A GIF as video, transcoded on first request and then kept encrypted next to the image in its
+/// storage, so it is only ever transcoded once
+async fn transcoded_video(
+    state: &Arc<AppState>,
+    master_key: &[u8; 32],
+    file_ref: &FileReference,
+    format: VideoFormat,
+) -> Result<Bytes> {
+    let key = ImageCache::variant_key(&file_ref.file_id, format.extension());
+    if let Some(cached) = state.cache.get(&key) {
+        return Ok(cached);
+    }
+
+    let indexed = state.index.find_by_file_id(&file_ref.file_id)?;
+    if let Some(stored) = indexed.as_ref().and_then(|entry| entry.transcodes.get(format.extension())) {
+        match load_image_data(state, master_key, stored).await {
+            Ok(video) => return Ok(video),
+            // Transcoded again below, replacing the unreadable copy
+            Err(e) => tracing::warn!("Stored transcode {} unreadable: {}", stored.file_id, e),
+        }
+    }
+
+    // Concurrent requests for the same video share one transcode
+    let (state, master_key, file_ref) = (state.clone(), *master_key, file_ref.clone());
+    let coalesce_key = key.clone();
+    state.clone().downloads.run(&coalesce_key, move || async move {
+        let gif = load_image_data(&state, &master_key, &file_ref).await?;
+        let ffmpeg = state.config.ffmpeg_path.as_deref().unwrap_or("ffmpeg");
+        let video = Bytes::from(transcode::transcode(ffmpeg, &state.config.spill_dir(), &gif, format).await?);
+        state.cache.insert(&key, video.clone());
+
+        // Images outside the index have nowhere to record the copy, so they are transcoded per cache
+        // miss, and nothing is stored while read-only
+        if let Some(entry) = indexed
+            && !state.modes.read_only()
+            && let Err(e) = store_transcode(&state, &master_key, &entry.id, &entry.reference, format, &video).await
+        {
+            tracing::warn!("Could not store {} transcode of {}: {}", format.extension(), entry.id, e);
+        }
+        Ok(video)
+    })
+    .await
+}
+
+/// Encrypt a transcode with the image's content key and store it in the image's backend
+async fn store_transcode(
+    state: &AppState,
+    master_key: &[u8; 32],
+    id: &str,
+    image: &FileReference,
+    format: VideoFormat,
+    video: &Bytes,
+) -> Result<()> {
+    let (content_key, telegram) = content_key_and_storage(state, master_key, image.tenant.as_deref())?;
+    let storage = Storage::for_backend(image.backend, telegram, state.discord.as_ref())?;
+    let plaintext = video.clone();
+    let encrypted = state
+        .cpu
+        .run(move || Ok(Bytes::from(CryptoService::new(&content_key).encrypt_data(&plaintext)?)))
+        .await?;
+
+    let filename = format!("{}.{}", uuid::Uuid::new_v4(), format.extension());
+    let stored = store_upload(&storage, &state.config, master_key, encrypted, &filename).await?;
+    let mut transcode = FileReference::new(stored.file_id, stored.message_id, video.len(), format.mime_type().to_string());
+    transcode.chunked = stored.chunked;
+    transcode.backend = storage.backend();
+    transcode.tenant = image.tenant.clone();
+    transcode.chat_id = storage.telegram_chat().filter(|chat_id| *chat_id != state.config.telegram_chat_id);
+
+    // The image may have been deleted or moved while it was being transcoded
+    if !state.index.set_transcode(id, &image.file_id, format.extension(), transcode.clone())? {
+        let chat_id = Some(transcode.chat_id.unwrap_or(state.config.telegram_chat_id));
+        manifest::delete_file_chunks(&storage, master_key, chat_id, &transcode).await;
+        storage.delete(chat_id, transcode.message_id).await?;
+    }
+    Ok(())
+}
+
+/// Return a stored image's decrypted bytes, from the cache or downloaded from Telegram
+pub(crate) async fn load_image_data(
+    state: &AppState,
+    master_key: &[u8; 32],
+    file_ref: &FileReference,
+) -> Result<Bytes> {
+    let (content_key, storage) = content_key_and_storage(state, master_key, file_ref.tenant.as_deref())?;
+
+    if let Some(cached) = state.cache.get(&file_ref.file_id) {
+        return Ok(cached);
+    }
+
+    // IDs minted before a re-upload still name the old file; the index knows where it went
+    let indexed = state.index.find_by_file_id(&file_ref.file_id)?;
+    let file_ref = match &indexed {
+        Some(entry) if entry.broken => return Err(AppError::Gone),
+        Some(entry) if entry.reference.file_id != file_ref.file_id => {
+            if let Some(cached) = state.cache.get(&entry.reference.file_id) {
+                return Ok(cached);
+            }
+            entry.reference.clone()
+        }
+        _ => file_ref.clone(),
+    };
+
+    let storage = Storage::for_backend(file_ref.backend, storage, state.discord.as_ref())?;
+
+    // Concurrent requests for the same file share one download and decrypt
+    let key = file_ref.file_id.clone();
+    let manifest_key = *master_key;
+    let cpu = state.cpu.clone();
+    let result = state
+        .downloads
+        .run(&key, move || async move {
+            // Download encrypted file from Telegram, reassembling it when it was stored in chunks
+            let encrypted_data = if file_ref.chunked {
+                manifest::load(&storage, &manifest_key, &file_ref).await?
+            } else {
+                storage.download(&file_ref.file_id, file_ref.message_id).await?
+            };
+
+            // Decrypt image data, unless it was imported in place without our encryption
+            let image_data = if file_ref.plaintext {
+                encrypted_data
+            } else {
+                cpu.run(move || Ok(CryptoService::new(&content_key).decrypt_data(&encrypted_data)?.into()))
+                    .await?
+            };
+
+            // Validate decrypted data size matches expected size
+            if image_data.len() != file_ref.size {
+                return Err(AppError::InternalError(
+                    "Decrypted file size mismatch".to_string(),
+                ));
+            }
+
+            Ok(image_data)
+        })
+        .await;
+
+    let image_data = match (result, indexed) {
+        (Err(AppError::Gone), Some(entry)) => {
+            let (_, image_data) = recover_stale_file(state, master_key, entry).await?;
+            return Ok(image_data);
+        }
+        (Err(AppError::FileUnavailable), Some(entry)) => {
+            let (_, image_data) = file_unavailable(state, master_key, entry).await?;
+            return Ok(image_data);
+        }
+        (result, indexed) => {
+            let image_data = result?;
+            if let Some(entry) = indexed.filter(|entry| entry.unavailable.is_some()) {
+                state.index.mark_available(&entry.id)?;
+            }
+            image_data
+        }
+    };
+
+    state.cache.insert(&key, image_data.clone());
+    Ok(image_data)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ThumbnailQuery {
+    // "smart" for a square crop around the most detailed region
+    pub fit: Option<String>,
+}
+
+// Downscaled preview, JPEG unless the image needs an alpha channel
+pub async fn get_thumbnail(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(path): Path<ImagePath>,
+    Query(query): Query<ThumbnailQuery>,
+    Query(link): Query<SignedLink>,
+) -> Result<Response> {
+    let smart = match query.fit.as_deref() {
+        None | Some("contain") => false,
+        Some("smart") => true,
+        Some(_) => return Err(AppError::ValidationError("fit must be contain or smart".to_string())),
+    };
+
+    let encryption_key = state.config.get_encryption_key_bytes()
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+    let crypto = CryptoService::new(&encryption_key);
+
+    let file_ref = path.file_reference(&state.index, &crypto)?;
+    ensure_visible(&state, &encryption_key, &file_ref, &path.id, (&api_key, &owner_token), &link)?;
+    let _slot = state.download_limiter.acquire(addr.ip())?;
+
+    let watermark = if forced_watermark(&state, &file_ref)? { state.watermark.clone() } else { None };
+    let (thumbnail, mime_type) = cached_thumbnail(&state, &encryption_key, &file_ref, smart, watermark).await?;
+
+    let headers = [
+        (header::CONTENT_TYPE, mime_type),
+        (header::CACHE_CONTROL, cache_control(&file_ref, "public, max-age=3600")),
+    ];
+
+    Ok((StatusCode::OK, headers, thumbnail).into_response())
+}
+
+/// A generated variant under its content-derived URL, cacheable forever
+pub async fn get_variant(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(path): Path<VariantPath>,
+    Query(link): Query<SignedLink>,
+) -> Result<Response> {
+    let (name, _) = path.variant.split_once('.').ok_or(AppError::NotFound)?;
+    let current = variant_segment(&path.id, name).ok_or(AppError::NotFound)?;
+
+    // Outdated digests point at the variant as it is rendered now
+    if path.variant != current {
+        let prefix = path.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+        return Ok(Redirect::permanent(&format!("{}/v/{}/{}", prefix, path.id, current)).into_response());
+    }
+
+    let encryption_key = state.config.get_encryption_key_bytes()
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+    let crypto = CryptoService::new(&encryption_key);
+
+    let image_path = ImagePath { tenant: path.tenant, id: path.id };
+    let file_ref = image_path.file_reference(&state.index, &crypto)?;
+    ensure_visible(&state, &encryption_key, &file_ref, &image_path.id, (&api_key, &owner_token), &link)?;
+    let _slot = state.download_limiter.acquire(addr.ip())?;
+
+    let watermark = if forced_watermark(&state, &file_ref)? { state.watermark.clone() } else { None };
+    // The variant URL doesn't change when a tenant turns the watermark on, so marked renders are
+    // only cached for a while
+    let public = if watermark.is_some() { "public, max-age=3600" } else { IMMUTABLE_CACHE_CONTROL };
+    let (thumbnail, mime_type) = cached_thumbnail(&state, &encryption_key, &file_ref, name == "square", watermark).await?;
+
+    let headers = [
+        (header::CONTENT_TYPE, mime_type),
+        (header::CACHE_CONTROL, cache_control(&file_ref, public)),
+    ];
+
+    Ok((StatusCode::OK, headers, thumbnail).into_response())
+}
+
+/// Downscale to THUMBNAIL_SIZE, encoded as JPEG unless the image needs an alpha channel;
+/// `smart` first crops to the most detailed square, for avatars and gallery tiles.
+/// Images stored before the dimension limits were tightened are refused rather than resized.
+/// `watermark` is composited over the thumbnail itself, so it is sized for it.
+fn render_thumbnail(
+    config: &Config,
+    image_data: &[u8],
+    smart: bool,
+    watermark: Option<&Watermark>,
+) -> Result<(Vec<u8>, &'static str)> {
+    if let Some((width, height)) = header_dimensions(image_data) {
+        config.check_dimensions(width, height)?;
+    }
+
+    let decoded = image::load_from_memory(image_data)?;
+    config.check_dimensions(decoded.width(), decoded.height())?;
+    let decoded = if smart { smart_square_crop(&decoded) } else { decoded };
+    let thumbnail = decoded.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    encode_rendition(match watermark {
+        Some(watermark) => watermark.apply(&thumbnail),
+        None => thumbnail,
+    })
+}
+
+/// Encode a rendered image as JPEG, or PNG when it has an alpha channel
+fn encode_rendition(rendered: DynamicImage) -> Result<(Vec<u8>, &'static str)> {
+    let mut output = Cursor::new(Vec::new());
+    let mime_type = if rendered.color().has_alpha() {
+        rendered.write_to(&mut output, ImageFormat::Png)?;
+        "image/png"
+    } else {
+        DynamicImage::ImageRgb8(rendered.to_rgb8()).write_to(&mut output, ImageFormat::Jpeg)?;
+        "image/jpeg"
+    };
+
+    Ok((output.into_inner(), mime_type))
+}
+
 // Alternative endpoint for getting image metadata without downloading
 pub async fn get_image_info(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Path(encrypted_id): Path<String>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(path): Path<ImagePath>,
+    Query(link): Query<SignedLink>,
 ) -> Result<axum::Json<serde_json::Value>> {
     // Initialize crypto service
     let encryption_key = state.config.get_encryption_key_bytes()
@@ -103,30 +761,98 @@ pub async fn get_image_info(
     let crypto = CryptoService::new(&encryption_key);
 
     // Decrypt file reference
-    let file_ref = crypto.decrypt_file_reference(&encrypted_id)?;
+    let file_ref = path.file_reference(&state.index, &crypto)?;
+    let encrypted_id = path.id;
+    ensure_visible(&state, &encryption_key, &file_ref, &encrypted_id, (&api_key, &owner_token), &link)?;
+
+    // Colors are only known for images uploaded through this service with the index in place
+    let palette = state
+        .index
+        .get(&encrypted_id)?
+        .map(|entry| entry.palette)
+        .unwrap_or_default();
 
     let response = serde_json::json!({
         "size": file_ref.size,
         "mime_type": file_ref.mime_type,
+        "width": file_ref.width,
+        "height": file_ref.height,
+        "expires_at": file_ref.expires_at,
+        "visibility": file_ref.visibility,
+        "dominant_color": palette.first(),
+        "palette": palette,
         "id": encrypted_id
     });
 
-    state.telegram_service.send_log_message(&format!(
-        "Image info retrieved: ID={}, Size={}, Type={}, IP={}",
-        encrypted_id,
-        file_ref.size,
-        file_ref.mime_type,
-        addr
-    )).await?;
+    let event = AuditEvent::new(AuditAction::InfoView)
+        .id(&encrypted_id)
+        .ip(addr.ip())
+        .detail(format!("{} bytes, {}", file_ref.size, file_ref.mime_type));
+    state.audit.record(event);
 
     Ok(axum::Json(response))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use axum::http::{header, HeaderMap};
+
     use crate::{crypto::CryptoService, models::FileReference};
 
+    #[test]
+    fn test_accepts_html() {
+        let mut headers = HeaderMap::new();
+        assert!(!super::accepts_html(&headers));
+
+        // What browsers send for <img> loads, then for navigations
+        headers.insert(header::ACCEPT, "image/avif,image/webp,*/*".parse().unwrap());
+        assert!(!super::accepts_html(&headers));
+        headers.insert(header::ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8".parse().unwrap());
+        assert!(super::accepts_html(&headers));
+    }
+
+    #[test]
+    fn test_sign_link() {
+        let key = [9u8; 32];
+        let sig = super::sign_link(&key, "abc", 100);
+        assert_eq!(sig, super::sign_link(&key, "abc", 100));
+        assert_ne!(sig, super::sign_link(&key, "abc", 101));
+        assert_ne!(sig, super::sign_link(&key, "abd", 100));
+        assert_ne!(sig, super::sign_link(&[8u8; 32], "abc", 100));
+        assert!(super::verify_link(&key, "abc", 100, &sig));
+        assert!(!super::verify_link(&key, "abc", 101, &sig));
+        assert!(!super::verify_link(&key, "abc", 100, &sig[..32]));
+        assert!(!super::verify_link(&key, "abc", 100, "not hex"));
+    }
+
+    #[test]
+    fn test_variant_segment() {
+        let segment = super::variant_segment("abc", "thumb").unwrap();
+        assert!(segment.starts_with("thumb.") && segment.len() == "thumb.".len() + 16);
+        assert_eq!(Some(segment.clone()), super::variant_segment("abc", "thumb"));
+        assert_ne!(Some(segment), super::variant_segment("abd", "thumb"));
+        assert!(super::variant_segment("abc", "unknown").is_none());
+    }
+
+    #[test]
+    fn test_download_filename() {
+        use super::{content_disposition, download_filename};
+
+        assert_eq!(download_filename(Some("../cat.png"), "image/png"), "cat.png");
+        assert_eq!(download_filename(Some("cat.png"), "image/jpeg"), "cat.jpg");
+        assert_eq!(download_filename(Some("notes"), "image/png"), "notes.png");
+        assert_eq!(download_filename(None, "image/gif"), "image.gif");
+        assert_eq!(download_filename(Some("a\"b;c.png"), "image/png"), "abc.png");
+        assert_eq!(download_filename(Some("dance.gif"), "video/mp4"), "dance.mp4");
+        assert_eq!(download_filename(Some("cat.html"), "image/png"), "cat.png");
+        assert_eq!(download_filename(Some("x.exe"), "image/webp"), "x.webp");
+
+        assert_eq!(
+            content_disposition("แมว.png"),
+            "attachment; filename=\"___.png\"; filename*=UTF-8''%E0%B9%81%E0%B8%A1%E0%B8%A7.png"
+        );
+    }
+
     #[tokio::test]
     async fn test_decrypt_file_reference() {
         let key = CryptoService::generate_key();
@@ -145,4 +871,123 @@ mod tests {
         assert_eq!(file_ref.file_id, decrypted.file_id);
         assert_eq!(file_ref.message_id, decrypted.message_id);
     }
+
+    #[tokio::test]
+    async fn test_tenant_watermark_on_thumbnails() {
+        use crate::{services::tenants::TenantSettings, test_support::TestApp};
+        use serde_json::Value;
+
+        let mark = std::env::temp_dir().join(format!("rustgram-mark-{}.png", uuid::Uuid::new_v4()));
+        image::RgbImage::from_pixel(64, 64, image::Rgb([255, 255, 255])).save(&mark).unwrap();
+        let app = TestApp::start(&[
+            ("WATERMARK_PATH", mark.to_str().unwrap()),
+            ("WATERMARK_POSITION", "center"),
+            ("WATERMARK_OPACITY", "1"),
+        ])
+        .await;
+        let settings = TenantSettings { watermark: true, ..Default::default() };
+        let master_key = app.state.config.get_encryption_key_bytes().unwrap();
+        let (_, api_key) = app.state.tenants.create("acme", settings, &master_key, None).unwrap();
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 64, image::Rgb([0, 0, 0])))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let request = app.client.put(app.url("/upload")).header("content-type", "image/png").header("x-api-key", api_key.clone());
+        let queued: Value = request.body(png).send().await.unwrap().json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let id = job["response"]["id"].as_str().unwrap();
+
+        // The mark is scaled to a quarter of the thumbnail's width, over its center
+        let thumbnail = app.get(&format!("/t/acme/thumb/{}", id)).await.bytes().await.unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap().to_rgb8();
+        assert!(thumbnail.get_pixel(128, 128)[0] > 200 && thumbnail.get_pixel(16, 16)[0] < 50);
+
+        let thumbnail_url = job["response"]["thumbnail_url"].as_str().unwrap();
+        let response = app.get(&thumbnail_url[thumbnail_url.find("/t/acme/v/").unwrap()..]).await;
+        assert_eq!(response.headers()[reqwest::header::CACHE_CONTROL], "public, max-age=3600");
+        let variant = image::load_from_memory(&response.bytes().await.unwrap()).unwrap().to_rgb8();
+        assert!(variant.get_pixel(128, 128)[0] > 200);
+
+        // Rendered once, then served from the cache
+        let file_id = app.state.index.get(id).unwrap().unwrap().reference.file_id;
+        assert!(app.state.cache.get(&crate::services::cache::ImageCache::variant_key(&file_id, "thumb=contain;wm")).is_some());
+
+        // GIFs are served as uploaded rather than flattened to carry the mark
+        let mut gif = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([0, 200, 0])))
+            .write_to(&mut std::io::Cursor::new(&mut gif), image::ImageFormat::Gif)
+            .unwrap();
+        let request = app.client.put(app.url("/upload")).header("content-type", "image/gif").header("x-api-key", api_key);
+        let queued: Value = request.body(gif.clone()).send().await.unwrap().json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let response = app.get(&format!("/t/acme/image/{}", job["response"]["id"].as_str().unwrap())).await;
+        assert_eq!(response.headers()[reqwest::header::CONTENT_TYPE], "image/gif");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), gif.as_slice());
+        std::fs::remove_file(&mark).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gif_served_as_video() {
+        use crate::test_support::{png_bytes, TestApp};
+        use serde_json::Value;
+        use std::{io::Cursor, os::unix::fs::PermissionsExt};
+
+        // Stands in for ffmpeg: writes a fixed body to the output path, its last argument
+        let ffmpeg = std::env::temp_dir().join(format!("rustgram-fake-ffmpeg-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&ffmpeg, "#!/bin/sh\nfor last; do :; done\nprintf fake-video > \"$last\"\n").unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let app = TestApp::start(&[("FFMPEG_PATH", ffmpeg.to_str().unwrap())]).await;
+
+        let upload = |body: Vec<u8>, content_type: &'static str| {
+            app.client.put(app.url("/upload?filename=dance.gif")).header("content-type", content_type).body(body).send()
+        };
+        let mut gif = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([0, 200, 0])))
+            .write_to(&mut Cursor::new(&mut gif), image::ImageFormat::Gif)
+            .unwrap();
+        let queued: Value = upload(gif, "image/gif").await.unwrap().json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let id = job["response"]["id"].as_str().unwrap().to_string();
+
+        // Served but not stored while read-only
+        app.state.modes.set_read_only(true);
+        let response = app.get(&format!("/image/{}?format=webm", id)).await;
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"fake-video");
+        assert!(app.state.index.get(&id).unwrap().unwrap().transcodes.is_empty());
+        assert_eq!(app.telegram.stored_files(), 1);
+        app.state.modes.set_read_only(false);
+
+        let response = app.get(&format!("/image/{}?format=mp4", id)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers()[reqwest::header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"fake-video");
+
+        // Kept as a second stored object, read back once the in-memory copy is gone
+        let entry = app.state.index.get(&id).unwrap().unwrap();
+        let stored = entry.transcodes["mp4"].clone();
+        assert_eq!((stored.mime_type.as_str(), stored.size), ("video/mp4", 10));
+        assert_eq!(app.telegram.stored_files(), 2);
+        app.state.cache.remove(&crate::services::cache::ImageCache::variant_key(&entry.reference.file_id, "mp4"));
+        std::fs::remove_file(&ffmpeg).unwrap();
+        let response = app.get(&format!("/image/{}?format=mp4", id)).await;
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"fake-video");
+
+        let queued: Value = upload(png_bytes(), "image/png").await.unwrap().json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let png_id = job["response"]["id"].as_str().unwrap();
+        let response = app.get(&format!("/image/{}?format=mp4", png_id)).await;
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // Deleting the image deletes its transcode too
+        let deleted = app
+            .client
+            .delete(app.url(&format!("/admin/images/{}", id)))
+            .header("x-admin-key", "admin")
+            .send()
+            .await
+            .unwrap();
+        assert!(deleted.status().is_success(), "{}", deleted.status());
+        assert_eq!(app.telegram.stored_files(), 1);
+    }
 }
@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Multipart, Path, State, ConnectInfo},
+    response::Json,
+};
+use base64::{engine::general_purpose, Engine as _};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken, UploadAuth},
+        delete::delete_by_token,
+        job::{build_upload_response, wait_for_job},
+        upload::{
+            enqueue_job, prepare_upload, read_field_limited, sniff_mime_type, upload_options, validate_image, HashedData,
+            HashingBuffer,
+        },
+        url_upload::fetch_remote_image,
+    },
+    models::{unix_timestamp, ImgurImage, ImgurResponse},
+    worker::JobPayload,
+    AppState,
+};
+
+// imgur answers uploads synchronously, so wait for the worker up to this long
+const IMGUR_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Emulates POST https://api.imgur.com/3/image (also mounted at /3/upload)
+pub async fn upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    mut multipart: Multipart,
+) -> Result<Json<ImgurResponse<ImgurImage>>> {
+    let mut image_field: Option<(HashedData, Option<String>, Option<String>)> = None;
+    let mut upload_type: Option<String> = None;
+    let mut title: Option<String> = None;
+
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("image") => {
+                let mime_type = field.content_type().map(|s| s.to_string());
+                let filename = field.file_name().map(|s| s.to_string());
+                // The field may hold base64, so allow for its overhead; the decoded size is checked later
+                let limit = state.config.max_file_size.div_ceil(3) * 4;
+                let buffer = HashingBuffer::new(limit).with_spill(&state.config);
+                image_field = Some((read_field_limited(field, buffer).await?, mime_type, filename));
+            }
+            Some("type") => upload_type = Some(field.text().await?),
+            Some("title") | Some("name") => title = Some(field.text().await?),
+            _ => {}
+        }
+    }
+
+    let (raw, field_mime_type, filename) =
+        image_field.ok_or_else(|| AppError::ValidationError("No image found".into()))?;
+
+    // imgur accepts the image as a file, a base64 string or a URL in the same field;
+    // only the file and URL forms arrive as the bytes that get hashed
+    let (image_data, sha256, mime_type, filename) = match upload_type.as_deref() {
+        Some("url") => {
+            let url = String::from_utf8(raw.bytes.to_vec())
+                .map_err(|_| AppError::ValidationError("Invalid URL".to_string()))?;
+            let (image_data, mime_type, filename) = fetch_remote_image(&state.remote_client, url.trim(), &state.config, &state.cpu).await?;
+            (image_data.bytes, Some(image_data.sha256), mime_type, filename)
+        }
+        Some("base64") => {
+            let image_data = general_purpose::STANDARD
+                .decode(raw.bytes.trim_ascii())
+                .map_err(|e| AppError::ValidationError(format!("Invalid base64 data: {}", e)))?;
+            let mime_type = sniff_mime_type(&image_data);
+            (image_data.into(), None, mime_type, filename.unwrap_or_else(|| "image.bin".to_string()))
+        }
+        _ => {
+            let mime_type = field_mime_type
+                .filter(|m| m != "application/octet-stream")
+                .unwrap_or_else(|| sniff_mime_type(&raw.bytes));
+            (raw.bytes, Some(raw.sha256), mime_type, filename.unwrap_or_else(|| "image.bin".to_string()))
+        }
+    };
+
+    validate_image(&state.cpu, &state.config, image_data.clone(), &mime_type).await?;
+
+    let options = upload_options(&state, None, api_key, owner_token)?;
+    let prepared = prepare_upload(
+        &state.cpu,
+        &state.config,
+        options.tenant.as_ref(),
+        image_data,
+        sha256,
+        &filename,
+        mime_type,
+    )
+    .await?;
+    let queued = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+    let job = wait_for_job(&state, &queued.job_id, IMGUR_UPLOAD_TIMEOUT).await?;
+    let upload = build_upload_response(&state.config, &job.file_ref, job.existing)?;
+
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let deletehash = (!job.existing).then(|| format!("{}.{}", upload.id, CryptoService::delete_token(&encryption_key, &upload.id)));
+
+    let image = ImgurImage {
+        deletehash,
+        id: upload.id,
+        title,
+        datetime: unix_timestamp(),
+        animated: upload.mime_type == "image/gif",
+        mime_type: upload.mime_type,
+        width: upload.width.unwrap_or_default(),
+        height: upload.height.unwrap_or_default(),
+        size: upload.size,
+        link: upload.url,
+    };
+
+    Ok(Json(ImgurResponse { data: image, success: true, status: 200 }))
+}
+
+/// Emulates DELETE https://api.imgur.com/3/image/{deletehash}
+pub async fn delete(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    api_key: ApiKey,
+    Path(deletehash): Path<String>,
+) -> Result<Json<ImgurResponse<bool>>> {
+    // URL-safe base64 IDs never contain '.', so it safely separates ID and token
+    let (id, token) = deletehash.split_once('.').ok_or(AppError::InvalidId)?;
+
+    delete_by_token(&state, id, token, addr, api_key).await?;
+
+    Ok(Json(ImgurResponse { data: true, success: true, status: 200 }))
+}
@@ -0,0 +1,204 @@
+use axum::{
+    extract::{State, ConnectInfo},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{
+        admin::is_admin_key,
+        auth::{OwnerToken, UploadAuth},
+        job::build_upload_response,
+        upload::{enqueue_job, prepare_upload, sniff_mime_type, upload_options, validate_image},
+    },
+    models::FileReference,
+    services::usage::{owner_subject, usage_subjects},
+    worker::{record_in_index, JobPayload},
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramImportPayload {
+    pub file_id: Option<String>,
+    // e.g. https://t.me/c/1234567890/42 or https://t.me/channel_name/42
+    pub message_link: Option<String>,
+    // Download, encrypt and upload a private copy instead of referencing the file in place
+    #[serde(default)]
+    pub reupload: bool,
+}
+
+pub async fn import_telegram_file(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    Json(payload): Json<TelegramImportPayload>,
+) -> Result<Response> {
+    let tenant = state.tenants.resolve_key(api_key.0.as_deref())?;
+    let (file_id, message_id) = match (&payload.file_id, &payload.message_link) {
+        (Some(file_id), None) => (file_id.clone(), 0),
+        (None, Some(link)) => {
+            let trusted = tenant.is_some()
+                || api_key.0.as_deref().is_some_and(|key| is_admin_key(&state.admin_secret, key));
+            resolve_message_link(&state, link, trusted).await?
+        }
+        _ => {
+            return Err(AppError::ValidationError(
+                "Provide exactly one of file_id or message_link".to_string(),
+            ))
+        }
+    };
+
+    // Fetch metadata to make sure the bot can actually access the file
+    let file_info = state.telegram_service.get_file_info(&file_id).await?;
+    let file_path = file_info
+        .file_path
+        .ok_or_else(|| AppError::TelegramError("No file path in response".to_string()))?;
+
+    // Refuse oversized files before downloading them, when Telegram reports the size
+    if file_info.file_size.is_some_and(|size| size as usize > state.config.max_file_size) {
+        return Err(AppError::FileTooLarge { max_size: state.config.max_file_size });
+    }
+
+    // Either way the file has to be an image, typed by its bytes rather than by its name
+    let image_data = state.telegram_service.download_file(&file_path).await?;
+    let mime_type = sniff_mime_type(&image_data);
+    validate_image(&state.cpu, &state.config, image_data.clone(), &mime_type).await?;
+
+    if payload.reupload {
+        let filename = file_path.rsplit('/').next().unwrap_or("image.bin");
+        let options = upload_options(&state, None, api_key, owner_token)?;
+        let prepared =
+            prepare_upload(&state.cpu, &state.config, options.tenant.as_ref(), image_data, None, filename, mime_type)
+                .await?;
+
+        let response = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+        return Ok((StatusCode::ACCEPTED, Json(response)).into_response());
+    }
+
+    // Referencing in place: the content is served exactly as Telegram stores it
+    let size = image_data.len();
+    // Held until the image is indexed, so concurrent imports can't both fit the last of the quota
+    let _reservation = tenant
+        .as_ref()
+        .map(|tenant| tenant.reserve_upload(&state.index, size, &mime_type))
+        .transpose()?;
+
+    let mut file_ref = FileReference::new(file_id, message_id, size, mime_type);
+    file_ref.plaintext = true;
+    file_ref.tenant = tenant.map(|tenant| tenant.id);
+
+    let mut subjects = usage_subjects(addr.ip(), api_key.0.as_deref());
+    subjects.extend(owner_token.0.as_deref().filter(|_| api_key.0.is_none()).map(owner_subject));
+    state.usage.record_upload(&subjects, file_ref.size);
+    let file_ref = record_in_index(&state.index, &state.config, file_ref, subjects, None, Vec::new())?;
+
+    tracing::info!("Imported Telegram file in place for IP: {}. Size: {}", addr, size);
+
+    let response = build_upload_response(&state.config, &file_ref, false)?;
+
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+/// Forward the linked message into the storage chat and pick the media it carries. Only admins
+/// and tenants (`trusted`) may link to chats outside IMPORT_SOURCE_CHATS, so anonymous callers
+/// can't have the bot copy messages out of every chat it belongs to.
+async fn resolve_message_link(state: &AppState, link: &str, trusted: bool) -> Result<(String, i64)> {
+    let (from_chat_id, message_id) = parse_message_link(link)
+        .ok_or_else(|| AppError::ValidationError("Invalid Telegram message link".to_string()))?;
+    if !trusted && !state.config.import_source_chats.contains(&from_chat_id.to_lowercase()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    let message = state
+        .telegram_service
+        .forward_message(&from_chat_id, message_id)
+        .await?;
+
+    if let Some(document) = message.document {
+        return Ok((document.file_id, message.message_id));
+    }
+
+    // Telegram lists photo sizes from smallest to largest
+    if let Some(photo) = message.photo.and_then(|sizes| sizes.into_iter().last()) {
+        return Ok((photo.file_id, message.message_id));
+    }
+
+    Err(AppError::ValidationError("Linked message contains no photo or document".to_string()))
+}
+
+/// Parse a t.me message link into the chat identifier and message ID
+fn parse_message_link(link: &str) -> Option<(String, i64)> {
+    let path = link
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .strip_prefix("t.me/")?;
+    let parts: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+
+    match parts.as_slice() {
+        // Private channel links carry the channel ID without the -100 prefix
+        ["c", chat, message] => {
+            let chat: i64 = chat.parse().ok()?;
+            Some((format!("-100{}", chat), message.parse().ok()?))
+        }
+        [username, message] => Some((format!("@{}", username), message.parse().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::telegram::TelegramService,
+        test_support::{png_bytes, TestApp},
+    };
+    use bytes::Bytes;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_import_checks_the_file_itself() {
+        let app = TestApp::start(&[]).await;
+        let telegram = TelegramService::new("test-token".to_string(), -1001, None).with_api_url(&app.telegram.url());
+        let import = |file_id: String| {
+            app.client.post(app.url("/import/telegram")).json(&json!({ "file_id": file_id })).send()
+        };
+
+        let page = telegram.upload_file(Bytes::from_static(b"<html></html>"), "cat.png").await.unwrap();
+        let response = import(page.document.unwrap().file_id).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let image = telegram.upload_file(Bytes::from(png_bytes()), "cat.html").await.unwrap();
+        let response = import(image.document.unwrap().file_id).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.json::<Value>().await.unwrap()["mime_type"], "image/png");
+
+        // Anonymous callers can't have the bot forward messages out of arbitrary chats
+        let response = app
+            .client
+            .post(app.url("/import/telegram"))
+            .json(&json!({ "message_link": "https://t.me/c/1234567890/1" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_parse_message_link() {
+        assert_eq!(
+            parse_message_link("https://t.me/c/1234567890/42"),
+            Some(("-1001234567890".to_string(), 42))
+        );
+        assert_eq!(
+            parse_message_link("https://t.me/some_channel/7"),
+            Some(("@some_channel".to_string(), 7))
+        );
+        assert_eq!(parse_message_link("https://example.com/c/1/2"), None);
+        assert_eq!(parse_message_link("https://t.me/c/abc/2"), None);
+    }
+}
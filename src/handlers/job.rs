@@ -1,47 +1,160 @@
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::Json,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Json,
+    },
 };
+use futures::{stream, Stream};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::{
+    config::Config,
     crypto::CryptoService,
     error::{AppError, Result},
-    models::{JobStatus, UploadResponse},
+    handlers::image::variant_segment,
+    models::{FileReference, JobStatus, UploadResponse},
+    services::cdn,
+    worker::FinishedJob,
     AppState,
 };
 
+// How often the event stream re-checks a pending job
+const JOB_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
 pub async fn get_job_status(
     State(state): State<Arc<AppState>>,
     Path(job_id): Path<String>,
 ) -> Result<(StatusCode, Json<JobStatus>)> {
+    let status = job_status(&state, &job_id)?;
+    let code = match status {
+        JobStatus::Pending { .. } => StatusCode::ACCEPTED,
+        _ => StatusCode::OK,
+    };
+
+    Ok((code, Json(status)))
+}
+
+/// Server-sent events with the job status, repeated until the job completes or fails
+pub async fn job_events(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    let events = stream::unfold(Some((state, job_id, true)), |cursor| async move {
+        let (state, job_id, first) = cursor?;
+        if !first {
+            tokio::time::sleep(JOB_EVENT_INTERVAL).await;
+        }
+
+        let (event, finished) = match job_status(&state, &job_id) {
+            Ok(status) => {
+                let finished = !matches!(status, JobStatus::Pending { .. });
+                let event = Event::default().json_data(&status).unwrap_or_default();
+                (event, finished)
+            }
+            Err(e) => (Event::default().event("error").data(e.to_string()), true),
+        };
+
+        let next = (!finished).then_some((state, job_id, false));
+        Some((Ok(event), next))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Current status of a job, including body progress for tracked uploads that are still arriving
+fn job_status(state: &AppState, job_id: &str) -> Result<JobStatus> {
     let job_store = state.job_store.lock().map_err(|_| {
         AppError::InternalError("Failed to acquire job store lock".to_string())
     })?;
 
-    match job_store.get(&job_id) {
-        Some(file_ref) => {
+    match job_store.get(job_id) {
+        Some(Ok(job)) => {
             // Job is complete, create the final response
-            let encryption_key = state.config.get_encryption_key_bytes()?;
-            let crypto = CryptoService::new(&encryption_key);
-            let encrypted_id = crypto.encrypt_file_reference(file_ref)?;
-
-            let response = UploadResponse {
-                id: encrypted_id.clone(),
-                url: format!("/image/{}", encrypted_id),
-                size: file_ref.size,
-                mime_type: file_ref.mime_type.clone(),
-            };
-
-            Ok((
-                StatusCode::OK,
-                Json(JobStatus::Completed { response }),
-            ))
+            let response = build_upload_response(&state.config, &job.file_ref, job.existing)?;
+            Ok(JobStatus::Completed { response })
+        }
+        Some(Err(error)) => Ok(JobStatus::Failed { error: error.clone() }),
+        // Job not found, which means it's pending or the ID is invalid
+        None => Ok(JobStatus::Pending { progress: state.upload_progress.get(job_id) }),
+    }
+}
+
+/// Build the client-facing response for a stored file reference; `existing` images were stored by
+/// someone else, so their delete link is left out
+pub(crate) fn build_upload_response(config: &Config, file_ref: &FileReference, existing: bool) -> Result<UploadResponse> {
+    let encryption_key = config.get_encryption_key_bytes()?;
+    let crypto = CryptoService::new(&encryption_key);
+    let encrypted_id = crypto.public_id(file_ref)?;
+
+    // Files referenced by a bare file_id have no message of ours that could be deleted
+    let delete_url = (file_ref.message_id != 0 && !existing).then(|| {
+        let token = CryptoService::delete_token(&encryption_key, &encrypted_id);
+        format!("{}/delete/{}/{}", config.public_base_url, encrypted_id, token)
+    });
+
+    // Tenant images live under their namespace prefix
+    let prefix = file_ref.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    let thumbnail = variant_segment(&encrypted_id, "thumb")
+        .ok_or_else(|| AppError::InternalError("Unknown thumbnail variant".to_string()))?;
+
+    Ok(UploadResponse {
+        url: share_url(config, file_ref.tenant.as_deref(), &encrypted_id),
+        thumbnail_url: cdn::public_url(config, &format!("{}/v/{}/{}", prefix, encrypted_id, thumbnail)),
+        delete_url,
+        id: encrypted_id,
+        size: file_ref.size,
+        mime_type: file_ref.mime_type.clone(),
+        width: file_ref.width,
+        height: file_ref.height,
+        expires_at: file_ref.expires_at,
+        visibility: file_ref.visibility,
+        existing,
+    })
+}
+
+/// Link handed out for an image: the raw /image/:id, or the /view/:id page with LINK_MODE=viewer
+pub(crate) fn share_url(config: &Config, tenant: Option<&str>, encrypted_id: &str) -> String {
+    let prefix = tenant.map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    // The viewer page is served by us, never the CDN
+    if config.link_to_viewer {
+        format!("{}{}/view/{}", config.public_base_url, prefix, encrypted_id)
+    } else {
+        cdn::public_url(config, &format!("{}/image/{}", prefix, encrypted_id))
+    }
+}
+
+/// Poll the job store until the worker has finished the job, for clients that need a final URL
+pub(crate) async fn wait_for_job(
+    state: &AppState,
+    job_id: &str,
+    timeout: Duration,
+) -> Result<FinishedJob> {
+    let started = Instant::now();
+
+    loop {
+        {
+            let job_store = state.job_store.lock().map_err(|_| {
+                AppError::InternalError("Failed to acquire job store lock".to_string())
+            })?;
+
+            match job_store.get(job_id) {
+                Some(Ok(job)) => return Ok(job.clone()),
+                Some(Err(error)) => return Err(AppError::TelegramError(error.clone())),
+                None => {}
+            }
         }
-        None => {
-            // Job not found, which means it's pending or the ID is invalid
-            Ok((StatusCode::ACCEPTED, Json(JobStatus::Pending)))
+
+        if started.elapsed() >= timeout {
+            return Err(AppError::Timeout(format!(
+                "Upload is still processing, check /job/{}",
+                job_id
+            )));
         }
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
     }
 }
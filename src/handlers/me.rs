@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken},
+        delete::delete_stored_image,
+        pagination::{paginate, Page, PageQuery},
+    },
+    models::Visibility,
+    services::{
+        audit::{AuditAction, AuditEvent},
+        cdn,
+        index::{ImageFilter, IndexEntry},
+        usage::{key_subject, owner_subject},
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct OwnedImage {
+    pub id: String,
+    pub url: String,
+    pub size: usize,
+    pub mime_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    pub visibility: Visibility,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+/// The caller's identity as recorded on their uploads: the API key if presented, else the owner token
+pub(crate) fn owner(api_key: &ApiKey, owner_token: &OwnerToken) -> Result<String> {
+    match (&api_key.0, &owner_token.0) {
+        (Some(key), _) => Ok(key_subject(key)),
+        (None, Some(token)) => Ok(owner_subject(token)),
+        (None, None) => Err(AppError::Unauthorized),
+    }
+}
+
+/// Images uploaded with the caller's API key or owner token, newest first, narrowed down
+/// by the same filters as /admin/images
+pub async fn list_my_images(
+    State(state): State<Arc<AppState>>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Query(query): Query<PageQuery>,
+    Query(mut filter): Query<ImageFilter>,
+) -> Result<Json<Page<OwnedImage>>> {
+    filter.owner = Some(owner(&api_key, &owner_token)?);
+    // One more than the page holds, so paginate can tell whether another follows
+    let owned = state.index.page(&filter, query.after()?.as_ref(), query.limit() + 1)?;
+
+    let images = paginate(owned, &query, IndexEntry::sort_key)?.map(|entry| {
+        let prefix = entry.reference.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+        OwnedImage {
+            url: cdn::public_url(&state.config, &format!("{}/image/{}", prefix, entry.id)),
+            id: entry.id,
+            size: entry.reference.size,
+            mime_type: entry.reference.mime_type,
+            width: entry.reference.width,
+            height: entry.reference.height,
+            filename: entry.filename,
+            tags: entry.tags,
+            visibility: entry.reference.visibility,
+            created_at: entry.created_at,
+            expires_at: entry.reference.expires_at,
+        }
+    });
+
+    Ok(Json(images))
+}
+
+/// Delete one of the caller's own images
+pub async fn delete_my_image(
+    State(state): State<Arc<AppState>>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    let owner = owner(&api_key, &owner_token)?;
+    let entry = state.index.get(&id)?.ok_or(AppError::NotFound)?;
+    // Someone else's image is reported as missing rather than forbidden
+    if !entry.uploader.contains(&owner) {
+        return Err(AppError::NotFound);
+    }
+
+    delete_stored_image(&state, &id, &[owner]).await?;
+    state.audit.record(AuditEvent::new(AuditAction::Delete).id(&id).key(api_key.0.as_deref()).detail("owner"));
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,12 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Counters in Prometheus text exposition format
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
@@ -0,0 +1,292 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::admin::AdminAuth,
+    models::{unix_timestamp, Backend, FileReference},
+    recovery::content_key_and_storage,
+    services::{
+        audit::{AuditAction, AuditEvent},
+        index::IndexEntry,
+        manifest,
+        migration::{MigrationFailure, MigrationReport, MigrationStatus, StorageLocation},
+        storage::Storage,
+        transcode,
+    },
+    worker::store_upload,
+    AppState,
+};
+
+// Pause between images so a long migration stays clear of the backends' rate limits
+const MIGRATION_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Deserialize)]
+pub struct MigrationRequest {
+    pub from: StorageLocation,
+    pub to: StorageLocation,
+    // Delete each image's old message once its copy checks out; kept by default
+    #[serde(default)]
+    pub delete_source: bool,
+}
+
+/// Start copying every image stored at `from` to `to`, pointing the index at the copies. Returns
+/// the job's initial report; poll GET /admin/migrations/:id for progress. An interrupted or
+/// partly failed migration is resumed by starting it again, since moved images no longer match `from`.
+pub async fn start_migration(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<MigrationRequest>,
+) -> Result<(StatusCode, Json<MigrationReport>)> {
+    let default_chat = state.config.telegram_chat_id;
+    if payload.from.backend == payload.to.backend && payload.from.chat(default_chat) == payload.to.chat(default_chat) {
+        return Err(AppError::ValidationError("from and to are the same location".to_string()));
+    }
+    if payload.to.backend == Backend::Discord && payload.to.chat_id.is_some() {
+        return Err(AppError::ValidationError("chat_id only applies to Telegram".to_string()));
+    }
+    // An orphan scan would take copies not yet in the index for orphans
+    if state.migrations.running() || state.orphan_scans.running() {
+        return Err(AppError::ValidationError("A migration or orphan scan is already running".to_string()));
+    }
+    let target = target_storage(&state, &payload.to)?;
+
+    let report = MigrationReport::new(Uuid::new_v4().to_string(), payload.from, payload.to, payload.delete_source);
+    state.migrations.update(&report);
+    tokio::spawn(run_migration(state.clone(), target, report.clone()));
+
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+pub async fn get_migration(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<MigrationReport>> {
+    state.migrations.get(&job_id).map(Json).ok_or(AppError::NotFound)
+}
+
+/// Storage uploading to `location`; Telegram copies go through the main bot
+fn target_storage(state: &AppState, location: &StorageLocation) -> Result<Storage> {
+    let telegram = match location.chat_id {
+        Some(chat_id) => Arc::new(state.telegram_service.with_storage(None, chat_id)),
+        None => state.telegram_service.clone(),
+    };
+    Storage::for_backend(location.backend, telegram, state.discord.as_ref())
+}
+
+async fn run_migration(state: Arc<AppState>, target: Storage, mut report: MigrationReport) {
+    let default_chat = state.config.telegram_chat_id;
+    let master_key = state.config.get_encryption_key_bytes().map_err(AppError::from);
+    let (entries, master_key) = match (state.index.list(), master_key) {
+        (Ok(entries), Ok(key)) => (entries, key),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Migration {} could not start: {}", report.job_id, e);
+            report.status = MigrationStatus::Completed;
+            report.finished_at = Some(unix_timestamp());
+            state.migrations.update(&report);
+            return;
+        }
+    };
+
+    // Files referenced by a bare file_id have no message of ours to move
+    let entries: Vec<IndexEntry> = entries
+        .into_iter()
+        .filter(|entry| {
+            !entry.broken && entry.reference.message_id != 0 && report.from.holds(&entry.reference, default_chat)
+        })
+        .collect();
+    report.images_found = entries.len();
+    state.migrations.update(&report);
+
+    for entry in entries {
+        // Tenants with their own bot read their files through it, so it couldn't see a copy sent by ours
+        let own_bot = state
+            .tenants
+            .owner(entry.reference.tenant.as_deref())
+            .is_ok_and(|tenant| tenant.is_some_and(|tenant| tenant.bot_token.is_some()));
+        if own_bot && target.backend().is_telegram() {
+            report.images_skipped += 1;
+            state.migrations.update(&report);
+            continue;
+        }
+
+        let id = entry.id.clone();
+        match migrate_image(&state, &master_key, &target, report.delete_source, entry).await {
+            Ok(size) => {
+                report.images_migrated += 1;
+                report.bytes_copied += size as u64;
+            }
+            Err(e) => {
+                tracing::warn!("Migration {} could not move image {}: {}", report.job_id, id, e);
+                report.failed.push(MigrationFailure { id, error: e.to_string() });
+            }
+        }
+        state.migrations.update(&report);
+        tokio::time::sleep(MIGRATION_INTERVAL).await;
+    }
+
+    report.status = MigrationStatus::Completed;
+    report.finished_at = Some(unix_timestamp());
+    state.migrations.update(&report);
+    state.audit.record(AuditEvent::new(AuditAction::Migration).detail(format!(
+        "job {}: {} of {} images migrated, {} skipped, {} failed",
+        report.job_id,
+        report.images_migrated,
+        report.images_found,
+        report.images_skipped,
+        report.failed.len()
+    )));
+    tracing::info!("Migration {} finished", report.job_id);
+}
+
+/// Copy one image's stored bytes to `target` as they are, check the copy reads back identical, then
+/// point the index at it. IDs minted earlier keep working through the entry's previous file_ids.
+/// Returns the number of bytes copied.
+async fn migrate_image(
+    state: &AppState,
+    master_key: &[u8; 32],
+    target: &Storage,
+    delete_source: bool,
+    entry: IndexEntry,
+) -> Result<usize> {
+    let (_, telegram) = content_key_and_storage(state, master_key, entry.reference.tenant.as_deref())?;
+    let source = Storage::for_backend(entry.reference.backend, telegram, state.discord.as_ref())?;
+    let data = fetch(&source, master_key, &entry.reference).await?;
+    let sha256 = CryptoService::hash_data(&data);
+
+    let filename = format!("{}_{}", Uuid::new_v4(), entry.filename.as_deref().unwrap_or("image.bin"));
+    let stored = store_upload(target, &state.config, master_key, data.clone(), &filename).await?;
+    let mut copy = entry.reference.clone();
+    copy.file_id = stored.file_id;
+    copy.message_id = stored.message_id;
+    copy.chunked = stored.chunked;
+    copy.backend = target.backend();
+    copy.chat_id = target.telegram_chat().filter(|chat_id| *chat_id != state.config.telegram_chat_id);
+
+    let verified = fetch(target, master_key, &copy).await;
+    if !verified.is_ok_and(|stored| CryptoService::hash_data(&stored) == sha256) {
+        discard(target, master_key, &copy).await;
+        return Err(AppError::InternalError("Copy on the target does not match the source".to_string()));
+    }
+
+    // The image may have been deleted or moved while it was being copied
+    let old = entry.reference;
+    let mut transcodes = Default::default();
+    let updated = state.index.update_stored_as(&entry.id, &old.file_id, |current| {
+        current.previous_file_ids.push(old.file_id.clone());
+        current.reference.file_id = copy.file_id.clone();
+        current.reference.message_id = copy.message_id;
+        current.reference.chunked = copy.chunked;
+        current.reference.backend = copy.backend;
+        current.reference.chat_id = copy.chat_id;
+        // Transcodes stay behind at the source; they are made again from the copy when next requested
+        transcodes = std::mem::take(&mut current.transcodes);
+    })?;
+    let Some(mut current) = updated else {
+        discard(target, master_key, &copy).await;
+        return Err(AppError::InternalError("Image changed while it was being copied".to_string()));
+    };
+
+    if delete_source {
+        let chat_id = Some(old.chat_id.unwrap_or(state.config.telegram_chat_id));
+        current.transcodes = transcodes;
+        transcode::delete_transcodes(&source, master_key, state.config.telegram_chat_id, &current).await;
+        manifest::delete_file_chunks(&source, master_key, chat_id, &old).await;
+        if let Err(e) = source.delete(chat_id, old.message_id).await {
+            tracing::warn!("Migrated image {} but could not delete its old message: {}", entry.id, e);
+        }
+    }
+    Ok(data.len())
+}
+
+/// A file's bytes as stored, reassembled when chunked but not decrypted
+async fn fetch(storage: &Storage, master_key: &[u8; 32], file_ref: &FileReference) -> Result<Bytes> {
+    if file_ref.chunked {
+        manifest::load(storage, master_key, file_ref).await
+    } else {
+        storage.download(&file_ref.file_id, file_ref.message_id).await
+    }
+}
+
+/// Remove a copy that won't be used; a leftover only takes up space
+async fn discard(target: &Storage, master_key: &[u8; 32], copy: &FileReference) {
+    manifest::delete_file_chunks(target, master_key, None, copy).await;
+    if let Err(e) = target.delete(None, copy.message_id).await {
+        tracing::warn!("Could not delete unused copy {}: {}", copy.file_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{png_bytes, TestApp};
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_migrate_between_chats() {
+        let app = TestApp::start(&[]).await;
+        let png = png_bytes();
+        let queued: Value = app
+            .client
+            .put(app.url("/upload?filename=red.png"))
+            .header("content-type", "image/png")
+            .body(png.clone())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let id = job["response"]["id"].as_str().unwrap().to_string();
+
+        let migrate = |body: Value| {
+            app.client.post(app.url("/admin/migrations")).header("x-admin-key", "admin").json(&body).send()
+        };
+        let same = json!({ "from": { "backend": "telegram" }, "to": { "backend": "telegram", "chat_id": -1001 } });
+        assert_eq!(migrate(same).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        let body = json!({ "from": { "backend": "telegram" }, "to": { "backend": "telegram", "chat_id": -1002 }, "delete_source": true });
+        let response = migrate(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = response.json::<Value>().await.unwrap()["job_id"].as_str().unwrap().to_string();
+
+        let mut report = Value::Null;
+        for _ in 0..100 {
+            report = app
+                .client
+                .get(app.url(&format!("/admin/migrations/{}", job_id)))
+                .header("x-admin-key", "admin")
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if report["status"] == "completed" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!((report["images_found"].as_u64(), report["images_migrated"].as_u64()), (Some(1), Some(1)), "{}", report);
+
+        let entry = app.state.index.get(&id).unwrap().unwrap();
+        assert_eq!(entry.reference.chat_id, Some(-1002));
+        assert_eq!(entry.previous_file_ids.len(), 1);
+        assert_eq!(app.telegram.stored_files(), 1);
+
+        // The ID handed out before the move still serves the image, now from the new chat
+        app.state.cache.remove(&entry.previous_file_ids[0]);
+        let response = app.get(&format!("/image/{}", id)).await;
+        assert_eq!(response.bytes().await.unwrap().as_ref(), png.as_slice());
+    }
+}
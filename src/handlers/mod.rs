@@ -4,3 +4,28 @@ pub mod upload;
 pub mod admin;
 pub mod url_upload;
 pub mod job;
+pub mod import;
+pub mod base64_upload;
+pub mod delete;
+pub mod imgur;
+pub mod auth;
+pub mod dashboard;
+pub mod home;
+pub mod errors;
+pub mod search;
+pub mod similar;
+pub mod metrics;
+pub mod erasure;
+pub mod migration;
+pub mod me;
+pub mod pagination;
+pub mod tags;
+pub mod albums;
+pub mod gallery;
+pub mod oembed;
+pub mod html;
+pub mod viewer;
+pub mod qr;
+pub mod preview;
+pub mod pubkey;
+pub mod webdav;
@@ -0,0 +1,132 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::Visibility,
+    services::cdn,
+    AppState,
+};
+
+const PROVIDER_NAME: &str = "RustGram";
+
+#[derive(Debug, Deserialize)]
+pub struct OEmbedQuery {
+    pub url: String,
+    pub maxwidth: Option<u32>,
+    pub maxheight: Option<u32>,
+    pub format: Option<String>,
+}
+
+/// oEmbed 1.0 response; images are "photo", anything without known dimensions is a "link"
+#[derive(Debug, Serialize)]
+pub struct OEmbedResponse {
+    pub version: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub provider_name: &'static str,
+    pub provider_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// oEmbed for a hosted image URL, so consumers can embed it at the right size
+pub async fn oembed(State(state): State<Arc<AppState>>, Query(query): Query<OEmbedQuery>) -> Result<Response> {
+    // The spec asks for 501 on formats the provider doesn't offer
+    if query.format.as_deref().is_some_and(|format| format != "json") {
+        return Ok(StatusCode::NOT_IMPLEMENTED.into_response());
+    }
+
+    let (tenant, id) = parse_image_url(&state, &query.url).ok_or(AppError::NotFound)?;
+    let crypto = CryptoService::new(&state.config.get_encryption_key_bytes()?);
+    let file_ref = state.index.resolve(&crypto, &id)?;
+    if file_ref.tenant != tenant || file_ref.visibility == Visibility::Private {
+        return Err(AppError::NotFound);
+    }
+    let entry = state.index.get(&id)?;
+    if entry.as_ref().is_some_and(|entry| entry.broken) {
+        return Err(AppError::Gone);
+    }
+
+    let prefix = tenant.map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    let title = entry.and_then(|entry| entry.filename);
+    let photo = match (file_ref.width, file_ref.height) {
+        (Some(width), Some(height)) if file_ref.mime_type.starts_with("image/") => {
+            Some(fit_within(width, height, query.maxwidth, query.maxheight))
+        }
+        _ => None,
+    };
+
+    let response = OEmbedResponse {
+        version: "1.0",
+        kind: if photo.is_some() { "photo" } else { "link" },
+        provider_name: PROVIDER_NAME,
+        provider_url: state.config.public_base_url.clone(),
+        title,
+        url: photo.map(|_| cdn::public_url(&state.config, &format!("{}/image/{}", prefix, id))),
+        width: photo.map(|(width, _)| width),
+        height: photo.map(|(_, height)| height),
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Tenant and image ID of an /image/:id or /view/:id URL on this deployment or its CDN
+fn parse_image_url(state: &AppState, url: &str) -> Option<(Option<String>, String)> {
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    let path = [Some(state.config.public_base_url.as_str()), state.config.cdn_base_url.as_deref()]
+        .into_iter()
+        .flatten()
+        .find_map(|base| url.strip_prefix(base))
+        .filter(|path| path.starts_with('/'))?;
+
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["image" | "view", id] => Some((None, id.to_string())),
+        ["t", tenant, "image" | "view", id] => Some((Some(tenant.to_string()), id.to_string())),
+        _ => None,
+    }
+}
+
+/// Scale `width`x`height` down to fit the consumer's bounds, keeping the aspect ratio
+fn fit_within(width: u32, height: u32, max_width: Option<u32>, max_height: Option<u32>) -> (u32, u32) {
+    let scale = [
+        max_width.map(|max| max as f64 / width.max(1) as f64),
+        max_height.map(|max| max as f64 / height.max(1) as f64),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0_f64, f64::min);
+
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_within() {
+        assert_eq!(fit_within(1600, 900, None, None), (1600, 900));
+        assert_eq!(fit_within(1600, 900, Some(800), None), (800, 450));
+        assert_eq!(fit_within(1600, 900, Some(800), Some(300)), (533, 300));
+        // Never scaled up
+        assert_eq!(fit_within(100, 50, Some(800), Some(600)), (100, 50));
+    }
+}
@@ -0,0 +1,116 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, Result};
+
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+pub const MAX_PAGE_LIMIT: usize = 500;
+
+/// `?limit=&cursor=` of a listing endpoint; the cursor is the `next_cursor` of the previous page
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+impl PageQuery {
+    /// Items per page, within the allowed range
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    /// Sort key of the last item of the previous page
+    pub fn after(&self) -> Result<Option<SortKey>> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+}
+
+/// One page of a listing; `next_cursor` is absent on the last page
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Sort position of a listed item, newest first: creation time, then ID to break ties
+pub type SortKey = (u64, String);
+
+/// Cut the page `query` asks for out of `items`, which must be ordered by descending `key`.
+/// Cursors name the last key handed out, so pages stay stable while items are added or removed.
+pub fn paginate<T>(
+    items: impl IntoIterator<Item = T>,
+    query: &PageQuery,
+    key: impl Fn(&T) -> SortKey,
+) -> Result<Page<T>> {
+    let (limit, after) = (query.limit(), query.after()?);
+
+    let mut items = items
+        .into_iter()
+        .filter(|item| after.as_ref().is_none_or(|after| key(item) < *after))
+        .take(limit + 1)
+        .collect::<Vec<_>>();
+
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(|item| encode_cursor(&key(item)))
+    } else {
+        None
+    };
+
+    Ok(Page { items, next_cursor })
+}
+
+fn encode_cursor((created_at, id): &SortKey) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(format!("{}:{}", created_at, id))
+}
+
+fn decode_cursor(cursor: &str) -> Result<SortKey> {
+    let invalid = || AppError::ValidationError("Invalid cursor".to_string());
+    let decoded = general_purpose::URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (created_at, id) = decoded.split_once(':').ok_or_else(invalid)?;
+    Ok((created_at.parse().map_err(|_| invalid())?, id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_walks_all_items_once() {
+        // Newest first, with a tie on created_at broken by ID
+        let items: Vec<SortKey> = vec![
+            (30, "c".into()),
+            (20, "b".into()),
+            (20, "a".into()),
+            (10, "z".into()),
+            (5, "y".into()),
+        ];
+        let key = |item: &SortKey| item.clone();
+
+        let mut query = PageQuery { limit: Some(2), cursor: None };
+        let mut seen = Vec::new();
+        loop {
+            let page = paginate(items.clone(), &query, key).unwrap();
+            seen.extend(page.items);
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, items);
+
+        let bad = PageQuery { limit: None, cursor: Some("not a cursor".into()) };
+        assert!(paginate(items, &bad, key).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::{
+        admin::AdminAuth,
+        auth::{ApiKey, OwnerToken},
+        image::{load_image_data, sign_link},
+        me::owner,
+    },
+    models::unix_timestamp,
+    services::audit::{AuditAction, AuditEvent},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct PreviewResponse {
+    pub url: String,
+    pub expires_at: u64,
+}
+
+/// Mint a single-use preview link for an image; allowed for its owner and for admins
+pub async fn create_preview(
+    State(state): State<Arc<AppState>>,
+    admin: Option<AdminAuth>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<PreviewResponse>)> {
+    let crypto = CryptoService::new(&state.config.get_encryption_key_bytes()?);
+    state.index.resolve(&crypto, &id)?;
+    ensure_owner(&state, admin, &api_key, &owner_token, &id)?;
+
+    let (token, expires_at) = state.previews.mint(&id, state.config.preview_ttl_secs);
+    let url = format!("{}/preview/{}", state.config.public_base_url, token);
+    Ok((StatusCode::CREATED, Json(PreviewResponse { url, expires_at })))
+}
+
+/// Mint a reusable link to a private image, valid for SIGNED_LINK_TTL_SECS; allowed for its owner
+/// and for admins
+pub async fn create_signed_link(
+    State(state): State<Arc<AppState>>,
+    admin: Option<AdminAuth>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<PreviewResponse>)> {
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let file_ref = state.index.resolve(&CryptoService::new(&encryption_key), &id)?;
+    ensure_owner(&state, admin, &api_key, &owner_token, &id)?;
+
+    let expires_at = unix_timestamp() + state.config.signed_link_ttl_secs;
+    let prefix = file_ref.tenant.map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    let url = format!(
+        "{}{}/image/{}?exp={}&sig={}",
+        state.config.public_base_url,
+        prefix,
+        id,
+        expires_at,
+        sign_link(&encryption_key, &id, expires_at)
+    );
+    Ok((StatusCode::CREATED, Json(PreviewResponse { url, expires_at })))
+}
+
+/// Unindexed images have no known owner, so only admins get past this for them
+fn ensure_owner(
+    state: &AppState,
+    admin: Option<AdminAuth>,
+    api_key: &ApiKey,
+    owner_token: &OwnerToken,
+    id: &str,
+) -> Result<()> {
+    if admin.is_some() {
+        return Ok(());
+    }
+    let owner = owner(api_key, owner_token)?;
+    match state.index.get(id)? {
+        Some(entry) if entry.uploader.contains(&owner) => Ok(()),
+        _ => Err(AppError::NotFound),
+    }
+}
+
+/// Serve the image a preview token grants, once; the token is spent even if serving fails
+pub async fn view_preview(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(token): Path<String>,
+) -> Result<Response> {
+    let id = state.previews.redeem(&token).ok_or(AppError::NotFound)?;
+
+    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let file_ref = state.index.resolve(&CryptoService::new(&encryption_key), &id)?;
+    let _slot = state.download_limiter.acquire(addr.ip())?;
+    let image_data = load_image_data(&state, &encryption_key, &file_ref).await?;
+
+    state.audit.record(AuditEvent::new(AuditAction::View).id(&id).ip(addr.ip()).detail("preview"));
+
+    let headers = [
+        (header::CONTENT_TYPE, file_ref.mime_type),
+        // Nothing between here and the viewer may keep a copy to hand out again
+        (header::CACHE_CONTROL, "private, no-store".to_string()),
+        (header::REFERRER_POLICY, "no-referrer".to_string()),
+    ];
+    Ok((StatusCode::OK, headers, image_data).into_response())
+}
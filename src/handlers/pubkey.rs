@@ -0,0 +1,26 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct PublicKey {
+    pub algorithm: &'static str,
+    pub public_key: String,
+    // What the X-Signature header signs
+    pub signed: &'static str,
+}
+
+/// The key /image signatures verify against; 404 unless RESPONSE_SIGNATURES is on
+pub async fn public_key(State(state): State<Arc<AppState>>) -> Result<Json<PublicKey>> {
+    let signer = state.signer.as_ref().ok_or(AppError::NotFound)?;
+    Ok(Json(PublicKey {
+        algorithm: "ed25519",
+        public_key: signer.public_key(),
+        signed: "sha256(body)",
+    }))
+}
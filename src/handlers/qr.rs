@@ -0,0 +1,103 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::{GrayImage, ImageFormat, Luma};
+use qrcode::{render::svg, Color, QrCode};
+use serde::Deserialize;
+use std::{io::Cursor, sync::Arc};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::job::share_url,
+    AppState,
+};
+
+// Pixels per QR module, and the bounds a client may ask for
+const DEFAULT_SCALE: u32 = 8;
+const MAX_SCALE: u32 = 32;
+// Light border around the code, in modules, as the QR spec recommends
+const QUIET_ZONE: u32 = 4;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct QrQuery {
+    // "png" (default) or "svg"
+    pub format: Option<String>,
+    pub scale: Option<u32>,
+}
+
+/// A QR code of the image's share link, the same URL an upload response hands out
+pub async fn get_qr_code(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<QrQuery>,
+) -> Result<Response> {
+    let crypto = CryptoService::new(&state.config.get_encryption_key_bytes()?);
+    let file_ref = state.index.resolve(&crypto, &id)?;
+    if state.index.get(&id)?.is_some_and(|entry| entry.broken) {
+        return Err(AppError::Gone);
+    }
+
+    let url = share_url(&state.config, file_ref.tenant.as_deref(), &id);
+    let code = QrCode::new(url.as_bytes())
+        .map_err(|e| AppError::InternalError(format!("Failed to encode QR code: {}", e)))?;
+    let scale = query.scale.unwrap_or(DEFAULT_SCALE).clamp(1, MAX_SCALE);
+
+    let (body, content_type) = match query.format.as_deref() {
+        None | Some("png") => (render_png(&code, scale)?, "image/png"),
+        Some("svg") => {
+            let svg = code
+                .render::<svg::Color>()
+                .module_dimensions(scale, scale)
+                .quiet_zone(true)
+                .build();
+            (svg.into_bytes(), "image/svg+xml")
+        }
+        Some(_) => return Err(AppError::ValidationError("format must be png or svg".to_string())),
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, content_type),
+        (header::CACHE_CONTROL, "public, max-age=3600"),
+    ];
+    Ok((StatusCode::OK, headers, body).into_response())
+}
+
+fn render_png(code: &QrCode, scale: u32) -> Result<Vec<u8>> {
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (modules + 2 * QUIET_ZONE) * scale;
+
+    let image = GrayImage::from_fn(size, size, |x, y| {
+        let (mx, my) = (x / scale, y / scale);
+        let inside = (QUIET_ZONE..QUIET_ZONE + modules).contains(&mx) && (QUIET_ZONE..QUIET_ZONE + modules).contains(&my);
+        let dark = inside && colors[((my - QUIET_ZONE) * modules + (mx - QUIET_ZONE)) as usize] == Color::Dark;
+        Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AppError::InternalError(format!("Failed to encode QR code: {}", e)))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_png() {
+        let code = QrCode::new(b"https://img.example/image/abc").unwrap();
+        let png = render_png(&code, 2).unwrap();
+
+        let image = image::load_from_memory(&png).unwrap().to_luma8();
+        let side = (code.width() as u32 + 2 * QUIET_ZONE) * 2;
+        assert_eq!(image.dimensions(), (side, side));
+        // Quiet zone is light, the finder pattern's corner is dark
+        assert_eq!(image.get_pixel(0, 0).0, [255]);
+        assert_eq!(image.get_pixel(QUIET_ZONE * 2, QUIET_ZONE * 2).0, [0]);
+    }
+}
@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::admin::AdminAuth,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub id: String,
+    pub tenant: Option<String>,
+    pub mime_type: String,
+    pub created_at: u64,
+    pub text: String,
+}
+
+/// Find images by the text OCR recognized in them, e.g. screenshots mentioning an error message
+pub async fn search_text(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>> {
+    if state.config.ocr_service_url.is_none() {
+        return Err(AppError::ConfigError("Text search requires OCR_SERVICE_URL".to_string()));
+    }
+    if query.q.trim().is_empty() {
+        return Err(AppError::ValidationError("q must not be empty".to_string()));
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let results = state
+        .index
+        .search_text(&query.q)?
+        .into_iter()
+        .take(limit)
+        .map(|entry| SearchResult {
+            id: entry.id,
+            tenant: entry.reference.tenant,
+            mime_type: entry.reference.mime_type,
+            created_at: entry.created_at,
+            text: entry.text.unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Json(results))
+}
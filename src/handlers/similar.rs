@@ -0,0 +1,113 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{admin::AdminAuth, upload::header_dimensions},
+    services::{index::IndexEntry, similarity},
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SimilarQuery {
+    // Minimum similarity from 0.0 to 1.0
+    pub threshold: Option<f32>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarImage {
+    pub id: String,
+    pub similarity: f32,
+    pub tenant: Option<String>,
+    pub size: usize,
+    pub mime_type: String,
+    pub created_at: u64,
+}
+
+/// Stored images that look like the given one, for moderation and cleanup of re-encoded copies
+pub async fn get_similar(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<SimilarQuery>,
+) -> Result<Json<Vec<SimilarImage>>> {
+    let entry = state.index.get(&id)?.ok_or(AppError::NotFound)?;
+    let hash = entry
+        .perceptual_hash
+        .as_deref()
+        .and_then(similarity::decode)
+        .ok_or_else(|| AppError::ValidationError("Image has no perceptual hash".to_string()))?;
+
+    similar_images(&state, hash, &query, Some(&id)).map(Json)
+}
+
+/// Stored images that look like an image sent as the raw request body, so moderators can
+/// check whether reported content is already hosted under other IDs
+pub async fn search_similar(
+    _admin: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SimilarQuery>,
+    body: Bytes,
+) -> Result<Json<Vec<SimilarImage>>> {
+    if body.is_empty() {
+        return Err(AppError::ValidationError("No image found".into()));
+    }
+    if body.len() > state.config.max_file_size {
+        return Err(AppError::FileTooLarge { max_size: state.config.max_file_size });
+    }
+    if let Some((width, height)) = header_dimensions(&body) {
+        state.config.check_dimensions(width, height)?;
+    }
+
+    let hash = state
+        .cpu
+        .run(move || {
+            let sample = image::load_from_memory(&body)
+                .map_err(|e| AppError::InvalidFileFormat(format!("Invalid image data: {}", e)))?;
+            Ok(similarity::dhash(&sample))
+        })
+        .await?;
+    similar_images(&state, hash, &query, None).map(Json)
+}
+
+/// Index matches for `hash` at the query's threshold, leaving out `exclude`
+pub(crate) fn similar_images(
+    state: &AppState,
+    hash: u64,
+    query: &SimilarQuery,
+    exclude: Option<&str>,
+) -> Result<Vec<SimilarImage>> {
+    let threshold = query.threshold.unwrap_or(similarity::DEFAULT_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(AppError::ValidationError("threshold must be between 0 and 1".to_string()));
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    Ok(state
+        .index
+        .find_similar(hash, threshold)?
+        .into_iter()
+        .filter(|(entry, _)| Some(entry.id.as_str()) != exclude)
+        .take(limit)
+        .map(|(entry, score)| SimilarImage::new(entry, score))
+        .collect())
+}
+
+impl SimilarImage {
+    fn new(entry: IndexEntry, similarity: f32) -> Self {
+        Self {
+            id: entry.id,
+            similarity,
+            tenant: entry.reference.tenant,
+            size: entry.reference.size,
+            mime_type: entry.reference.mime_type,
+            created_at: entry.created_at,
+        }
+    }
+}
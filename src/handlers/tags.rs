@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    handlers::{
+        admin::AdminAuth,
+        auth::{ApiKey, OwnerToken},
+        me::owner,
+    },
+    services::index::normalize_tags,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TagsUpdate {
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagsResponse {
+    pub id: String,
+    pub tags: Vec<String>,
+}
+
+/// Replace an image's tags; allowed for its owner and for admins
+pub async fn set_image_tags(
+    State(state): State<Arc<AppState>>,
+    admin: Option<AdminAuth>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    Path(id): Path<String>,
+    Json(update): Json<TagsUpdate>,
+) -> Result<Json<TagsResponse>> {
+    let entry = state.index.get(&id)?.ok_or(AppError::NotFound)?;
+    if admin.is_none() && !entry.uploader.contains(&owner(&api_key, &owner_token)?) {
+        return Err(AppError::NotFound);
+    }
+
+    let tags = normalize_tags(&update.tags)?;
+    let entry = state.index.set_tags(&id, tags)?.ok_or(AppError::NotFound)?;
+    Ok(Json(TagsResponse { id: entry.id, tags: entry.tags }))
+}
@@ -1,106 +1,592 @@
 use axum::{
-    extract::{Multipart, State, ConnectInfo},
-    http::StatusCode,
-    response::Json,
+    body::{Body, Bytes},
+    extract::{multipart::Field, Multipart, Query, State, ConnectInfo},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::{
+    config::Config,
     crypto::CryptoService,
-    error::{AppError, Result},
-    models::QueuedResponse,
-    worker::UploadJob,
+    error::{AppError, FieldErrors, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken, UploadAuth},
+        job::{build_upload_response, wait_for_job},
+    },
+    middleware::upload_progress::UploadId,
+    models::{unix_timestamp, QueuedResponse, ShareXResponse, Visibility},
+    services::{checksum::{Crc32c, ExpectedChecksums}, cpu_pool::CpuPool, index::parse_tag_list, palette, optimize, similarity, spill::{Payload, SpooledBody}, tenants::Tenant},
+    worker::{reject_duplicate, ImageMetadata, JobPayload, PreparedUpload, UploadJob, UploadOptions},
     AppState,
 };
 
+// How long ShareX-style uploads wait for the worker before giving up
+pub(crate) const SYNC_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    // "sharex" waits for the upload to finish and returns ShareX's expected fields
+    pub format: Option<String>,
+    // Seconds until the image is removed by the cleanup worker
+    pub expires_in: Option<u64>,
+    // Comma-separated tags stored with the image
+    pub tags: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
 pub async fn upload_image(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    upload_id: UploadId,
+    Query(params): Query<UploadParams>,
     mut multipart: Multipart,
-) -> Result<(StatusCode, Json<QueuedResponse>)> {
-    let mut image_data: Option<Vec<u8>> = None;
+) -> Result<Response> {
+    let tags = parse_tag_list(params.tags.as_deref())?;
+    let mut image_data: Option<HashedData> = None;
     let mut filename: Option<String> = None;
     let mut mime_type: Option<String> = None;
+    // Clients streaming the form can only send the image's checksum in a field after it
+    let mut checksums = ExpectedChecksums::default();
 
     // Process multipart form data
     while let Some(field) = multipart.next_field().await? {
-        if let Some(name) = field.name() {
-            if name == "image" || name == "file" {
-                mime_type = field.content_type().map(|s| s.to_string());
-                filename = field.file_name().map(|s| s.to_string());
-                image_data = Some(field.bytes().await?.to_vec());
-                break; // Found the image, no need to process further
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "image" || name == "file" {
+            // The checksum fields could only ever describe one of them
+            if image_data.is_some() {
+                return Err(AppError::ValidationError("Only one image can be uploaded per request".into()));
             }
+            mime_type = field.content_type().map(|s| s.to_string());
+            filename = field.file_name().map(|s| s.to_string());
+            let buffer = HashingBuffer::new(state.config.max_file_size).with_spill(&state.config);
+            image_data = Some(read_field_limited(field, buffer).await?);
+        } else if ExpectedChecksums::is_field(&name) {
+            checksums.read_field(&name, &field.text().await?)?;
         }
     }
-
-    let image_data = image_data.ok_or_else(|| AppError::ValidationError("No image found".into()))?;
-    let original_size = image_data.len();
-
-    // --- All validations from here ---
-    if original_size > state.config.max_file_size {
-        return Err(AppError::FileTooLarge { max_size: state.config.max_file_size });
+    if let Some(data) = &image_data {
+        checksums.verify(&data.sha256, data.crc32c)?;
     }
 
     let final_mime_type = mime_type.unwrap_or_else(|| {
         mime_guess::from_path(filename.as_deref().unwrap_or("")).first_or_octet_stream().to_string()
     });
 
-    if !state.config.allowed_image_types.contains(&final_mime_type) {
+    // Report every problem with the form at once; decoding only runs on a plausible image
+    let mut errors = FieldErrors::default();
+    match &image_data {
+        Some(data) => check_image_field(&state.config, &mut errors, "image", &data.bytes, &final_mime_type),
+        None => errors.add("image", "is required (as an \"image\" or \"file\" field)"),
+    }
+    check_upload_fields(&mut errors, filename.as_deref(), params.expires_in);
+    errors.into_result()?;
+
+    let Some(image_data) = image_data else {
+        return Err(AppError::ValidationError("No image found".into()));
+    };
+    validate_image(&state.cpu, &state.config, image_data.bytes.clone(), &final_mime_type).await?;
+
+    let mut options = upload_options(&state, params.expires_in, api_key, owner_token)?;
+    options.job_id = upload_id.0;
+    options.tags = tags;
+    options.visibility = params.visibility;
+    let prepared = prepare_upload(
+        &state.cpu,
+        &state.config,
+        options.tenant.as_ref(),
+        image_data.bytes,
+        Some(image_data.sha256),
+        filename.as_deref().unwrap_or("image.bin"),
+        final_mime_type,
+    )
+    .await?;
+
+    let response = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+    respond_to_upload(&state, response, params.format.as_deref()).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawUploadParams {
+    pub filename: Option<String>,
+    pub format: Option<String>,
+    pub expires_in: Option<u64>,
+    pub tags: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Accept the image bytes as the whole request body, typed by the Content-Type header
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_raw(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    upload_id: UploadId,
+    Query(params): Query<RawUploadParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response> {
+    let buffer = HashingBuffer::new(state.config.max_file_size).with_spill(&state.config);
+    let body = read_body_limited(body, &headers, buffer).await?;
+    if body.bytes.is_empty() {
+        return Err(AppError::ValidationError("No image found".into()));
+    }
+    let tags = parse_tag_list(params.tags.as_deref())?;
+
+    // Ignore parameters such as "; charset=binary" some clients append
+    let header_mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty() && v != "application/octet-stream");
+
+    let final_mime_type = header_mime_type.unwrap_or_else(|| {
+        mime_guess::from_path(params.filename.as_deref().unwrap_or("")).first_or_octet_stream().to_string()
+    });
+
+    validate_image(&state.cpu, &state.config, body.bytes.clone(), &final_mime_type).await?;
+
+    let mut options = upload_options(&state, params.expires_in, api_key, owner_token)?;
+    options.job_id = upload_id.0;
+    options.tags = tags;
+    options.visibility = params.visibility;
+    let prepared = prepare_upload(
+        &state.cpu,
+        &state.config,
+        options.tenant.as_ref(),
+        body.bytes,
+        Some(body.sha256),
+        params.filename.as_deref().unwrap_or("image.bin"),
+        final_mime_type,
+    )
+    .await?;
+
+    let response = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+    respond_to_upload(&state, response, params.format.as_deref()).await
+}
+
+/// Reply with the queued job, or wait for the final URLs when the client needs them up front
+async fn respond_to_upload(
+    state: &AppState,
+    queued: QueuedResponse,
+    format: Option<&str>,
+) -> Result<Response> {
+    match format {
+        None => Ok((StatusCode::ACCEPTED, Json(queued)).into_response()),
+        Some("sharex") => {
+            let job = wait_for_job(state, &queued.job_id, SYNC_UPLOAD_TIMEOUT).await?;
+            let upload = build_upload_response(&state.config, &job.file_ref, job.existing)?;
+
+            let response = ShareXResponse {
+                url: upload.url,
+                thumbnail_url: upload.thumbnail_url,
+                deletion_url: upload.delete_url.unwrap_or_default(),
+            };
+            Ok((StatusCode::OK, Json(response)).into_response())
+        }
+        Some(other) => Err(AppError::ValidationError(format!("Unknown response format: {}", other))),
+    }
+}
+
+/// Bytes received chunk by chunk, with their SHA-256 and CRC32C computed on the way in
+pub(crate) struct HashedData {
+    pub bytes: Bytes,
+    pub sha256: [u8; 32],
+    pub crc32c: u32,
+}
+
+/// Collects chunks up to `max_size`, hashing each as it arrives so large uploads are not
+/// walked a second time just for the content hash
+pub(crate) struct HashingBuffer {
+    bytes: SpooledBody,
+    hasher: Sha256,
+    crc32c: Crc32c,
+    max_size: usize,
+}
+
+impl HashingBuffer {
+    pub fn new(max_size: usize) -> Self {
+        Self { bytes: SpooledBody::new(0, PathBuf::new()), hasher: Sha256::new(), crc32c: Crc32c::new(), max_size }
+    }
+
+    /// Spool what arrives past SPILL_THRESHOLD_BYTES to an encrypted temp file in SPILL_DIR
+    pub fn with_spill(mut self, config: &Config) -> Self {
+        self.bytes = SpooledBody::new(config.spill_threshold_bytes, config.spill_dir());
+        self
+    }
+
+    pub async fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        if self.bytes.len() + chunk.len() > self.max_size {
+            return Err(AppError::FileTooLarge { max_size: self.max_size });
+        }
+        self.hasher.update(chunk);
+        self.crc32c.update(chunk);
+        self.bytes.push(chunk).await
+    }
+
+    pub async fn finish(self) -> Result<HashedData> {
+        Ok(HashedData {
+            bytes: self.bytes.into_bytes().await?,
+            sha256: self.hasher.finalize().into(),
+            crc32c: self.crc32c.finish(),
+        })
+    }
+}
+
+/// Read a multipart field chunk by chunk into `buffer`, giving up as soon as it grows past its
+/// limit so an oversized file is never buffered whole
+pub(crate) async fn read_field_limited(mut field: Field<'_>, mut buffer: HashingBuffer) -> Result<HashedData> {
+    while let Some(chunk) = field.chunk().await? {
+        if let Err(e) = buffer.push(&chunk).await {
+            tracing::warn!("Aborted multipart field over {} bytes", buffer.max_size);
+            return Err(e);
+        }
+    }
+    buffer.finish().await
+}
+
+/// Read a whole request body the same way, then check it against the checksums in `headers` or,
+/// for chunked bodies whose checksum isn't known up front, in the trailers
+pub(crate) async fn read_body_limited(mut body: Body, headers: &HeaderMap, mut buffer: HashingBuffer) -> Result<HashedData> {
+    let mut checksums = ExpectedChecksums::default();
+    checksums.read_headers(headers)?;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| AppError::ValidationError(format!("Failed to read request body: {}", e)))?;
+        match frame.into_data() {
+            Ok(chunk) => buffer.push(&chunk).await?,
+            Err(frame) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    checksums.read_headers(trailers)?;
+                }
+            }
+        }
+    }
+    let data = buffer.finish().await?;
+    checksums.verify(&data.sha256, data.crc32c)?;
+    Ok(data)
+}
+
+/// Check the size and type of an image sent in `field`, recording problems instead of failing,
+/// so forms can report every invalid field at once
+pub(crate) fn check_image_field(
+    config: &Config,
+    errors: &mut FieldErrors,
+    field: &str,
+    image_data: &[u8],
+    mime_type: &str,
+) {
+    if image_data.is_empty() {
+        errors.add(field, "is empty");
+    }
+    if image_data.len() > config.max_file_size {
+        errors.add(field, format!("is larger than the maximum of {} bytes", config.max_file_size));
+    }
+    if !config.allowed_image_types.iter().any(|t| t == mime_type) {
+        errors.add(field, format!("has unsupported type {}; allowed: {}", mime_type, config.allowed_image_types.join(", ")));
+    }
+}
+
+/// Check the options shared by upload forms
+pub(crate) fn check_upload_fields(errors: &mut FieldErrors, filename: Option<&str>, expires_in: Option<u64>) {
+    if filename.is_some_and(|name| name.len() > 255) {
+        errors.add("filename", "must be at most 255 bytes");
+    }
+    if expires_in == Some(0) {
+        errors.add("expires_in", "must be a positive number of seconds");
+    }
+}
+
+/// MIME type of the image format the bytes are in, whatever the client claimed
+pub(crate) fn sniff_mime_type(data: &[u8]) -> String {
+    image::guess_format(data)
+        .map(|format| format.to_mime_type().to_string())
+        .unwrap_or_else(|_| "application/octet-stream".to_string())
+}
+
+/// Run the size, type and decodability checks shared by every upload path, on the CPU pool since
+/// they decode the whole image
+pub(crate) async fn validate_image(cpu: &CpuPool, config: &Arc<Config>, image_data: Bytes, mime_type: &str) -> Result<()> {
+    let (config, mime_type) = (config.clone(), mime_type.to_string());
+    cpu.run(move || check_image(&config, &image_data, &mime_type)).await
+}
+
+fn check_image(config: &Config, image_data: &[u8], mime_type: &str) -> Result<()> {
+    if image_data.len() > config.max_file_size {
+        return Err(AppError::FileTooLarge { max_size: config.max_file_size });
+    }
+
+    if !config.allowed_image_types.iter().any(|t| t == mime_type) {
         return Err(AppError::InvalidFileFormat(format!(
             "Unsupported type: {}. Allowed: {:?}",
-            final_mime_type, state.config.allowed_image_types
+            mime_type, config.allowed_image_types
         )));
     }
 
-    if let Err(e) = image::load_from_memory(&image_data) {
-        return Err(AppError::InvalidFileFormat(format!("Invalid image data: {}", e)));
+    // Reject oversized canvases from the header before the decoder allocates them
+    if let Some((width, height)) = header_dimensions(image_data) {
+        config.check_dimensions(width, height)?;
     }
-    // --- End of validations ---
 
-    // Encrypt image data
-    let encryption_key = state.config.get_encryption_key_bytes()?;
+    let decoded = image::load_from_memory(image_data)
+        .map_err(|e| AppError::InvalidFileFormat(format!("Invalid image data: {}", e)))?;
+    config.check_dimensions(decoded.width(), decoded.height())
+}
+
+/// Width and height read from the image header, without decoding pixel data
+pub(crate) fn header_dimensions(image_data: &[u8]) -> Option<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(image_data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
+
+/// Options shared by every upload path, including the tenant owning the API key.
+/// Uploads without an API key are owned by the client's owner token, issuing one if needed.
+pub(crate) fn upload_options(
+    state: &AppState,
+    expires_in: Option<u64>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+) -> Result<UploadOptions> {
+    let (owner_token, owner_token_issued) = match (&api_key.0, owner_token.0) {
+        (Some(_), _) => (None, false),
+        (None, Some(token)) => (Some(token), false),
+        (None, None) => (Some(Uuid::new_v4().simple().to_string()), true),
+    };
+
+    Ok(UploadOptions {
+        expires_at: expires_in.map(|secs| unix_timestamp() + secs),
+        tenant: state.tenants.resolve_key(api_key.0.as_deref())?,
+        api_key: api_key.0,
+        job_id: None,
+        owner_token,
+        owner_token_issued,
+        tags: Vec::new(),
+        visibility: Visibility::Public,
+    })
+}
+
+/// Encrypt validated image data and give it a unique filename for Telegram, on the CPU pool.
+/// `sha256` is the content hash when it was already computed while receiving the data.
+pub(crate) async fn prepare_upload(
+    cpu: &CpuPool,
+    config: &Arc<Config>,
+    tenant: Option<&Tenant>,
+    image_data: Bytes,
+    sha256: Option<[u8; 32]>,
+    filename: &str,
+    mime_type: String,
+) -> Result<PreparedUpload> {
+    let (config, tenant, filename) = (config.clone(), tenant.cloned(), filename.to_string());
+    cpu.run(move || encrypt_and_describe(&config, tenant.as_ref(), &image_data, sha256, &filename, mime_type))
+        .await
+}
+
+fn encrypt_and_describe(
+    config: &Config,
+    tenant: Option<&Tenant>,
+    image_data: &[u8],
+    sha256: Option<[u8; 32]>,
+    filename: &str,
+    mime_type: String,
+) -> Result<PreparedUpload> {
+    // Tenant images are encrypted with the tenant's own content key
+    let encryption_key = config.get_encryption_key_bytes()?;
+    let encryption_key = match tenant {
+        Some(tenant) => tenant.content_key(&encryption_key)?,
+        None => encryption_key,
+    };
     let crypto = CryptoService::new(&encryption_key);
-    let encrypted_data = crypto.encrypt_data(&image_data)?;
+    // The content hash stays that of the upload as sent, so duplicates are still caught
+    let content_hash = hex::encode(sha256.unwrap_or_else(|| CryptoService::hash_data(image_data)));
+    let optimized = match optimize::optimize(image_data, &mime_type, &config.optimize_steps, config.optimize_max_dimension) {
+        Ok(optimized) => optimized,
+        Err(e) => {
+            tracing::warn!("Could not optimize {}, storing it as uploaded: {}", filename, e);
+            None
+        }
+    };
+    let image_data = optimized.as_deref().unwrap_or(image_data);
+    let encrypted_data = Payload::new(
+        crypto.encrypt_data(image_data)?,
+        config.spill_threshold_bytes,
+        &config.spill_dir(),
+    )?;
 
-    // Generate a unique job ID
-    let job_id = Uuid::new_v4().to_string();
+    // Decoded again for the hash and palette; dimensions come from the header when that fails
+    let decoded = image::load_from_memory(image_data).ok();
+    let dimensions = match &decoded {
+        Some(decoded) => Some((decoded.width(), decoded.height())),
+        None => header_dimensions(image_data),
+    };
+    let metadata = ImageMetadata {
+        content_hash,
+        perceptual_hash: decoded.as_ref().map(|decoded| similarity::encode(similarity::dhash(decoded))),
+        palette: decoded.as_ref().map(palette::extract_palette).unwrap_or_default(),
+        filename: filename.to_string(),
+        text: None,
+    };
 
-    // Generate unique filename for Telegram
-    let unique_filename = format!(
-        "{}_{}",
-        Uuid::new_v4(),
-        filename.unwrap_or_else(|| "image.bin".to_string())
-    );
+    Ok(PreparedUpload {
+        encrypted_data,
+        unique_filename: format!("{}_{}", Uuid::new_v4(), filename),
+        original_size: image_data.len(),
+        mime_type,
+        dimensions,
+        metadata,
+    })
+}
 
-    // Create an upload job
+/// Hand a job to the upload worker and build the response pointing at its status
+pub(crate) async fn enqueue_job(
+    state: &AppState,
+    payload: JobPayload,
+    mut options: UploadOptions,
+    client_ip: SocketAddr,
+) -> Result<QueuedResponse> {
+    // Tenant limits apply here for prepared data, whose bytes count against the quota while queued;
+    // the worker checks remote URLs after fetching
+    let mut reservation = None;
+    if let JobPayload::Ready(prepared) = &payload {
+        if let Some(tenant) = &options.tenant {
+            reservation = Some(tenant.reserve_upload(&state.index, prepared.original_size, &prepared.mime_type)?);
+        }
+        reject_duplicate(&state.index, &state.config, prepared, options.tenant.as_ref())?;
+    }
+
+    // Use the tracked upload ID so progress and result share one ID, or generate a unique one
+    let job_id = options.job_id.take().unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    match &payload {
+        JobPayload::Ready(prepared) => tracing::info!(
+            "Queued job ID: {} for IP: {}. Size: {}, Type: {}",
+            job_id, client_ip, prepared.original_size, prepared.mime_type
+        ),
+        JobPayload::RemoteUrl(url) => tracing::info!(
+            "Queued job ID: {} for URL: {} and IP: {}",
+            job_id, url, client_ip
+        ),
+    }
+
+    let owner_token = options.owner_token.clone().filter(|_| options.owner_token_issued);
     let job = UploadJob {
         job_id: job_id.clone(),
-        encrypted_data,
-        unique_filename,
-        original_size,
-        mime_type: final_mime_type.clone(),
-        client_ip: addr,
+        payload,
+        options,
+        client_ip,
+        reservation,
     };
 
     // Send the job to the worker queue
+    state.worker.enqueued(&job);
     state.upload_queue.send(job).await.map_err(|e| {
+        state.worker.enqueue_failed(&job_id);
         tracing::error!("Failed to send job to queue: {}", e);
         AppError::InternalError("Failed to queue upload job".to_string())
     })?;
 
-    tracing::info!(
-        "Queued job ID: {} for IP: {}. Size: {}, Type: {}",
-        job_id, addr, original_size, final_mime_type
-    );
-
-    // Respond to the client immediately
-    let response = QueuedResponse {
-        job_id: job_id.clone(),
+    Ok(QueuedResponse {
         status_url: format!("/job/{}", job_id),
-    };
+        job_id,
+        owner_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hashing_buffer() {
+        let mut buffer = HashingBuffer::new(8);
+        buffer.push(b"abcd").await.unwrap();
+        buffer.push(b"efg").await.unwrap();
+        let data = buffer.finish().await.unwrap();
+        assert_eq!(data.bytes, &b"abcdefg"[..]);
+        assert_eq!(data.sha256, CryptoService::hash_data(b"abcdefg"));
+
+        let mut buffer = HashingBuffer::new(4);
+        buffer.push(b"abc").await.unwrap();
+        assert!(matches!(buffer.push(b"de").await, Err(AppError::FileTooLarge { max_size: 4 })));
+    }
+
+    #[tokio::test]
+    async fn test_spooled_upload_roundtrip() {
+        use crate::test_support::{png_bytes, TestApp};
+        use reqwest::multipart::{Form, Part};
+
+        // Every body past a few bytes is received into a temp file
+        let app = TestApp::start(&[("SPILL_THRESHOLD_BYTES", "16")]).await;
+        let image = Part::bytes(png_bytes()).file_name("red.png").mime_str("image/png").unwrap();
+        let response = app.client.post(app.url("/upload")).multipart(Form::new().part("image", image)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        let queued: serde_json::Value = response.json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let id = job["response"]["id"].as_str().unwrap();
+
+        let response = app.get(&format!("/image/{}", id)).await;
+        assert_eq!(response.bytes().await.unwrap().as_ref(), png_bytes().as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_checksum_field() {
+        use crate::test_support::{png_bytes, TestApp};
+        use reqwest::multipart::{Form, Part};
+
+        let app = TestApp::start(&[]).await;
+        let image = || Part::bytes(png_bytes()).file_name("red.png").mime_str("image/png").unwrap();
+        let upload = |form: Form| app.client.post(app.url("/upload")).multipart(form).send();
 
-    Ok((StatusCode::ACCEPTED, Json(response)))
+        // The checksum may follow the image it describes
+        let crc32c = {
+            let mut crc = crate::services::checksum::Crc32c::new();
+            crc.update(&png_bytes());
+            format!("{:08x}", crc.finish())
+        };
+        let response = upload(Form::new().part("image", image()).text("crc32c", crc32c)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+
+        let response = upload(Form::new().part("image", image()).text("crc32c", "00000000")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!((body["code"].as_str(), body["details"]["algorithm"].as_str()), (Some("checksum_mismatch"), Some("crc32c")));
+
+        let response = upload(Form::new().part("image", image()).part("file", image())).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_body_checksum_in_trailers() {
+        use crate::services::checksum::CRC32C_HEADER;
+        use http_body_util::Full;
+
+        // A body whose CRC32C only arrives once it has been sent
+        let chunked = |crc32c: &str| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert(CRC32C_HEADER, crc32c.parse().unwrap());
+            let trailers = async move { Some(Ok::<_, std::convert::Infallible>(trailers)) };
+            Body::new(Full::new(Bytes::from_static(b"123456789")).with_trailers(trailers))
+        };
+
+        let data = read_body_limited(chunked("e3069283"), &HeaderMap::new(), HashingBuffer::new(64)).await.unwrap();
+        assert_eq!((data.bytes.as_ref(), data.crc32c), (&b"123456789"[..], 0xE306_9283));
+        let corrupted = read_body_limited(chunked("e3069284"), &HeaderMap::new(), HashingBuffer::new(64)).await;
+        assert!(matches!(corrupted, Err(AppError::ChecksumMismatch { algorithm: "crc32c" })));
+    }
 }
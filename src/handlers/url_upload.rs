@@ -5,103 +5,161 @@ use axum::{
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use serde::Deserialize;
-use uuid::Uuid;
 
 use crate::{
-    crypto::CryptoService,
+    config::Config,
     error::{AppError, Result},
-    models::QueuedResponse,
-    worker::UploadJob,
+    handlers::{
+        auth::{OwnerToken, UploadAuth},
+        upload::{enqueue_job, prepare_upload, upload_options, validate_image, HashedData, HashingBuffer},
+    },
+    models::{QueuedResponse, Visibility},
+    services::{cpu_pool::CpuPool, index::normalize_tags},
+    worker::JobPayload,
     AppState,
 };
 
+// A remote server gets this long to accept the connection, and to send the whole image, so one that
+// never answers can't hold up a request or the upload worker
+const REMOTE_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REMOTE_FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
 #[derive(Deserialize)]
 pub struct UrlUploadPayload {
     pub url: String,
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
 }
 
 pub async fn upload_from_url(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
     Json(payload): Json<UrlUploadPayload>,
 ) -> Result<(StatusCode, Json<QueuedResponse>)> {
-    // Download image from URL
-    let response = reqwest::get(&payload.url).await.map_err(|e| {
-        AppError::ValidationError(format!("Failed to download image from URL: {}", e))
-    })?;
+    let tags = normalize_tags(&payload.tags)?;
+    let (image_data, mime_type, filename) = fetch_remote_image(&state.remote_client, &payload.url, &state.config, &state.cpu).await?;
+
+    let mut options = upload_options(&state, payload.expires_in, api_key, owner_token)?;
+    options.tags = tags;
+    options.visibility = payload.visibility;
+    let prepared = prepare_upload(
+        &state.cpu,
+        &state.config,
+        options.tenant.as_ref(),
+        image_data.bytes,
+        Some(image_data.sha256),
+        &filename,
+        mime_type,
+    )
+    .await?;
+
+    let response = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+    // Respond to the client immediately
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
 
-    if !response.status().is_success() {
-        return Err(AppError::ValidationError(format!(
-            "Failed to download image: status code {}",
-            response.status()
-        )));
+/// Queue the download itself, so the request returns before the remote server is contacted
+pub async fn upload_from_url_async(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    UploadAuth(api_key): UploadAuth,
+    owner_token: OwnerToken,
+    Json(payload): Json<UrlUploadPayload>,
+) -> Result<(StatusCode, Json<QueuedResponse>)> {
+    if !payload.url.starts_with("http://") && !payload.url.starts_with("https://") {
+        return Err(AppError::ValidationError("URL must use http or https".to_string()));
     }
 
-    let image_data = response.bytes().await.map_err(|e| {
-        AppError::ValidationError(format!("Failed to read image bytes: {}", e))
-    })?.to_vec();
-    let original_size = image_data.len();
+    let mut options = upload_options(&state, payload.expires_in, api_key, owner_token)?;
+    options.tags = normalize_tags(&payload.tags)?;
+    options.visibility = payload.visibility;
+    let response = enqueue_job(&state, JobPayload::RemoteUrl(payload.url), options, addr).await?;
 
-    // --- All validations from here ---
-    if original_size > state.config.max_file_size {
-        return Err(AppError::FileTooLarge { max_size: state.config.max_file_size });
-    }
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
 
-    let mime_type = mime_guess::from_ext(payload.url.split('.').last().unwrap_or(""))
-        .first_or_octet_stream();
-    let final_mime_type = mime_type.to_string();
+/// A failed download of a remote image, and whether trying again could succeed
+pub(crate) struct FetchError {
+    pub error: AppError,
+    pub retryable: bool,
+}
 
-    if !state.config.allowed_image_types.contains(&final_mime_type) {
-        return Err(AppError::InvalidFileFormat(format!(
-            "Unsupported type: {}. Allowed: {:?}",
-            final_mime_type, state.config.allowed_image_types
-        )));
+impl FetchError {
+    fn transient(error: AppError) -> Self {
+        Self { error, retryable: true }
     }
+}
 
-    if let Err(e) = image::load_from_memory(&image_data) {
-        return Err(AppError::InvalidFileFormat(format!("Invalid image data: {}", e)));
+impl From<AppError> for FetchError {
+    fn from(error: AppError) -> Self {
+        Self { error, retryable: false }
     }
-    // --- End of validations ---
-
-    // Encrypt image data
-    let encryption_key = state.config.get_encryption_key_bytes()?;
-    let crypto = CryptoService::new(&encryption_key);
-    let encrypted_data = crypto.encrypt_data(&image_data)?;
-
-    // Generate a unique job ID
-    let job_id = Uuid::new_v4().to_string();
-
-    // Generate unique filename for Telegram
-    let filename = payload.url.split('/').last().unwrap_or("image.bin").to_string();
-    let unique_filename = format!("{}_{}", Uuid::new_v4(), filename);
-
-    // Create an upload job
-    let job = UploadJob {
-        job_id: job_id.clone(),
-        encrypted_data,
-        unique_filename,
-        original_size,
-        mime_type: final_mime_type.clone(),
-        client_ip: addr,
-    };
-
-    // Send the job to the worker queue
-    state.upload_queue.send(job).await.map_err(|e| {
-        tracing::error!("Failed to send job to queue: {}", e);
-        AppError::InternalError("Failed to queue upload job".to_string())
+}
+
+/// Client for downloading remote images, with the connect and total timeouts applied
+pub(crate) fn remote_client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .connect_timeout(REMOTE_CONNECT_TIMEOUT)
+        .timeout(REMOTE_FETCH_TIMEOUT)
+        .build()
+}
+
+/// Download a remote image and validate it, returning the data, MIME type and filename
+pub(crate) async fn fetch_remote_image(
+    client: &reqwest::Client,
+    url: &str,
+    config: &Arc<Config>,
+    cpu: &CpuPool,
+) -> Result<(HashedData, String, String)> {
+    try_fetch_remote_image(client, url, config, cpu).await.map_err(|e| e.error)
+}
+
+/// As [`fetch_remote_image`], telling transport errors and 5xx/429 responses apart from failures
+/// that would recur on every attempt
+pub(crate) async fn try_fetch_remote_image(
+    client: &reqwest::Client,
+    url: &str,
+    config: &Arc<Config>,
+    cpu: &CpuPool,
+) -> std::result::Result<(HashedData, String, String), FetchError> {
+    // Download image from URL; the timeout also holds for clients built without one
+    let mut response = client.get(url).timeout(REMOTE_FETCH_TIMEOUT).send().await.map_err(|e| {
+        FetchError::transient(AppError::ValidationError(format!("Failed to download image from URL: {}", e)))
     })?;
 
-    tracing::info!(
-        "Queued job ID: {} for URL: {} and IP: {}. Size: {}, Type: {}",
-        job_id, payload.url, addr, original_size, final_mime_type
-    );
+    let status = response.status();
+    if !status.is_success() {
+        let error = AppError::ValidationError(format!("Failed to download image: status code {}", status));
+        return Err(if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            FetchError::transient(error)
+        } else {
+            error.into()
+        });
+    }
+
+    let mut buffer = HashingBuffer::new(config.max_file_size);
+    while let Some(chunk) = response.chunk().await.map_err(|e| {
+        FetchError::transient(AppError::ValidationError(format!("Failed to read image bytes: {}", e)))
+    })? {
+        buffer.push(&chunk).await?;
+    }
+    let image_data = buffer.finish().await?;
 
-    // Respond to the client immediately
-    let response = QueuedResponse {
-        job_id: job_id.clone(),
-        status_url: format!("/job/{}", job_id),
-    };
+    let mime_type = mime_guess::from_ext(url.split('.').next_back().unwrap_or(""))
+        .first_or_octet_stream()
+        .to_string();
 
-    Ok((StatusCode::ACCEPTED, Json(response)))
-}
\ No newline at end of file
+    validate_image(cpu, config, image_data.bytes.clone(), &mime_type).await?;
+
+    let filename = url.split('/').next_back().unwrap_or("image.bin").to_string();
+
+    Ok((image_data, mime_type, filename))
+}
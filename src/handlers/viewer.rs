@@ -0,0 +1,166 @@
+use axum::{
+    extract::{Path, State},
+    response::Html,
+};
+use std::sync::Arc;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::{html::escape_html, image::ImagePath},
+    models::{FileReference, Visibility},
+    services::cdn,
+    AppState,
+};
+
+const STYLE: &str = "html,body{margin:0;height:100%;background:#111;color:#ddd;font-family:system-ui,sans-serif}\
+main{min-height:100%;display:flex;flex-direction:column;align-items:center;justify-content:center;gap:.75rem}\
+img,video{max-width:100vw;max-height:90vh}a{color:#8ab4f8}";
+
+/// A page showing one image, with OpenGraph and Twitter Card tags so shared links unfurl into previews
+pub async fn view_image(State(state): State<Arc<AppState>>, Path(path): Path<ImagePath>) -> Result<Html<String>> {
+    let crypto = CryptoService::new(&state.config.get_encryption_key_bytes()?);
+    let file_ref = path.file_reference(&state.index, &crypto)?;
+    // Crawlers fetching the card have no credentials for a private image
+    if file_ref.visibility == Visibility::Private {
+        return Err(AppError::NotFound);
+    }
+    let entry = state.index.get(&path.id)?;
+    if entry.as_ref().is_some_and(|entry| entry.broken) {
+        return Err(AppError::Gone);
+    }
+
+    let prefix = path.tenant.as_ref().map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    let image_url = cdn::public_url(&state.config, &format!("{}/image/{}", prefix, path.id));
+    // With LINK_MODE=viewer a bare /image/:id navigation comes straight back here; any query opts out
+    let original_url = if state.config.link_to_viewer && !image_url.contains('?') {
+        format!("{}?raw=1", image_url)
+    } else {
+        image_url.clone()
+    };
+    let page = ViewerPage {
+        title: entry.and_then(|entry| entry.filename).unwrap_or_else(|| "Image".to_string()),
+        page_url: format!("{}{}/view/{}", state.config.public_base_url, prefix, path.id),
+        image_url,
+        original_url,
+        oembed_url: format!("{}/oembed", state.config.public_base_url),
+    };
+
+    Ok(Html(page.render(&file_ref)))
+}
+
+struct ViewerPage {
+    title: String,
+    page_url: String,
+    image_url: String,
+    original_url: String,
+    oembed_url: String,
+}
+
+impl ViewerPage {
+    fn render(&self, file_ref: &FileReference) -> String {
+        let title = escape_html(&self.title);
+        let image_url = escape_html(&self.image_url);
+        let is_video = file_ref.mime_type.starts_with("video/");
+
+        let mut meta = vec![
+            meta_property("og:type", "website"),
+            meta_property("og:site_name", "RustGram"),
+            meta_property("og:title", &self.title),
+            meta_property("og:url", &self.page_url),
+        ];
+        if is_video {
+            meta.push(meta_property("og:video", &self.image_url));
+            meta.push(meta_property("og:video:type", &file_ref.mime_type));
+            meta.push(meta_name("twitter:card", "summary"));
+        } else {
+            meta.push(meta_property("og:image", &self.image_url));
+            meta.push(meta_property("og:image:type", &file_ref.mime_type));
+            if let (Some(width), Some(height)) = (file_ref.width, file_ref.height) {
+                meta.push(meta_property("og:image:width", &width.to_string()));
+                meta.push(meta_property("og:image:height", &height.to_string()));
+            }
+            meta.push(meta_name("twitter:card", "summary_large_image"));
+            meta.push(meta_name("twitter:image", &self.image_url));
+        }
+        meta.push(meta_name("twitter:title", &self.title));
+
+        let oembed = reqwest::Url::parse_with_params(&self.oembed_url, [("url", &self.image_url)])
+            .map(String::from)
+            .unwrap_or_default();
+        let media = if is_video {
+            format!("<video src=\"{}\" controls autoplay muted loop></video>", image_url)
+        } else {
+            format!("<img src=\"{}\" alt=\"{}\">", image_url, title)
+        };
+
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n{}\n\
+<link rel=\"alternate\" type=\"application/json+oembed\" href=\"{}\" title=\"{}\">\n<style>{}</style>\n</head>\n\
+<body>\n<main>\n{}\n<a href=\"{}\">Open original</a>\n</main>\n</body>\n</html>\n",
+            title,
+            meta.join("\n"),
+            escape_html(&oembed),
+            title,
+            STYLE,
+            media,
+            escape_html(&self.original_url),
+        )
+    }
+}
+
+fn meta_property(property: &str, content: &str) -> String {
+    format!("<meta property=\"{}\" content=\"{}\">", property, escape_html(content))
+}
+
+fn meta_name(name: &str, content: &str) -> String {
+    format!("<meta name=\"{}\" content=\"{}\">", name, escape_html(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_ref(mime_type: &str) -> FileReference {
+        FileReference {
+            file_id: "f".into(),
+            message_id: 1,
+            nonce: [0; 12],
+            size: 10,
+            mime_type: mime_type.into(),
+            plaintext: false,
+            chunked: false,
+            backend: crate::models::Backend::Telegram,
+            width: Some(640),
+            height: Some(480),
+            expires_at: None,
+            tenant: None,
+            chat_id: None,
+            visibility: Visibility::Public,
+            content_id: None,
+        }
+    }
+
+    #[test]
+    fn test_render_card_metadata() {
+        let page = ViewerPage {
+            title: "cat \"best\".png".into(),
+            page_url: "https://img.example/view/abc".into(),
+            image_url: "https://img.example/image/abc".into(),
+            original_url: "https://img.example/image/abc?raw=1".into(),
+            oembed_url: "https://img.example/oembed".into(),
+        };
+
+        let html = page.render(&file_ref("image/png"));
+        assert!(html.contains("<meta property=\"og:image\" content=\"https://img.example/image/abc\">"));
+        assert!(html.contains("<meta property=\"og:image:width\" content=\"640\">"));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary_large_image\">"));
+        assert!(html.contains("oembed?url=https%3A%2F%2Fimg.example%2Fimage%2Fabc"));
+        assert!(html.contains("cat &quot;best&quot;.png"));
+
+        let html = page.render(&file_ref("video/mp4"));
+        assert!(html.contains("og:video"));
+        assert!(!html.contains("og:image"));
+    }
+}
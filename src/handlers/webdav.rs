@@ -0,0 +1,435 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::{
+        auth::{ApiKey, OwnerToken, UploadAuth},
+        delete::delete_stored_image,
+        html::escape_html,
+        image::load_image_data,
+        job::wait_for_job,
+        me::owner,
+        upload::{
+            enqueue_job, prepare_upload, read_body_limited, upload_options, validate_image, HashingBuffer,
+            SYNC_UPLOAD_TIMEOUT,
+        },
+    },
+    services::{
+        albums::Album,
+        audit::{AuditAction, AuditEvent},
+        geoip::ClientLocation,
+        index::{ImageFilter, IndexEntry},
+        usage::usage_subjects,
+    },
+    worker::JobPayload,
+    AppState,
+};
+
+// Where the tree is mounted; hrefs in PROPFIND replies are built from it
+const DAV_ROOT: &str = "/dav";
+
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE";
+
+/// A place in the virtual tree of the caller's images: their uploads by month, and their albums
+enum Node {
+    Root,
+    Dates,
+    // "YYYY-MM" of the images inside
+    Month(String),
+    Albums,
+    Album(Album),
+    // An image, and the album folder it was reached through
+    Image(Box<IndexEntry>, Option<Album>),
+}
+
+impl Node {
+    fn is_collection(&self) -> bool {
+        !matches!(self, Node::Image(..))
+    }
+}
+
+/// Serve the caller's images as a WebDAV share, so it can be mounted in a file manager. Files
+/// copied into any folder are uploaded, and also added to the album when that folder is one;
+/// deleting a file deletes the image, or only takes it out of the album inside an album folder.
+pub async fn dav(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    api_key: ApiKey,
+    owner_token: OwnerToken,
+    path: Option<Path<String>>,
+    request: Request,
+) -> Result<Response> {
+    if request.method() == Method::OPTIONS {
+        return Ok((StatusCode::OK, [("dav", "1"), (header::ALLOW.as_str(), ALLOWED_METHODS)]).into_response());
+    }
+
+    // File managers only send credentials once asked for them
+    let Ok(owner) = owner(&api_key, &owner_token) else {
+        let challenge = [(header::WWW_AUTHENTICATE, "Basic realm=\"RustGram\"")];
+        return Ok((challenge, AppError::Unauthorized).into_response());
+    };
+    let segments: Vec<String> = path
+        .map(|Path(path)| path.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+    let tree = Tree { state: &state, owner: &owner };
+
+    match request.method().as_str() {
+        "PROPFIND" => {
+            let node = tree.resolve(&segments)?;
+            let depth = request.headers().get("depth").and_then(|v| v.to_str().ok()).unwrap_or("1");
+            // Depth "infinity" is answered like 1; clients walk the tree themselves
+            let children = if depth == "0" { Vec::new() } else { tree.children(&node)? };
+            Ok(multistatus(&segments, &node, &children))
+        }
+        "GET" | "HEAD" => {
+            let Node::Image(entry, _) = tree.resolve(&segments)? else {
+                return Ok(method_not_allowed());
+            };
+            let country = request.extensions().get::<ClientLocation>().and_then(|l| l.country.as_deref());
+            state.geo.download.check(country)?;
+
+            let encryption_key = state.config.get_encryption_key_bytes()?;
+            let _slot = state.download_limiter.acquire(addr.ip())?;
+            let image_data = load_image_data(&state, &encryption_key, &entry.reference).await?;
+            state
+                .usage
+                .record_download(&usage_subjects(addr.ip(), api_key.0.as_deref()), image_data.len());
+
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, header_value(&entry.reference.mime_type)?);
+            headers.insert(header::LAST_MODIFIED, header_value(&http_date(entry.created_at))?);
+            Ok((StatusCode::OK, headers, image_data).into_response())
+        }
+        "PUT" => {
+            let Some((name, parent)) = segments.split_last() else {
+                return Ok(method_not_allowed());
+            };
+            // Uploads land in a folder that exists; the file shows up under the month it was sent
+            let parent = match tree.resolve(parent) {
+                Ok(node) if node.is_collection() => node,
+                _ => return Ok(StatusCode::CONFLICT.into_response()),
+            };
+            if tree.resolve(&segments).is_ok_and(|node| node.is_collection()) {
+                return Ok(method_not_allowed());
+            }
+            let (mut parts, body) = request.into_parts();
+            let UploadAuth(api_key) = UploadAuth::from_request_parts(&mut parts, &state).await?;
+
+            let buffer = HashingBuffer::new(state.config.max_file_size).with_spill(&state.config);
+            let body = read_body_limited(body, &parts.headers, buffer).await?;
+            // Some clients create an empty file before writing its contents
+            if body.bytes.is_empty() {
+                return Ok(StatusCode::CREATED.into_response());
+            }
+            let mime_type = mime_guess::from_path(name).first_or_octet_stream().to_string();
+            validate_image(&state.cpu, &state.config, body.bytes.clone(), &mime_type).await?;
+
+            let options = upload_options(&state, None, api_key, owner_token)?;
+            let prepared = prepare_upload(
+                &state.cpu,
+                &state.config,
+                options.tenant.as_ref(),
+                body.bytes,
+                Some(body.sha256),
+                name,
+                mime_type,
+            )
+            .await?;
+            let queued = enqueue_job(&state, JobPayload::Ready(Box::new(prepared)), options, addr).await?;
+
+            // Clients list the folder straight after copying, so the image has to be stored by then
+            let file_ref = wait_for_job(&state, &queued.job_id, SYNC_UPLOAD_TIMEOUT).await?.file_ref;
+            if let Node::Album(album) = parent {
+                let id = CryptoService::new(&state.config.get_encryption_key_bytes()?).public_id(&file_ref)?;
+                state.albums.add_image(&album.id, &id)?;
+            }
+            Ok(StatusCode::CREATED.into_response())
+        }
+        "DELETE" => {
+            match tree.resolve(&segments)? {
+                Node::Image(entry, Some(album)) => {
+                    state.albums.remove_image(&album.id, &entry.id)?;
+                }
+                Node::Image(entry, None) => {
+                    delete_stored_image(&state, &entry.id, std::slice::from_ref(&owner)).await?;
+                    let event = AuditEvent::new(AuditAction::Delete).id(&entry.id).key(api_key.0.as_deref());
+                    state.audit.record(event.detail("webdav"));
+                }
+                Node::Album(album) => {
+                    state.albums.remove(&album.id)?;
+                }
+                _ => return Ok(method_not_allowed()),
+            }
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        _ => Ok(method_not_allowed()),
+    }
+}
+
+/// The tree as seen by one owner; folders are computed from the index and album store on each request
+struct Tree<'a> {
+    state: &'a AppState,
+    owner: &'a str,
+}
+
+impl Tree<'_> {
+    fn resolve(&self, segments: &[String]) -> Result<Node> {
+        let mut node = Node::Root;
+        for segment in segments {
+            node = self
+                .children(&node)?
+                .into_iter()
+                .find_map(|(name, child)| (name == *segment).then_some(child))
+                .ok_or(AppError::NotFound)?;
+        }
+        Ok(node)
+    }
+
+    /// Entries of a folder with their names, which are unique within it
+    fn children(&self, node: &Node) -> Result<Vec<(String, Node)>> {
+        let children = match node {
+            Node::Root => vec![("by-date".to_string(), Node::Dates), ("albums".to_string(), Node::Albums)],
+            Node::Dates => {
+                let mut months: Vec<String> = self.images()?.iter().map(|entry| month(entry.created_at)).collect();
+                months.dedup();
+                months.into_iter().map(|month| (month.clone(), Node::Month(month))).collect()
+            }
+            Node::Month(wanted) => {
+                let images = self.images()?.into_iter().filter(|entry| month(entry.created_at) == *wanted);
+                unique_names(images.map(|entry| {
+                    let name = file_name(&entry);
+                    (name, entry.id.clone(), Node::Image(Box::new(entry), None))
+                }))
+            }
+            Node::Albums => {
+                let albums = self.state.albums.list_owned(self.owner)?;
+                unique_names(albums.into_iter().map(|album| {
+                    let name = sanitize(&album.title).unwrap_or_else(|| album.id.clone());
+                    (name, album.id.clone(), Node::Album(album))
+                }))
+            }
+            Node::Album(album) => {
+                let mut images = Vec::with_capacity(album.image_ids.len());
+                for id in &album.image_ids {
+                    if let Some(entry) = self.state.index.get(id)? {
+                        images.push((file_name(&entry), entry.id.clone(), Node::Image(Box::new(entry), Some(album.clone()))));
+                    }
+                }
+                unique_names(images)
+            }
+            Node::Image(..) => Vec::new(),
+        };
+        Ok(children)
+    }
+
+    /// The owner's images, oldest first
+    fn images(&self) -> Result<Vec<IndexEntry>> {
+        let filter = ImageFilter { owner: Some(self.owner.to_string()), ..Default::default() };
+        self.state.index.filter(&filter)
+    }
+}
+
+/// Give every name shared by several entries the entry's ID, before a file's extension
+fn unique_names(entries: impl IntoIterator<Item = (String, String, Node)>) -> Vec<(String, Node)> {
+    let entries: Vec<(String, String, Node)> = entries.into_iter().collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (name, _, _) in &entries {
+        *counts.entry(name.as_str()).or_default() += 1;
+    }
+    let shared: Vec<bool> = entries.iter().map(|(name, _, _)| counts[name.as_str()] > 1).collect();
+
+    entries
+        .into_iter()
+        .zip(shared)
+        .map(|((name, id, node), shared)| {
+            if !shared {
+                return (name, node);
+            }
+            let name = match name.rsplit_once('.') {
+                Some((stem, extension)) if !node.is_collection() && !stem.is_empty() => {
+                    format!("{} ({}).{}", stem, id, extension)
+                }
+                _ => format!("{} ({})", name, id),
+            };
+            (name, node)
+        })
+        .collect()
+}
+
+/// The uploaded filename, or the image ID with an extension for its type
+fn file_name(entry: &IndexEntry) -> String {
+    entry.filename.as_deref().and_then(sanitize).unwrap_or_else(|| {
+        let extension = mime_guess::get_mime_extensions_str(&entry.reference.mime_type)
+            .and_then(|extensions| extensions.first())
+            .unwrap_or(&"bin");
+        format!("{}.{}", entry.id, extension)
+    })
+}
+
+/// A name usable as one path segment, or None when nothing usable is left
+fn sanitize(name: &str) -> Option<String> {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    (!name.is_empty() && name != "." && name != "..").then_some(name)
+}
+
+fn multistatus(segments: &[String], node: &Node, children: &[(String, Node)]) -> Response {
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    let base: String = segments.iter().map(|segment| format!("/{}", encode_segment(segment))).collect();
+    push_response(&mut body, &format!("{}{}", DAV_ROOT, base), segments.last().map_or("RustGram", String::as_str), node);
+    for (name, child) in children {
+        push_response(&mut body, &format!("{}{}/{}", DAV_ROOT, base, encode_segment(name)), name, child);
+    }
+    body.push_str("</D:multistatus>\n");
+
+    let content_type = [(header::CONTENT_TYPE, "application/xml; charset=utf-8")];
+    (StatusCode::MULTI_STATUS, content_type, body).into_response()
+}
+
+fn push_response(body: &mut String, href: &str, name: &str, node: &Node) {
+    let href = if node.is_collection() { format!("{}/", href) } else { href.to_string() };
+    body.push_str(&format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+        escape_html(&href),
+        escape_html(name)
+    ));
+    match node {
+        Node::Image(entry, _) => body.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype><D:getlastmodified>{}</D:getlastmodified>",
+            entry.reference.size,
+            escape_html(&entry.reference.mime_type),
+            http_date(entry.created_at)
+        )),
+        Node::Album(album) => body.push_str(&format!(
+            "<D:resourcetype><D:collection/></D:resourcetype><D:getlastmodified>{}</D:getlastmodified>",
+            http_date(album.created_at)
+        )),
+        _ => body.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+    }
+    body.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+/// Percent-encode everything in a path segment but unreserved characters
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn method_not_allowed() -> Response {
+    (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOWED_METHODS)]).into_response()
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    value.parse().map_err(|_| AppError::InternalError(format!("Invalid header value: {}", value)))
+}
+
+/// Year, month and day of a Unix time, in UTC
+fn civil_date(secs: u64) -> (i64, u32, u32) {
+    // Days are counted from 0000-03-01 so leap days fall at the end of each year
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Folder name of the month an image was uploaded in, e.g. "2024-03"
+fn month(secs: u64) -> String {
+    let (year, month, _) = civil_date(secs);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// RFC 7231 date, as WebDAV clients expect in getlastmodified
+fn http_date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (year, month, day) = civil_date(secs);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(secs / 86_400 % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs % 86_400 / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{png_bytes, TestApp};
+    use reqwest::{header, StatusCode};
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(1_709_251_199), "Thu, 29 Feb 2024 23:59:59 GMT");
+        assert_eq!(month(1_709_251_200), "2024-03");
+    }
+
+    #[tokio::test]
+    async fn test_mount_upload_browse_delete() {
+        let app = TestApp::start(&[]).await;
+        let dav = |method: &str, path: &str| {
+            app.client
+                .request(method.parse().unwrap(), app.url(path))
+                .basic_auth("me", Some("secret-key"))
+        };
+
+        let response = app.client.request("PROPFIND".parse().unwrap(), app.url("/dav/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let response = dav("PUT", "/dav/by-date/1999-01/red%20dot.png").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = dav("PUT", "/dav/red%20dot.png").body(png_bytes()).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let month = month(crate::models::unix_timestamp());
+        let response = dav("PROPFIND", "/dav/by-date/").header("depth", "1").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        assert!(response.text().await.unwrap().contains(&format!("<D:href>/dav/by-date/{}/</D:href>", month)));
+
+        let file = format!("/dav/by-date/{}/red%20dot.png", month);
+        let response = dav("GET", &file).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), png_bytes().as_slice());
+
+        // Someone else's share is empty
+        let response = app
+            .client
+            .request("PROPFIND".parse().unwrap(), app.url("/dav/by-date/"))
+            .basic_auth("me", Some("other-key"))
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.text().await.unwrap().contains(&month));
+
+        assert_eq!(dav("DELETE", &file).send().await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(app.telegram.stored_files(), 0);
+        assert_eq!(dav("GET", &file).send().await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+}
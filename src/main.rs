@@ -1,36 +1,91 @@
+mod cleanup;
 mod config;
 mod crypto;
 mod error;
 mod handlers;
 mod middleware;
 mod models;
+mod orphans;
+mod recovery;
+mod scheduler;
+mod self_test;
 mod services;
+#[cfg(test)]
+mod test_support;
 mod worker;
 
 use axum::{
-    routing::{get, post, delete},
+    body::Bytes,
+    extract::DefaultBodyLimit,
+    routing::{any, get, patch, post, delete},
     Router,
 };
 use std::{
     collections::HashMap,
     net::SocketAddr,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use tokio::sync::mpsc;
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer};
-use tracing::{info, Level};
-use tracing_subscriber;
+use anyhow::Context;
+use tracing::{info, warn, Level};
 
 use crate::{
+    cleanup::run_cleanup,
     config::Config,
-    handlers::{admin, health, image, job, upload, url_upload},
-    middleware::rate_limit::RateLimitLayer,
-    models::FileReference,
-    services::telegram::TelegramService,
-    worker::{run_upload_worker, UploadJob},
+    orphans::OrphanScans,
+    recovery::check_references,
+    handlers::{admin, albums, base64_upload, dashboard, delete, erasure, errors, gallery, health, home, metrics, image, imgur, import, job, me, migration, oembed, preview, pubkey, qr, search, similar, tags, upload, url_upload, viewer, webdav},
+    middleware::{
+        abuse::{detect_abuse, AbuseDetector, AbuseLimits},
+        catch_panic::catch_panics,
+        compression::api_compression,
+        download_limit::DownloadLimiter,
+        geo::{locate_client, restrict_downloads},
+        load_shed::{shed_load, LoadShedder},
+        locale::negotiate_language,
+        privacy::{anonymize_client_ip, IpPrivacy},
+        rate_limit::{mark_rate_limit_exempt, RateLimitLayer},
+        read_only::enforce_read_only,
+        request_id::assign_request_id,
+        timeout::enforce_timeout,
+        upload_progress::{track_upload_progress, UploadProgressStore},
+    },
+    scheduler::Scheduler,
+    services::{
+        albums::AlbumStore,
+        audit::AuditLog,
+        cache::ImageCache,
+        captcha::CaptchaVerifier,
+        chaos::FaultInjector,
+        geoip::GeoPolicy,
+        modes::RuntimeModes,
+        cdn::CdnService,
+        chat_migrations::ChatMigrations,
+        coalesce::RequestCoalescer,
+        cpu_pool::CpuPool,
+        discord::DiscordService,
+        erasure::ErasureJobs,
+        migration::MigrationJobs,
+        index::ImageIndex,
+        log_queue::{run_log_delivery, LogQueue},
+        metering::send_metering_event,
+        metrics::Metrics,
+        previews::PreviewTokens,
+        signing::ResponseSigner,
+        telegram::TelegramService,
+        tenants::TenantStore,
+        usage::UsageStore,
+        watermark::Watermark,
+    },
+    worker::{run_upload_worker, JobStore, UploadJob, WorkerStatus},
 };
 
+// Body limit for every route that doesn't take image data
+const JSON_BODY_LIMIT: usize = 256 * 1024;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
@@ -44,65 +99,404 @@ async fn main() -> anyhow::Result<()> {
     // Load configuration
     let config = Arc::new(Config::from_env()?);
     info!("Configuration loaded successfully");
+    if let Some(url) = &config.error_docs_url {
+        error::set_docs_base_url(url.clone());
+    }
 
     // Initialize services
-    let telegram_service = Arc::new(TelegramService::new(
+    let metrics = Arc::new(Metrics::default());
+    let faults = FaultInjector::from_config(&config);
+    if faults.is_some() {
+        warn!(
+            "CHAOS_MODE is on: {}% of Telegram calls and cache lookups fail and {}% of Telegram calls are delayed by {}ms",
+            config.chaos_failure_percent, config.chaos_delay_percent, config.chaos_delay_ms
+        );
+    }
+    let telegram_service = Arc::new(build_telegram(&config, metrics.clone(), faults)?);
+
+    // `telegram-image-host test-telegram` only runs the round trip and exits
+    let test_telegram = std::env::args().nth(1).as_deref() == Some("test-telegram");
+    if test_telegram || config.startup_self_test {
+        self_test::telegram_roundtrip(&telegram_service, &config.get_encryption_key_bytes()?).await?;
+        info!("Telegram self-test passed for chat {}", config.telegram_chat_id);
+        if test_telegram {
+            return Ok(());
+        }
+    }
+
+    // A wrong log chat would otherwise only show up as failed requests later on
+    if let Some(log_chat_id) = config.telegram_log_chat_id {
+        telegram_service
+            .send_log_message("RustGram started")
+            .await
+            .with_context(|| format!("Failed to post a test message to TELEGRAM_LOG_CHAT_ID {}", log_chat_id))?;
+        info!("Log chat {} reachable", log_chat_id);
+    }
+
+    let app_state = build_state(config.clone(), telegram_service, metrics, faults)?;
+
+    let rate_limit = RateLimitLayer::new(config.rate_limit_per_minute);
+    let abuse = AbuseDetector::new(
+        AbuseLimits {
+            ban_secs: config.abuse_ban_secs,
+            max_errors: config.abuse_max_errors,
+            max_id_probes: config.abuse_max_id_probes,
+            max_churn: config.abuse_max_churn,
+        },
+        app_state.audit.clone(),
+    );
+
+    // Register periodic background tasks
+    let mut scheduler = Scheduler::new();
+    if let Some(schedule) = config.task_schedule("cleanup", "3600")? {
+        let (index, usage, tenants, cache, cdn, telegram_service, discord, audit, config, modes) = (
+            app_state.index.clone(),
+            app_state.usage.clone(),
+            app_state.tenants.clone(),
+            app_state.cache.clone(),
+            app_state.cdn.clone(),
+            app_state.telegram_service.clone(),
+            app_state.discord.clone(),
+            app_state.audit.clone(),
+            config.clone(),
+            app_state.modes.clone(),
+        );
+        scheduler.add("cleanup", schedule, move || {
+            // Nothing is deleted while the server is read-only
+            let read_only = modes.read_only();
+            let cleanup = run_cleanup(
+                index.clone(),
+                usage.clone(),
+                tenants.clone(),
+                cache.clone(),
+                cdn.clone(),
+                telegram_service.clone(),
+                discord.clone(),
+                audit.clone(),
+                config.clone(),
+            );
+            async move {
+                if read_only {
+                    tracing::info!("Skipping scheduled cleanup: server is read-only");
+                    return;
+                }
+                cleanup.await
+            }
+        });
+    }
+    if let Some(schedule) = config.task_schedule("log_summary", "3600")? {
+        let audit = app_state.audit.clone();
+        scheduler.add("log_summary", schedule, move || {
+            let audit = audit.clone();
+            async move { audit.record_summary() }
+        });
+    }
+    if let Some(schedule) = config.task_schedule("usage_flush", "60")? {
+        let (usage, retention_days) = (app_state.usage.clone(), config.usage_retention_days);
+        scheduler.add("usage_flush", schedule, move || {
+            let usage = usage.clone();
+            async move {
+                if let Err(e) = usage.flush(retention_days) {
+                    tracing::error!("Failed to flush usage: {}", e);
+                }
+            }
+        });
+    }
+    if let Some(schedule) = config.task_schedule("rate_limit_prune", "300")? {
+        let rate_limit = rate_limit.clone();
+        scheduler.add("rate_limit_prune", schedule, move || {
+            let rate_limit = rate_limit.clone();
+            async move { rate_limit.prune_idle() }
+        });
+    }
+    if abuse.enabled()
+        && let Some(schedule) = config.task_schedule("abuse_prune", "300")?
+    {
+        let abuse = abuse.clone();
+        scheduler.add("abuse_prune", schedule, move || {
+            let abuse = abuse.clone();
+            async move { abuse.prune_idle() }
+        });
+    }
+    if let Some(schedule) = config.task_schedule("upload_progress_prune", "600")? {
+        let upload_progress = app_state.upload_progress.clone();
+        scheduler.add("upload_progress_prune", schedule, move || {
+            let upload_progress = upload_progress.clone();
+            async move { upload_progress.prune_stale() }
+        });
+    }
+    if config.billing_webhook_url.is_some()
+        && let Some(schedule) = config.task_schedule("metering", "3600")?
+    {
+        let (usage, config, client) = (app_state.usage.clone(), config.clone(), reqwest::Client::new());
+        scheduler.add("metering", schedule, move || {
+            let (usage, config, client) = (usage.clone(), config.clone(), client.clone());
+            async move {
+                if let Err(e) = send_metering_event(&client, usage, config).await {
+                    tracing::error!("Failed to send usage report: {}", e);
+                }
+            }
+        });
+    }
+
+    if let Some(schedule) = config.task_schedule("reference_check", "86400")? {
+        let state = app_state.clone();
+        scheduler.add("reference_check", schedule, move || check_references(state.clone()));
+    }
+    scheduler.start();
+
+    let app = build_router(app_state, rate_limit, abuse);
+
+    // Start server
+    let listener = tokio::net::TcpListener::bind(&config.bind_address).await?;
+    info!("Server starting on {}", config.bind_address);
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The Telegram client for the configured bot, storage chat and Bot API server
+fn build_telegram(config: &Config, metrics: Arc<Metrics>, faults: Option<FaultInjector>) -> anyhow::Result<TelegramService> {
+    Ok(TelegramService::new(
         config.telegram_bot_token.clone(),
         config.telegram_chat_id,
-        None, // Consider adding a log_chat_id from config
+        config.telegram_log_chat_id,
+    )
+    .with_api_url(&config.telegram_api_url)
+    .with_parallel_downloads(config.download_chunk_bytes, config.download_parallelism)
+    .with_metrics(metrics)
+    .with_fault_injection(faults)
+    .with_chat_migrations(Arc::new(ChatMigrations::open(
+        config.chat_migrations_path.as_ref().map(Into::into),
+    )?)))
+}
+
+/// Open the stores and start the upload worker and log delivery behind the shared state
+fn build_state(
+    config: Arc<Config>,
+    telegram_service: Arc<TelegramService>,
+    metrics: Arc<Metrics>,
+    faults: Option<FaultInjector>,
+) -> anyhow::Result<Arc<AppState>> {
+    // Load the image index and usage rollups
+    let index = Arc::new(ImageIndex::open(config.index_path.as_ref().map(Into::into))?);
+    let usage = Arc::new(UsageStore::open(config.usage_path.as_ref().map(Into::into))?);
+    let tenants = Arc::new(TenantStore::open(config.tenants_path.as_ref().map(Into::into), &config.get_encryption_key_bytes()?)?);
+    if config.upload_requires_auth && tenants.list()?.is_empty() {
+        anyhow::bail!("UPLOAD_REQUIRES_AUTH is on without tenants, so no API key could upload");
+    }
+    let captcha = CaptchaVerifier::from_config(&config)?.map(Arc::new);
+    let signer = ResponseSigner::from_config(&config)?.map(Arc::new);
+    let geo = Arc::new(GeoPolicy::from_config(&config)?);
+    let modes = Arc::new(RuntimeModes::new(&config));
+    let albums = Arc::new(AlbumStore::open(config.albums_path.as_ref().map(Into::into))?);
+    let cache = Arc::new(ImageCache::new(config.image_cache_bytes).with_fault_injection(faults));
+    let cdn = Arc::new(CdnService::new());
+    let watermark = Watermark::load(&config)?.map(Arc::new);
+    let (log_queue, log_rx) = LogQueue::new();
+    tokio::spawn(run_log_delivery(
+        log_rx,
+        telegram_service.clone(),
+        config.audit_chat_id,
+        Duration::from_millis(config.log_batch_window_ms),
     ));
+    let audit = Arc::new(AuditLog::open(
+        config.audit_log_path.as_ref().map(Into::into),
+        config.audit_log_capacity,
+        log_queue,
+        config.audit_encrypt.then(|| config.get_encryption_key_bytes()).transpose()?,
+    )?
+    .with_sampling(&config.log_sample_rates)?);
 
     // Create a channel for the upload queue
     let (tx, rx) = mpsc::channel::<UploadJob>(100); // Buffer size of 100
 
     // Create a job store to hold job results
-    let job_store = Arc::new(Mutex::new(HashMap::<String, FileReference>::new()));
+    let job_store: JobStore = Arc::new(Mutex::new(HashMap::new()));
+    let worker = Arc::new(WorkerStatus::default());
+    let cpu = CpuPool::new(config.cpu_pool_size);
+    let discord = DiscordService::from_config(&config).map(Arc::new);
+    let remote_client = url_upload::remote_client()?;
 
     // Spawn the upload worker
     tokio::spawn(run_upload_worker(
         rx,
         job_store.clone(),
+        index.clone(),
+        usage.clone(),
         telegram_service.clone(),
+        discord.clone(),
+        audit.clone(),
         config.clone(),
+        worker.clone(),
+        cpu.clone(),
+        remote_client.clone(),
     ));
 
-    // Build application state
-    let app_state = Arc::new(AppState {
+    let privacy = IpPrivacy::new(&config.privacy_mode, config.get_encryption_key_bytes()?)?;
+    let upload_progress = UploadProgressStore::default();
+    Ok(Arc::new(AppState {
         config: config.clone(),
         telegram_service,
         admin_secret: config.admin_secret.clone(),
         upload_queue: tx,
         job_store,
-    });
+        index,
+        usage,
+        tenants,
+        upload_progress,
+        download_limiter: DownloadLimiter::new(config.max_concurrent_downloads_per_ip),
+        downloads: Arc::new(RequestCoalescer::new()),
+        cpu,
+        discord,
+        cache,
+        cdn,
+        watermark,
+        metrics,
+        worker,
+        audit,
+        privacy,
+        erasures: Arc::new(ErasureJobs::default()),
+        migrations: Arc::new(MigrationJobs::default()),
+        orphan_scans: Arc::new(OrphanScans::default()),
+        albums,
+        captcha,
+        signer,
+        previews: Arc::new(PreviewTokens::default()),
+        geo,
+        modes,
+        remote_client,
+    }))
 
-    // Build router
-    let app = Router::new()
-        .route("/health", get(health::health_check))
-        .route("/upload", post(upload::upload_image))
+}
+
+/// Every route with its middleware, serving `app_state`
+fn build_router(app_state: Arc<AppState>, rate_limit: RateLimitLayer, abuse: AbuseDetector) -> Router {
+    let transfer_timeout = Duration::from_secs(app_state.config.transfer_timeout_secs);
+
+    // Only routes that carry image data get a body limit sized for files
+    let upload_routes = Router::new()
+        .route("/upload", post(upload::upload_image).put(upload::upload_raw))
+        .route("/upload/base64", post(base64_upload::upload_base64))
+        .route("/3/image", post(imgur::upload))
+        .route("/3/upload", post(imgur::upload))
+        .route("/search/similar", post(similar::search_similar))
+        .route("/dav", any(webdav::dav))
+        .route("/dav/", any(webdav::dav))
+        .route("/dav/*path", any(webdav::dav))
+        .layer(DefaultBodyLimit::max(app_state.config.upload_body_limit()))
+        .layer(axum::middleware::from_fn_with_state(transfer_timeout, enforce_timeout));
+
+    // Routes that move image data to or from Telegram get the longer transfer budget
+    let transfer_routes = Router::new()
+        .route("/image/:id", get(image::get_image))
+        .route("/thumb/:id", get(image::get_thumbnail))
+        .route("/v/:id/:variant", get(image::get_variant))
+        .route("/t/:tenant/image/:id", get(image::get_image))
+        .route("/view/:id", get(viewer::view_image))
+        .route("/t/:tenant/view/:id", get(viewer::view_image))
+        .route("/t/:tenant/thumb/:id", get(image::get_thumbnail))
+        .route("/t/:tenant/v/:id/:variant", get(image::get_variant))
+        .route_layer(axum::middleware::from_fn_with_state(app_state.geo.clone(), restrict_downloads))
         .route("/upload_from_url", post(url_upload::upload_from_url))
+        .route("/import/telegram", post(import::import_telegram_file))
+        .layer(DefaultBodyLimit::max(JSON_BODY_LIMIT))
+        .layer(axum::middleware::from_fn_with_state(transfer_timeout, enforce_timeout));
+
+    Router::new()
+        .route("/", get(home::upload_page))
+        .route("/health", get(health::health_check))
+        .route("/errors", get(errors::error_catalog))
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/upload_from_url/async", post(url_upload::upload_from_url_async))
         .route("/job/:id", get(job::get_job_status)) // New route for job status
-        .route("/image/:id", get(image::get_image))
+        .route("/job/:id/events", get(job::job_events))
         .route("/info/:id", get(image::get_image_info))
+        .route("/image/:id/tags", patch(tags::set_image_tags))
+        .route("/image/:id/sign", post(preview::create_signed_link))
+        .route("/t/:tenant/info/:id", get(image::get_image_info))
+        .route("/similar/:id", get(similar::get_similar))
+        .route("/oembed", get(oembed::oembed))
+        .route("/pubkey", get(pubkey::public_key))
+        .route("/qr/:id", get(qr::get_qr_code))
+        // POST takes an image ID, GET the preview token it minted
+        .route(
+            "/preview/:id",
+            get(preview::view_preview)
+                .route_layer(axum::middleware::from_fn_with_state(app_state.geo.clone(), restrict_downloads))
+                .post(preview::create_preview),
+        )
+        .route("/search", get(search::search_text))
+        .route("/delete/:id/:token", get(delete::delete_with_token))
+        .route("/me/images", get(me::list_my_images))
+        .route("/me/images/:id", delete(me::delete_my_image))
+        .route("/me/albums", get(albums::list_my_albums).post(albums::create_album))
+        .route("/me/albums/:id", delete(albums::delete_my_album))
+        .route("/gallery/:id", get(gallery::gallery).post(gallery::unlock_gallery))
+        .route("/3/image/:deletehash", delete(imgur::delete))
+        .route("/admin", get(dashboard::dashboard))
+        .route("/admin/login", post(dashboard::login))
+        .route("/admin/logout", post(dashboard::logout))
         .route("/admin/image/:id", delete(admin::delete_image))
+        .route("/admin/images", get(admin::list_images))
+        .route("/admin/images/:id", delete(admin::delete_indexed_image))
+        .route("/admin/stats", get(admin::get_stats))
+        .route("/admin/queue", get(admin::get_queue))
+        .route("/admin/worker/pause", post(admin::pause_worker))
+        .route("/admin/worker/resume", post(admin::resume_worker))
+        .route("/admin/maintenance", get(admin::get_maintenance).put(admin::set_maintenance))
+        .route("/admin/read-only", get(admin::get_read_only).put(admin::set_read_only))
+        .route("/admin/audit", get(admin::get_audit_events))
+        .route("/admin/erasure", post(erasure::start_erasure))
+        .route("/admin/erasure/:id", get(erasure::get_erasure))
+        .route("/admin/migrations", post(migration::start_migration))
+        .route("/admin/migrations/:id", get(migration::get_migration))
+        .route("/admin/orphans/scan", post(admin::start_orphan_scan))
+        .route("/admin/orphans/:id", get(admin::get_orphan_scan))
+        .route("/admin/check/:id", get(admin::check_reference))
+        .route("/admin/cleanup/preview", get(admin::preview_cleanup))
+        .route("/admin/cleanup/run", post(admin::trigger_cleanup))
+        .route("/admin/prewarm", post(admin::prewarm_cache))
+        .route("/admin/usage", get(admin::get_usage))
+        .route("/admin/tenants", get(admin::list_tenants).post(admin::create_tenant))
+        .route("/admin/tenants/:id", delete(admin::delete_tenant))
+        .route("/admin/tenants/:id/keys", post(admin::create_tenant_key))
+        .layer(DefaultBodyLimit::max(JSON_BODY_LIMIT))
+        .layer(axum::middleware::from_fn_with_state(
+            Duration::from_secs(app_state.config.request_timeout_secs),
+            enforce_timeout,
+        ))
+        .merge(transfer_routes)
+        .merge(upload_routes)
+        .layer(axum::middleware::from_fn_with_state(app_state.modes.clone(), enforce_read_only))
+        .layer(api_compression())
+        .layer(axum::middleware::from_fn_with_state(app_state.upload_progress.clone(), track_upload_progress))
+        .layer(axum::middleware::from_fn_with_state(
+            LoadShedder::new(app_state.config.max_in_flight_requests),
+            shed_load,
+        ))
+        .layer(catch_panics(app_state.metrics.clone()))
+        .layer(axum::middleware::from_fn(negotiate_language))
         .layer(
             ServiceBuilder::new()
-                .layer(RequestBodyLimitLayer::new(config.max_file_size))
-                .layer(RateLimitLayer::new(config.rate_limit_per_minute))
+                // Locates the client while its real address is still known
+                .layer(axum::middleware::from_fn_with_state(app_state.geo.clone(), locate_client))
+                .layer(axum::middleware::from_fn_with_state(app_state.config.clone(), mark_rate_limit_exempt))
+                // Masks the client IP before the rate limiter keys on it
+                .layer(axum::middleware::from_fn_with_state(app_state.privacy.clone(), anonymize_client_ip))
+                .layer(axum::middleware::from_fn_with_state(abuse, detect_abuse))
+                .layer(RequestBodyLimitLayer::new(app_state.config.upload_body_limit()))
+                .layer(rate_limit)
                 .layer(CorsLayer::permissive()),
         )
-        .with_state(app_state);
-
-    // Start server
-    let listener = tokio::net::TcpListener::bind(&config.bind_address).await?;
-    info!("Server starting on {}", config.bind_address);
-
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await?;
-
-    Ok(())
+        // Outermost, so rate-limited and preflight responses carry an ID too
+        .layer(axum::middleware::from_fn(assign_request_id))
+        .with_state(app_state)
 }
 
 #[derive(Clone)]
@@ -111,5 +505,34 @@ pub struct AppState {
     pub telegram_service: Arc<TelegramService>,
     pub admin_secret: String,
     pub upload_queue: mpsc::Sender<UploadJob>,
-    pub job_store: Arc<Mutex<HashMap<String, FileReference>>>,
+    pub job_store: JobStore,
+    pub index: Arc<ImageIndex>,
+    pub usage: Arc<UsageStore>,
+    pub tenants: Arc<TenantStore>,
+    pub upload_progress: UploadProgressStore,
+    pub download_limiter: DownloadLimiter,
+    // In-flight Telegram downloads of decrypted image data, keyed by file_id
+    pub downloads: Arc<RequestCoalescer<Bytes>>,
+    // Blocking threads for decoding, transforming and encrypting images
+    pub cpu: CpuPool,
+    // Storage for files kept as Discord attachments, when configured
+    pub discord: Option<Arc<DiscordService>>,
+    pub cache: Arc<ImageCache>,
+    pub cdn: Arc<CdnService>,
+    pub watermark: Option<Arc<Watermark>>,
+    pub metrics: Arc<Metrics>,
+    pub worker: Arc<WorkerStatus>,
+    pub audit: Arc<AuditLog>,
+    pub privacy: IpPrivacy,
+    pub erasures: Arc<ErasureJobs>,
+    pub migrations: Arc<MigrationJobs>,
+    pub orphan_scans: Arc<OrphanScans>,
+    pub albums: Arc<AlbumStore>,
+    pub previews: Arc<PreviewTokens>,
+    pub captcha: Option<Arc<CaptchaVerifier>>,
+    pub signer: Option<Arc<ResponseSigner>>,
+    pub geo: Arc<GeoPolicy>,
+    pub modes: Arc<RuntimeModes>,
+    // Fetches images for URL uploads and imports, with timeouts against servers that never answer
+    pub remote_client: reqwest::Client,
 }
@@ -0,0 +1,241 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{AppError, ErrorCode},
+    services::audit::{AuditAction, AuditEvent, AuditLog},
+};
+
+// Counters are reset once a client's window is this old
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Per-window thresholds past which a client is soft-banned; a ban of zero seconds disables detection
+#[derive(Debug, Clone, Copy)]
+pub struct AbuseLimits {
+    pub ban_secs: u64,
+    pub max_errors: u32,
+    pub max_id_probes: u32,
+    pub max_churn: u32,
+}
+
+/// Watches each client's responses for scanning and churn, and turns clients that trip a
+/// threshold away with 429 for a while
+#[derive(Clone)]
+pub struct AbuseDetector {
+    limits: AbuseLimits,
+    clients: Arc<Mutex<HashMap<IpAddr, ClientActivity>>>,
+    audit: Arc<AuditLog>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    // Any 4xx other than a 429
+    Error,
+    // A malformed or unknown image ID
+    IdProbe,
+    Upload,
+    Delete,
+}
+
+#[derive(Debug)]
+struct ClientActivity {
+    window_start: Instant,
+    errors: u32,
+    id_probes: u32,
+    uploads: u32,
+    deletes: u32,
+    banned_until: Option<Instant>,
+}
+
+impl ClientActivity {
+    fn new(now: Instant) -> Self {
+        Self { window_start: now, errors: 0, id_probes: 0, uploads: 0, deletes: 0, banned_until: None }
+    }
+
+    /// Count one signal, returning the reason for a ban when it crosses a threshold
+    fn observe(&mut self, signal: Signal, limits: &AbuseLimits, now: Instant) -> Option<String> {
+        if now.duration_since(self.window_start) >= WINDOW {
+            *self = Self { banned_until: self.banned_until, ..Self::new(now) };
+        }
+        match signal {
+            Signal::Error => self.errors += 1,
+            Signal::IdProbe => {
+                self.errors += 1;
+                self.id_probes += 1;
+            }
+            Signal::Upload => self.uploads += 1,
+            Signal::Delete => self.deletes += 1,
+        }
+
+        let reason = if limits.max_id_probes > 0 && self.id_probes >= limits.max_id_probes {
+            format!("{} invalid or unknown image IDs", self.id_probes)
+        } else if limits.max_errors > 0 && self.errors >= limits.max_errors {
+            format!("{} error responses", self.errors)
+        } else if limits.max_churn > 0 && self.uploads.min(self.deletes) >= limits.max_churn {
+            format!("{} uploads and {} deletes", self.uploads, self.deletes)
+        } else {
+            return None;
+        };
+        self.banned_until = Some(now + Duration::from_secs(limits.ban_secs));
+        Some(format!("{} within {}s", reason, WINDOW.as_secs()))
+    }
+
+    fn ban_remaining(&self, now: Instant) -> Option<Duration> {
+        self.banned_until.and_then(|until| until.checked_duration_since(now)).filter(|left| !left.is_zero())
+    }
+}
+
+impl AbuseDetector {
+    pub fn new(limits: AbuseLimits, audit: Arc<AuditLog>) -> Self {
+        Self {
+            limits,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            audit,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limits.ban_secs > 0
+    }
+
+    /// Forget clients that are neither banned nor active in the current window
+    pub fn prune_idle(&self) {
+        let now = Instant::now();
+        self.clients.lock().unwrap().retain(|_, activity| {
+            activity.ban_remaining(now).is_some() || now.duration_since(activity.window_start) < WINDOW
+        });
+    }
+
+    fn ban_remaining(&self, ip: IpAddr) -> Option<Duration> {
+        self.clients.lock().unwrap().get(&ip)?.ban_remaining(Instant::now())
+    }
+
+    fn observe(&self, ip: IpAddr, signal: Signal) {
+        let now = Instant::now();
+        let reason = {
+            let mut clients = self.clients.lock().unwrap();
+            let activity = clients.entry(ip).or_insert_with(|| ClientActivity::new(now));
+            activity.observe(signal, &self.limits, now)
+        };
+        if let Some(reason) = reason {
+            tracing::warn!("Soft-banning {} for {}s: {}", ip, self.limits.ban_secs, reason);
+            self.audit.record(AuditEvent::new(AuditAction::SoftBan).ip(ip).detail(reason));
+        }
+    }
+}
+
+/// What a finished request says about its client, if anything
+fn classify(method: &Method, path: &str, response: &Response) -> Option<Signal> {
+    let status = response.status();
+    if status.is_success() {
+        // Admins cleaning up are not churn
+        if path.starts_with("/admin") {
+            return None;
+        }
+        let is_upload = matches!(*method, Method::POST | Method::PUT)
+            && ["/upload", "/3/image", "/3/upload", "/import/"].iter().any(|prefix| path.starts_with(prefix));
+        let is_delete = *method == Method::DELETE || path.starts_with("/delete/");
+        return if is_upload {
+            Some(Signal::Upload)
+        } else if is_delete {
+            Some(Signal::Delete)
+        } else {
+            None
+        };
+    }
+    if !status.is_client_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    match response.extensions().get::<ErrorCode>() {
+        Some(ErrorCode::InvalidImageId | ErrorCode::InvalidId | ErrorCode::NotFound) => Some(Signal::IdProbe),
+        _ => Some(Signal::Error),
+    }
+}
+
+/// Turn soft-banned clients away with 429, and feed every other response into their counters.
+/// Runs after PRIVACY_MODE masking, so bans apply to the masked address.
+pub async fn detect_abuse(State(detector): State<AbuseDetector>, request: Request, next: Next) -> Response {
+    if !detector.enabled() {
+        return next.run(request).await;
+    }
+    let Some(ip) = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ci| ci.0.ip()) else {
+        return next.run(request).await;
+    };
+
+    if let Some(remaining) = detector.ban_remaining(ip) {
+        let mut response = AppError::RateLimitExceeded.into_response();
+        // Round up so clients never retry a moment too early
+        let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        if let Ok(value) = retry_after.to_string().parse() {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+        return response;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    if let Some(signal) = classify(&method, &path, &response) {
+        detector.observe(ip, signal);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: AbuseLimits = AbuseLimits { ban_secs: 600, max_errors: 5, max_id_probes: 3, max_churn: 2 };
+
+    #[test]
+    fn test_bans_on_id_probing() {
+        let now = Instant::now();
+        let mut activity = ClientActivity::new(now);
+        assert!(activity.observe(Signal::IdProbe, &LIMITS, now).is_none());
+        assert!(activity.observe(Signal::IdProbe, &LIMITS, now).is_none());
+        let reason = activity.observe(Signal::IdProbe, &LIMITS, now).unwrap();
+        assert!(reason.contains("3 invalid or unknown image IDs"));
+        assert!(activity.ban_remaining(now).is_some());
+        assert!(activity.ban_remaining(now + Duration::from_secs(601)).is_none());
+    }
+
+    #[test]
+    fn test_window_resets_counters_but_not_bans() {
+        let now = Instant::now();
+        let mut activity = ClientActivity::new(now);
+        for _ in 0..4 {
+            assert!(activity.observe(Signal::Error, &LIMITS, now).is_none());
+        }
+        // A fresh window starts the count over
+        let later = now + WINDOW;
+        assert!(activity.observe(Signal::Error, &LIMITS, later).is_none());
+        assert_eq!(activity.errors, 1);
+
+        activity.banned_until = Some(later + Duration::from_secs(10));
+        activity.observe(Signal::Upload, &LIMITS, later + WINDOW);
+        assert!(activity.ban_remaining(later + WINDOW).is_none());
+        assert!(activity.ban_remaining(later).is_some());
+    }
+
+    #[test]
+    fn test_classify() {
+        let error = |error: AppError| error.into_response();
+        assert_eq!(classify(&Method::GET, "/image/x", &error(AppError::InvalidImageId)), Some(Signal::IdProbe));
+        assert_eq!(classify(&Method::GET, "/image/x", &error(AppError::Unauthorized)), Some(Signal::Error));
+        assert_eq!(classify(&Method::GET, "/image/x", &error(AppError::RateLimitExceeded)), None);
+        assert_eq!(classify(&Method::POST, "/upload", &StatusCode::OK.into_response()), Some(Signal::Upload));
+        assert_eq!(classify(&Method::DELETE, "/me/images/x", &StatusCode::OK.into_response()), Some(Signal::Delete));
+        assert_eq!(classify(&Method::DELETE, "/admin/image/x", &StatusCode::OK.into_response()), None);
+        assert_eq!(classify(&Method::GET, "/image/x", &StatusCode::OK.into_response()), None);
+    }
+}
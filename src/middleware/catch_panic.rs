@@ -0,0 +1,27 @@
+use axum::response::{IntoResponse, Response};
+use std::{any::Any, sync::Arc};
+use tower_http::catch_panic::CatchPanicLayer;
+
+use crate::{error::AppError, middleware::request_id::current_request_id, services::metrics::Metrics};
+
+/// Turn a panicking handler into the usual JSON 500 instead of a dropped connection
+pub fn catch_panics(
+    metrics: Arc<Metrics>,
+) -> CatchPanicLayer<impl Fn(Box<dyn Any + Send + 'static>) -> Response + Clone> {
+    CatchPanicLayer::custom(move |panic: Box<dyn Any + Send + 'static>| {
+        let message = panic
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| panic.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown panic");
+        tracing::error!(
+            "Handler panicked (request {}): {}",
+            current_request_id().as_deref().unwrap_or("-"),
+            message
+        );
+        metrics.record_panic();
+
+        // The panic message may contain internals, so clients only get the generic error
+        AppError::InternalError("Handler panicked".to_string()).into_response()
+    })
+}
@@ -0,0 +1,23 @@
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+// Bodies smaller than this are not worth the compression overhead
+const MIN_COMPRESS_BYTES: u16 = 1024;
+
+/// Response compression negotiated via Accept-Encoding, applied only to JSON and HTML responses;
+/// image bytes are already compressed and event streams must not be buffered
+pub fn api_compression() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(SizeAbove::new(MIN_COMPRESS_BYTES).and(is_api_response))
+}
+
+fn is_api_response(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("application/json") || content_type.starts_with("text/html")
+        })
+}
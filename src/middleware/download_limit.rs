@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::error::{AppError, Result};
+
+/// Caps how many image downloads a single client IP may have in flight at once
+#[derive(Clone)]
+pub struct DownloadLimiter {
+    max_per_ip: usize,
+    in_flight: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl DownloadLimiter {
+    // A cap of 0 disables the limit
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            max_per_ip,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a download slot for `ip`, released when the returned guard is dropped
+    pub fn acquire(&self, ip: IpAddr) -> Result<DownloadSlot> {
+        if self.max_per_ip > 0 {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let count = in_flight.entry(ip).or_insert(0);
+            if *count >= self.max_per_ip {
+                return Err(AppError::RateLimitExceeded);
+            }
+            *count += 1;
+        }
+
+        Ok(DownloadSlot {
+            limiter: (self.max_per_ip > 0).then(|| self.clone()),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&ip);
+            }
+        }
+    }
+}
+
+pub struct DownloadSlot {
+    limiter: Option<DownloadLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for DownloadSlot {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.release(self.ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_cap_per_ip() {
+        let limiter = DownloadLimiter::new(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.acquire(ip).unwrap();
+        let _second = limiter.acquire(ip).unwrap();
+        assert!(matches!(limiter.acquire(ip), Err(AppError::RateLimitExceeded)));
+        assert!(limiter.acquire(other).is_ok());
+
+        drop(first);
+        assert!(limiter.acquire(ip).is_ok());
+
+        let unlimited = DownloadLimiter::new(0);
+        let _slots: Vec<_> = (0..10).map(|_| unlimited.acquire(ip).unwrap()).collect();
+    }
+}
@@ -0,0 +1,41 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::services::geoip::{ClientLocation, GeoPolicy};
+
+tokio::task_local! {
+    static CLIENT_LOCATION: ClientLocation;
+}
+
+/// Location of the client being served, if GeoIP is configured and it went through `locate_client`
+pub fn current_location() -> Option<ClientLocation> {
+    CLIENT_LOCATION.try_with(Clone::clone).ok()
+}
+
+/// Look the client up before PRIVACY_MODE masks its address, attach the location to the request
+/// and to everything it records, and count the outcome towards the per-origin stats
+pub async fn locate_client(State(policy): State<Arc<GeoPolicy>>, mut request: Request, next: Next) -> Response {
+    let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>().filter(|_| policy.enabled())
+    else {
+        return next.run(request).await;
+    };
+
+    let location = policy.locate(addr.ip());
+    request.extensions_mut().insert(location.clone());
+    let response = CLIENT_LOCATION.scope(location.clone(), next.run(request)).await;
+    policy.record(&location, response.status().is_client_error());
+    response
+}
+
+/// Turn away downloads from countries the GEOIP_DOWNLOAD_* lists exclude
+pub async fn restrict_downloads(State(policy): State<Arc<GeoPolicy>>, request: Request, next: Next) -> Response {
+    let country = request.extensions().get::<ClientLocation>().and_then(|location| location.country.as_deref());
+    if let Err(e) = policy.download.check(country) {
+        return e.into_response();
+    }
+    next.run(request).await
+}
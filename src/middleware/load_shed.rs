@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::error::AppError;
+
+// Clients turned away are told to come back after this many seconds
+const RETRY_AFTER_SECS: u64 = 2;
+
+/// Counts requests being served and turns new ones away beyond a threshold
+#[derive(Clone)]
+pub struct LoadShedder {
+    in_flight: Arc<AtomicUsize>,
+    // 0 disables shedding
+    max_in_flight: usize,
+}
+
+impl LoadShedder {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight,
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn try_enter(&self) -> Option<InFlight> {
+        let previous = self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let guard = InFlight(self.in_flight.clone());
+        if self.max_in_flight > 0 && previous >= self.max_in_flight {
+            return None;
+        }
+        Some(guard)
+    }
+}
+
+// Marks one request as in flight until dropped
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reject requests with 503 and Retry-After while too many are already being served,
+/// so the ones in progress keep their latency
+pub async fn shed_load(State(shedder): State<LoadShedder>, request: Request, next: Next) -> Response {
+    let Some(_in_flight) = shedder.try_enter() else {
+        tracing::warn!("Shedding request to {}: {} in flight", request.uri().path(), shedder.in_flight());
+        return AppError::Overloaded { retry_after_secs: RETRY_AFTER_SECS }.into_response();
+    };
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheds_beyond_threshold() {
+        let shedder = LoadShedder::new(2);
+        let first = shedder.try_enter();
+        let second = shedder.try_enter();
+        assert!(first.is_some() && second.is_some());
+        assert!(shedder.try_enter().is_none());
+        // Rejected requests don't count
+        assert_eq!(shedder.in_flight(), 2);
+
+        drop(first);
+        assert!(shedder.try_enter().is_some());
+        assert!(LoadShedder::new(0).try_enter().is_some());
+    }
+}
@@ -0,0 +1,20 @@
+use axum::{
+    extract::Request,
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::error::Language;
+
+/// Pick the language of error messages from the request's Accept-Language header
+pub async fn negotiate_language(request: Request, next: Next) -> Response {
+    let language = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(Language::negotiate)
+        .unwrap_or_default();
+
+    language.scope(next.run(request)).await
+}
@@ -1 +1,13 @@
+pub mod abuse;
+pub mod catch_panic;
+pub mod compression;
+pub mod download_limit;
+pub mod geo;
+pub mod load_shed;
+pub mod locale;
+pub mod privacy;
 pub mod rate_limit;
+pub mod read_only;
+pub mod request_id;
+pub mod timeout;
+pub mod upload_progress;
@@ -0,0 +1,94 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::crypto::CryptoService;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Off,
+    // Keep the network: IPv4 /24, IPv6 /48
+    Truncate,
+    // Replace the address with a keyed hash, mapped into the fd00::/8 unique-local range
+    Hash,
+}
+
+/// How client IPs are masked before handlers, the rate limiter, logs or the audit trail see them
+#[derive(Clone)]
+pub struct IpPrivacy {
+    mode: Mode,
+    key: [u8; 32],
+}
+
+impl IpPrivacy {
+    /// `mode` is PRIVACY_MODE: "off", "truncate" or "hash"
+    pub fn new(mode: &str, key: [u8; 32]) -> anyhow::Result<Self> {
+        let mode = match mode {
+            "" | "off" => Mode::Off,
+            "truncate" => Mode::Truncate,
+            "hash" => Mode::Hash,
+            other => anyhow::bail!("PRIVACY_MODE must be off, truncate or hash, got '{}'", other),
+        };
+        Ok(Self { mode, key })
+    }
+
+    pub fn mask(&self, ip: IpAddr) -> IpAddr {
+        match (self.mode, ip) {
+            (Mode::Off, ip) => ip,
+            (Mode::Truncate, IpAddr::V4(ip)) => {
+                let [a, b, c, _] = ip.octets();
+                IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+            }
+            (Mode::Truncate, IpAddr::V6(ip)) => {
+                let segments = ip.segments();
+                IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0))
+            }
+            (Mode::Hash, ip) => {
+                let digest = CryptoService::hmac_sha256(&self.key, format!("client-ip:{}", ip).as_bytes());
+                let mut octets = [0u8; 16];
+                octets[0] = 0xfd;
+                octets[1..].copy_from_slice(&digest[..15]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        }
+    }
+}
+
+/// Replace the connection's address with its masked form for everything further in
+pub async fn anonymize_client_ip(State(privacy): State<IpPrivacy>, mut request: Request, next: Next) -> Response {
+    if privacy.mode != Mode::Off
+        && let Some(ConnectInfo(addr)) = request.extensions_mut().get_mut::<ConnectInfo<SocketAddr>>()
+    {
+        *addr = SocketAddr::new(privacy.mask(addr.ip()), 0);
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        let v4: IpAddr = "203.0.113.77".parse().unwrap();
+        let v6: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+
+        let off = IpPrivacy::new("off", [0; 32]).unwrap();
+        assert_eq!(off.mask(v4), v4);
+
+        let truncate = IpPrivacy::new("truncate", [0; 32]).unwrap();
+        assert_eq!(truncate.mask(v4), "203.0.113.0".parse::<IpAddr>().unwrap());
+        assert_eq!(truncate.mask(v6), "2001:db8:1234::".parse::<IpAddr>().unwrap());
+
+        let hash = IpPrivacy::new("hash", [7; 32]).unwrap();
+        assert_eq!(hash.mask(v4), hash.mask(v4));
+        assert_ne!(hash.mask(v4), hash.mask(v6));
+        assert!(!hash.mask(v4).to_string().contains("203"));
+        assert_ne!(hash.mask(v4), IpPrivacy::new("hash", [8; 32]).unwrap().mask(v4));
+
+        assert!(IpPrivacy::new("blur", [0; 32]).is_err());
+    }
+}
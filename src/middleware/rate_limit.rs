@@ -1,16 +1,87 @@
 use axum::{
-    extract::ConnectInfo,
-    http::{Request, StatusCode},
-    response::Response,
+    extract::{ConnectInfo, FromRequestParts, Request as AxumRequest, State},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
+use serde::Deserialize;
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 use tower::{Layer, Service};
 
+use crate::{config::Config, error::AppError, handlers::auth::ApiKey};
+
+/// An IP address or CIDR block, e.g. "10.0.0.0/8" or "2001:db8::1"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let (addr, prefix) = match spec.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (spec, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| anyhow::anyhow!("'{}' is not an IP address or CIDR block", spec))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| anyhow::anyhow!("'{}' has an invalid prefix length", spec))?,
+            None => max_prefix,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Marks a request as exempt from rate limiting
+#[derive(Debug, Clone, Copy)]
+struct RateLimitExempt;
+
+/// Let clients from RATE_LIMIT_EXEMPT_IPS or presenting a key from RATE_LIMIT_EXEMPT_KEYS past the
+/// rate limiter. Runs before client IPs are masked, so exemptions match real addresses.
+pub async fn mark_rate_limit_exempt(State(config): State<Arc<Config>>, request: AxumRequest, next: Next) -> Response {
+    let exempt_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(addr)| config.rate_limit_exempt_ips.iter().any(|network| network.contains(addr.ip())));
+
+    let (mut parts, body) = request.into_parts();
+    let exempt_key = !config.rate_limit_exempt_keys.is_empty() && {
+        let Ok(ApiKey(key)) = ApiKey::from_request_parts(&mut parts, &()).await;
+        key.is_some_and(|key| config.rate_limit_exempt_keys.contains(&key))
+    };
+    let mut request = AxumRequest::from_parts(parts, body);
+
+    if exempt_ip || exempt_key {
+        request.extensions_mut().insert(RateLimitExempt);
+    }
+    next.run(request).await
+}
+
 #[derive(Clone)]
 pub struct RateLimitLayer {
     requests_per_minute: u32,
@@ -24,6 +95,13 @@ impl RateLimitLayer {
             store: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    /// Drop buckets of clients that have been idle long enough to be full again
+    pub fn prune_idle(&self) {
+        let mut store = self.store.lock().unwrap();
+        let now = Instant::now();
+        store.retain(|_, bucket| now.duration_since(bucket.last_refill) < Duration::from_secs(300));
+    }
 }
 
 impl<S> Layer<S> for RateLimitLayer {
@@ -68,6 +146,10 @@ where
         let requests_per_minute = self.requests_per_minute;
 
         Box::pin(async move {
+            if req.extensions().get::<RateLimitExempt>().is_some() {
+                return inner.call(req).await;
+            }
+
             // Get client IP
             let client_ip = req
                 .extensions()
@@ -86,27 +168,7 @@ where
             };
 
             if !allowed {
-                let response = Response::builder()
-                    .status(StatusCode::TOO_MANY_REQUESTS)
-                    .header("Content-Type", "application/json")
-                    .body(
-                        serde_json::json!({
-                            "error": "Rate limit exceeded",
-                            "status": 429
-                        })
-                        .to_string()
-                        .into(),
-                    )
-                    .unwrap();
-                return Ok(response);
-            }
-
-            // Clean up old entries periodically
-            if fastrand::f32() < 0.01 {
-                // 1% chance to cleanup
-                let mut store = store.lock().unwrap();
-                let now = Instant::now();
-                store.retain(|_, bucket| now.duration_since(bucket.last_refill) < Duration::from_secs(300));
+                return Ok(AppError::RateLimitExceeded.into_response());
             }
 
             inner.call(req).await
@@ -152,3 +214,47 @@ impl TokenBucket {
         self.last_refill = now;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestApp;
+
+    #[test]
+    fn test_ip_network() {
+        let network = IpNetwork::parse("10.1.0.0/16").unwrap();
+        assert!(network.contains("10.1.200.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.0.9".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+
+        let host = IpNetwork::parse("2001:db8::1").unwrap();
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+        assert!(!host.contains("2001:db8::2".parse().unwrap()));
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains("192.0.2.1".parse().unwrap()));
+
+        assert!(IpNetwork::parse("10.0.0.0/33").is_err());
+        assert!(IpNetwork::parse("example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exempt_key_skips_the_bucket() {
+        let app = TestApp::start(&[("RATE_LIMIT_PER_MINUTE", "2"), ("RATE_LIMIT_EXEMPT_KEYS", "health-checker")]).await;
+        let get = |key: Option<&'static str>| {
+            let request = app.client.get(app.url("/health"));
+            match key {
+                Some(key) => request.header("x-api-key", key),
+                None => request,
+            }
+            .send()
+        };
+
+        for _ in 0..5 {
+            assert!(get(Some("health-checker")).await.unwrap().status().is_success());
+        }
+        // Exempt requests left the client's own bucket untouched
+        assert!(get(Some("other-key")).await.unwrap().status().is_success());
+        assert!(get(None).await.unwrap().status().is_success());
+        assert_eq!(get(None).await.unwrap().status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert!(get(Some("health-checker")).await.unwrap().status().is_success());
+    }
+}
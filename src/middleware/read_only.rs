@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::{error::AppError, services::modes::RuntimeModes};
+
+// Non-GET routes that change nothing stored, or are needed to operate the server while read-only
+const ALLOWED_PREFIXES: &[&str] = &[
+    "/admin/login",
+    "/admin/logout",
+    "/admin/read-only",
+    "/admin/maintenance",
+    "/admin/worker/",
+    "/admin/prewarm",
+    "/search/similar",
+    "/gallery/",
+    "/preview/",
+];
+
+/// Whether a request would upload, change or delete something
+fn is_mutating(method: &Method, path: &str) -> bool {
+    // Token deletes are plain GET links
+    if path.starts_with("/delete/") {
+        return true;
+    }
+    // PROPFIND only lists WebDAV folders
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) || method.as_str() == "PROPFIND" {
+        return false;
+    }
+    // Signed links are computed, not stored
+    if *method == Method::POST && path.starts_with("/image/") && path.ends_with("/sign") {
+        return false;
+    }
+    !ALLOWED_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Refuse every upload, change and delete with 503 while read-only mode is on
+pub async fn enforce_read_only(State(modes): State<Arc<RuntimeModes>>, request: Request, next: Next) -> Response {
+    if modes.read_only() && is_mutating(request.method(), request.uri().path()) {
+        return AppError::ReadOnly.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_mutating() {
+        assert!(is_mutating(&Method::POST, "/upload"));
+        assert!(is_mutating(&Method::PUT, "/upload"));
+        assert!(is_mutating(&Method::GET, "/delete/abc/token"));
+        assert!(is_mutating(&Method::DELETE, "/admin/images/abc"));
+        assert!(is_mutating(&Method::POST, "/admin/erasure"));
+        assert!(is_mutating(&Method::PATCH, "/image/abc/tags"));
+        assert!(!is_mutating(&Method::GET, "/image/abc"));
+        assert!(!is_mutating(&Method::from_bytes(b"PROPFIND").unwrap(), "/dav/albums/"));
+        assert!(is_mutating(&Method::PUT, "/dav/cat.png"));
+        assert!(!is_mutating(&Method::POST, "/image/abc/sign"));
+        assert!(!is_mutating(&Method::PUT, "/admin/read-only"));
+        assert!(!is_mutating(&Method::POST, "/gallery/abc"));
+    }
+}
@@ -0,0 +1,23 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+use crate::error::AppError;
+
+/// Fail requests that take longer than `budget` to produce a response with a 504.
+/// Only the time to the response head counts, so streamed bodies such as SSE are unaffected.
+pub async fn enforce_timeout(State(budget): State<Duration>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+
+    match tokio::time::timeout(budget, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request to {} timed out after {:?}", path, budget);
+            AppError::Timeout(format!("Request did not complete within {} seconds", budget.as_secs()))
+                .into_response()
+        }
+    }
+}
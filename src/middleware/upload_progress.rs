@@ -0,0 +1,148 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+// Header carrying a client-chosen UUID, which becomes the job ID of the upload
+const UPLOAD_ID_HEADER: &str = "x-upload-id";
+
+// Progress entries outlive their request so /job/:id can report them until the job finishes
+const PROGRESS_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadProgress {
+    pub received_bytes: u64,
+    // From Content-Length; absent for chunked bodies
+    pub total_bytes: Option<u64>,
+}
+
+/// Bytes received so far for each in-flight upload that sent an `X-Upload-Id`
+#[derive(Clone, Default)]
+pub struct UploadProgressStore {
+    entries: Arc<Mutex<HashMap<String, (UploadProgress, Instant)>>>,
+}
+
+impl UploadProgressStore {
+    pub fn get(&self, upload_id: &str) -> Option<UploadProgress> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(upload_id).map(|(progress, _)| progress.clone())
+    }
+
+    /// Drop progress of uploads that have not moved for a while
+    pub fn prune_stale(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, updated)| updated.elapsed() < PROGRESS_TTL);
+    }
+
+    fn start(&self, upload_id: &str, total_bytes: Option<u64>) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains_key(upload_id) {
+            return false;
+        }
+        let progress = UploadProgress { received_bytes: 0, total_bytes };
+        entries.insert(upload_id.to_string(), (progress, Instant::now()));
+        true
+    }
+
+    fn add(&self, upload_id: &str, bytes: usize) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((progress, updated)) = entries.get_mut(upload_id) {
+            progress.received_bytes += bytes as u64;
+            *updated = Instant::now();
+        }
+    }
+}
+
+/// The validated upload ID of a tracked request, used as its job ID
+pub struct UploadId(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for UploadId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(UploadId(parts.extensions.get::<TrackedUpload>().map(|tracked| tracked.0.clone())))
+    }
+}
+
+#[derive(Clone)]
+struct TrackedUpload(String);
+
+/// Count request body bytes as they arrive for requests that carry an `X-Upload-Id` UUID
+pub async fn track_upload_progress(
+    State(store): State<UploadProgressStore>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(header_value) = request.headers().get(UPLOAD_ID_HEADER) else {
+        return next.run(request).await;
+    };
+
+    let Some(upload_id) = header_value
+        .to_str()
+        .ok()
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .map(|uuid| uuid.to_string())
+    else {
+        return AppError::ValidationError("X-Upload-Id must be a UUID".to_string()).into_response();
+    };
+
+    let total_bytes = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    if !store.start(&upload_id, total_bytes) {
+        return AppError::ValidationError("X-Upload-Id is already in use".to_string()).into_response();
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let counter = (store.clone(), upload_id.clone());
+    let counted = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            counter.0.add(&counter.1, bytes.len());
+        }
+        chunk
+    });
+
+    parts.extensions.insert(TrackedUpload(upload_id));
+    request = Request::from_parts(parts, Body::from_stream(counted));
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_store() {
+        let store = UploadProgressStore::default();
+        assert!(store.start("a", Some(100)));
+        assert!(!store.start("a", None));
+
+        store.add("a", 40);
+        store.add("missing", 10);
+
+        let progress = store.get("a").unwrap();
+        assert_eq!(progress.received_bytes, 40);
+        assert_eq!(progress.total_bytes, Some(100));
+        assert!(store.get("missing").is_none());
+    }
+}
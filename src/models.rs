@@ -1,4 +1,17 @@
 use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use crate::middleware::upload_progress::UploadProgress;
+
+/// Every MIME type an image can be stored with
+pub const STORED_IMAGE_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+// Bounds no reference the server issues comes close to: Telegram's file_ids are well under
+// 256 characters, bots can't store files over 2000 MB, and no supported format gets past 65535 pixels
+const MAX_FILE_ID_LEN: usize = 256;
+const MAX_REFERENCE_SIZE: usize = 2000 * 1024 * 1024;
+const MAX_REFERENCE_DIMENSION: u32 = 65535;
+const MAX_TENANT_ID_LEN: usize = 32;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileReference {
@@ -7,14 +20,120 @@ pub struct FileReference {
     pub nonce: [u8; 12], // AES-GCM nonce
     pub size: usize,
     pub mime_type: String,
+    // Imported files referenced in place are stored in Telegram without our encryption
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plaintext: bool,
+    // file_id and message_id name the encrypted manifest of a file stored as several chunks
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub chunked: bool,
+    // Service holding the file; absent for Telegram, so existing IDs stay valid
+    #[serde(default, skip_serializing_if = "Backend::is_telegram")]
+    pub backend: Backend,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    // Unix timestamp after which the image may be removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    // Tenant namespace the image is served under, e.g. /t/<tenant>/image/<id>
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    // Storage chat holding the message, when it isn't the default TELEGRAM_CHAT_ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    // Who may fetch the image; absent for public images, so their IDs stay as short as before
+    #[serde(default, skip_serializing_if = "Visibility::is_public")]
+    pub visibility: Visibility,
+    // Content-addressed ID the image is shared under instead of this reference encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+}
+
+/// Public images are served and listed for anyone. Unlisted ones are served to anyone with the link
+/// but left out of search and similarity results; private ones need the owner's credentials or a
+/// signed link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    Private,
+}
+
+impl Visibility {
+    pub fn is_public(&self) -> bool {
+        *self == Visibility::Public
+    }
+}
+
+/// The remote service a file is stored in. For Discord, message_id is the message carrying the
+/// attachment and file_id the attachment's ID.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Telegram,
+    Discord,
+}
+
+impl Backend {
+    pub fn is_telegram(&self) -> bool {
+        *self == Backend::Telegram
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct UploadResponse {
     pub id: String,
     pub url: String,
+    pub thumbnail_url: String,
+    pub delete_url: Option<String>,
     pub size: usize,
     pub mime_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Visibility::is_public")]
+    pub visibility: Visibility,
+    // A content-addressed re-upload of an image already stored; no delete_url is given out for it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub existing: bool,
+}
+
+// Response shape expected by ShareX custom uploaders
+#[derive(Debug, Serialize)]
+pub struct ShareXResponse {
+    pub url: String,
+    pub thumbnail_url: String,
+    pub deletion_url: String,
+}
+
+// imgur-style envelope wrapping every /3/ response
+#[derive(Debug, Serialize)]
+pub struct ImgurResponse<T> {
+    pub data: T,
+    pub success: bool,
+    pub status: u16,
+}
+
+// The subset of imgur's image model that clients commonly read
+#[derive(Debug, Serialize)]
+pub struct ImgurImage {
+    pub id: String,
+    pub title: Option<String>,
+    pub datetime: u64,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    pub animated: bool,
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+    // Absent for re-uploads of an image already stored, which only its uploader may delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deletehash: Option<String>,
+    pub link: String,
 }
 
 // The immediate response when a file is queued for upload
@@ -22,13 +141,20 @@ pub struct UploadResponse {
 pub struct QueuedResponse {
     pub job_id: String,
     pub status_url: String,
+    // Issued at an anonymous client's first upload; send it as X-Owner-Token from then on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_token: Option<String>,
 }
 
 // Represents the status of an upload job
 #[derive(Debug, Serialize)]
 #[serde(tag = "status")]
 pub enum JobStatus {
-    Pending,
+    // Progress is reported for uploads sent with an X-Upload-Id header
+    Pending {
+        #[serde(flatten)]
+        progress: Option<UploadProgress>,
+    },
     Completed { response: UploadResponse },
     Failed { error: String },
 }
@@ -38,6 +164,27 @@ pub struct HealthResponse {
     pub status: String,
     pub timestamp: u64,
     pub version: String,
+    pub queue: QueueHealth,
+    pub worker: WorkerHealth,
+    // Unix time Telegram last answered a call successfully
+    pub last_telegram_success: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueHealth {
+    pub depth: usize,
+    pub capacity: usize,
+    // Seconds the oldest waiting upload has been queued
+    pub oldest_job_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerHealth {
+    pub alive: bool,
+    // Held by an admin; queued jobs wait until it is resumed
+    pub paused: bool,
+    // Seconds the upload being processed has been running
+    pub current_job_secs: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -45,6 +192,15 @@ pub struct TelegramResponse<T> {
     pub ok: bool,
     pub result: Option<T>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<ResponseParameters>,
+}
+
+// Extra details Telegram attaches to some errors
+#[derive(Debug, Deserialize)]
+pub struct ResponseParameters {
+    // Set when a group was upgraded to a supergroup under this new ID
+    pub migrate_to_chat_id: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -96,6 +252,47 @@ impl FileReference {
             nonce,
             size,
             mime_type,
+            plaintext: false,
+            chunked: false,
+            backend: Backend::Telegram,
+            width: None,
+            height: None,
+            expires_at: None,
+            tenant: None,
+            chat_id: None,
+            visibility: Visibility::Public,
+            content_id: None,
         }
     }
-} 
+
+    /// Whether every field is something the server could have issued, so a reference that
+    /// decrypted but is nonetheless off never reaches a handler
+    pub fn is_well_formed(&self) -> bool {
+        let is_id_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        let file_id_ok = !self.file_id.is_empty()
+            && self.file_id.len() <= MAX_FILE_ID_LEN
+            && self.file_id.chars().all(is_id_char);
+        let dimension_ok = |dimension: Option<u32>| dimension.is_none_or(|d| d > 0 && d <= MAX_REFERENCE_DIMENSION);
+        let tenant_ok = self
+            .tenant
+            .as_deref()
+            .is_none_or(|tenant| !tenant.is_empty() && tenant.len() <= MAX_TENANT_ID_LEN && tenant.chars().all(is_id_char));
+
+        file_id_ok
+            && self.message_id >= 0
+            && self.size <= MAX_REFERENCE_SIZE
+            && STORED_IMAGE_TYPES.contains(&self.mime_type.as_str())
+            && dimension_ok(self.width)
+            && dimension_ok(self.height)
+            && tenant_ok
+            && self.chat_id != Some(0)
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,295 @@
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    error::{AppError, Result},
+    models::{unix_timestamp, FileReference},
+    recovery::content_key_and_storage,
+    services::{
+        audit::{AuditAction, AuditEvent},
+        manifest,
+        storage::Storage,
+        telegram::MessageProbe,
+    },
+    AppState,
+};
+
+// Pause between probes so a scan over a long chat stays clear of Telegram's limits
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanScanStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrphanFailure {
+    pub message_id: i64,
+    pub error: String,
+}
+
+/// Progress and outcome of one pass over a storage chat for messages nothing references
+#[derive(Debug, Clone, Serialize)]
+pub struct OrphanScanReport {
+    pub job_id: String,
+    pub status: OrphanScanStatus,
+    pub chat_id: i64,
+    // Whether orphans are deleted, or only reported
+    pub delete: bool,
+    // Report-only scan whose orphans a deleting scan is limited to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run_id: Option<String>,
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    // Message IDs scanned, up to the newest referenced message, exclusive
+    pub first_message_id: i64,
+    pub last_message_id: i64,
+    pub messages_checked: usize,
+    // Messages of ours that no image references
+    pub orphans: Vec<i64>,
+    pub orphans_deleted: usize,
+    pub failed: Vec<OrphanFailure>,
+    // Why the scan stopped early, for a Failed scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OrphanScanReport {
+    /// A report-only scan of `chat_id` from `first_message_id`
+    pub fn new(job_id: String, chat_id: i64, first_message_id: i64) -> Self {
+        Self {
+            job_id,
+            status: OrphanScanStatus::Running,
+            chat_id,
+            delete: false,
+            dry_run_id: None,
+            started_at: unix_timestamp(),
+            finished_at: None,
+            first_message_id,
+            last_message_id: first_message_id,
+            messages_checked: 0,
+            orphans: Vec::new(),
+            orphans_deleted: 0,
+            failed: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A scan deleting the orphans `dry_run` reported, over the same chat and message range
+    pub fn deleting(job_id: String, dry_run: &OrphanScanReport) -> Self {
+        Self {
+            delete: true,
+            dry_run_id: Some(dry_run.job_id.clone()),
+            last_message_id: dry_run.last_message_id,
+            ..Self::new(job_id, dry_run.chat_id, dry_run.first_message_id)
+        }
+    }
+}
+
+/// Reports of orphan scans started since the process came up
+#[derive(Default)]
+pub struct OrphanScans {
+    reports: Mutex<HashMap<String, OrphanScanReport>>,
+}
+
+impl OrphanScans {
+    pub fn update(&self, report: &OrphanScanReport) {
+        self.reports.lock().unwrap().insert(report.job_id.clone(), report.clone());
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<OrphanScanReport> {
+        self.reports.lock().unwrap().get(job_id).cloned()
+    }
+
+    pub fn running(&self) -> bool {
+        self.reports.lock().unwrap().values().any(|report| report.status == OrphanScanStatus::Running)
+    }
+}
+
+/// Messages in `chat_id` that indexed images and their transcodes are stored in, chunks included
+async fn referenced_messages(state: &AppState, master_key: &[u8; 32], chat_id: i64) -> Result<HashSet<i64>> {
+    let default_chat = state.config.telegram_chat_id;
+    let mut referenced = HashSet::new();
+    for entry in state.index.list()? {
+        // Transcodes are kept in the image's backend, not necessarily in its chat
+        let stored = std::iter::once(&entry.reference).chain(entry.transcodes.values());
+        let in_chat = |file_ref: &&FileReference| {
+            file_ref.backend.is_telegram() && file_ref.chat_id.unwrap_or(default_chat) == chat_id
+        };
+        for file_ref in stored.filter(in_chat) {
+            referenced.insert(file_ref.message_id);
+            if file_ref.chunked {
+                // A manifest that can't be read hides chunks that are still in use, so the scan stops
+                let (_, telegram) = content_key_and_storage(state, master_key, file_ref.tenant.as_deref())?;
+                let chunks = manifest::chunk_message_ids(&Storage::Telegram(telegram), master_key, file_ref)
+                    .await
+                    .map_err(|e| AppError::InternalError(format!("Manifest of {} unreadable: {}", entry.id, e)))?;
+                referenced.extend(chunks);
+            }
+        }
+    }
+    Ok(referenced)
+}
+
+/// Walk the message IDs of the storage chat below its newest referenced message, since bots can't
+/// list chat history, and report every message of ours that no image references, such as uploads
+/// of jobs that crashed before indexing their file. The upload worker finishes one job before
+/// taking the next, so a message newer than every indexed one may still be about to be indexed and
+/// is left alone. A deleting scan only goes over the orphans its dry run reported, each checked
+/// again before it is deleted.
+pub async fn run_orphan_scan(state: Arc<AppState>, mut report: OrphanScanReport) {
+    let result = scan(&state, &mut report).await;
+    report.finished_at = Some(unix_timestamp());
+    report.status = match result {
+        Ok(()) => OrphanScanStatus::Completed,
+        Err(e) => {
+            tracing::error!("Orphan scan {} stopped: {}", report.job_id, e);
+            report.error = Some(e.to_string());
+            OrphanScanStatus::Failed
+        }
+    };
+    state.orphan_scans.update(&report);
+    state.audit.record(AuditEvent::new(AuditAction::OrphanScan).detail(format!(
+        "job {}: {} messages checked, {} orphans, {} deleted",
+        report.job_id,
+        report.messages_checked,
+        report.orphans.len(),
+        report.orphans_deleted
+    )));
+    tracing::info!("Orphan scan {} finished", report.job_id);
+}
+
+async fn scan(state: &AppState, report: &mut OrphanScanReport) -> Result<()> {
+    let master_key = state.config.get_encryption_key_bytes()?;
+    let referenced = referenced_messages(state, &master_key, report.chat_id).await?;
+    let candidates: Vec<i64> = match &report.dry_run_id {
+        Some(dry_run_id) => state
+            .orphan_scans
+            .get(dry_run_id)
+            .ok_or_else(|| AppError::InternalError(format!("Dry run {} is gone", dry_run_id)))?
+            .orphans,
+        None => {
+            report.last_message_id = referenced.iter().copied().max().unwrap_or(0).max(report.first_message_id);
+            (report.first_message_id..report.last_message_id).collect()
+        }
+    };
+    state.orphan_scans.update(report);
+
+    let telegram = &state.telegram_service;
+    for message_id in candidates {
+        if referenced.contains(&message_id) {
+            continue;
+        }
+        report.messages_checked += 1;
+        match telegram.probe_message(report.chat_id, message_id).await {
+            Ok(MessageProbe::Ours) => {
+                report.orphans.push(message_id);
+                // Indexed since the scan started, e.g. by a migration or restore
+                let claimed = state.index.find_by_message(report.chat_id, message_id, state.config.telegram_chat_id)?.is_some();
+                if report.delete && !claimed {
+                    match telegram.delete_message(report.chat_id, message_id).await {
+                        Ok(()) => report.orphans_deleted += 1,
+                        Err(e) => report.failed.push(OrphanFailure { message_id, error: e.to_string() }),
+                    }
+                }
+            }
+            Ok(MessageProbe::Missing | MessageProbe::NotOurs) => {}
+            Err(e) => report.failed.push(OrphanFailure { message_id, error: e.to_string() }),
+        }
+        state.orphan_scans.update(report);
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        services::telegram::TelegramService,
+        test_support::{png_bytes, TestApp},
+    };
+    use bytes::Bytes;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_scan_deletes_unreferenced_messages() {
+        let index_path = std::env::temp_dir().join(format!("rustgram-index-{}.json", uuid::Uuid::new_v4()));
+        let app = TestApp::start(&[("INDEX_PATH", index_path.to_str().unwrap())]).await;
+        // A file sent by a job that died before indexing it, then an image that made it
+        let telegram = TelegramService::new("test-token".to_string(), -1001, None).with_api_url(&app.telegram.url());
+        telegram.upload_file(Bytes::from_static(b"orphan"), "orphan.bin").await.unwrap();
+        let queued: Value = app
+            .client
+            .put(app.url("/upload?filename=red.png"))
+            .header("content-type", "image/png")
+            .body(png_bytes())
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        assert_eq!(app.telegram.stored_files(), 2);
+
+        // Deleting needs the report of a dry run first
+        let response = start_scan(&app, json!({ "delete": true })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let dry_run = finished_scan(&app, start_scan(&app, json!({})).await).await;
+        assert_eq!(dry_run["orphans"], json!([1]));
+        assert_eq!(dry_run["orphans_deleted"], 0);
+        assert_eq!(app.telegram.stored_files(), 2);
+
+        let response = start_scan(&app, json!({ "delete": true, "dry_run_id": dry_run["job_id"] })).await;
+        let report = finished_scan(&app, response).await;
+        assert_eq!(report["orphans"], json!([1]));
+        assert_eq!(report["orphans_deleted"], 1);
+        assert_eq!(app.telegram.stored_files(), 1);
+        let _ = std::fs::remove_file(index_path);
+    }
+
+    async fn start_scan(app: &TestApp, body: Value) -> reqwest::Response {
+        app.client
+            .post(app.url("/admin/orphans/scan"))
+            .header("x-admin-key", "admin")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn finished_scan(app: &TestApp, response: reqwest::Response) -> Value {
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = response.json::<Value>().await.unwrap()["job_id"].as_str().unwrap().to_string();
+
+        let mut report = Value::Null;
+        for _ in 0..100 {
+            report = app
+                .client
+                .get(app.url(&format!("/admin/orphans/{}", job_id)))
+                .header("x-admin-key", "admin")
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if report["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(report["status"], "completed", "{}", report);
+        report
+    }
+}
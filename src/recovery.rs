@@ -0,0 +1,228 @@
+use axum::body::Bytes;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::{unix_timestamp, Backend, FileReference},
+    services::{index::IndexEntry, manifest, storage::Storage, telegram::TelegramService},
+    AppState,
+};
+
+/// Key the image's content is encrypted with and the service storing it, per its tenant
+pub(crate) fn content_key_and_storage(
+    state: &AppState,
+    master_key: &[u8; 32],
+    tenant: Option<&str>,
+) -> Result<([u8; 32], Arc<TelegramService>)> {
+    // A deleted tenant's key is gone, so its images can no longer be decrypted
+    Ok(match state.tenants.owner(tenant)? {
+        Some(tenant) => (tenant.content_key(master_key)?, tenant.storage(&state.telegram_service)),
+        None => (*master_key, state.telegram_service.clone()),
+    })
+}
+
+// Telegram's "temporarily unavailable" answers only count as the file being gone once there have been
+// this many, spread over at least this long, so an outage never marks images broken
+const UNAVAILABLE_FAILURES: u32 = 3;
+const UNAVAILABLE_SPAN_SECS: u64 = 24 * 60 * 60;
+
+/// Count an unavailable answer for an indexed image's file. Once such answers have kept coming for
+/// long enough, the file is taken as gone and recovered like one; until then the caller gets
+/// `FileUnavailable` (503) to retry later.
+pub(crate) async fn file_unavailable(
+    state: &AppState,
+    master_key: &[u8; 32],
+    entry: IndexEntry,
+) -> Result<(FileReference, Bytes)> {
+    let now = unix_timestamp();
+    let Some(run) = state.index.record_unavailable(&entry.id, now)? else {
+        return Err(AppError::FileUnavailable);
+    };
+    if run.failures < UNAVAILABLE_FAILURES || now.saturating_sub(run.since) < UNAVAILABLE_SPAN_SECS {
+        tracing::warn!("File {} of image {} is unavailable ({} times)", entry.reference.file_id, entry.id, run.failures);
+        return Err(AppError::FileUnavailable);
+    }
+    tracing::warn!("File {} of image {} unavailable since {}, treating it as gone", entry.reference.file_id, entry.id, run.since);
+    recover_stale_file(state, master_key, entry).await
+}
+
+/// Re-upload an image whose Telegram file_id no longer resolves, from the decrypted copy in the
+/// image cache, and point its index entry at the new file. Without a copy the entry is marked
+/// broken, so the image is answered with 410 from then on instead of failing against Telegram.
+pub(crate) async fn recover_stale_file(
+    state: &AppState,
+    master_key: &[u8; 32],
+    entry: IndexEntry,
+) -> Result<(FileReference, Bytes)> {
+    let stale_file_id = entry.reference.file_id.clone();
+    let Some(image_data) = state.cache.get(&stale_file_id) else {
+        tracing::warn!("File {} of image {} is gone and no copy is cached", stale_file_id, entry.id);
+        state.index.update_stored_as(&entry.id, &stale_file_id, |entry| {
+            entry.broken = true;
+            entry.unavailable = None;
+        })?;
+        return Err(AppError::Gone);
+    };
+
+    // Nothing is stored while read-only; the copy is served and the file restored once writes resume
+    if state.modes.read_only() {
+        return Ok((entry.reference, image_data));
+    }
+
+    let (content_key, storage) = content_key_and_storage(state, master_key, entry.reference.tenant.as_deref())?;
+    let encrypted_data = CryptoService::new(&content_key).encrypt_data(&image_data)?;
+    let filename = format!("{}_{}", Uuid::new_v4(), entry.filename.as_deref().unwrap_or("image.bin"));
+    // Restored files go back to Telegram, wherever they were stored before
+    let storage = Storage::Telegram(storage);
+    let stored = manifest::store(
+        &storage,
+        master_key,
+        Bytes::from(encrypted_data),
+        &filename,
+        state.config.storage_chunk_bytes,
+    )
+    .await?;
+    let file_id = stored.file_id;
+
+    // The entry is updated as it is now: the image may have been deleted, re-tagged or restored
+    // by another request while the copy was uploading
+    let chat_id = storage.telegram_chat().filter(|chat_id| *chat_id != state.config.telegram_chat_id);
+    let updated = state.index.update_stored_as(&entry.id, &stale_file_id, |entry| {
+        entry.previous_file_ids.push(stale_file_id.clone());
+        entry.reference.file_id = file_id.clone();
+        entry.reference.message_id = stored.message_id;
+        entry.reference.chunked = stored.chunked;
+        entry.reference.backend = Backend::Telegram;
+        entry.reference.chat_id = chat_id;
+        entry.reference.plaintext = false;
+        entry.broken = false;
+        entry.unavailable = None;
+    })?;
+    let Some(updated) = updated else {
+        let copy = FileReference { file_id, message_id: stored.message_id, chunked: stored.chunked, ..entry.reference };
+        manifest::delete_file_chunks(&storage, master_key, None, &copy).await;
+        if let Err(e) = storage.delete(None, copy.message_id).await {
+            tracing::warn!("Could not delete unused copy {}: {}", copy.file_id, e);
+        }
+        return match state.index.get(&entry.id)? {
+            Some(current) => Ok((current.reference, image_data)),
+            None => Err(AppError::NotFound),
+        };
+    };
+    tracing::info!("Re-uploaded image {} from cache: {} -> {}", entry.id, stale_file_id, file_id);
+
+    state.cache.insert(&file_id, image_data.clone());
+    Ok((updated.reference, image_data))
+}
+
+// Pause between getFile calls so a pass over a large index stays clear of Telegram's limits
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Ask Telegram about every indexed file, so content deleted directly in the chat is noticed
+/// (and restored from the cache or marked broken) before a visitor runs into it. Broken images are
+/// asked about again, and served again once their file resolves.
+pub async fn check_references(state: Arc<AppState>) {
+    let master_key = state.config.get_encryption_key_bytes().map_err(AppError::from);
+    let (entries, master_key) = match (state.index.list(), master_key) {
+        (Ok(entries), Ok(key)) => (entries, key),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Reference check could not start: {}", e);
+            return;
+        }
+    };
+
+    let (mut checked, mut gone, mut unavailable, mut failed, mut restored) = (0, 0, 0, 0, 0);
+    // Only Telegram answers getFile; Discord files are checked when they are served
+    for entry in entries.into_iter().filter(|entry| entry.reference.backend.is_telegram()) {
+        // Images of deleted tenants can't be served anyway
+        let Ok((_, storage)) = content_key_and_storage(&state, &master_key, entry.reference.tenant.as_deref()) else {
+            continue;
+        };
+
+        checked += 1;
+        match storage.get_file_info(&entry.reference.file_id).await {
+            Ok(_) => match state.index.mark_available(&entry.id) {
+                Ok(true) => {
+                    restored += 1;
+                    tracing::info!("File {} of broken image {} resolves again", entry.reference.file_id, entry.id);
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Could not clear failures of {}: {}", entry.id, e),
+            },
+            Err(AppError::FileUnavailable) => {
+                unavailable += 1;
+                let id = entry.id.clone();
+                match file_unavailable(&state, &master_key, entry).await {
+                    Ok(_) | Err(AppError::FileUnavailable) => {}
+                    Err(e) => tracing::warn!("Image {} could not be restored: {}", id, e),
+                }
+            }
+            Err(AppError::Gone) => {
+                gone += 1;
+                let id = entry.id.clone();
+                if let Err(e) = recover_stale_file(&state, &master_key, entry).await {
+                    tracing::warn!("Image {} could not be restored: {}", id, e);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                tracing::warn!("Reference check of {} failed: {}", entry.id, e);
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+
+    tracing::info!(
+        "Reference check: {} checked, {} gone, {} unavailable, {} failed, {} restored",
+        checked,
+        gone,
+        unavailable,
+        failed,
+        restored
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::index::Unavailable,
+        test_support::{png_bytes, TestApp},
+    };
+    use reqwest::StatusCode;
+
+    #[tokio::test]
+    async fn test_outage_does_not_break_images() {
+        let app = TestApp::start(&[]).await;
+        let response = app.client.put(app.url("/upload")).header("content-type", "image/png").body(png_bytes()).send().await.unwrap();
+        let queued: serde_json::Value = response.json().await.unwrap();
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        let id = job["response"]["id"].as_str().unwrap().to_string();
+
+        // Unavailable answers are retried, however many arrive at once
+        app.telegram.set_outage(true);
+        for _ in 0..UNAVAILABLE_FAILURES {
+            assert_eq!(app.get(&format!("/image/{}", id)).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        check_references(app.state.clone()).await;
+        let entry = app.state.index.get(&id).unwrap().unwrap();
+        assert!(!entry.broken);
+        assert_eq!(entry.unavailable.unwrap().failures, UNAVAILABLE_FAILURES + 1);
+
+        // Only once they have kept coming for long enough is the file taken as gone
+        let mut entry = entry;
+        entry.unavailable = Some(Unavailable { since: unix_timestamp() - UNAVAILABLE_SPAN_SECS, failures: 5 });
+        app.state.index.insert(entry).unwrap();
+        assert_eq!(app.get(&format!("/image/{}", id)).await.status(), StatusCode::GONE);
+        assert!(app.state.index.get(&id).unwrap().unwrap().broken);
+
+        // The next check that finds the file again undoes it
+        app.telegram.set_outage(false);
+        check_references(app.state.clone()).await;
+        let entry = app.state.index.get(&id).unwrap().unwrap();
+        assert!(!entry.broken && entry.unavailable.is_none());
+        assert_eq!(app.get(&format!("/image/{}", id)).await.status(), StatusCode::OK);
+    }
+}
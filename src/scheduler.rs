@@ -0,0 +1,281 @@
+use anyhow::{anyhow, Context};
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// When a task runs: a fixed interval, or a cron expression evaluated in UTC
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Parse a schedule spec: a number of seconds ("3600") or a 5-field cron expression ("*/15 * * * *")
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let spec = spec.trim();
+        if let Ok(secs) = spec.parse::<u64>() {
+            if secs == 0 {
+                return Err(anyhow!("Schedule interval must be greater than zero"));
+            }
+            return Ok(Schedule::Interval(Duration::from_secs(secs)));
+        }
+        Ok(Schedule::Cron(CronSchedule::parse(spec)?))
+    }
+
+    /// Time to wait from `now` until the next run
+    fn delay_from(&self, now: SystemTime) -> Duration {
+        match self {
+            Schedule::Interval(interval) => *interval,
+            Schedule::Cron(cron) => {
+                let now_secs = now
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                Duration::from_secs(cron.next_after(now_secs).saturating_sub(now_secs))
+            }
+        }
+    }
+}
+
+/// A parsed `minute hour day-of-month month day-of-week` expression
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Standard cron: when both day fields are restricted, either one matching is enough
+    day_fields_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("Cron expression must have 5 fields: {}", expr));
+        }
+
+        let dom_restricted = fields[2] != "*";
+        let dow_restricted = fields[4] != "*";
+
+        // Sunday may be written as 0 or 7
+        let mut days_of_week = parse_field(fields[4], 0, 7).context("invalid day-of-week field")?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        let schedule = Self {
+            minutes: parse_field(fields[0], 0, 59).context("invalid minute field")?,
+            hours: parse_field(fields[1], 0, 23).context("invalid hour field")?,
+            days_of_month: parse_field(fields[2], 1, 31).context("invalid day-of-month field")?,
+            months: parse_field(fields[3], 1, 12).context("invalid month field")?,
+            days_of_week,
+            day_fields_restricted: dom_restricted && dow_restricted,
+        };
+
+        if !schedule.can_match() {
+            return Err(anyhow!("Cron expression never matches a date: {}", expr));
+        }
+        Ok(schedule)
+    }
+
+    /// Whether some month has one of the days of the month; when both day fields are restricted
+    /// the day-of-week alone is enough
+    fn can_match(&self) -> bool {
+        const MONTH_LENGTHS: [u64; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+        self.day_fields_restricted
+            || (1..=12u64).any(|month| {
+                self.months & (1 << month) != 0
+                    && (1..=MONTH_LENGTHS[month as usize - 1]).any(|day| self.days_of_month & (1 << day) != 0)
+            })
+    }
+
+    /// First matching minute strictly after `after` (Unix seconds)
+    fn next_after(&self, after: u64) -> u64 {
+        let mut minute_start = (after / 60 + 1) * 60;
+        // parse() refuses expressions that never match, and the rarest date, February 29, recurs
+        // within 8 years
+        for _ in 0..(9 * 366 * 24 * 60) {
+            if self.matches(minute_start) {
+                return minute_start;
+            }
+            minute_start += 60;
+        }
+        minute_start
+    }
+
+    fn matches(&self, timestamp: u64) -> bool {
+        let days = timestamp / 86_400;
+        let secs_of_day = timestamp % 86_400;
+        let (_, month, day) = civil_from_days(days as i64);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4) % 7;
+
+        let bit = |mask: u64, value: u64| mask & (1 << value) != 0;
+
+        let dom_match = bit(self.days_of_month, day);
+        let dow_match = bit(self.days_of_week, weekday);
+        let day_match = if self.day_fields_restricted {
+            dom_match || dow_match
+        } else {
+            dom_match && dow_match
+        };
+
+        bit(self.minutes, (secs_of_day / 60) % 60)
+            && bit(self.hours, secs_of_day / 3600)
+            && bit(self.months, month)
+            && day_match
+    }
+}
+
+/// Parse one cron field (`*`, `*/n`, `a`, `a-b`, `a-b/n`, comma lists) into a bitmask
+fn parse_field(field: &str, min: u64, max: u64) -> anyhow::Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("step must be greater than zero"));
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value = range.parse()?;
+            // "5/10" means every 10 starting at 5
+            (value, if step > 1 { max } else { value })
+        };
+
+        if start < min || end > max || start > end {
+            return Err(anyhow!("{} is out of range {}-{}", part, min, max));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+type TaskFn = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct ScheduledTask {
+    name: &'static str,
+    schedule: Schedule,
+    run: TaskFn,
+}
+
+/// Hosts the service's periodic background tasks
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a task; runs of the same task never overlap
+    pub fn add<F, Fut>(&mut self, name: &'static str, schedule: Schedule, task: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.push(ScheduledTask {
+            name,
+            schedule,
+            run: Arc::new(move || Box::pin(task())),
+        });
+    }
+
+    /// Spawn one loop per registered task
+    pub fn start(self) {
+        for task in self.tasks {
+            tracing::info!("Scheduled task '{}' registered: {:?}", task.name, task.schedule);
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(task.schedule.delay_from(SystemTime::now())).await;
+
+                    // Run each tick on its own task so a panic is logged instead of ending the loop
+                    let started = Instant::now();
+                    match tokio::spawn((task.run)()).await {
+                        Ok(()) => {
+                            tracing::debug!("Scheduled task '{}' finished in {:?}", task.name, started.elapsed())
+                        }
+                        Err(e) => tracing::error!("Scheduled task '{}' failed: {}", task.name, e),
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!(matches!(Schedule::parse("60").unwrap(), Schedule::Interval(d) if d == Duration::from_secs(60)));
+        assert!(matches!(Schedule::parse("*/5 * * * *").unwrap(), Schedule::Cron(_)));
+        assert!(Schedule::parse("0").is_err());
+        assert!(Schedule::parse("61 * * * *").is_err());
+        assert!(Schedule::parse("* * *").is_err());
+        assert!(Schedule::parse("0 0 31 2 *").is_err());
+        assert!(Schedule::parse("0 0 31 4,6 *").is_err());
+        assert!(Schedule::parse("0 0 29 2 *").is_ok());
+        assert!(Schedule::parse("0 0 31 2 1").is_ok());
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        // 2024-01-01 00:00:00 UTC, a Monday
+        let base = 1_704_067_200;
+
+        let every_15 = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(every_15.next_after(base), base + 15 * 60);
+
+        let daily_3am = CronSchedule::parse("0 3 * * *").unwrap();
+        assert_eq!(daily_3am.next_after(base), base + 3 * 3600);
+
+        // Next Sunday is 2024-01-07
+        let sundays = CronSchedule::parse("30 2 * * 0").unwrap();
+        assert_eq!(sundays.next_after(base), base + 6 * 86_400 + 2 * 3600 + 30 * 60);
+
+        let first_of_month = CronSchedule::parse("0 0 1 * *").unwrap();
+        assert_eq!(first_of_month.next_after(base), base + 31 * 86_400);
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use axum::body::Bytes;
+use rand::RngCore;
+
+use crate::{crypto::CryptoService, error::AppError, services::telegram::TelegramService};
+
+const TEST_FILENAME: &str = "rustgram-self-test.bin";
+
+/// Upload a tiny encrypted file to the storage chat, fetch it back, check it decrypts to what was
+/// sent and delete it again, so a bot without the rights the server needs is caught before it
+/// takes uploads
+pub async fn telegram_roundtrip(telegram: &TelegramService, key: &[u8; 32]) -> anyhow::Result<()> {
+    let chat_id = telegram.chat_id();
+    telegram
+        .test_connection()
+        .await
+        .context("Telegram self-test: getMe failed, check TELEGRAM_BOT_TOKEN")?;
+
+    let mut payload = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut payload);
+    let crypto = CryptoService::new(key);
+    let encrypted = crypto.encrypt_data(&payload)?;
+
+    let message = telegram
+        .upload_file(Bytes::from(encrypted), TEST_FILENAME)
+        .await
+        .map_err(|e| failure("upload a test file to", chat_id, &e))?;
+    let verified = match &message.document {
+        Some(document) => verify_download(telegram, &crypto, &document.file_id, &payload).await,
+        None => Err(anyhow::anyhow!("Telegram self-test: the uploaded test file came back without a document")),
+    };
+
+    // Clean up whether or not the download worked, but report the first failure
+    let deleted = telegram
+        .delete_message(chat_id, message.message_id)
+        .await
+        .map_err(|e| failure("delete the test message from", chat_id, &e));
+    verified?;
+    deleted
+}
+
+async fn verify_download(
+    telegram: &TelegramService,
+    crypto: &CryptoService,
+    file_id: &str,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    let downloaded = telegram
+        .download_file_by_id(file_id)
+        .await
+        .map_err(|e| failure("download the test file from", telegram.chat_id(), &e))?;
+    let decrypted = crypto
+        .decrypt_data(&downloaded)
+        .context("Telegram self-test: the downloaded test file does not decrypt with ENCRYPTION_KEY")?;
+    anyhow::ensure!(decrypted == payload, "Telegram self-test: the downloaded test file differs from the upload");
+    Ok(())
+}
+
+fn failure(step: &str, chat_id: i64, error: &AppError) -> anyhow::Error {
+    let mut message = format!("Telegram self-test: could not {} chat {}: {}", step, chat_id, error);
+    if let Some(hint) = permission_hint(error) {
+        message.push_str(&format!(" ({})", hint));
+    }
+    anyhow::anyhow!(message)
+}
+
+/// What to fix when Telegram refused the bot, from the error description it sent
+fn permission_hint(error: &AppError) -> Option<&'static str> {
+    let AppError::TelegramError(description) = error else {
+        return None;
+    };
+    let description = description.to_ascii_lowercase();
+    if description.contains("chat not found") {
+        Some("check TELEGRAM_CHAT_ID and that the bot has been added to the chat")
+    } else if description.contains("message can't be deleted") {
+        Some("the bot needs the Delete Messages admin right in the chat")
+    } else if description.contains("not enough rights") || description.contains("have no rights") {
+        Some("the bot needs the right to post messages and send documents in the chat")
+    } else if description.contains("forbidden") || description.contains("kicked") {
+        Some("the bot is not a member of the chat, or was removed from it")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_hint() {
+        let hint = |description: &str| permission_hint(&AppError::TelegramError(description.to_string()));
+        assert!(hint("Bad Request: chat not found").unwrap().contains("TELEGRAM_CHAT_ID"));
+        assert!(hint("Bad Request: message can't be deleted").unwrap().contains("Delete Messages"));
+        assert!(hint("Bad Request: not enough rights to send documents to the chat").is_some());
+        assert!(hint("Forbidden: bot is not a member of the channel chat").is_some());
+        assert!(hint("Too Many Requests: retry after 5").is_none());
+        assert!(permission_hint(&AppError::Gone).is_none());
+    }
+}
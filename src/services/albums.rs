@@ -0,0 +1,278 @@
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::unix_timestamp,
+};
+
+pub const MAX_ALBUM_IMAGES: usize = 500;
+const MAX_TITLE_CHARS: usize = 200;
+
+// A named, ordered set of images shared as one /gallery link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Album {
+    pub id: String,
+    pub title: String,
+    // Uploader subject of the creator, as recorded in the index
+    pub owner: String,
+    pub image_ids: Vec<String>,
+    // HMAC of the gallery password under the master key; open galleries have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    pub created_at: u64,
+}
+
+// Settings chosen when an album is created
+#[derive(Debug, Default, Deserialize)]
+pub struct AlbumSettings {
+    #[serde(default)]
+    pub title: String,
+    pub image_ids: Vec<String>,
+    pub password: Option<String>,
+}
+
+impl Album {
+    pub fn is_protected(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    /// Check a gallery password against the stored MAC in constant time
+    pub fn verify_password(&self, password: &str, key: &[u8; 32]) -> bool {
+        let Some(Ok(hash)) = self.password_hash.as_deref().map(hex::decode) else {
+            return false;
+        };
+        CryptoService::verify_hmac_sha256(key, password_message(&self.id, password).as_bytes(), &hash)
+    }
+
+    /// Cookie value proving the gallery password was entered; changes with the password
+    pub fn session_token(&self, key: &[u8; 32]) -> Option<String> {
+        self.password_hash
+            .as_deref()
+            .map(|hash| hex::encode(CryptoService::hmac_sha256(key, session_message(hash).as_bytes())))
+    }
+
+    /// Check a gallery cookie value in constant time
+    pub fn verify_session_token(&self, key: &[u8; 32], token: &str) -> bool {
+        let (Some(hash), Ok(token)) = (self.password_hash.as_deref(), hex::decode(token)) else {
+            return false;
+        };
+        CryptoService::verify_hmac_sha256(key, session_message(hash).as_bytes(), &token)
+    }
+}
+
+fn session_message(password_hash: &str) -> String {
+    format!("gallery-session:{}", password_hash)
+}
+
+pub struct AlbumStore {
+    albums: Mutex<HashMap<String, Album>>,
+    path: Option<PathBuf>,
+}
+
+impl AlbumStore {
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let albums = match &path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read(path)?;
+                let list: Vec<Album> = serde_json::from_slice(&data)?;
+                list.into_iter().map(|album| (album.id.clone(), album)).collect()
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            albums: Mutex::new(albums),
+            path,
+        })
+    }
+
+    /// Create an album owned by `owner`. Whether the images exist and belong to `owner` is
+    /// the caller's to check.
+    pub fn create(&self, owner: &str, settings: AlbumSettings, key: &[u8; 32]) -> Result<Album> {
+        let title = settings.title.trim();
+        if title.chars().count() > MAX_TITLE_CHARS {
+            return Err(AppError::ValidationError(format!(
+                "Album title must be at most {} characters",
+                MAX_TITLE_CHARS
+            )));
+        }
+        if settings.image_ids.is_empty() || settings.image_ids.len() > MAX_ALBUM_IMAGES {
+            return Err(AppError::ValidationError(format!(
+                "An album holds between 1 and {} images",
+                MAX_ALBUM_IMAGES
+            )));
+        }
+        if settings.password.as_deref() == Some("") {
+            return Err(AppError::ValidationError("Album password must not be empty".to_string()));
+        }
+
+        let mut image_ids = Vec::with_capacity(settings.image_ids.len());
+        for id in settings.image_ids {
+            if !image_ids.contains(&id) {
+                image_ids.push(id);
+            }
+        }
+
+        let id = generate_album_id();
+        let album = Album {
+            password_hash: settings.password.map(|password| password_hash(&id, &password, key)),
+            id,
+            title: title.to_string(),
+            owner: owner.to_string(),
+            image_ids,
+            created_at: unix_timestamp(),
+        };
+
+        let mut albums = self.lock()?;
+        albums.insert(album.id.clone(), album.clone());
+        self.persist(&albums)?;
+        Ok(album)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Album>> {
+        Ok(self.lock()?.get(id).cloned())
+    }
+
+    /// Append an image to an album, returning the updated album. Whether the image belongs to the
+    /// album's owner is the caller's to check.
+    pub fn add_image(&self, id: &str, image_id: &str) -> Result<Option<Album>> {
+        let mut albums = self.lock()?;
+        let Some(album) = albums.get_mut(id) else {
+            return Ok(None);
+        };
+        if !album.image_ids.iter().any(|existing| existing == image_id) {
+            if album.image_ids.len() >= MAX_ALBUM_IMAGES {
+                return Err(AppError::ValidationError(format!(
+                    "An album holds between 1 and {} images",
+                    MAX_ALBUM_IMAGES
+                )));
+            }
+            album.image_ids.push(image_id.to_string());
+        }
+        let updated = album.clone();
+        self.persist(&albums)?;
+        Ok(Some(updated))
+    }
+
+    /// Take an image out of an album, keeping the image; an album's last image can't be removed
+    pub fn remove_image(&self, id: &str, image_id: &str) -> Result<Option<Album>> {
+        let mut albums = self.lock()?;
+        let Some(album) = albums.get_mut(id) else {
+            return Ok(None);
+        };
+        if album.image_ids.iter().all(|existing| existing == image_id) {
+            return Err(AppError::ValidationError(
+                "An album can't be left empty; delete the album instead".to_string(),
+            ));
+        }
+        album.image_ids.retain(|existing| existing != image_id);
+        let updated = album.clone();
+        self.persist(&albums)?;
+        Ok(Some(updated))
+    }
+
+    /// Albums created by `owner`, newest first
+    pub fn list_owned(&self, owner: &str) -> Result<Vec<Album>> {
+        let mut owned: Vec<Album> = self.lock()?.values().filter(|album| album.owner == owner).cloned().collect();
+        owned.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+        Ok(owned)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<Album>> {
+        let mut albums = self.lock()?;
+        let removed = albums.remove(id);
+        if removed.is_some() {
+            self.persist(&albums)?;
+        }
+        Ok(removed)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Album>>> {
+        self.albums
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire album lock".to_string()))
+    }
+
+    fn persist(&self, albums: &HashMap<String, Album>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let list: Vec<&Album> = albums.values().collect();
+        let data = serde_json::to_vec(&list)?;
+        let tmp_path = path.with_extension("tmp");
+
+        std::fs::write(&tmp_path, data)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| AppError::InternalError(format!("Failed to persist albums: {}", e)))
+    }
+}
+
+fn generate_album_id() -> String {
+    let mut bytes = [0u8; 9];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn password_hash(album_id: &str, password: &str, key: &[u8; 32]) -> String {
+    hex::encode(CryptoService::hmac_sha256(key, password_message(album_id, password).as_bytes()))
+}
+
+fn password_message(album_id: &str, password: &str) -> String {
+    format!("album-password:{}:{}", album_id, password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3u8; 32];
+
+    fn settings(image_ids: &[&str], password: Option<&str>) -> AlbumSettings {
+        AlbumSettings {
+            title: " Holiday ".to_string(),
+            image_ids: image_ids.iter().map(|id| id.to_string()).collect(),
+            password: password.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn test_create_and_password() {
+        let store = AlbumStore::open(None).unwrap();
+
+        let open = store.create("key:alice", settings(&["a", "b", "a"], None), &KEY).unwrap();
+        assert_eq!(open.title, "Holiday");
+        assert_eq!(open.image_ids, vec!["a", "b"]);
+        assert!(!open.is_protected());
+        assert!(!open.verify_password("", &KEY));
+        assert!(open.session_token(&KEY).is_none());
+
+        let locked = store.create("key:alice", settings(&["c"], Some("hunter2")), &KEY).unwrap();
+        assert!(locked.verify_password("hunter2", &KEY));
+        assert!(!locked.verify_password("hunter3", &KEY));
+        assert!(!locked.verify_password("hunter2", &[4u8; 32]));
+        assert_ne!(locked.session_token(&KEY), locked.password_hash);
+        assert!(locked.verify_session_token(&KEY, &locked.session_token(&KEY).unwrap()));
+        assert!(!locked.verify_session_token(&KEY, "00"));
+        assert!(!open.verify_session_token(&KEY, ""));
+
+        assert_eq!(store.list_owned("key:alice").unwrap().len(), 2);
+        assert!(store.list_owned("key:bob").unwrap().is_empty());
+
+        assert!(store.create("key:alice", settings(&[], None), &KEY).is_err());
+        assert!(store.create("key:alice", settings(&["a"], Some("")), &KEY).is_err());
+
+        let grown = store.add_image(&open.id, "c").unwrap().unwrap();
+        assert_eq!(grown.image_ids, vec!["a", "b", "c"]);
+        assert_eq!(store.remove_image(&open.id, "a").unwrap().unwrap().image_ids, vec!["b", "c"]);
+        assert!(store.remove_image(&locked.id, "c").is_err());
+
+        store.remove(&open.id).unwrap();
+        assert!(store.get(&open.id).unwrap().is_none());
+        assert!(store.get(&locked.id).unwrap().is_some());
+    }
+}
@@ -0,0 +1,340 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    middleware::geo::current_location,
+    models::unix_timestamp,
+    services::log_queue::LogQueue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Upload,
+    UploadFailed,
+    View,
+    InfoView,
+    Delete,
+    DeleteFailed,
+    DeleteDenied,
+    CleanupDelete,
+    // Right-to-erasure job; carries counts only, never the erased subject
+    Erasure,
+    // Backend migration job; carries counts only
+    Migration,
+    // Pass over a storage chat for unreferenced messages; carries counts only
+    OrphanScan,
+    // Periodic counts of every action, including those sampled out of the chat
+    Summary,
+    // Client turned away for a while by the abuse heuristics
+    SoftBan,
+}
+
+/// One auditable action. Image IDs and API keys are only kept as short hashes, enough to
+/// correlate events without letting the log grant access to anything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    // Size, MIME type, error or reason, depending on the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    // Where the client was located, when GeoIP databases are configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+}
+
+impl AuditEvent {
+    /// Events recorded while serving a request carry the client's location, if known
+    pub fn new(action: AuditAction) -> Self {
+        let location = current_location().unwrap_or_default();
+        Self {
+            action,
+            timestamp: unix_timestamp(),
+            id_hash: None,
+            ip: None,
+            key: None,
+            detail: None,
+            country: location.country,
+            asn: location.asn,
+        }
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id_hash = Some(short_hash(id));
+        self
+    }
+
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip.to_string());
+        self
+    }
+
+    pub fn key(mut self, api_key: Option<&str>) -> Self {
+        self.key = api_key.map(short_hash);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+// Same truncated SHA-256 the usage store keys API keys by
+fn short_hash(value: &str) -> String {
+    hex::encode(&CryptoService::hash_data(value.as_bytes())[..8])
+}
+
+/// Recent audit events kept for GET /admin/audit, optionally appended to a JSON-lines file,
+/// and queued for AUDIT_CHAT_ID (or the log chat) as JSON, encrypted unless AUDIT_ENCRYPT is off
+pub struct AuditLog {
+    events: Mutex<VecDeque<AuditEvent>>,
+    capacity: usize,
+    path: Option<PathBuf>,
+    queue: LogQueue,
+    // Key events are encrypted with before they leave the server
+    encryption_key: Option<[u8; 32]>,
+    // Only every Nth event of these actions is posted; the rest show up in the summary
+    sample_rates: HashMap<AuditAction, u64>,
+    // Events seen and posted per action since the last summary
+    counts: Mutex<BTreeMap<AuditAction, (u64, u64)>>,
+}
+
+impl AuditLog {
+    /// Load the newest `capacity` events from `path` if it exists
+    pub fn open(
+        path: Option<PathBuf>,
+        capacity: usize,
+        queue: LogQueue,
+        encryption_key: Option<[u8; 32]>,
+    ) -> anyhow::Result<Self> {
+        let mut events = VecDeque::new();
+        if let Some(path) = path.as_ref().filter(|path| path.exists()) {
+            for line in std::fs::read_to_string(path)?.lines().filter(|line| !line.is_empty()) {
+                events.push_back(serde_json::from_str(line)?);
+                if events.len() > capacity {
+                    events.pop_front();
+                }
+            }
+        }
+
+        Ok(Self {
+            events: Mutex::new(events),
+            capacity,
+            path,
+            queue,
+            encryption_key,
+            sample_rates: HashMap::new(),
+            counts: Mutex::default(),
+        })
+    }
+
+    /// Post only 1 in N events of the named actions, as configured by LOG_SAMPLE_RATES
+    pub fn with_sampling(mut self, rates: &HashMap<String, u64>) -> anyhow::Result<Self> {
+        for (name, &rate) in rates {
+            let action = serde_json::from_value(serde_json::Value::String(name.clone()))
+                .map_err(|_| anyhow::anyhow!("LOG_SAMPLE_RATES: unknown action '{}'", name))?;
+            self.sample_rates.insert(action, rate.max(1));
+        }
+        Ok(self)
+    }
+
+    /// Store the event locally and queue it for the audit chat. Never fails the caller:
+    /// problems are logged, since a request should not be refused over its audit trail.
+    pub fn record(&self, event: AuditEvent) {
+        if let Err(e) = self.try_record(event) {
+            tracing::error!("Failed to record audit event: {}", e);
+        }
+    }
+
+    fn try_record(&self, event: AuditEvent) -> Result<()> {
+        let line = serde_json::to_string(&event)?;
+        let action = event.action;
+        self.remember(event, &line)?;
+        if !self.should_post(action) {
+            return Ok(());
+        }
+
+        let message = match &self.encryption_key {
+            Some(key) => general_purpose::STANDARD.encode(CryptoService::new(key).encrypt_data(line.as_bytes())?),
+            None => line,
+        };
+        self.queue.push(message);
+        Ok(())
+    }
+
+    fn should_post(&self, action: AuditAction) -> bool {
+        if action == AuditAction::Summary {
+            return true;
+        }
+        let rate = self.sample_rates.get(&action).copied().unwrap_or(1);
+        let mut counts = self.counts.lock().unwrap();
+        let (seen, posted) = counts.entry(action).or_default();
+        *seen += 1;
+        let post = (*seen - 1) % rate == 0;
+        if post {
+            *posted += 1;
+        }
+        post
+    }
+
+    /// Record a summary of the events seen since the last one, if there were any
+    pub fn record_summary(&self) {
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        if counts.is_empty() {
+            return;
+        }
+        let detail = counts
+            .iter()
+            .map(|(action, (seen, posted))| {
+                let name = serde_json::to_value(action).ok();
+                let name = name.as_ref().and_then(|name| name.as_str()).unwrap_or("unknown");
+                format!("{}: {} ({} posted)", name, seen, posted)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.record(AuditEvent::new(AuditAction::Summary).detail(detail));
+    }
+
+    fn remember(&self, event: AuditEvent, line: &str) -> Result<()> {
+        // Held while appending so a purge never rewrites the file under a concurrent append
+        let mut events = self.events.lock().unwrap();
+        events.push_back(event);
+        if events.len() > self.capacity {
+            events.pop_front();
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line))
+            .map_err(|e| AppError::InternalError(format!("Failed to write audit log: {}", e)))
+    }
+
+    /// Drop every event `matches` selects from memory and the log file, returning how many
+    /// were removed. Copies already posted to the audit chat are not touched.
+    pub fn purge(&self, matches: impl Fn(&AuditEvent) -> bool) -> Result<usize> {
+        let mut events = self.events.lock().unwrap();
+        let before = events.len();
+        events.retain(|event| !matches(event));
+        let removed = before - events.len();
+
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(removed);
+        };
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| AppError::InternalError(format!("Failed to read audit log: {}", e)))?;
+        let mut kept = String::with_capacity(data.len());
+        let mut dropped = 0;
+        for line in data.lines().filter(|line| !line.is_empty()) {
+            if matches(&serde_json::from_str(line)?) {
+                dropped += 1;
+            } else {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, kept)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| AppError::InternalError(format!("Failed to rewrite audit log: {}", e)))?;
+        Ok(removed.max(dropped))
+    }
+
+    /// Newest events first, optionally only those of one action
+    pub fn recent(&self, action: Option<AuditAction>, limit: usize) -> Vec<AuditEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|event| action.is_none_or(|action| event.action == action))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_filters_and_reloads() {
+        let path = std::env::temp_dir().join(format!("rustgram-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let (queue, _rx) = LogQueue::new();
+        let log = AuditLog::open(Some(path.clone()), 2, queue.clone(), None).unwrap();
+        for action in [AuditAction::Upload, AuditAction::View, AuditAction::Delete] {
+            let event = AuditEvent::new(action).id("image-id").key(Some("secret"));
+            let line = serde_json::to_string(&event).unwrap();
+            log.remember(event, &line).unwrap();
+        }
+
+        let recent = log.recent(None, 10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].action, AuditAction::Delete);
+        assert_ne!(recent[0].key.as_deref(), Some("secret"));
+        assert_eq!(log.recent(Some(AuditAction::View), 10).len(), 1);
+
+        let reloaded = AuditLog::open(Some(path.clone()), 2, queue.clone(), None).unwrap();
+        assert_eq!(reloaded.recent(None, 10).len(), 2);
+
+        // The file still holds the upload that fell out of memory
+        assert_eq!(log.purge(|event| event.action != AuditAction::Delete).unwrap(), 2);
+        let reloaded = AuditLog::open(Some(path.clone()), 2, queue, None).unwrap();
+        assert_eq!(reloaded.recent(None, 10).len(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sampling_and_summary() {
+        let (queue, mut rx) = LogQueue::new();
+        let rates = HashMap::from([("view".to_string(), 3)]);
+        let log = AuditLog::open(None, 100, queue, None).unwrap().with_sampling(&rates).unwrap();
+        for _ in 0..7 {
+            log.record(AuditEvent::new(AuditAction::View));
+        }
+        log.record(AuditEvent::new(AuditAction::Delete));
+
+        let mut posted = 0;
+        while rx.try_recv().is_ok() {
+            posted += 1;
+        }
+        assert_eq!(posted, 4);
+        assert_eq!(log.recent(Some(AuditAction::View), 100).len(), 7);
+
+        log.record_summary();
+        let summary: AuditEvent = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(summary.detail.as_deref(), Some("view: 7 (3 posted), delete: 1 (1 posted)"));
+        log.record_summary();
+        assert!(rx.try_recv().is_err());
+
+        let unknown = HashMap::from([("peek".to_string(), 2)]);
+        let (queue, _rx) = LogQueue::new();
+        assert!(AuditLog::open(None, 1, queue, None).unwrap().with_sampling(&unknown).is_err());
+    }
+}
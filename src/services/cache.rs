@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::services::chaos::FaultInjector;
+
+/// In-memory LRU of decrypted image data keyed by Telegram file_id, bounded by total bytes.
+/// Renditions derived from an image are keyed "<file_id>#<variant>" and evicted with it.
+pub struct ImageCache {
+    max_bytes: usize,
+    inner: Mutex<CacheInner>,
+    // CHAOS_MODE: lookups that miss and writes that are dropped at random
+    faults: Option<FaultInjector>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    // Data plus the tick it was last used at
+    entries: HashMap<String, (Bytes, u64)>,
+    total_bytes: usize,
+    tick: u64,
+}
+
+impl ImageCache {
+    // A limit of 0 disables caching
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::new(CacheInner::default()),
+            faults: None,
+        }
+    }
+
+    /// Miss lookups and drop writes at random as configured by CHAOS_MODE
+    pub fn with_fault_injection(mut self, faults: Option<FaultInjector>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    fn inject_fault(&self) -> bool {
+        self.faults.as_ref().is_some_and(FaultInjector::cache_fault)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    pub fn get(&self, file_id: &str) -> Option<Bytes> {
+        if self.inject_fault() {
+            return None;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.entries.get_mut(file_id).map(|(data, used)| {
+            *used = tick;
+            data.clone()
+        })
+    }
+
+    /// Store data, evicting the least recently used entries to stay within the limit
+    pub fn insert(&self, file_id: &str, data: Bytes) {
+        if data.len() > self.max_bytes || self.inject_fault() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((old, _)) = inner.entries.remove(file_id) {
+            inner.total_bytes -= old.len();
+        }
+
+        while inner.total_bytes + data.len() > self.max_bytes {
+            let Some(oldest) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some((evicted, _)) = inner.entries.remove(&oldest) {
+                inner.total_bytes -= evicted.len();
+            }
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.total_bytes += data.len();
+        inner.entries.insert(file_id.to_string(), (data, tick));
+    }
+
+    pub fn remove(&self, file_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let variant_prefix = format!("{}#", file_id);
+        let keys: Vec<String> = inner
+            .entries
+            .keys()
+            .filter(|key| *key == file_id || key.starts_with(&variant_prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some((data, _)) = inner.entries.remove(&key) {
+                inner.total_bytes -= data.len();
+            }
+        }
+    }
+
+    /// Key of a rendition derived from the image stored under `file_id`
+    pub fn variant_key(file_id: &str, variant: &str) -> String {
+        format!("{}#{}", file_id, variant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = ImageCache::new(10);
+        cache.insert("a", Bytes::from_static(b"aaaa"));
+        cache.insert("b", Bytes::from_static(b"bbbb"));
+
+        // Touch "a" so "b" becomes the least recently used
+        assert!(cache.get("a").is_some());
+        cache.insert("c", Bytes::from_static(b"cccc"));
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+
+        cache.insert("huge", Bytes::from_static(b"0123456789abc"));
+        assert!(cache.get("huge").is_none());
+
+        cache.insert(&ImageCache::variant_key("a", "wm"), Bytes::from_static(b"w"));
+        cache.remove("a");
+        assert!(cache.get("a").is_none());
+        assert!(cache.get(&ImageCache::variant_key("a", "wm")).is_none());
+        assert!(!ImageCache::new(0).is_enabled());
+    }
+}
@@ -0,0 +1,84 @@
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+// Verification sits in front of every anonymous upload
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Provider {
+    Turnstile,
+    HCaptcha,
+}
+
+impl Provider {
+    fn verify_url(self) -> &'static str {
+        match self {
+            Provider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            Provider::HCaptcha => "https://api.hcaptcha.com/siteverify",
+        }
+    }
+}
+
+// Both providers answer siteverify in this shape
+#[derive(Debug, Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Checks CAPTCHA tokens with Cloudflare Turnstile or hCaptcha
+pub struct CaptchaVerifier {
+    client: Client,
+    provider: Provider,
+    secret: String,
+}
+
+impl CaptchaVerifier {
+    /// The verifier CAPTCHA_PROVIDER and CAPTCHA_SECRET describe, or `None` when CAPTCHA is off
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let provider = match config.captcha_provider.as_deref() {
+            None | Some("") | Some("off") => return Ok(None),
+            Some("turnstile") => Provider::Turnstile,
+            Some("hcaptcha") => Provider::HCaptcha,
+            Some(other) => anyhow::bail!("CAPTCHA_PROVIDER must be turnstile or hcaptcha, got '{}'", other),
+        };
+        let secret = config
+            .captcha_secret
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("CAPTCHA_PROVIDER is set but CAPTCHA_SECRET is missing"))?;
+
+        Ok(Some(Self { client: Client::new(), provider, secret }))
+    }
+
+    /// Ask the provider whether `token` is a solved, unused challenge. The client IP is not sent,
+    /// since it may already have been masked by PRIVACY_MODE.
+    pub async fn verify(&self, token: Option<&str>) -> Result<()> {
+        let token = token.filter(|token| !token.is_empty()).ok_or(AppError::CaptchaFailed)?;
+
+        let response: VerifyResponse = self
+            .client
+            .post(self.provider.verify_url())
+            .timeout(VERIFY_TIMEOUT)
+            .form(&[("secret", self.secret.as_str()), ("response", token)])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AppError::InternalError(format!("CAPTCHA verification request failed: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| AppError::InternalError(format!("Invalid CAPTCHA verification response: {}", e)))?;
+
+        if !response.success {
+            tracing::info!("CAPTCHA token rejected: {:?}", response.error_codes);
+            return Err(AppError::CaptchaFailed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::Client;
+use std::sync::Arc;
+
+use crate::{
+    config::Config,
+    crypto::CryptoService,
+    error::{AppError, Result},
+    handlers::image::variant_segment,
+    models::unix_timestamp,
+};
+
+/// Public URL for `path` (e.g. "/image/<id>"): on the CDN when configured, signed when a token key is set
+pub fn public_url(config: &Config, path: &str) -> String {
+    let Some(cdn_base_url) = &config.cdn_base_url else {
+        return format!("{}{}", config.public_base_url, path);
+    };
+
+    let url = format!("{}{}", cdn_base_url, path);
+    match &config.cdn_token_key {
+        Some(key) => {
+            let expires = unix_timestamp() + config.cdn_token_ttl_secs;
+            format!("{}?{}", url, sign_path(config.cdn_provider.as_deref(), key, path, expires))
+        }
+        None => url,
+    }
+}
+
+/// Query string authorizing `path` until `expires`, in the format the provider's edge verifies
+fn sign_path(provider: Option<&str>, key: &str, path: &str, expires: u64) -> String {
+    match provider {
+        // Bunny token authentication: base64url(SHA256(key + path + expires)) without padding
+        Some("bunny") => {
+            let digest = CryptoService::hash_data(format!("{}{}{}", key, path, expires).as_bytes());
+            format!("token={}&expires={}", general_purpose::URL_SAFE_NO_PAD.encode(digest), expires)
+        }
+        // Cloudflare WAF token authentication: "<timestamp>-<base64 HMAC-SHA256(key, path + timestamp)>"
+        _ => {
+            let mac = CryptoService::hmac_sha256(key.as_bytes(), format!("{}{}", path, expires).as_bytes());
+            let mac = general_purpose::STANDARD.encode(mac);
+            format!("verify={}-{}", expires, urlencode(&mac))
+        }
+    }
+}
+
+fn urlencode(value: &str) -> String {
+    value.replace('+', "%2B").replace('/', "%2F").replace('=', "%3D")
+}
+
+/// Paths a stored image can be requested at, which all have to be purged on deletion
+pub fn image_paths(encrypted_id: &str, tenant: Option<&str>) -> Vec<String> {
+    let prefix = tenant.map(|tenant| format!("/t/{}", tenant)).unwrap_or_default();
+    let mut paths: Vec<String> = ["image", "thumb", "info"]
+        .iter()
+        .map(|route| format!("{}/{}/{}", prefix, route, encrypted_id))
+        .collect();
+
+    // Immutable variants would otherwise stay cached at the edge for a year
+    if let Some(thumbnail) = variant_segment(encrypted_id, "thumb") {
+        paths.push(format!("{}/v/{}/{}", prefix, encrypted_id, thumbnail));
+    }
+    paths
+}
+
+/// Cache purges against the configured CDN provider's API
+pub struct CdnService {
+    client: Client,
+}
+
+impl Default for CdnService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CdnService {
+    pub fn new() -> Self {
+        Self { client: Client::new() }
+    }
+
+    /// Purge cached copies of `paths`; a no-op unless a CDN and its API token are configured
+    pub async fn purge(&self, config: &Config, paths: &[String]) -> Result<()> {
+        let (Some(cdn_base_url), Some(api_token)) = (&config.cdn_base_url, &config.cdn_api_token) else {
+            return Ok(());
+        };
+        let urls: Vec<String> = paths.iter().map(|path| format!("{}{}", cdn_base_url, path)).collect();
+
+        match config.cdn_provider.as_deref() {
+            Some("cloudflare") => {
+                let zone_id = config.cdn_zone_id.as_deref().ok_or_else(|| {
+                    AppError::ConfigError("CDN_ZONE_ID is required for Cloudflare purges".to_string())
+                })?;
+                let response = self
+                    .client
+                    .post(format!("https://api.cloudflare.com/client/v4/zones/{}/purge_cache", zone_id))
+                    .bearer_auth(api_token)
+                    .json(&serde_json::json!({ "files": urls }))
+                    .send()
+                    .await
+                    .map_err(|e| AppError::InternalError(format!("CDN purge failed: {}", e)))?;
+                check_status(response.status())
+            }
+            Some("bunny") => {
+                for url in urls {
+                    let response = self
+                        .client
+                        .post("https://api.bunny.net/purge")
+                        .query(&[("url", url.as_str())])
+                        .header("AccessKey", api_token)
+                        .send()
+                        .await
+                        .map_err(|e| AppError::InternalError(format!("CDN purge failed: {}", e)))?;
+                    check_status(response.status())?;
+                }
+                Ok(())
+            }
+            other => Err(AppError::ConfigError(format!("Unsupported CDN_PROVIDER: {:?}", other))),
+        }
+    }
+
+    /// Purge in the background so deletions never wait on the CDN
+    pub fn purge_in_background(self: &Arc<Self>, config: Arc<Config>, paths: Vec<String>) {
+        if config.cdn_api_token.is_none() {
+            return;
+        }
+        let cdn = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cdn.purge(&config, &paths).await {
+                tracing::error!("Failed to purge {} CDN paths: {}", paths.len(), e);
+            }
+        });
+    }
+}
+
+fn check_status(status: reqwest::StatusCode) -> Result<()> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(AppError::InternalError(format!("CDN purge returned status {}", status)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_path() {
+        let bunny = sign_path(Some("bunny"), "key", "/image/abc", 1700000000);
+        assert!(bunny.starts_with("token=") && bunny.ends_with("&expires=1700000000"));
+        assert_ne!(bunny, sign_path(Some("bunny"), "key", "/image/abd", 1700000000));
+
+        let cloudflare = sign_path(Some("cloudflare"), "key", "/image/abc", 1700000000);
+        assert!(cloudflare.starts_with("verify=1700000000-"));
+        assert!(!cloudflare.contains('+') && !cloudflare.contains('/'));
+    }
+
+    #[test]
+    fn test_image_paths() {
+        assert_eq!(image_paths("abc", None)[0], "/image/abc");
+        assert_eq!(image_paths("abc", Some("acme"))[1], "/t/acme/thumb/abc");
+    }
+}
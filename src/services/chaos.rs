@@ -0,0 +1,70 @@
+use rand::Rng;
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+/// Randomly slows down or fails Telegram calls and image cache lookups, to rehearse how the server
+/// copes with a flaky backend before it meets one in production. Only built with CHAOS_MODE on.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultInjector {
+    failure_percent: u8,
+    delay_percent: u8,
+    delay: Duration,
+}
+
+impl FaultInjector {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.chaos_mode.then(|| Self {
+            failure_percent: config.chaos_failure_percent,
+            delay_percent: config.chaos_delay_percent,
+            delay: Duration::from_millis(config.chaos_delay_ms),
+        })
+    }
+
+    /// Run before a Telegram call: maybe wait, then maybe fail it the way a Telegram outage would
+    pub async fn telegram_call(&self, method: &str) -> Result<()> {
+        let (delay, fail) = self.roll();
+        if delay {
+            tracing::debug!("Chaos: delaying {} by {:?}", method, self.delay);
+            tokio::time::sleep(self.delay).await;
+        }
+        if fail {
+            tracing::debug!("Chaos: failing {}", method);
+            return Err(AppError::TelegramError(format!("Injected fault: {} timed out", method)));
+        }
+        Ok(())
+    }
+
+    /// Whether a cache lookup should miss, or a cache write be dropped
+    pub fn cache_fault(&self) -> bool {
+        chance(self.failure_percent)
+    }
+
+    fn roll(&self) -> (bool, bool) {
+        (chance(self.delay_percent), chance(self.failure_percent))
+    }
+}
+
+fn chance(percent: u8) -> bool {
+    percent > 0 && rand::thread_rng().gen_range(0..100) < percent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_extremes() {
+        let never = FaultInjector { failure_percent: 0, delay_percent: 0, delay: Duration::from_secs(60) };
+        let always = FaultInjector { failure_percent: 100, delay_percent: 100, delay: Duration::ZERO };
+        for _ in 0..50 {
+            assert!(never.telegram_call("getFile").await.is_ok());
+            assert!(!never.cache_fault());
+            assert!(matches!(always.telegram_call("getFile").await, Err(AppError::TelegramError(_))));
+            assert!(always.cache_fault());
+        }
+    }
+}
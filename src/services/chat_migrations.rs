@@ -0,0 +1,76 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use crate::error::{AppError, Result};
+
+// Longest old -> new chain followed, in case a bad record ever forms a loop
+const MAX_HOPS: usize = 8;
+
+/// Group chats Telegram upgraded to supergroups, old ID -> new ID, optionally persisted as JSON.
+/// References keep the chat ID they were stored with and are resolved through this at send time.
+#[derive(Default)]
+pub struct ChatMigrations {
+    chats: Mutex<HashMap<i64, i64>>,
+    path: Option<PathBuf>,
+}
+
+impl ChatMigrations {
+    /// Load recorded migrations from `path` if it exists; without a path they live in memory only
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let chats = match &path {
+            Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?)?,
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            chats: Mutex::new(chats),
+            path,
+        })
+    }
+
+    /// The chat `chat_id` lives in now
+    pub fn resolve(&self, chat_id: i64) -> i64 {
+        let chats = self.chats.lock().unwrap();
+        let mut current = chat_id;
+        for _ in 0..MAX_HOPS {
+            match chats.get(&current) {
+                Some(&next) => current = next,
+                None => break,
+            }
+        }
+        current
+    }
+
+    pub fn record(&self, from: i64, to: i64) -> Result<()> {
+        let mut chats = self.chats.lock().unwrap();
+        chats.insert(from, to);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&*chats)?)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| AppError::InternalError(format!("Failed to persist chat migrations: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_follows_chain() {
+        let path = std::env::temp_dir().join(format!("rustgram-chats-{}.json", uuid::Uuid::new_v4()));
+        let migrations = ChatMigrations::open(Some(path.clone())).unwrap();
+        migrations.record(-1, -100).unwrap();
+        migrations.record(-100, -200).unwrap();
+        migrations.record(-5, -5).unwrap();
+
+        let reloaded = ChatMigrations::open(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.resolve(-1), -200);
+        assert_eq!(reloaded.resolve(-7), -7);
+        assert_eq!(reloaded.resolve(-5), -5);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
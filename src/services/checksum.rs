@@ -0,0 +1,139 @@
+use axum::http::HeaderMap;
+
+use crate::error::{AppError, Result};
+
+// Headers, or trailers when sent after a chunked body, carrying the upload's checksums in hex
+pub const SHA256_HEADER: &str = "x-content-sha256";
+pub const CRC32C_HEADER: &str = "x-content-crc32c";
+
+// Castagnoli polynomial, bit-reversed
+const CRC32C_POLYNOMIAL: u32 = 0x82F6_3B78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC32C_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32C computed chunk by chunk, as used by iSCSI, ext4 and cloud storage APIs
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32c(u32);
+
+impl Crc32c {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = CRC32C_TABLE[((self.0 ^ *byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// Checksums a client sent for an upload, as headers, trailers or multipart fields, to be checked
+/// once the whole body has arrived
+#[derive(Debug, Default)]
+pub struct ExpectedChecksums {
+    sha256: Option<[u8; 32]>,
+    crc32c: Option<u32>,
+}
+
+impl ExpectedChecksums {
+    /// Multipart fields carrying a checksum rather than form data
+    pub fn is_field(name: &str) -> bool {
+        matches!(name, "sha256" | "crc32c")
+    }
+
+    /// Take the checksums found in `headers`; trailers read after the headers replace them
+    pub fn read_headers(&mut self, headers: &HeaderMap) -> Result<()> {
+        for (header, field) in [(SHA256_HEADER, "sha256"), (CRC32C_HEADER, "crc32c")] {
+            if let Some(value) = headers.get(header) {
+                let value = value
+                    .to_str()
+                    .map_err(|_| AppError::ValidationError(format!("{} must be hex digits", header)))?;
+                self.read_field(field, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the checksum in a multipart field named "sha256" or "crc32c"
+    pub fn read_field(&mut self, name: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        match name {
+            "sha256" => {
+                let digest = parse_hex::<32>(value)
+                    .ok_or_else(|| AppError::ValidationError("sha256 checksum must be 64 hex digits".to_string()))?;
+                self.sha256 = Some(digest);
+            }
+            "crc32c" => {
+                let crc = parse_hex::<4>(value)
+                    .ok_or_else(|| AppError::ValidationError("crc32c checksum must be 8 hex digits".to_string()))?;
+                self.crc32c = Some(u32::from_be_bytes(crc));
+            }
+            _ => return Err(AppError::ValidationError(format!("{} is not a checksum field", name))),
+        }
+        Ok(())
+    }
+
+    pub fn verify(&self, sha256: &[u8; 32], crc32c: u32) -> Result<()> {
+        if self.sha256.is_some_and(|expected| expected != *sha256) {
+            return Err(AppError::ChecksumMismatch { algorithm: "sha256" });
+        }
+        if self.crc32c.is_some_and(|expected| expected != crc32c) {
+            return Err(AppError::ChecksumMismatch { algorithm: "crc32c" });
+        }
+        Ok(())
+    }
+}
+
+// Exactly N bytes as 2N hex digits of either case; signs, prefixes and separators are refused
+fn parse_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    hex::decode(value).ok()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoService;
+
+    #[test]
+    fn test_verify_checksums() {
+        // Check value from the CRC catalogue, fed in two chunks
+        let mut crc = Crc32c::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xE306_9283);
+
+        let sha256 = CryptoService::hash_data(b"123456789");
+        let mut headers = HeaderMap::new();
+        headers.insert(CRC32C_HEADER, "E3069283".parse().unwrap());
+        let mut expected = ExpectedChecksums::default();
+        expected.read_headers(&headers).unwrap();
+        expected.read_field("sha256", &hex::encode(sha256)).unwrap();
+        assert!(expected.read_field("filename", "x.png").is_err());
+        assert!(expected.verify(&sha256, 0xE306_9283).is_ok());
+
+        let mismatch = expected.verify(&sha256, 0xE306_9284).unwrap_err();
+        assert!(matches!(mismatch, AppError::ChecksumMismatch { algorithm: "crc32c" }));
+        assert!(matches!(expected.verify(&[0; 32], 0xE306_9283), Err(AppError::ChecksumMismatch { algorithm: "sha256" })));
+        for malformed in ["nothex", "+3069283", "0xe30692", "e306928", "e3069283e3"] {
+            assert!(matches!(expected.read_field("crc32c", malformed), Err(AppError::ValidationError(_))), "{}", malformed);
+        }
+    }
+}
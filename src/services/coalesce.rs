@@ -0,0 +1,86 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use crate::error::Result;
+
+type InFlight<T> = Shared<BoxFuture<'static, Result<T>>>;
+
+/// Runs at most one instance of a keyed operation at a time; concurrent callers share its result
+pub struct RequestCoalescer<T: Clone> {
+    in_flight: Mutex<HashMap<String, InFlight<T>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for RequestCoalescer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> RequestCoalescer<T> {
+    pub fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Await the in-flight operation for `key`, starting it with `start` if there is none
+    pub async fn run<F>(&self, key: &str, start: impl FnOnce() -> F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        let operation = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight
+                .entry(key.to_string())
+                .or_insert_with(|| start().boxed().shared())
+                .clone()
+        };
+
+        let result = operation.clone().await;
+
+        // Whoever finishes first clears the entry; a newer operation under the same key is left alone
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(key).is_some_and(|current| current.ptr_eq(&operation)) {
+            in_flight.remove(key);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_operation() {
+        let coalescer = Arc::new(RequestCoalescer::<u32>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let fetch = |coalescer: Arc<RequestCoalescer<u32>>, calls: Arc<AtomicUsize>| async move {
+            coalescer
+                .run("image", move || async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(7)
+                })
+                .await
+        };
+
+        let (a, b) = tokio::join!(
+            fetch(coalescer.clone(), calls.clone()),
+            fetch(coalescer.clone(), calls.clone())
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (7, 7));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Finished operations are not cached
+        fetch(coalescer.clone(), calls.clone()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
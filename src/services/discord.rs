@@ -0,0 +1,193 @@
+use bytes::Bytes;
+use reqwest::{multipart, Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+const DEFAULT_API_URL: &str = "https://discord.com/api/v10";
+
+// Longest rate-limit pause honoured before giving up on a request
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct DiscordMessage {
+    id: String,
+    #[serde(default)]
+    attachments: Vec<DiscordAttachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordAttachment {
+    id: String,
+    // Signed CDN link that expires, so it is fetched fresh from the message on every download
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    retry_after: f64,
+}
+
+/// A file sent to the storage channel: its message, and the attachment holding the data
+pub struct StoredAttachment {
+    pub message_id: i64,
+    pub attachment_id: String,
+}
+
+/// Stores files as attachments of bot messages in one Discord channel, as an alternative or
+/// overflow to Telegram. Errors surface as `TelegramError`, the server's "external service
+/// unavailable" error.
+pub struct DiscordService {
+    client: Client,
+    bot_token: String,
+    channel_id: u64,
+    api_url: String,
+}
+
+impl DiscordService {
+    pub fn new(bot_token: String, channel_id: u64) -> Self {
+        Self { client: Client::new(), bot_token, channel_id, api_url: DEFAULT_API_URL.to_string() }
+    }
+
+    /// The service for DISCORD_BOT_TOKEN and DISCORD_CHANNEL_ID, when both are set
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let (token, channel_id) = (config.discord_bot_token.clone()?, config.discord_channel_id?);
+        Some(Self::new(token, channel_id).with_api_url(&config.discord_api_url))
+    }
+
+    /// Send requests to another API server, such as a fake one in tests
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn message_url(&self, message_id: Option<i64>) -> String {
+        match message_id {
+            Some(id) => format!("{}/channels/{}/messages/{}", self.api_url, self.channel_id, id),
+            None => format!("{}/channels/{}/messages", self.api_url, self.channel_id),
+        }
+    }
+
+    /// Post `data` as the attachment of a new message
+    pub async fn upload_file(&self, data: Bytes, filename: &str) -> Result<StoredAttachment> {
+        let length = data.len() as u64;
+        let response = self
+            .send(|| {
+                let part = multipart::Part::stream_with_length(data.clone(), length)
+                    .file_name(filename.to_string())
+                    .mime_str("application/octet-stream")
+                    .map_err(|e| AppError::InternalError(e.to_string()))?;
+                let form = multipart::Form::new().part("files[0]", part);
+                Ok(self.client.post(self.message_url(None)).multipart(form))
+            })
+            .await?;
+
+        let message: DiscordMessage = response.json().await?;
+        let attachment = message
+            .attachments
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::TelegramError("Discord message has no attachment".to_string()))?;
+        Ok(StoredAttachment { message_id: snowflake(&message.id)?, attachment_id: attachment.id })
+    }
+
+    /// Fetch the attachment of a stored message; `Gone` once the message was deleted
+    pub async fn download_file(&self, message_id: i64) -> Result<Bytes> {
+        let response = self.send(|| Ok(self.client.get(self.message_url(Some(message_id))))).await?;
+        let message: DiscordMessage = response.json().await?;
+        let attachment = message.attachments.into_iter().next().ok_or(AppError::Gone)?;
+
+        let response = self.client.get(&attachment.url).send().await?;
+        if !response.status().is_success() {
+            return Err(AppError::TelegramError(format!(
+                "Discord attachment download failed: {}",
+                response.status()
+            )));
+        }
+        Ok(response.bytes().await?)
+    }
+
+    pub async fn delete_message(&self, message_id: i64) -> Result<()> {
+        self.send(|| Ok(self.client.delete(self.message_url(Some(message_id))))).await?;
+        Ok(())
+    }
+
+    /// Send an authorized request, waiting out one rate limit. A missing message is `Gone`.
+    async fn send(&self, build: impl Fn() -> Result<RequestBuilder>) -> Result<Response> {
+        let mut retried = false;
+        loop {
+            let response = build()?.header("Authorization", format!("Bot {}", self.bot_token)).send().await?;
+            match response.status() {
+                status if status.is_success() => return Ok(response),
+                StatusCode::NOT_FOUND => return Err(AppError::Gone),
+                StatusCode::TOO_MANY_REQUESTS if !retried => {
+                    let retry_after = response.json::<RateLimited>().await.map_or(1.0, |limit| limit.retry_after);
+                    let retry_after = Duration::from_secs_f64(retry_after.max(0.0)).min(MAX_RETRY_AFTER);
+                    tracing::warn!("Discord rate limit hit, retrying in {:?}", retry_after);
+                    tokio::time::sleep(retry_after).await;
+                    retried = true;
+                }
+                status => {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(AppError::TelegramError(format!("Discord API error {}: {}", status, body)));
+                }
+            }
+        }
+    }
+}
+
+// Discord IDs are 64-bit snowflakes sent as strings; every one issued so far fits an i64
+fn snowflake(id: &str) -> Result<i64> {
+    id.parse()
+        .map_err(|_| AppError::TelegramError(format!("Discord returned an invalid ID: {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_upload_download_delete() {
+        let server = MockServer::start().await;
+        let message = json!({
+            "id": "1234567890123456789",
+            "attachments": [{ "id": "42", "url": format!("{}/attachments/42/image.bin", server.uri()) }],
+        });
+        Mock::given(method("POST"))
+            .and(path("/channels/7/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message.clone()))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/channels/7/messages/1234567890123456789"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/attachments/42/image.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"stored".to_vec()))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/channels/7/messages/1234567890123456789"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+
+        let discord = DiscordService::new("token".to_string(), 7).with_api_url(&server.uri());
+        let stored = discord.upload_file(Bytes::from_static(b"stored"), "image.bin").await.unwrap();
+        assert_eq!((stored.message_id, stored.attachment_id.as_str()), (1234567890123456789, "42"));
+        assert_eq!(discord.download_file(stored.message_id).await.unwrap().as_ref(), b"stored");
+        discord.delete_message(stored.message_id).await.unwrap();
+        assert!(matches!(discord.download_file(1).await, Err(AppError::Gone)));
+    }
+}
@@ -0,0 +1,65 @@
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::models::unix_timestamp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErasureStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureFailure {
+    pub id: String,
+    pub error: String,
+}
+
+/// Progress and outcome of one right-to-erasure job. The subject itself is not kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureReport {
+    pub job_id: String,
+    pub status: ErasureStatus,
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    pub images_found: usize,
+    pub images_deleted: usize,
+    // Images whose Telegram message could not be deleted; they stay indexed for a retry
+    pub failed: Vec<ErasureFailure>,
+    pub audit_events_removed: usize,
+    pub usage_records_removed: usize,
+}
+
+impl ErasureReport {
+    pub fn new(job_id: String) -> Self {
+        Self {
+            job_id,
+            status: ErasureStatus::Running,
+            started_at: unix_timestamp(),
+            finished_at: None,
+            images_found: 0,
+            images_deleted: 0,
+            failed: Vec::new(),
+            audit_events_removed: 0,
+            usage_records_removed: 0,
+        }
+    }
+}
+
+/// Reports of erasure jobs started since the process came up
+#[derive(Default)]
+pub struct ErasureJobs {
+    reports: Mutex<HashMap<String, ErasureReport>>,
+}
+
+impl ErasureJobs {
+    pub fn update(&self, report: &ErasureReport) {
+        self.reports.lock().unwrap().insert(report.job_id.clone(), report.clone());
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<ErasureReport> {
+        self.reports.lock().unwrap().get(job_id).cloned()
+    }
+}
@@ -0,0 +1,194 @@
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+/// Allow and deny lists of ISO 3166 country codes for one kind of route
+#[derive(Debug, Clone, Default)]
+pub struct CountryRule {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl CountryRule {
+    pub fn new(allow: &[String], deny: &[String]) -> Self {
+        let normalize = |codes: &[String]| codes.iter().map(|code| code.to_uppercase()).collect();
+        Self { allow: normalize(allow), deny: normalize(deny) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Denied countries are always turned away. With an allow list, so are clients whose
+    /// country is unknown.
+    pub fn check(&self, country: Option<&str>) -> Result<()> {
+        let denied = country.is_some_and(|country| self.deny.iter().any(|code| code == country));
+        let allowed = self.allow.is_empty() || country.is_some_and(|country| self.allow.iter().any(|code| code == country));
+        if denied || !allowed {
+            return Err(AppError::RegionBlocked);
+        }
+        Ok(())
+    }
+}
+
+/// Where a client's unmasked address was located, as far as the configured databases know
+#[derive(Debug, Clone, Default)]
+pub struct ClientLocation {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+}
+
+/// Requests from one country or network since startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OriginCounts {
+    pub origin: String,
+    pub requests: u64,
+    // 4xx responses, including rate limiting, soft bans and region blocks
+    pub client_errors: u64,
+}
+
+/// Per-country and per-ASN traffic, busiest first
+#[derive(Debug, Clone, Serialize)]
+pub struct OriginStats {
+    pub countries: Vec<OriginCounts>,
+    pub asns: Vec<OriginCounts>,
+}
+
+// Origins listed per breakdown in the stats
+const TOP_ORIGINS: usize = 20;
+
+/// MaxMind-format country and ASN databases, the per-route country rules applied with them,
+/// and traffic counts by origin
+pub struct GeoPolicy {
+    reader: Option<Reader<Vec<u8>>>,
+    asn_reader: Option<Reader<Vec<u8>>>,
+    pub upload: CountryRule,
+    pub download: CountryRule,
+    countries: Mutex<HashMap<String, OriginCounts>>,
+    asns: Mutex<HashMap<String, OriginCounts>>,
+}
+
+impl GeoPolicy {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let upload = CountryRule::new(&config.geoip_upload_allow, &config.geoip_upload_deny);
+        let download = CountryRule::new(&config.geoip_download_allow, &config.geoip_download_deny);
+        let reader = match &config.geoip_db_path {
+            Some(path) => Some(open_database(path)?),
+            None if upload.is_empty() && download.is_empty() => None,
+            None => anyhow::bail!("GeoIP country rules are set but GEOIP_DB_PATH is missing"),
+        };
+        let asn_reader = config.geoip_asn_db_path.as_deref().map(open_database).transpose()?;
+        Ok(Self {
+            reader,
+            asn_reader,
+            upload,
+            download,
+            countries: Mutex::default(),
+            asns: Mutex::default(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.reader.is_some() || self.asn_reader.is_some()
+    }
+
+    pub fn locate(&self, ip: IpAddr) -> ClientLocation {
+        let country = self
+            .reader
+            .as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Country>(ip).ok())
+            .and_then(|record| record.country?.iso_code.map(str::to_string));
+        let asn = self.asn_reader.as_ref().and_then(|reader| reader.lookup::<geoip2::Asn>(ip).ok());
+        ClientLocation {
+            country,
+            asn: asn.as_ref().and_then(|asn| asn.autonomous_system_number),
+            asn_org: asn.and_then(|asn| asn.autonomous_system_organization.map(str::to_string)),
+        }
+    }
+
+    /// Count one finished request against its country and network
+    pub fn record(&self, location: &ClientLocation, client_error: bool) {
+        let count = |origins: &Mutex<HashMap<String, OriginCounts>>, origin: String| {
+            let mut origins = origins.lock().unwrap();
+            let counts = origins.entry(origin.clone()).or_insert_with(|| OriginCounts { origin, ..Default::default() });
+            counts.requests += 1;
+            counts.client_errors += u64::from(client_error);
+        };
+        if self.reader.is_some() {
+            count(&self.countries, location.country.clone().unwrap_or_else(|| "unknown".to_string()));
+        }
+        if self.asn_reader.is_some() {
+            let asn = match (location.asn, &location.asn_org) {
+                (Some(asn), Some(org)) => format!("AS{} {}", asn, org),
+                (Some(asn), None) => format!("AS{}", asn),
+                (None, _) => "unknown".to_string(),
+            };
+            count(&self.asns, asn);
+        }
+    }
+
+    /// The busiest origins, or `None` when no database is configured
+    pub fn stats(&self) -> Option<OriginStats> {
+        self.enabled().then(|| OriginStats {
+            countries: top_origins(&self.countries),
+            asns: top_origins(&self.asns),
+        })
+    }
+}
+
+fn open_database(path: &str) -> anyhow::Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path).map_err(|e| anyhow::anyhow!("Failed to open GeoIP database {}: {}", path, e))
+}
+
+fn top_origins(origins: &Mutex<HashMap<String, OriginCounts>>) -> Vec<OriginCounts> {
+    let mut origins: Vec<OriginCounts> = origins.lock().unwrap().values().cloned().collect();
+    origins.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.origin.cmp(&b.origin)));
+    origins.truncate(TOP_ORIGINS);
+    origins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn test_country_rule() {
+        let open = CountryRule::default();
+        assert!(open.check(None).is_ok());
+        assert!(open.check(Some("TH")).is_ok());
+
+        let deny = CountryRule::new(&[], &codes(&["kp"]));
+        assert!(deny.check(Some("KP")).is_err());
+        assert!(deny.check(Some("TH")).is_ok());
+        assert!(deny.check(None).is_ok());
+
+        let allow = CountryRule::new(&codes(&["TH", "US"]), &codes(&["US"]));
+        assert!(allow.check(Some("TH")).is_ok());
+        assert!(allow.check(Some("US")).is_err());
+        assert!(allow.check(Some("DE")).is_err());
+        assert!(allow.check(None).is_err());
+    }
+
+    #[test]
+    fn test_top_origins_busiest_first() {
+        let origins = Mutex::new(HashMap::new());
+        for (origin, requests) in (0..TOP_ORIGINS as u64 + 5).map(|n| (format!("AS{}", n), n)) {
+            origins.lock().unwrap().insert(origin.clone(), OriginCounts { origin, requests, client_errors: 0 });
+        }
+
+        let top = top_origins(&origins);
+        assert_eq!(top.len(), TOP_ORIGINS);
+        assert_eq!(top[0].origin, format!("AS{}", TOP_ORIGINS + 4));
+        assert!(top.windows(2).all(|pair| pair[0].requests >= pair[1].requests));
+    }
+}
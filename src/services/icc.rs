@@ -0,0 +1,235 @@
+use image::{ColorType, DynamicImage};
+
+// XYZ (D50, as ICC profiles define their colorants) to linear sRGB, Bradford-adapted
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+// Profiles this close to sRGB are left alone rather than converted through rounding
+const SRGB_TOLERANCE: f32 = 0.02;
+
+// Entries of the lookup tables standing in for the curves during conversion
+const LUT_SIZE: usize = 4096;
+
+/// The color space an image's embedded profile puts it in, as far as converting it to sRGB goes
+#[derive(Debug)]
+pub enum ColorSpace {
+    // sRGB or near enough, including images without a profile
+    Srgb,
+    // An RGB profile built from colorants and tone curves, such as Display P3 or Adobe RGB
+    Rgb(RgbProfile),
+    // Four-channel JPEGs, which the decoder turns into RGB
+    Cmyk,
+    // Lookup-table and grayscale profiles, kept as they are
+    Unsupported,
+}
+
+/// Tone curve of one channel, from encoded values to linear light
+#[derive(Debug, Clone)]
+enum Curve {
+    Gamma(f32),
+    // Samples over 0..=1, interpolated linearly
+    Table(Vec<f32>),
+    // ICC parametric curve in its general form: g, a, b, c, d, e, f
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn eval(&self, x: f32) -> f32 {
+        match self {
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Table(table) => {
+                let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+                let below = position.floor() as usize;
+                let above = (below + 1).min(table.len() - 1);
+                let weight = position - below as f32;
+                table[below] * (1.0 - weight) + table[above] * weight
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= *d { (a * x + b).max(0.0).powf(*g) + e } else { c * x + f }
+            }
+        }
+    }
+}
+
+/// An RGB matrix/TRC profile, as the matrix from its linear values to linear sRGB and its curves
+#[derive(Debug)]
+pub struct RgbProfile {
+    to_srgb: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+/// Read an ICC profile far enough to know how to bring its colors to sRGB
+pub fn parse(profile: &[u8]) -> ColorSpace {
+    match profile.get(16..20) {
+        Some(b"RGB ") => {}
+        Some(b"CMYK") => return ColorSpace::Cmyk,
+        _ => return ColorSpace::Unsupported,
+    }
+    let Some(profile) = rgb_profile(profile) else {
+        return ColorSpace::Unsupported;
+    };
+
+    let identity = (0..3).all(|row| {
+        (0..3).all(|col| {
+            let expected = if row == col { 1.0 } else { 0.0 };
+            (profile.to_srgb[row][col] - expected).abs() < SRGB_TOLERANCE
+        })
+    });
+    let srgb_curves = profile
+        .curves
+        .iter()
+        .all(|curve| [0.1, 0.3, 0.5, 0.7, 0.9].iter().all(|x| (curve.eval(*x) - srgb_to_linear(*x)).abs() < SRGB_TOLERANCE));
+    if identity && srgb_curves { ColorSpace::Srgb } else { ColorSpace::Rgb(profile) }
+}
+
+fn rgb_profile(profile: &[u8]) -> Option<RgbProfile> {
+    let tag = |signature: &[u8; 4]| -> Option<&[u8]> {
+        let count = read_u32(profile, 128)? as usize;
+        (0..count).find_map(|i| {
+            let entry = 132 + i * 12;
+            if profile.get(entry..entry + 4)? != signature {
+                return None;
+            }
+            let offset = read_u32(profile, entry + 4)? as usize;
+            let size = read_u32(profile, entry + 8)? as usize;
+            profile.get(offset..offset.checked_add(size)?)
+        })
+    };
+    let colorant = |signature: &[u8; 4]| -> Option<[f32; 3]> {
+        let data = tag(signature)?;
+        if data.get(..4)? != b"XYZ " {
+            return None;
+        }
+        Some([s15_fixed16(data, 8)?, s15_fixed16(data, 12)?, s15_fixed16(data, 16)?])
+    };
+
+    let [red, green, blue] = [colorant(b"rXYZ")?, colorant(b"gXYZ")?, colorant(b"bXYZ")?];
+    let mut to_srgb = [[0.0; 3]; 3];
+    for (row, conversion) in XYZ_TO_SRGB.iter().enumerate() {
+        for (col, primary) in [red, green, blue].iter().enumerate() {
+            to_srgb[row][col] = (0..3).map(|k| conversion[k] * primary[k]).sum();
+        }
+    }
+    let curves = [curve(tag(b"rTRC")?)?, curve(tag(b"gTRC")?)?, curve(tag(b"bTRC")?)?];
+    Some(RgbProfile { to_srgb, curves })
+}
+
+fn curve(data: &[u8]) -> Option<Curve> {
+    match data.get(..4)? {
+        b"curv" => {
+            let count = read_u32(data, 8)? as usize;
+            match count {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(read_u16(data, 12)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| read_u16(data, 12 + i * 2).map(|v| v as f32 / 65535.0))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Curve::Table),
+            }
+        }
+        b"para" => {
+            let kind = read_u16(data, 8)?;
+            let param = |i: usize| s15_fixed16(data, 12 + i * 4);
+            let g = param(0)?;
+            let [a, b, c, d, e, f] = match kind {
+                0 => [1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                1 => {
+                    let (a, b) = (param(1)?, param(2)?);
+                    [a, b, 0.0, -b / a, 0.0, 0.0]
+                }
+                2 => {
+                    let (a, b, c) = (param(1)?, param(2)?, param(3)?);
+                    [a, b, 0.0, -b / a, c, c]
+                }
+                3 => [param(1)?, param(2)?, param(3)?, param(4)?, 0.0, 0.0],
+                4 => [param(1)?, param(2)?, param(3)?, param(4)?, param(5)?, param(6)?],
+                _ => return None,
+            };
+            Some(Curve::Parametric([g, a, b, c, d, e, f]))
+        }
+        _ => None,
+    }
+}
+
+impl RgbProfile {
+    /// The image with its colors converted to sRGB, at its own bit depth and with its alpha
+    pub fn convert(&self, image: &DynamicImage) -> DynamicImage {
+        let step = (LUT_SIZE - 1) as f32;
+        let decode: Vec<[f32; LUT_SIZE]> = self
+            .curves
+            .iter()
+            .map(|curve| std::array::from_fn(|i| curve.eval(i as f32 / step)))
+            .collect();
+        let encode: [f32; LUT_SIZE] = std::array::from_fn(|i| linear_to_srgb(i as f32 / step));
+        let index = |value: f32| (value.clamp(0.0, 1.0) * step).round() as usize;
+
+        let mut pixels = image.to_rgba32f();
+        for pixel in pixels.pixels_mut() {
+            let linear = [decode[0][index(pixel[0])], decode[1][index(pixel[1])], decode[2][index(pixel[2])]];
+            for (channel, row) in self.to_srgb.iter().enumerate() {
+                let value: f32 = (0..3).map(|k| row[k] * linear[k]).sum();
+                pixel[channel] = encode[index(value)];
+            }
+        }
+
+        let converted = DynamicImage::ImageRgba32F(pixels);
+        let color = image.color();
+        let sixteen_bit = matches!(color, ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16);
+        match (sixteen_bit, color.has_alpha()) {
+            (false, false) => DynamicImage::ImageRgb8(converted.to_rgb8()),
+            (false, true) => DynamicImage::ImageRgba8(converted.to_rgba8()),
+            (true, false) => DynamicImage::ImageRgb16(converted.to_rgb16()),
+            (true, true) => DynamicImage::ImageRgba16(converted.to_rgba16()),
+        }
+    }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], at: usize) -> Option<f32> {
+    Some(i32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{icc_profile, LINEAR_ICC_CURVE};
+
+    #[test]
+    fn test_convert_to_srgb() {
+        // sRGB's own curve as parametric type 3
+        let mut srgb_curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            srgb_curve.extend_from_slice(&((v * 65536.0f32).round() as i32).to_be_bytes());
+        }
+        assert!(matches!(parse(&icc_profile(&srgb_curve)), ColorSpace::Srgb));
+        assert!(matches!(parse(b"not a profile"), ColorSpace::Unsupported));
+
+        // Mid-grey in linear light is much lighter once encoded for sRGB; alpha is kept
+        let ColorSpace::Rgb(profile) = parse(&icc_profile(LINEAR_ICC_CURVE)) else {
+            panic!("linear profile not recognized");
+        };
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(2, 2, image::Rgba([128, 128, 128, 77])));
+        let converted = profile.convert(&image).to_rgba8();
+        let pixel = converted.get_pixel(0, 0);
+        assert!((186..=190).contains(&pixel[0]), "{:?}", pixel);
+        assert_eq!((pixel[0], pixel[3]), (pixel[2], 77));
+    }
+}
@@ -0,0 +1,922 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Write,
+    ops::Bound,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::{
+    crypto::{self, CryptoService},
+    error::{AppError, Result},
+    models::{FileReference, Visibility},
+    services::similarity,
+};
+
+// One stored image, keyed by its public encrypted ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub id: String,
+    pub reference: FileReference,
+    pub created_at: u64,
+    // Usage subjects of the uploader, so deletes can release their stored bytes
+    #[serde(default)]
+    pub uploader: Vec<String>,
+    // Subjects that later uploaded the same bytes under a content-addressed ID; the image is listed
+    // as theirs too, but only the original uploader can delete it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reuploaders: Vec<String>,
+    // Hex SHA-256 of the original image bytes; absent for files imported in place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perceptual_hash: Option<String>,
+    // Most common colors as "#rrggbb", dominant first, for placeholders shown while loading
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub palette: Vec<String>,
+    // Original filename, used for Content-Disposition on download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // Text recognized by OCR, searchable through /search
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    // file_ids the image was stored under before being re-uploaded; IDs minted earlier still carry them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_file_ids: Vec<String>,
+    // Telegram lost the file and no copy was available to restore it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub broken: bool,
+    // Telegram has been answering that the file is unavailable, which is not yet taken as it being gone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable: Option<Unavailable>,
+    // Normalized labels set at upload or through PATCH /image/:id/tags, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Video versions of an animated GIF by extension, stored encrypted like the image itself
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub transcodes: BTreeMap<String, FileReference>,
+}
+
+/// A run of "temporarily unavailable" answers for an image's file, cleared once it resolves again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unavailable {
+    // Unix time of the first answer in the run
+    pub since: u64,
+    pub failures: u32,
+}
+
+// Tags are short labels, not descriptions
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LEN: usize = 32;
+
+/// Lowercase, trim, sort and dedupe tags; letters, digits, '-', '_' and ':' only
+pub fn normalize_tags<S: AsRef<str>>(tags: impl IntoIterator<Item = S>) -> Result<Vec<String>> {
+    let tags: BTreeSet<String> = tags
+        .into_iter()
+        .map(|tag| tag.as_ref().trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    if tags.len() > MAX_TAGS {
+        return Err(AppError::ValidationError(format!("At most {} tags per image", MAX_TAGS)));
+    }
+    if let Some(tag) = tags.iter().find(|tag| {
+        tag.chars().count() > MAX_TAG_LEN
+            || !tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':'))
+    }) {
+        return Err(AppError::ValidationError(format!(
+            "Invalid tag '{}': up to {} letters, digits, '-', '_' or ':'",
+            tag, MAX_TAG_LEN
+        )));
+    }
+    Ok(tags.into_iter().collect())
+}
+
+/// Comma-separated tags from a query parameter
+pub fn parse_tag_list(tags: Option<&str>) -> Result<Vec<String>> {
+    normalize_tags(tags.unwrap_or_default().split(','))
+}
+
+// Changes are written to the index file together, this long after the first of them
+const PERSIST_DELAY: Duration = Duration::from_millis(250);
+
+/// Record of every image stored through this service, optionally persisted as JSON
+pub struct ImageIndex {
+    entries: Arc<Mutex<Entries>>,
+    // Kept up to date on insert and remove, so quota checks don't go over every entry
+    tenant_bytes: Arc<Mutex<HashMap<String, TenantBytes>>>,
+    writer: Option<Arc<IndexWriter>>,
+}
+
+/// Writes the index file on a thread of its own, so a change only marks the index as changed
+/// instead of rewriting the whole file while holding the index lock
+struct IndexWriter {
+    path: PathBuf,
+    entries: Arc<Mutex<Entries>>,
+    state: Mutex<WriterState>,
+    wake: Condvar,
+    // Held from taking a snapshot until it is written, so writes land in the order of their snapshots
+    file: Mutex<()>,
+}
+
+#[derive(Default)]
+struct WriterState {
+    // Changed since the last snapshot
+    dirty: bool,
+    // The index was dropped; the thread stops once nothing is left to write
+    closed: bool,
+}
+
+/// Entries by public ID, with the lookups done on every download, upload and listing kept
+/// alongside so none of them goes over every entry
+#[derive(Default)]
+struct Entries {
+    by_id: HashMap<String, IndexEntry>,
+    // Every file_id an image is or was stored under
+    by_file_id: HashMap<String, String>,
+    // IDs by tenant and content hash
+    by_hash: HashMap<(Option<String>, String), BTreeSet<String>>,
+    // Sort keys of the images each usage subject uploaded or re-uploaded
+    by_owner: HashMap<String, BTreeSet<(u64, String)>>,
+    // Sort keys of every image, oldest first
+    ordered: BTreeSet<(u64, String)>,
+}
+
+impl Entries {
+    /// Add or replace an entry, returning the one replaced
+    fn insert(&mut self, entry: IndexEntry) -> Option<IndexEntry> {
+        let replaced = self.remove(&entry.id);
+        let key = entry.sort_key();
+        for file_id in entry.file_ids() {
+            self.by_file_id.insert(file_id.to_string(), entry.id.clone());
+        }
+        if let Some(hash) = &entry.content_hash {
+            let hash_key = (entry.reference.tenant.clone(), hash.clone());
+            self.by_hash.entry(hash_key).or_default().insert(entry.id.clone());
+        }
+        for owner in entry.uploader.iter().chain(&entry.reuploaders) {
+            self.by_owner.entry(owner.clone()).or_default().insert(key.clone());
+        }
+        self.ordered.insert(key);
+        self.by_id.insert(entry.id.clone(), entry);
+        replaced
+    }
+
+    fn remove(&mut self, id: &str) -> Option<IndexEntry> {
+        let entry = self.by_id.remove(id)?;
+        let key = entry.sort_key();
+        for file_id in entry.file_ids() {
+            if self.by_file_id.get(file_id) == Some(&entry.id) {
+                self.by_file_id.remove(file_id);
+            }
+        }
+        if let Some(hash) = &entry.content_hash {
+            let hash_key = (entry.reference.tenant.clone(), hash.clone());
+            if let Some(ids) = self.by_hash.get_mut(&hash_key) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.by_hash.remove(&hash_key);
+                }
+            }
+        }
+        for owner in entry.uploader.iter().chain(&entry.reuploaders) {
+            if let Some(keys) = self.by_owner.get_mut(owner) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    self.by_owner.remove(owner);
+                }
+            }
+        }
+        self.ordered.remove(&key);
+        Some(entry)
+    }
+
+    /// Sort keys of the images `filter` can match, oldest first; None when there are none
+    fn candidates(&self, filter: &ImageFilter) -> Option<&BTreeSet<(u64, String)>> {
+        match &filter.owner {
+            Some(owner) => self.by_owner.get(owner),
+            None => Some(&self.ordered),
+        }
+    }
+}
+
+/// Bytes of a tenant's stored images, and of its uploads queued but not stored yet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TenantBytes {
+    pub stored: u64,
+    pub reserved: u64,
+}
+
+/// Bytes held against a tenant's quota by an upload on its way to the index, given back when
+/// dropped; by then the stored image counts instead
+#[derive(Debug)]
+pub struct QuotaReservation {
+    tenant_bytes: Arc<Mutex<HashMap<String, TenantBytes>>>,
+    tenant_id: String,
+    size: u64,
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        let mut tenant_bytes = self.tenant_bytes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(bytes) = tenant_bytes.get_mut(&self.tenant_id) {
+            bytes.reserved = bytes.reserved.saturating_sub(self.size);
+        }
+    }
+}
+
+/// Criteria for `ImageIndex::filter`; every given one has to match
+#[derive(Debug, Default, Deserialize)]
+pub struct ImageFilter {
+    // Exact MIME type, or a family such as "image/*"
+    pub mime_type: Option<String>,
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    // Unix times bounding created_at, inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    // Usage subject of the uploader, e.g. "key:<hash>", "owner:<hash>" or "ip:<address>"
+    pub owner: Option<String>,
+    pub broken: Option<bool>,
+    // Comma-separated tags the image must all carry
+    pub tag: Option<String>,
+    pub visibility: Option<Visibility>,
+}
+
+impl ImageFilter {
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        let mime_type = &entry.reference.mime_type;
+        let mime_matches = self.mime_type.as_deref().is_none_or(|wanted| match wanted.strip_suffix("/*") {
+            Some(family) => mime_type.split('/').next() == Some(family),
+            None => mime_type.eq_ignore_ascii_case(wanted),
+        });
+
+        mime_matches
+            && self.min_size.is_none_or(|min| entry.reference.size >= min)
+            && self.max_size.is_none_or(|max| entry.reference.size <= max)
+            && self.since.is_none_or(|since| entry.created_at >= since)
+            && self.until.is_none_or(|until| entry.created_at <= until)
+            && self.owner.as_ref().is_none_or(|owner| entry.uploader.contains(owner) || entry.reuploaders.contains(owner))
+            && self.broken.is_none_or(|broken| entry.broken == broken)
+            && self.visibility.is_none_or(|visibility| entry.reference.visibility == visibility)
+            && self.tag.as_deref().is_none_or(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().to_lowercase())
+                    .filter(|tag| !tag.is_empty())
+                    .all(|tag| entry.tags.contains(&tag))
+            })
+    }
+}
+
+impl IndexEntry {
+    /// Position in listings, which run newest first like `ImageIndex::list` reversed
+    pub fn sort_key(&self) -> (u64, String) {
+        (self.created_at, self.id.clone())
+    }
+
+    /// The file_id the image is stored under, then those it was stored under before
+    fn file_ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.reference.file_id.as_str()).chain(self.previous_file_ids.iter().map(String::as_str))
+    }
+}
+
+impl ImageIndex {
+    /// Load the index from `path` if it exists; without a path the index lives in memory only
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut entries = Entries::default();
+        if let Some(path) = path.as_ref().filter(|path| path.exists()) {
+            let data = std::fs::read(path)?;
+            let list: Vec<IndexEntry> = serde_json::from_slice(&data)?;
+            for entry in list {
+                entries.insert(entry);
+            }
+        }
+
+        let mut tenant_bytes: HashMap<String, TenantBytes> = HashMap::new();
+        for entry in entries.by_id.values() {
+            if let Some(tenant) = &entry.reference.tenant {
+                tenant_bytes.entry(tenant.clone()).or_default().stored += entry.reference.size as u64;
+            }
+        }
+
+        let entries = Arc::new(Mutex::new(entries));
+        let writer = path.map(|path| IndexWriter::start(path, entries.clone())).transpose()?;
+        Ok(Self {
+            entries,
+            tenant_bytes: Arc::new(Mutex::new(tenant_bytes)),
+            writer,
+        })
+    }
+
+    pub fn insert(&self, entry: IndexEntry) -> Result<()> {
+        let mut entries = self.lock()?;
+        self.count_stored(&entry, true)?;
+        if let Some(replaced) = entries.insert(entry) {
+            self.count_stored(&replaced, false)?;
+        }
+        self.changed();
+        Ok(())
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<IndexEntry>> {
+        let mut entries = self.lock()?;
+        let removed = entries.remove(id);
+        if let Some(removed) = &removed {
+            self.count_stored(removed, false)?;
+            self.changed();
+        }
+        Ok(removed)
+    }
+
+    /// Bytes stored and reserved by a tenant
+    pub fn tenant_bytes(&self, tenant_id: &str) -> Result<TenantBytes> {
+        Ok(self.lock_tenant_bytes()?.get(tenant_id).copied().unwrap_or_default())
+    }
+
+    /// Hold `size` bytes against a tenant's quota until the upload is stored or given up, refusing
+    /// them when stored and reserved bytes would go past `quota`
+    pub fn reserve(&self, tenant_id: &str, size: usize, quota: Option<u64>) -> Result<QuotaReservation> {
+        let mut tenant_bytes = self.lock_tenant_bytes()?;
+        let bytes = tenant_bytes.entry(tenant_id.to_string()).or_default();
+        if quota.is_some_and(|quota| bytes.stored + bytes.reserved + size as u64 > quota) {
+            return Err(AppError::QuotaExceeded);
+        }
+        bytes.reserved += size as u64;
+
+        Ok(QuotaReservation {
+            tenant_bytes: self.tenant_bytes.clone(),
+            tenant_id: tenant_id.to_string(),
+            size: size as u64,
+        })
+    }
+
+    // Called with the entries locked, so the counts move together with the entries
+    fn count_stored(&self, entry: &IndexEntry, added: bool) -> Result<()> {
+        let Some(tenant) = &entry.reference.tenant else {
+            return Ok(());
+        };
+        let mut tenant_bytes = self.lock_tenant_bytes()?;
+        let bytes = tenant_bytes.entry(tenant.clone()).or_default();
+        let size = entry.reference.size as u64;
+        bytes.stored = if added { bytes.stored + size } else { bytes.stored.saturating_sub(size) };
+        Ok(())
+    }
+
+    /// Record a stored transcode of an image; false when the image has gone or was re-uploaded meanwhile
+    pub fn set_transcode(&self, id: &str, source_file_id: &str, extension: &str, transcode: FileReference) -> Result<bool> {
+        let mut entries = self.lock()?;
+        let Some(entry) = entries.by_id.get_mut(id).filter(|entry| entry.reference.file_id == source_file_id) else {
+            return Ok(false);
+        };
+        entry.transcodes.insert(extension.to_string(), transcode);
+        self.changed();
+        Ok(true)
+    }
+
+    /// Change the entry of an image still stored as `file_id`, returning it updated; None when the
+    /// image has been deleted or its file replaced meanwhile
+    pub fn update_stored_as(
+        &self,
+        id: &str,
+        file_id: &str,
+        update: impl FnOnce(&mut IndexEntry),
+    ) -> Result<Option<IndexEntry>> {
+        let mut entries = self.lock()?;
+        if entries.by_id.get(id).is_none_or(|entry| entry.reference.file_id != file_id) {
+            return Ok(None);
+        }
+        // Taken out and put back, so the lookups follow whatever `update` changes
+        let Some(mut entry) = entries.remove(id) else {
+            return Ok(None);
+        };
+        self.count_stored(&entry, false)?;
+        update(&mut entry);
+        self.count_stored(&entry, true)?;
+        let updated = entry.clone();
+        entries.insert(entry);
+        self.changed();
+        Ok(Some(updated))
+    }
+
+    /// Record subjects re-uploading an image's bytes, unless they already uploaded it
+    pub fn add_reuploaders(&self, id: &str, subjects: &[String]) -> Result<()> {
+        let mut entries = self.lock()?;
+        let Some(entry) = entries.by_id.get_mut(id) else {
+            return Ok(());
+        };
+        let key = entry.sort_key();
+        let added: Vec<String> = subjects
+            .iter()
+            .filter(|subject| !entry.uploader.contains(subject) && !entry.reuploaders.contains(subject))
+            .cloned()
+            .collect();
+        if added.is_empty() {
+            return Ok(());
+        }
+        entry.reuploaders.extend(added.iter().cloned());
+        for subject in added {
+            entries.by_owner.entry(subject).or_default().insert(key.clone());
+        }
+        self.changed();
+        Ok(())
+    }
+
+    /// Count another unavailable answer for an image's file, returning the run so far
+    pub fn record_unavailable(&self, id: &str, now: u64) -> Result<Option<Unavailable>> {
+        let mut entries = self.lock()?;
+        let Some(entry) = entries.by_id.get_mut(id) else {
+            return Ok(None);
+        };
+        let run = entry.unavailable.get_or_insert(Unavailable { since: now, failures: 0 });
+        run.failures += 1;
+        let run = *run;
+        self.changed();
+        Ok(Some(run))
+    }
+
+    /// Forget an image's failures once its file resolved again, broken flag included; true when
+    /// it had been marked broken
+    pub fn mark_available(&self, id: &str) -> Result<bool> {
+        let mut entries = self.lock()?;
+        let Some(entry) = entries.by_id.get_mut(id).filter(|entry| entry.broken || entry.unavailable.is_some()) else {
+            return Ok(false);
+        };
+        let was_broken = entry.broken;
+        entry.broken = false;
+        entry.unavailable = None;
+        self.changed();
+        Ok(was_broken)
+    }
+
+    /// Replace an image's tags, returning the updated entry
+    pub fn set_tags(&self, id: &str, tags: Vec<String>) -> Result<Option<IndexEntry>> {
+        let mut entries = self.lock()?;
+        let Some(entry) = entries.by_id.get_mut(id) else {
+            return Ok(None);
+        };
+        entry.tags = tags;
+        let updated = entry.clone();
+        self.changed();
+        Ok(Some(updated))
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<IndexEntry>> {
+        Ok(self.lock()?.by_id.get(id).cloned())
+    }
+
+    /// The file behind a public ID: looked up here for content-addressed IDs, decrypted otherwise
+    pub fn resolve(&self, crypto: &CryptoService, id: &str) -> Result<FileReference> {
+        if crypto::is_content_id(id) {
+            return self.get(id)?.map(|entry| entry.reference).ok_or(AppError::NotFound);
+        }
+        crypto.decrypt_file_reference(id)
+    }
+
+    /// The image whose Telegram message is `message_id` in `chat_id`; references without a chat
+    /// are in `default_chat_id`
+    pub fn find_by_message(&self, chat_id: i64, message_id: i64, default_chat_id: i64) -> Result<Option<IndexEntry>> {
+        Ok(self
+            .lock()?
+            .by_id
+            .values()
+            .find(|entry| {
+                let reference = &entry.reference;
+                reference.backend.is_telegram()
+                    && reference.message_id == message_id
+                    && reference.chat_id.unwrap_or(default_chat_id) == chat_id
+            })
+            .cloned())
+    }
+
+    /// The image stored under `file_id`, now or before it was re-uploaded
+    pub fn find_by_file_id(&self, file_id: &str) -> Result<Option<IndexEntry>> {
+        let entries = self.lock()?;
+        Ok(entries.by_file_id.get(file_id).and_then(|id| entries.by_id.get(id)).cloned())
+    }
+
+    /// An image with the same content stored in the same tenant namespace
+    pub fn find_by_hash(&self, content_hash: &str, tenant: Option<&str>) -> Result<Option<IndexEntry>> {
+        let entries = self.lock()?;
+        let ids = entries.by_hash.get(&(tenant.map(str::to_string), content_hash.to_string()));
+        Ok(ids.and_then(|ids| ids.first()).and_then(|id| entries.by_id.get(id)).cloned())
+    }
+
+    /// Images whose perceptual hash scores at least `threshold` against `hash`, most similar first
+    pub fn find_similar(&self, hash: u64, threshold: f32) -> Result<Vec<(IndexEntry, f32)>> {
+        let mut matches: Vec<(IndexEntry, f32)> = self
+            .lock()?
+            .by_id
+            .values()
+            .filter_map(|entry| {
+                let other = similarity::decode(entry.perceptual_hash.as_deref()?)?;
+                let score = similarity::similarity(hash, other);
+                (score >= threshold).then(|| (entry.clone(), score))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.id.cmp(&b.0.id)));
+        Ok(matches)
+    }
+
+    /// Images whose recognized text contains every word of `query`, ignoring case, newest first
+    pub fn search_text(&self, query: &str) -> Result<Vec<IndexEntry>> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut matches: Vec<IndexEntry> = self
+            .lock()?
+            .by_id
+            .values()
+            .filter(|entry| {
+                entry.text.as_deref().is_some_and(|text| {
+                    let text = text.to_lowercase();
+                    words.iter().all(|word| text.contains(word))
+                })
+            })
+            .cloned()
+            .collect();
+        matches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(matches)
+    }
+
+    /// Entries matching `filter`, oldest first like `list`; only matches are copied out
+    pub fn filter(&self, filter: &ImageFilter) -> Result<Vec<IndexEntry>> {
+        let entries = self.lock()?;
+        Ok(entries
+            .candidates(filter)
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, id)| entries.by_id.get(id))
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect())
+    }
+
+    /// Up to `limit` entries matching `filter`, newest first, starting after the entry whose sort
+    /// key is `after`. A listing page only goes over the entries before its cursor until it is full.
+    pub fn page(&self, filter: &ImageFilter, after: Option<&(u64, String)>, limit: usize) -> Result<Vec<IndexEntry>> {
+        let entries = self.lock()?;
+        let Some(keys) = entries.candidates(filter) else {
+            return Ok(Vec::new());
+        };
+        let upper = after.map_or(Bound::Unbounded, |after| Bound::Excluded(after.clone()));
+        Ok(keys
+            .range((Bound::Unbounded, upper))
+            .rev()
+            .filter_map(|(_, id)| entries.by_id.get(id))
+            .filter(|entry| filter.matches(entry))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    /// All entries, oldest first
+    pub fn list(&self) -> Result<Vec<IndexEntry>> {
+        let entries = self.lock()?;
+        Ok(entries.ordered.iter().filter_map(|(_, id)| entries.by_id.get(id)).cloned().collect())
+    }
+
+    /// Write pending changes to the index file now rather than when the writer gets to them
+    pub fn flush(&self) -> Result<()> {
+        self.writer.as_ref().map_or(Ok(()), |writer| writer.write())
+    }
+
+    // Only marks the index for writing, which happens on the writer's thread
+    fn changed(&self) {
+        if let Some(writer) = &self.writer {
+            writer.changed();
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Entries>> {
+        self.entries
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire index lock".to_string()))
+    }
+
+    fn lock_tenant_bytes(&self) -> Result<MutexGuard<'_, HashMap<String, TenantBytes>>> {
+        self.tenant_bytes
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire tenant usage lock".to_string()))
+    }
+
+}
+
+impl Drop for ImageIndex {
+    fn drop(&mut self) {
+        if let Some(writer) = &self.writer {
+            writer.state().closed = true;
+            writer.wake.notify_one();
+            if let Err(e) = writer.write() {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
+impl IndexWriter {
+    fn start(path: PathBuf, entries: Arc<Mutex<Entries>>) -> std::io::Result<Arc<Self>> {
+        let writer = Arc::new(Self {
+            path,
+            entries,
+            state: Mutex::new(WriterState::default()),
+            wake: Condvar::new(),
+            file: Mutex::new(()),
+        });
+        let running = writer.clone();
+        std::thread::Builder::new().name("index-writer".to_string()).spawn(move || running.run())?;
+        Ok(writer)
+    }
+
+    fn changed(&self) {
+        self.state().dirty = true;
+        self.wake.notify_one();
+    }
+
+    fn run(&self) {
+        loop {
+            let mut state = self.state();
+            while !state.dirty && !state.closed {
+                state = self.wake.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            if !state.dirty {
+                return;
+            }
+            drop(state);
+
+            // Gives the changes following this one time to be written along with it
+            std::thread::sleep(PERSIST_DELAY);
+            if let Err(e) = self.write() {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+
+    /// Write the entries as they are now, if they changed since the last snapshot. Goes through a
+    /// temporary file synced to disk before it replaces the index, so a crash never leaves a
+    /// truncated index behind.
+    fn write(&self) -> Result<()> {
+        let _file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        {
+            let mut state = self.state();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+        }
+        // Copied out so serializing and writing happen without the index lock
+        let list: Vec<IndexEntry> = self
+            .entries
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire index lock".to_string()))?
+            .by_id
+            .values()
+            .cloned()
+            .collect();
+        let data = serde_json::to_vec(&list)?;
+        let tmp_path = self.path.with_extension("tmp");
+
+        let written = std::fs::File::create(&tmp_path)
+            .and_then(|mut file| file.write_all(&data).and_then(|_| file.sync_all()))
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path));
+        if let Err(e) = written {
+            // Tried again after the next delay
+            self.changed();
+            return Err(AppError::InternalError(format!("Failed to persist index: {}", e)));
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, WriterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, created_at: u64) -> IndexEntry {
+        IndexEntry {
+            id: id.to_string(),
+            reference: FileReference::new("file".to_string(), 1, 10, "image/png".to_string()),
+            created_at,
+            uploader: Vec::new(),
+            reuploaders: Vec::new(),
+            content_hash: None,
+            perceptual_hash: None,
+            palette: Vec::new(),
+            filename: None,
+            text: None,
+            previous_file_ids: Vec::new(),
+            broken: false,
+            unavailable: None,
+            tags: Vec::new(),
+            transcodes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_index_persists_and_reloads() {
+        let path = std::env::temp_dir().join(format!("rustgram-index-{}.json", uuid::Uuid::new_v4()));
+
+        let index = ImageIndex::open(Some(path.clone())).unwrap();
+        index.insert(entry("b", 2)).unwrap();
+        index.insert(entry("a", 1)).unwrap();
+        index.remove("b").unwrap();
+        index.flush().unwrap();
+
+        let ids = || -> Vec<String> {
+            let reloaded = ImageIndex::open(Some(path.clone())).unwrap();
+            reloaded.list().unwrap().into_iter().map(|e| e.id).collect()
+        };
+        assert_eq!(ids(), vec!["a".to_string()]);
+
+        // Without a flush, changes reach the file shortly after
+        index.insert(entry("c", 3)).unwrap();
+        std::thread::sleep(PERSIST_DELAY * 4);
+        assert_eq!(ids(), vec!["a".to_string(), "c".to_string()]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_tenant_bytes_and_reservations() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut stored = entry("a", 1);
+        stored.reference.tenant = Some("acme".to_string());
+        index.insert(stored.clone()).unwrap();
+        index.insert(stored).unwrap();
+        assert_eq!(index.tenant_bytes("acme").unwrap(), TenantBytes { stored: 10, reserved: 0 });
+
+        // Queued uploads count against the quota until they are stored or dropped
+        let queued = index.reserve("acme", 15, Some(30)).unwrap();
+        assert!(matches!(index.reserve("acme", 6, Some(30)), Err(AppError::QuotaExceeded)));
+        assert_eq!(index.tenant_bytes("acme").unwrap(), TenantBytes { stored: 10, reserved: 15 });
+        drop(queued);
+        assert!(index.reserve("acme", 20, Some(30)).is_ok());
+        assert_eq!(index.tenant_bytes("acme").unwrap().reserved, 0);
+
+        index.remove("a").unwrap();
+        assert_eq!(index.tenant_bytes("acme").unwrap().stored, 0);
+    }
+
+    #[test]
+    fn test_find_by_hash_is_scoped_to_tenant() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut stored = entry("a", 1);
+        stored.content_hash = Some("abc".to_string());
+        index.insert(stored).unwrap();
+
+        assert_eq!(index.find_by_hash("abc", None).unwrap().unwrap().id, "a");
+        assert!(index.find_by_hash("abc", Some("acme")).unwrap().is_none());
+        assert!(index.find_by_hash("def", None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_by_previous_file_id() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut moved = entry("a", 1);
+        moved.reference.file_id = "new".to_string();
+        moved.previous_file_ids.push("old".to_string());
+        index.insert(moved).unwrap();
+
+        assert_eq!(index.find_by_file_id("old").unwrap().unwrap().id, "a");
+        assert_eq!(index.find_by_file_id("new").unwrap().unwrap().id, "a");
+        assert!(index.find_by_file_id("other").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_update_stored_as_keeps_concurrent_changes() {
+        let index = ImageIndex::open(None).unwrap();
+        index.insert(entry("a", 1)).unwrap();
+        index.set_tags("a", vec!["cat".to_string()]).unwrap();
+
+        let updated = index.update_stored_as("a", "file", |entry| entry.reference.file_id = "new".to_string());
+        assert_eq!(updated.unwrap().unwrap().tags, ["cat"]);
+        // Already moved, or deleted: nothing is written back
+        assert!(index.update_stored_as("a", "file", |entry| entry.broken = true).unwrap().is_none());
+        index.remove("a").unwrap();
+        assert!(index.update_stored_as("a", "new", |entry| entry.broken = true).unwrap().is_none());
+        assert!(index.get("a").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_by_message_matches_chat() {
+        let index = ImageIndex::open(None).unwrap();
+        index.insert(entry("default", 1)).unwrap();
+        let mut tenant = entry("tenant", 2);
+        tenant.reference.chat_id = Some(-2002);
+        index.insert(tenant).unwrap();
+
+        assert_eq!(index.find_by_message(-1001, 1, -1001).unwrap().unwrap().id, "default");
+        assert_eq!(index.find_by_message(-2002, 1, -1001).unwrap().unwrap().id, "tenant");
+        assert!(index.find_by_message(-3003, 1, -1001).unwrap().is_none());
+        assert!(index.find_by_message(-1001, 2, -1001).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_search_text() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut screenshot = entry("a", 1);
+        screenshot.text = Some("Error: Connection Refused".to_string());
+        index.insert(screenshot).unwrap();
+        index.insert(entry("b", 2)).unwrap();
+
+        assert_eq!(index.search_text("connection error").unwrap().len(), 1);
+        assert!(index.search_text("timeout").unwrap().is_empty());
+        assert!(index.search_text("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut gif = entry("gif", 5);
+        gif.reference.mime_type = "image/gif".to_string();
+        gif.reference.size = 500;
+        gif.uploader = vec!["key:abc".to_string()];
+        gif.tags = vec!["cats".to_string(), "funny".to_string()];
+        gif.reference.visibility = Visibility::Private;
+        index.insert(gif).unwrap();
+        let mut broken = entry("broken", 3);
+        broken.broken = true;
+        index.insert(broken).unwrap();
+        index.insert(entry("png", 1)).unwrap();
+
+        let ids = |filter: ImageFilter| -> Vec<String> {
+            index.filter(&filter).unwrap().into_iter().map(|e| e.id).collect()
+        };
+        assert_eq!(ids(ImageFilter::default()), vec!["png", "broken", "gif"]);
+        assert_eq!(ids(ImageFilter { mime_type: Some("image/png".into()), ..Default::default() }), vec!["png", "broken"]);
+        assert_eq!(ids(ImageFilter { mime_type: Some("image/*".into()), min_size: Some(100), ..Default::default() }), vec!["gif"]);
+        assert_eq!(ids(ImageFilter { since: Some(2), until: Some(4), ..Default::default() }), vec!["broken"]);
+        assert_eq!(ids(ImageFilter { owner: Some("key:abc".into()), ..Default::default() }), vec!["gif"]);
+        assert_eq!(ids(ImageFilter { broken: Some(false), max_size: Some(10), ..Default::default() }), vec!["png"]);
+        assert_eq!(ids(ImageFilter { tag: Some("Funny,cats".into()), ..Default::default() }), vec!["gif"]);
+        assert!(ids(ImageFilter { tag: Some("cats,dogs".into()), ..Default::default() }).is_empty());
+        assert_eq!(ids(ImageFilter { visibility: Some(Visibility::Private), ..Default::default() }), vec!["gif"]);
+
+        index.set_tags("png", vec!["dogs".to_string()]).unwrap();
+        assert_eq!(ids(ImageFilter { tag: Some("dogs".into()), ..Default::default() }), vec!["png"]);
+        assert!(index.set_tags("missing", Vec::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_lookups_follow_changes() {
+        let index = ImageIndex::open(None).unwrap();
+        let mut first = entry("a", 1);
+        first.content_hash = Some("hash".to_string());
+        first.uploader = vec!["key:abc".to_string()];
+        let mut second = first.clone();
+        (second.id, second.reference.file_id) = ("b".to_string(), "file-b".to_string());
+        index.insert(first).unwrap();
+        index.insert(second).unwrap();
+
+        // Another image with the same bytes is found once the first is gone
+        index.remove("a").unwrap();
+        assert_eq!(index.find_by_hash("hash", None).unwrap().unwrap().id, "b");
+        assert!(index.find_by_hash("hash", Some("acme")).unwrap().is_none());
+        assert!(index.find_by_file_id("file").unwrap().is_none());
+
+        index.update_stored_as("b", "file-b", |entry| {
+            entry.previous_file_ids.push("file-b".to_string());
+            entry.reference.file_id = "moved".to_string();
+        })
+        .unwrap();
+        assert_eq!(index.find_by_file_id("file-b").unwrap().unwrap().id, "b");
+        assert_eq!(index.find_by_file_id("moved").unwrap().unwrap().id, "b");
+
+        index.add_reuploaders("b", &["key:def".to_string()]).unwrap();
+        let filter = ImageFilter { owner: Some("key:def".into()), ..Default::default() };
+        assert_eq!(index.filter(&filter).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_page() {
+        let index = ImageIndex::open(None).unwrap();
+        for (id, created_at) in [("a", 1), ("b", 2), ("c", 2), ("d", 3)] {
+            index.insert(entry(id, created_at)).unwrap();
+        }
+        let ids = |after: Option<(u64, String)>, limit| -> Vec<String> {
+            let page = index.page(&ImageFilter::default(), after.as_ref(), limit).unwrap();
+            page.into_iter().map(|e| e.id).collect()
+        };
+        assert_eq!(ids(None, 2), vec!["d", "c"]);
+        assert_eq!(ids(Some((2, "c".into())), 5), vec!["b", "a"]);
+        let owned = ImageFilter { owner: Some("key:none".into()), ..Default::default() };
+        assert!(index.page(&owned, None, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        assert_eq!(normalize_tags([" Cats", "cats", "", "old-photos"]).unwrap(), vec!["cats", "old-photos"]);
+        assert_eq!(parse_tag_list(Some("b,a")).unwrap(), vec!["a", "b"]);
+        assert!(parse_tag_list(None).unwrap().is_empty());
+        assert!(normalize_tags(["two words"]).is_err());
+        assert!(normalize_tags(["x".repeat(33)]).is_err());
+        assert!(normalize_tags((0..21).map(|i| i.to_string())).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+    time::{timeout_at, Instant},
+};
+
+use crate::services::telegram::TelegramService;
+
+// Messages waiting for delivery before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+// Telegram rejects messages over 4096 characters; stay clear of it when joining lines
+const MAX_BATCH_CHARS: usize = 3800;
+
+/// Hands log lines to a background task so request handlers never wait on Telegram
+#[derive(Clone)]
+pub struct LogQueue {
+    tx: Sender<String>,
+}
+
+impl LogQueue {
+    pub fn new() -> (Self, Receiver<String>) {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        (Self { tx }, rx)
+    }
+
+    /// Queue a line for the next batch; dropped with a warning if delivery has fallen behind
+    pub fn push(&self, message: String) {
+        match self.tx.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => tracing::warn!("Log queue full, dropping log message"),
+            Err(TrySendError::Closed(_)) => tracing::warn!("Log delivery stopped, dropping log message"),
+        }
+    }
+}
+
+/// Post queued lines to `chat_id` (the log chat when unset), joining everything that
+/// arrives within `window` of the first line into one message
+pub async fn run_log_delivery(
+    mut rx: Receiver<String>,
+    telegram_service: Arc<TelegramService>,
+    chat_id: Option<i64>,
+    window: Duration,
+) {
+    let mut carry = None;
+    while let Some(batch) = next_batch(&mut rx, &mut carry, window).await {
+        let result = match chat_id {
+            Some(chat_id) => telegram_service.send_message(chat_id, &batch).await,
+            None => telegram_service.send_log_message(&batch).await,
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to deliver log batch: {}", e);
+        }
+    }
+    tracing::info!("Log delivery stopped");
+}
+
+// A line that would have pushed the previous batch over the limit starts the next one
+async fn next_batch(rx: &mut Receiver<String>, carry: &mut Option<String>, window: Duration) -> Option<String> {
+    let mut batch = match carry.take() {
+        Some(line) => line,
+        None => rx.recv().await?,
+    };
+
+    let deadline = Instant::now() + window;
+    while let Ok(Some(line)) = timeout_at(deadline, rx.recv()).await {
+        if batch.len() + 1 + line.len() > MAX_BATCH_CHARS {
+            *carry = Some(line);
+            break;
+        }
+        batch.push('\n');
+        batch.push_str(&line);
+    }
+    Some(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_next_batch_joins_and_splits() {
+        let (queue, mut rx) = LogQueue::new();
+        queue.push("first".to_string());
+        queue.push("second".to_string());
+        queue.push("x".repeat(MAX_BATCH_CHARS));
+        drop(queue);
+
+        let mut carry = None;
+        let window = Duration::from_millis(10);
+        assert_eq!(next_batch(&mut rx, &mut carry, window).await.unwrap(), "first\nsecond");
+        assert_eq!(next_batch(&mut rx, &mut carry, window).await.unwrap().len(), MAX_BATCH_CHARS);
+        assert!(next_batch(&mut rx, &mut carry, window).await.is_none());
+    }
+}
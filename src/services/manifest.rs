@@ -0,0 +1,183 @@
+use bytes::{Bytes, BytesMut};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::FileReference,
+    services::storage::Storage,
+};
+
+// Chunks of one file fetched at a time while it is put back together
+const CHUNK_DOWNLOAD_PARALLELISM: usize = 4;
+
+/// The pieces of a file stored as several documents, in order. It is kept encrypted in a document
+/// of its own, so an image ID only has to name the manifest however many chunks there are.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub chunks: Vec<ManifestChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestChunk {
+    pub file_id: String,
+    pub message_id: i64,
+    pub size: usize,
+    // Hex SHA-256 of the chunk as stored, checked when the file is reassembled
+    pub sha256: String,
+}
+
+/// Where `store` put a file: its document, or the manifest of its chunks
+pub struct StoredFile {
+    pub file_id: String,
+    pub message_id: i64,
+    pub chunked: bool,
+}
+
+/// Upload `data` as one document or, when it is larger than `chunk_bytes` (0 never splits), as
+/// chunks followed by their manifest encrypted with `manifest_key`
+pub async fn store(
+    storage: &Storage,
+    manifest_key: &[u8; 32],
+    data: Bytes,
+    filename: &str,
+    chunk_bytes: usize,
+) -> Result<StoredFile> {
+    if chunk_bytes == 0 || data.len() <= chunk_bytes {
+        let stored = storage.upload(data, filename).await?;
+        return Ok(StoredFile { file_id: stored.file_id, message_id: stored.message_id, chunked: false });
+    }
+
+    let mut manifest = ChunkManifest::default();
+    let stored = store_chunks(storage, manifest_key, &data, filename, chunk_bytes, &mut manifest).await;
+    if stored.is_err() {
+        // Don't leave the chunks sent before the failure behind
+        delete_chunks(storage, None, &manifest).await;
+    }
+    stored
+}
+
+async fn store_chunks(
+    storage: &Storage,
+    manifest_key: &[u8; 32],
+    data: &Bytes,
+    filename: &str,
+    chunk_bytes: usize,
+    manifest: &mut ChunkManifest,
+) -> Result<StoredFile> {
+    for (number, chunk) in data.chunks(chunk_bytes).enumerate() {
+        let stored = storage.upload(data.slice_ref(chunk), &format!("{}.part{}", filename, number)).await?;
+        manifest.chunks.push(ManifestChunk {
+            file_id: stored.file_id,
+            message_id: stored.message_id,
+            size: chunk.len(),
+            sha256: hex::encode(CryptoService::hash_data(chunk)),
+        });
+    }
+
+    let encrypted = CryptoService::new(manifest_key).encrypt_data(&serde_json::to_vec(manifest)?)?;
+    let stored = storage.upload(Bytes::from(encrypted), &format!("{}.manifest", filename)).await?;
+    Ok(StoredFile { file_id: stored.file_id, message_id: stored.message_id, chunked: true })
+}
+
+/// Reassemble a chunked file from its manifest, refusing it if any chunk differs from what was stored
+pub async fn load(storage: &Storage, manifest_key: &[u8; 32], file_ref: &FileReference) -> Result<Bytes> {
+    let manifest = fetch_manifest(storage, manifest_key, file_ref).await?;
+    let size = manifest.chunks.iter().map(|chunk| chunk.size).sum();
+
+    let chunks: Vec<Bytes> = stream::iter(manifest.chunks)
+        .map(|chunk| fetch_chunk(storage, chunk, &file_ref.file_id))
+        .buffered(CHUNK_DOWNLOAD_PARALLELISM)
+        .try_collect()
+        .await?;
+
+    let mut data = BytesMut::with_capacity(size);
+    for chunk in chunks {
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data.freeze())
+}
+
+async fn fetch_chunk(storage: &Storage, chunk: ManifestChunk, manifest_file_id: &str) -> Result<Bytes> {
+    let data = storage.download(&chunk.file_id, chunk.message_id).await?;
+    if data.len() != chunk.size || hex::encode(CryptoService::hash_data(&data)) != chunk.sha256 {
+        return Err(AppError::InternalError(format!(
+            "Chunk {} of {} failed its integrity check",
+            chunk.file_id, manifest_file_id
+        )));
+    }
+    Ok(data)
+}
+
+/// Delete the chunk messages of a chunked reference in `chat_id` (see `Storage::delete`), leaving the
+/// manifest's own message to the caller. Failures are only logged, since a leftover chunk just
+/// takes up space.
+pub async fn delete_file_chunks(
+    storage: &Storage,
+    manifest_key: &[u8; 32],
+    chat_id: Option<i64>,
+    file_ref: &FileReference,
+) {
+    if !file_ref.chunked {
+        return;
+    }
+    match fetch_manifest(storage, manifest_key, file_ref).await {
+        Ok(manifest) => delete_chunks(storage, chat_id, &manifest).await,
+        Err(e) => tracing::warn!("Could not read manifest {} to delete its chunks: {}", file_ref.file_id, e),
+    }
+}
+
+/// Messages holding the chunks of a chunked reference; none for a file stored whole
+pub async fn chunk_message_ids(storage: &Storage, manifest_key: &[u8; 32], file_ref: &FileReference) -> Result<Vec<i64>> {
+    if !file_ref.chunked {
+        return Ok(Vec::new());
+    }
+    let manifest = fetch_manifest(storage, manifest_key, file_ref).await?;
+    Ok(manifest.chunks.iter().map(|chunk| chunk.message_id).collect())
+}
+
+async fn delete_chunks(storage: &Storage, chat_id: Option<i64>, manifest: &ChunkManifest) {
+    for chunk in &manifest.chunks {
+        if let Err(e) = storage.delete(chat_id, chunk.message_id).await {
+            tracing::warn!("Failed to delete chunk message {}: {}", chunk.message_id, e);
+        }
+    }
+}
+
+async fn fetch_manifest(storage: &Storage, manifest_key: &[u8; 32], file_ref: &FileReference) -> Result<ChunkManifest> {
+    let encrypted = storage.download(&file_ref.file_id, file_ref.message_id).await?;
+    let manifest = CryptoService::new(manifest_key).decrypt_data(&encrypted)?;
+    Ok(serde_json::from_slice(&manifest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::telegram::TelegramService, test_support::FakeTelegram};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_chunked_roundtrip() {
+        let telegram = FakeTelegram::start().await;
+        let storage = Storage::Telegram(Arc::new(
+            TelegramService::new("token".to_string(), -1001, None).with_api_url(&telegram.url()),
+        ));
+        let key = [9u8; 32];
+        let data = Bytes::from((0..2500u32).map(|i| i as u8).collect::<Vec<_>>());
+
+        let stored = store(&storage, &key, data.clone(), "big.bin", 1000).await.unwrap();
+        assert!(stored.chunked);
+        assert_eq!(telegram.stored_files(), 4);
+        let mut file_ref = FileReference::new(stored.file_id, stored.message_id, data.len(), "image/png".to_string());
+        file_ref.chunked = true;
+        assert_eq!(load(&storage, &key, &file_ref).await.unwrap(), data);
+        assert!(load(&storage, &[1u8; 32], &file_ref).await.is_err());
+
+        delete_file_chunks(&storage, &key, None, &file_ref).await;
+        assert_eq!(telegram.stored_files(), 1);
+
+        let small = store(&storage, &key, Bytes::from_static(b"small"), "small.bin", 1000).await.unwrap();
+        assert!(!small.chunked);
+    }
+}
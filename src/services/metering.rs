@@ -0,0 +1,138 @@
+use reqwest::Client;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::{
+    config::Config,
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::unix_timestamp,
+    services::usage::{UsageCounters, UsageStore},
+};
+
+// One API key's metered usage for the current UTC day
+#[derive(Debug, Serialize)]
+pub struct MeteringEntry {
+    pub subject: String,
+    // Days since the Unix epoch; billing systems should upsert on (subject, day)
+    pub day: u64,
+    pub requests: u64,
+    pub bytes_served: u64,
+    // Net bytes currently stored by the key across the retention window
+    pub bytes_stored: i64,
+    pub counters: UsageCounters,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MeteringEvent {
+    pub event: &'static str,
+    pub generated_at: u64,
+    pub entries: Vec<MeteringEntry>,
+}
+
+/// Build the usage event reported to the billing webhook; only API keys are billable
+pub fn build_metering_event(usage: &UsageStore, retention_days: u64) -> Result<MeteringEvent> {
+    let today = unix_timestamp() / 86_400;
+
+    let entries = usage
+        .summaries(None, retention_days.max(1))?
+        .into_iter()
+        .filter(|summary| summary.subject.starts_with("key:"))
+        .map(|summary| {
+            let counters = summary
+                .daily
+                .into_iter()
+                .find(|record| record.day == today)
+                .map(|record| record.counters)
+                .unwrap_or_default();
+
+            MeteringEntry {
+                subject: summary.subject,
+                day: today,
+                requests: counters.uploads + counters.deletes + counters.downloads,
+                bytes_served: counters.bytes_served,
+                bytes_stored: summary.totals.bytes_stored,
+                counters,
+            }
+        })
+        .collect();
+
+    Ok(MeteringEvent {
+        event: "usage.report",
+        generated_at: unix_timestamp(),
+        entries,
+    })
+}
+
+/// Signature sent in `X-RustGram-Signature`: HMAC-SHA256 over "<timestamp>.<body>"
+pub fn sign_payload(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    format!("sha256={}", hex::encode(CryptoService::hmac_sha256(secret.as_bytes(), &signed)))
+}
+
+/// POST the current usage event to the configured billing webhook
+pub async fn send_metering_event(client: &Client, usage: Arc<UsageStore>, config: Arc<Config>) -> Result<()> {
+    let Some(url) = &config.billing_webhook_url else {
+        return Ok(());
+    };
+
+    let event = build_metering_event(&usage, config.usage_retention_days)?;
+    if event.entries.is_empty() {
+        return Ok(());
+    }
+
+    let body = serde_json::to_vec(&event)?;
+    let timestamp = event.generated_at;
+
+    let mut request = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-RustGram-Timestamp", timestamp.to_string());
+    if let Some(secret) = &config.billing_webhook_secret {
+        request = request.header("X-RustGram-Signature", sign_payload(secret, timestamp, &body));
+    }
+
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| AppError::InternalError(format!("Billing webhook failed: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(AppError::InternalError(format!(
+            "Billing webhook returned status {}",
+            response.status()
+        )));
+    }
+
+    tracing::info!("Sent usage report for {} keys to billing webhook", event.entries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::usage::usage_subjects;
+
+    #[test]
+    fn test_metering_event_only_reports_keys() {
+        let usage = UsageStore::open(None).unwrap();
+        usage.record_upload(&usage_subjects("10.0.0.1".parse().unwrap(), Some("key")), 100);
+        usage.record_download(&usage_subjects("10.0.0.2".parse().unwrap(), None), 100);
+
+        let event = build_metering_event(&usage, 30).unwrap();
+        assert_eq!(event.entries.len(), 1);
+        assert!(event.entries[0].subject.starts_with("key:"));
+        assert_eq!(event.entries[0].requests, 1);
+        assert_eq!(event.entries[0].bytes_stored, 100);
+    }
+
+    #[test]
+    fn test_sign_payload() {
+        let signature = sign_payload("secret", 1700000000, b"{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature, sign_payload("secret", 1700000000, b"{}"));
+        assert_ne!(signature, sign_payload("secret", 1700000001, b"{}"));
+    }
+}
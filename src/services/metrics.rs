@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Process-wide counters, exported in Prometheus text format by GET /metrics
+#[derive(Default)]
+pub struct Metrics {
+    panics: AtomicU64,
+    // Per Telegram method, e.g. "send_document"
+    telegram_latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    // Per (method, error class)
+    telegram_errors: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+impl Metrics {
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count one Telegram call, failed ones under their error class
+    pub fn record_telegram_call(&self, method: &'static str, elapsed: Duration, error_class: Option<&'static str>) {
+        self.telegram_latency
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .observe(elapsed);
+        if let Some(class) = error_class {
+            *self.telegram_errors.lock().unwrap().entry((method, class)).or_default() += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "rustgram_panics_total",
+            "Requests whose handler panicked",
+            self.panics.load(Ordering::Relaxed),
+        );
+
+        let name = "rustgram_telegram_request_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Latency of Telegram API calls and file downloads", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (method, histogram) in self.telegram_latency.lock().unwrap().iter() {
+            histogram.render(&mut out, name, method);
+        }
+
+        let name = "rustgram_telegram_errors_total";
+        let _ = writeln!(out, "# HELP {} Failed Telegram calls by method and error class", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for ((method, class), count) in self.telegram_errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{method=\"{}\",class=\"{}\"}} {}", name, method, class, count);
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Non-cumulative counts per bucket, plus one for observations above the last bound
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum_secs: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum_secs += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, method: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{method=\"{}\",le=\"{}\"}} {}", name, method, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{method=\"{}\",le=\"+Inf\"}} {}", name, method, self.count);
+        let _ = writeln!(out, "{}_sum{{method=\"{}\"}} {}", name, method, self.sum_secs);
+        let _ = writeln!(out, "{}_count{{method=\"{}\"}} {}", name, method, self.count);
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counts_panics() {
+        let metrics = Metrics::default();
+        metrics.record_panic();
+        metrics.record_panic();
+        assert!(metrics.render().contains("\nrustgram_panics_total 2\n"));
+    }
+
+    #[test]
+    fn test_render_telegram_histogram() {
+        let metrics = Metrics::default();
+        metrics.record_telegram_call("get_file", Duration::from_millis(80), None);
+        metrics.record_telegram_call("get_file", Duration::from_secs(3), Some("timeout"));
+
+        let out = metrics.render();
+        assert!(out.contains("rustgram_telegram_request_duration_seconds_bucket{method=\"get_file\",le=\"0.1\"} 1\n"));
+        assert!(out.contains("rustgram_telegram_request_duration_seconds_bucket{method=\"get_file\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("rustgram_telegram_errors_total{method=\"get_file\",class=\"timeout\"} 1\n"));
+    }
+}
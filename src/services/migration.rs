@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::models::{unix_timestamp, Backend, FileReference};
+
+/// A place images are stored: a backend, and for Telegram the chat (the default chat when None)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageLocation {
+    pub backend: Backend,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+}
+
+impl StorageLocation {
+    /// The Telegram chat of this location, resolving the default; None for other backends
+    pub fn chat(&self, default_chat_id: i64) -> Option<i64> {
+        self.backend.is_telegram().then(|| self.chat_id.unwrap_or(default_chat_id))
+    }
+
+    /// Whether `file_ref` is stored here
+    pub fn holds(&self, file_ref: &FileReference, default_chat_id: i64) -> bool {
+        let location = StorageLocation { backend: file_ref.backend, chat_id: file_ref.chat_id };
+        self.backend == file_ref.backend && self.chat(default_chat_id) == location.chat(default_chat_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationFailure {
+    pub id: String,
+    pub error: String,
+}
+
+/// Progress and outcome of one migration of stored images between locations
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationReport {
+    pub job_id: String,
+    pub status: MigrationStatus,
+    pub from: StorageLocation,
+    pub to: StorageLocation,
+    pub delete_source: bool,
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    pub images_found: usize,
+    pub images_migrated: usize,
+    // Images the target can't hold for their tenant, such as those of a tenant with its own bot
+    pub images_skipped: usize,
+    pub bytes_copied: u64,
+    // Images left where they were; running the migration again retries them
+    pub failed: Vec<MigrationFailure>,
+}
+
+impl MigrationReport {
+    pub fn new(job_id: String, from: StorageLocation, to: StorageLocation, delete_source: bool) -> Self {
+        Self {
+            job_id,
+            status: MigrationStatus::Running,
+            from,
+            to,
+            delete_source,
+            started_at: unix_timestamp(),
+            finished_at: None,
+            images_found: 0,
+            images_migrated: 0,
+            images_skipped: 0,
+            bytes_copied: 0,
+            failed: Vec::new(),
+        }
+    }
+}
+
+/// Reports of migration jobs started since the process came up
+#[derive(Default)]
+pub struct MigrationJobs {
+    reports: Mutex<HashMap<String, MigrationReport>>,
+}
+
+impl MigrationJobs {
+    pub fn update(&self, report: &MigrationReport) {
+        self.reports.lock().unwrap().insert(report.job_id.clone(), report.clone());
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<MigrationReport> {
+        self.reports.lock().unwrap().get(job_id).cloned()
+    }
+
+    /// Whether a migration is still copying; two at once could copy the same image twice
+    pub fn running(&self) -> bool {
+        self.reports.lock().unwrap().values().any(|report| report.status == MigrationStatus::Running)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_holds() {
+        let mut file_ref = FileReference::new("file".to_string(), 1, 10, "image/png".to_string());
+        let default_chat = StorageLocation { backend: Backend::Telegram, chat_id: None };
+        let other_chat = StorageLocation { backend: Backend::Telegram, chat_id: Some(-1002) };
+        let discord = StorageLocation { backend: Backend::Discord, chat_id: None };
+
+        assert!(default_chat.holds(&file_ref, -1001));
+        assert!(StorageLocation { chat_id: Some(-1001), ..default_chat }.holds(&file_ref, -1001));
+        assert!(!other_chat.holds(&file_ref, -1001));
+
+        file_ref.chat_id = Some(-1002);
+        assert!(other_chat.holds(&file_ref, -1001));
+        assert!(!default_chat.holds(&file_ref, -1001));
+
+        file_ref.backend = Backend::Discord;
+        assert!(discord.holds(&file_ref, -1001));
+        assert!(!other_chat.holds(&file_ref, -1001));
+    }
+}
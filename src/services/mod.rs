@@ -1 +1,35 @@
 pub mod telegram;
+pub mod albums;
+pub mod audit;
+pub mod cache;
+pub mod captcha;
+pub mod chaos;
+pub mod cdn;
+pub mod chat_migrations;
+pub mod checksum;
+pub mod coalesce;
+pub mod cpu_pool;
+pub mod discord;
+pub mod erasure;
+pub mod geoip;
+pub mod icc;
+pub mod index;
+pub mod log_queue;
+pub mod manifest;
+pub mod migration;
+pub mod usage;
+pub mod watermark;
+pub mod metering;
+pub mod metrics;
+pub mod modes;
+pub mod optimize;
+pub mod ocr;
+pub mod palette;
+pub mod previews;
+pub mod signing;
+pub mod similarity;
+pub mod spill;
+pub mod storage;
+pub mod tenants;
+pub mod transcode;
+pub mod transform;
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+/// Operating modes an admin can switch at runtime, starting from their configured values.
+/// Switches are not persisted, so a restart goes back to the configuration.
+pub struct RuntimeModes {
+    maintenance: AtomicBool,
+    maintenance_retry_after_secs: u64,
+    read_only: AtomicBool,
+}
+
+impl RuntimeModes {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            maintenance: AtomicBool::new(config.maintenance_mode),
+            maintenance_retry_after_secs: config.maintenance_retry_after_secs,
+            read_only: AtomicBool::new(config.read_only),
+        }
+    }
+
+    pub fn maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the mode changed
+    pub fn set_maintenance(&self, enabled: bool) -> bool {
+        self.maintenance.swap(enabled, Ordering::Relaxed) != enabled
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the mode changed
+    pub fn set_read_only(&self, enabled: bool) -> bool {
+        self.read_only.swap(enabled, Ordering::Relaxed) != enabled
+    }
+
+    /// Fails with 503 and Retry-After while maintenance mode is on
+    pub fn check_uploads(&self) -> Result<()> {
+        if self.maintenance() {
+            return Err(AppError::Maintenance { retry_after_secs: self.maintenance_retry_after_secs });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_refuses_uploads() {
+        let modes = RuntimeModes {
+            maintenance: AtomicBool::new(false),
+            maintenance_retry_after_secs: 60,
+            read_only: AtomicBool::new(false),
+        };
+        assert!(modes.check_uploads().is_ok());
+
+        assert!(modes.set_maintenance(true));
+        assert!(!modes.set_maintenance(true));
+        assert!(matches!(modes.check_uploads(), Err(AppError::Maintenance { retry_after_secs: 60 })));
+    }
+}
@@ -0,0 +1,57 @@
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::error::{AppError, Result};
+
+// OCR runs inside the upload worker, so a slow service must not hold up the queue for long
+const OCR_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Longest text kept per image
+const MAX_TEXT_CHARS: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct OcrResponse {
+    text: String,
+}
+
+/// POST the image bytes to the OCR service at `url` and return the text it found, if any.
+/// The service receives the raw image with its Content-Type and answers `{"text": "..."}`.
+pub async fn extract_text(client: &Client, url: &str, image_data: Vec<u8>, mime_type: &str) -> Result<Option<String>> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, mime_type)
+        .timeout(OCR_TIMEOUT)
+        .body(image_data)
+        .send()
+        .await
+        .map_err(|e| AppError::InternalError(format!("OCR request failed: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(AppError::InternalError(format!("OCR service returned {}", response.status())));
+    }
+
+    let result: OcrResponse = response
+        .json()
+        .await
+        .map_err(|e| AppError::InternalError(format!("Invalid OCR response: {}", e)))?;
+
+    Ok(normalize_text(&result.text))
+}
+
+/// Collapse whitespace and cap the length; `None` when nothing readable was found
+fn normalize_text(text: &str) -> Option<String> {
+    let text: String = text.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(MAX_TEXT_CHARS).collect();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("  Hello\n\n  world\t!"), Some("Hello world !".to_string()));
+        assert_eq!(normalize_text(" \n "), None);
+    }
+}
@@ -0,0 +1,398 @@
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    imageops, DynamicImage, ImageEncoder,
+};
+use flate2::read::ZlibDecoder;
+use serde::Deserialize;
+use std::io::Read;
+
+use crate::{
+    error::{AppError, Result},
+    services::icc::{self, ColorSpace},
+};
+
+// Quality of JPEGs re-encoded after orienting or downscaling
+const JPEG_QUALITY: u8 = 90;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// Ancillary PNG chunks carrying text, EXIF or timestamps rather than anything affecting the pixels
+const PNG_METADATA_CHUNKS: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+// Larger "profiles" are not color profiles worth reading
+const MAX_ICC_PROFILE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// One step of the upload optimization pipeline, run in the order OPTIMIZE_STEPS lists them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizeStep {
+    // Drop EXIF, XMP, IPTC, comments and text chunks; color profiles are kept
+    Strip,
+    // Rotate or flip JPEGs upright per their EXIF orientation
+    Orient,
+    // Shrink images whose longest edge is over OPTIMIZE_MAX_DIMENSION
+    Downscale,
+    // Recompress PNGs at the highest lossless setting, when that comes out smaller
+    Recompress,
+    // Convert CMYK JPEGs and images with RGB profiles other than sRGB, such as Display P3, to sRGB
+    Srgb,
+}
+
+/// Comma-separated steps, e.g. "strip,orient,downscale,recompress"
+pub fn parse_steps(spec: &str) -> anyhow::Result<Vec<OptimizeStep>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| match step.to_lowercase().as_str() {
+            "strip" => Ok(OptimizeStep::Strip),
+            "orient" => Ok(OptimizeStep::Orient),
+            "downscale" => Ok(OptimizeStep::Downscale),
+            "recompress" => Ok(OptimizeStep::Recompress),
+            "srgb" => Ok(OptimizeStep::Srgb),
+            other => anyhow::bail!(
+                "Unknown OPTIMIZE_STEPS step '{}': use strip, orient, downscale, recompress or srgb",
+                other
+            ),
+        })
+        .collect()
+}
+
+/// The image as it goes through the pipeline: as encoded, or decoded once a step changed its pixels
+enum Stage {
+    Encoded(Vec<u8>),
+    Decoded(DynamicImage),
+}
+
+/// Run `steps` over a JPEG or PNG, returning the optimized bytes, or None when nothing changed or
+/// the type isn't optimized. Pixel steps decode the image and encode it once at the end, which
+/// drops its metadata too, color profile included.
+pub fn optimize(
+    data: &[u8],
+    mime_type: &str,
+    steps: &[OptimizeStep],
+    max_dimension: Option<u32>,
+) -> Result<Option<Vec<u8>>> {
+    let is_png = match mime_type {
+        "image/png" => true,
+        "image/jpeg" => false,
+        _ => return Ok(None),
+    };
+    // Read before any step, so stripping or re-encoding first doesn't lose them
+    let orientation = if is_png { None } else { jpeg_orientation(data) };
+    let color = if is_png { png_color_space(data) } else { jpeg_color_space(data) };
+
+    let mut stage = Stage::Encoded(data.to_vec());
+    let mut best_png = false;
+    for step in steps {
+        stage = match (step, stage) {
+            (OptimizeStep::Strip, Stage::Encoded(bytes)) => {
+                Stage::Encoded(if is_png { strip_png(&bytes) } else { strip_jpeg(&bytes) })
+            }
+            (OptimizeStep::Orient, stage) => match orientation {
+                Some(orientation @ 2..=8) => Stage::Decoded(orient(decode(stage)?, orientation)),
+                _ => stage,
+            },
+            (OptimizeStep::Downscale, stage) => {
+                let Some(max) = max_dimension else {
+                    return Err(AppError::ConfigError("downscale requires OPTIMIZE_MAX_DIMENSION".to_string()));
+                };
+                let decoded = decode(stage)?;
+                if decoded.width().max(decoded.height()) > max {
+                    Stage::Decoded(decoded.resize(max, max, imageops::FilterType::Lanczos3))
+                } else {
+                    Stage::Decoded(decoded)
+                }
+            }
+            (OptimizeStep::Recompress, Stage::Encoded(bytes)) if is_png => {
+                let recompressed = encode(&image::load_from_memory(&bytes)?, true, true)?;
+                Stage::Encoded(if recompressed.len() < bytes.len() { recompressed } else { bytes })
+            }
+            (OptimizeStep::Recompress, stage) => {
+                best_png = true;
+                stage
+            }
+            (OptimizeStep::Srgb, stage) => match &color {
+                // The decoder converts CMYK to RGB, and the output carries no profile to misread it by
+                ColorSpace::Cmyk => Stage::Decoded(decode(stage)?),
+                ColorSpace::Rgb(profile) => Stage::Decoded(profile.convert(&decode(stage)?)),
+                ColorSpace::Srgb | ColorSpace::Unsupported => stage,
+            },
+            // Decoded images lose their metadata when encoded anyway
+            (OptimizeStep::Strip, stage) => stage,
+        };
+    }
+
+    let optimized = match stage {
+        Stage::Encoded(bytes) => bytes,
+        Stage::Decoded(decoded) => encode(&decoded, is_png, best_png)?,
+    };
+    Ok((optimized != data).then_some(optimized))
+}
+
+fn decode(stage: Stage) -> Result<DynamicImage> {
+    match stage {
+        Stage::Encoded(bytes) => Ok(image::load_from_memory(&bytes)?),
+        Stage::Decoded(decoded) => Ok(decoded),
+    }
+}
+
+fn encode(image: &DynamicImage, png: bool, best: bool) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    if png {
+        let compression = if best { CompressionType::Best } else { CompressionType::Default };
+        let encoder = PngEncoder::new_with_quality(&mut output, compression, FilterType::Adaptive);
+        encoder.write_image(image.as_bytes(), image.width(), image.height(), image.color())?;
+    } else {
+        let rgb = image.to_rgb8();
+        JpegEncoder::new_with_quality(&mut output, JPEG_QUALITY).encode_image(&rgb)?;
+    }
+    Ok(output)
+}
+
+/// Apply an EXIF orientation (2-8), leaving the image as it should be displayed
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// A JPEG segment's marker, and the whole segment including marker and length
+type Segment<'a> = (u8, &'a [u8]);
+
+/// JPEG segments before the image data, and the scan from its start-of-scan marker on
+fn jpeg_segments(data: &[u8]) -> Option<(Vec<Segment<'_>>, &[u8])> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any number of 0xFF fill bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if data.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        // Start of scan: the rest is entropy-coded data up to the end of the file
+        if marker == 0xDA {
+            return Some((segments, &data[pos..]));
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let end = pos + 2 + length;
+        segments.push((marker, data.get(pos..end)?));
+        pos = end;
+    }
+}
+
+/// Drop APP1 (EXIF, XMP), APP3-APP13 and APP15 (IPTC and vendor data) and comments, keeping
+/// JFIF, the ICC profile (APP2) and Adobe's color transform (APP14). Unparseable files are kept as they are.
+fn strip_jpeg(data: &[u8]) -> Vec<u8> {
+    let Some((segments, scan)) = jpeg_segments(data) else {
+        return data.to_vec();
+    };
+    let mut stripped = vec![0xFF, 0xD8];
+    for (marker, segment) in segments {
+        let metadata = matches!(marker, 0xE1 | 0xE3..=0xED | 0xEF | 0xFE);
+        if !metadata {
+            stripped.extend_from_slice(segment);
+        }
+    }
+    stripped.extend_from_slice(scan);
+    stripped
+}
+
+/// Orientation tag (1-8) of a JPEG's EXIF data
+fn jpeg_orientation(data: &[u8]) -> Option<u16> {
+    let (segments, _) = jpeg_segments(data)?;
+    let exif = segments
+        .iter()
+        .find_map(|(marker, segment)| (*marker == 0xE1).then(|| segment[4..].strip_prefix(b"Exif\0\0")).flatten())?;
+
+    let big_endian = match exif.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |at: usize| -> Option<u16> {
+        let bytes = [*exif.get(at)?, *exif.get(at + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |at: usize| -> Option<u32> {
+        let bytes = [*exif.get(at)?, *exif.get(at + 1)?, *exif.get(at + 2)?, *exif.get(at + 3)?];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries).find_map(|i| {
+        let entry = ifd + 2 + i * 12;
+        (read_u16(entry)? == 0x0112).then(|| read_u16(entry + 8)).flatten()
+    })
+}
+
+/// Color space of a JPEG: CMYK for four components, otherwise per its ICC profile, which may be
+/// split over several APP2 segments
+fn jpeg_color_space(data: &[u8]) -> ColorSpace {
+    let Some((segments, _)) = jpeg_segments(data) else {
+        return ColorSpace::Unsupported;
+    };
+    // Start-of-frame markers, less DHT (C4), JPG (C8) and DAC (CC)
+    let components = segments
+        .iter()
+        .find(|(marker, _)| matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC))
+        .and_then(|(_, segment)| segment.get(9).copied());
+    if components == Some(4) {
+        return ColorSpace::Cmyk;
+    }
+
+    let mut parts: Vec<(u8, &[u8])> = segments
+        .iter()
+        .filter(|(marker, _)| *marker == 0xE2)
+        .filter_map(|(_, segment)| segment[4..].strip_prefix(b"ICC_PROFILE\0"))
+        .filter_map(|part| Some((*part.first()?, part.get(2..)?)))
+        .collect();
+    if parts.is_empty() {
+        return ColorSpace::Srgb;
+    }
+    parts.sort_by_key(|(sequence, _)| *sequence);
+    let profile: Vec<u8> = parts.iter().flat_map(|(_, part)| part.iter().copied()).collect();
+    icc::parse(&profile)
+}
+
+/// Color space of a PNG per its iCCP chunk; sRGB without one
+fn png_color_space(data: &[u8]) -> ColorSpace {
+    let Some(chunks) = png_chunks(data) else {
+        return ColorSpace::Unsupported;
+    };
+    let Some((_, chunk)) = chunks.iter().find(|(kind, _)| kind == b"iCCP") else {
+        return ColorSpace::Srgb;
+    };
+    // Profile name, NUL, compression method, then the zlib-compressed profile
+    let body = &chunk[8..chunk.len() - 4];
+    let Some(compressed) = body.iter().position(|b| *b == 0).and_then(|name_end| body.get(name_end + 2..)) else {
+        return ColorSpace::Unsupported;
+    };
+    let mut profile = Vec::new();
+    match ZlibDecoder::new(compressed).take(MAX_ICC_PROFILE_BYTES).read_to_end(&mut profile) {
+        Ok(_) => icc::parse(&profile),
+        Err(_) => ColorSpace::Unsupported,
+    }
+}
+
+// A PNG chunk's type, and the whole chunk: length, type, data and CRC
+type PngChunk<'a> = (&'a [u8], &'a [u8]);
+
+/// Chunks of a PNG in order, or None when it doesn't parse
+fn png_chunks(data: &[u8]) -> Option<Vec<PngChunk<'_>>> {
+    let mut rest = data.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let chunk = rest.get(..length.checked_add(12)?)?;
+        chunks.push((&chunk[4..8], chunk));
+        rest = &rest[chunk.len()..];
+    }
+    Some(chunks)
+}
+
+/// Drop PNG text, EXIF and timestamp chunks. Unparseable files are kept as they are.
+fn strip_png(data: &[u8]) -> Vec<u8> {
+    let Some(chunks) = png_chunks(data) else {
+        return data.to_vec();
+    };
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    for (kind, chunk) in chunks {
+        if !PNG_METADATA_CHUNKS.iter().any(|metadata| kind == &metadata[..]) {
+            stripped.extend_from_slice(chunk);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // 4x2 JPEG with an EXIF block saying it has to be turned 90 degrees clockwise
+    fn rotated_jpeg() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 2, image::Rgb([10, 120, 200])))
+            .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        let tiff: &[u8] = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(tiff);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn test_jpeg_pipeline() {
+        let jpeg = rotated_jpeg();
+        assert_eq!(jpeg_orientation(&jpeg), Some(6));
+
+        let stripped = optimize(&jpeg, "image/jpeg", &[OptimizeStep::Strip], None).unwrap().unwrap();
+        assert_eq!(jpeg_orientation(&stripped), None);
+        assert_eq!(stripped.len(), jpeg.len() - 36);
+
+        // Orientation is read up front, so it still applies after stripping
+        let steps = [OptimizeStep::Strip, OptimizeStep::Orient, OptimizeStep::Downscale];
+        let upright = optimize(&jpeg, "image/jpeg", &steps, Some(2)).unwrap().unwrap();
+        let decoded = image::load_from_memory(&upright).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (1, 2));
+
+        assert!(optimize(&stripped, "image/jpeg", &[OptimizeStep::Strip], None).unwrap().is_none());
+        assert!(optimize(&jpeg, "image/gif", &steps, Some(2)).unwrap().is_none());
+        assert!(parse_steps("strip, Orient").unwrap() == [OptimizeStep::Strip, OptimizeStep::Orient]);
+        assert!(parse_steps("sharpen").is_err());
+    }
+
+    #[test]
+    fn test_srgb_from_embedded_profile() {
+        use crate::test_support::{icc_profile, LINEAR_ICC_CURVE};
+
+        // Grey JPEG tagged as linear light, the profile split over two APP2 segments
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([100, 100, 100])))
+            .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        let profile = icc_profile(LINEAR_ICC_CURVE);
+        let (first, second) = profile.split_at(100);
+        let mut segments = Vec::new();
+        for (sequence, part) in [(1u8, first), (2, second)] {
+            segments.extend_from_slice(&[0xFF, 0xE2]);
+            segments.extend_from_slice(&((2 + 14 + part.len()) as u16).to_be_bytes());
+            segments.extend_from_slice(b"ICC_PROFILE\0");
+            segments.extend_from_slice(&[sequence, 2]);
+            segments.extend_from_slice(part);
+        }
+        jpeg.splice(2..2, segments);
+        assert!(matches!(jpeg_color_space(&jpeg), ColorSpace::Rgb(_)));
+
+        // Stripping keeps the profile; converting brightens the pixels to match it
+        let stripped = optimize(&jpeg, "image/jpeg", &[OptimizeStep::Strip], None).unwrap();
+        assert!(stripped.is_none());
+        let converted = optimize(&jpeg, "image/jpeg", &[OptimizeStep::Srgb], None).unwrap().unwrap();
+        assert!(matches!(jpeg_color_space(&converted), ColorSpace::Srgb));
+        let pixel = image::load_from_memory(&converted).unwrap().to_rgb8().get_pixel(4, 4).0;
+        assert!(pixel.iter().all(|channel| (165..=175).contains(channel)), "{:?}", pixel);
+
+        // Untagged images are already sRGB
+        assert!(optimize(&converted, "image/jpeg", &[OptimizeStep::Srgb], None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,59 @@
+use image::{imageops::FilterType, DynamicImage};
+use std::collections::HashMap;
+
+// Colors kept per image, most common first
+const PALETTE_SIZE: usize = 5;
+
+// Pixels are sampled from a thumbnail this size; placeholders don't need more detail
+const SAMPLE_SIZE: u32 = 64;
+
+/// The most common colors of an image as "#rrggbb", dominant color first.
+/// Pixels are bucketed by their top 4 bits per channel and each bucket reports its mean color;
+/// mostly transparent pixels are ignored.
+pub fn extract_palette(image: &DynamicImage) -> Vec<String> {
+    let sample = image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle).to_rgba8();
+
+    let mut buckets: HashMap<(u8, u8, u8), (u64, [u64; 3])> = HashMap::new();
+    for pixel in sample.pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let (count, sums) = buckets.entry((r >> 4, g >> 4, b >> 4)).or_default();
+        *count += 1;
+        sums[0] += r as u64;
+        sums[1] += g as u64;
+        sums[2] += b as u64;
+    }
+
+    let mut buckets: Vec<(u64, [u64; 3])> = buckets.into_values().collect();
+    buckets.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    buckets
+        .into_iter()
+        .take(PALETTE_SIZE)
+        .map(|(count, sums)| {
+            format!("#{:02x}{:02x}{:02x}", sums[0] / count, sums[1] / count, sums[2] / count)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_extract_palette() {
+        // Three quarters red, one quarter blue, with a transparent stripe that is ignored
+        let image = RgbaImage::from_fn(100, 100, |x, y| match (x, y) {
+            (_, 0..=4) => Rgba([0, 255, 0, 0]),
+            (0..=74, _) => Rgba([200, 10, 10, 255]),
+            _ => Rgba([10, 10, 200, 255]),
+        });
+
+        let palette = extract_palette(&DynamicImage::ImageRgba8(image));
+        assert_eq!(palette.first().map(String::as_str), Some("#c80a0a"));
+        assert!(palette.iter().any(|color| color == "#0a0ac8"));
+        assert!(palette.len() <= PALETTE_SIZE);
+    }
+}
@@ -0,0 +1,58 @@
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::models::unix_timestamp;
+
+struct PreviewGrant {
+    image_id: String,
+    expires_at: u64,
+}
+
+/// Single-use tokens letting anyone fetch one image once, for a few minutes. Held in memory only,
+/// so a restart revokes every outstanding preview.
+#[derive(Default)]
+pub struct PreviewTokens {
+    grants: Mutex<HashMap<String, PreviewGrant>>,
+}
+
+impl PreviewTokens {
+    /// Mint a token for `image_id`, returning it with its expiry
+    pub fn mint(&self, image_id: &str, ttl_secs: u64) -> (String, u64) {
+        let mut bytes = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        let now = unix_timestamp();
+        let expires_at = now + ttl_secs;
+
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|_, grant| grant.expires_at > now);
+        grants.insert(token.clone(), PreviewGrant { image_id: image_id.to_string(), expires_at });
+        (token, expires_at)
+    }
+
+    /// Use up a token, returning the image it grants unless it is unknown, spent or expired
+    pub fn redeem(&self, token: &str) -> Option<String> {
+        let grant = self.grants.lock().unwrap().remove(token)?;
+        (grant.expires_at > unix_timestamp()).then_some(grant.image_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_single_use() {
+        let previews = PreviewTokens::default();
+        let (token, expires_at) = previews.mint("abc", 60);
+        assert!(expires_at > unix_timestamp());
+
+        assert_eq!(previews.redeem(&token).as_deref(), Some("abc"));
+        assert!(previews.redeem(&token).is_none());
+        assert!(previews.redeem("made-up").is_none());
+
+        let (expired, _) = previews.mint("abc", 0);
+        assert!(previews.redeem(&expired).is_none());
+    }
+}
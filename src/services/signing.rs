@@ -0,0 +1,57 @@
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+
+use crate::{config::Config, crypto::CryptoService};
+
+/// Signs the SHA-256 of served image bodies with Ed25519, so clients holding the public key from
+/// /pubkey can check an intermediary didn't alter them
+pub struct ResponseSigner {
+    key: SigningKey,
+}
+
+impl ResponseSigner {
+    /// The signer when RESPONSE_SIGNATURES is on, keyed by RESPONSE_SIGNING_KEY or, without it, a
+    /// key derived from the master key so it survives restarts
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        if !config.response_signatures {
+            return Ok(None);
+        }
+        let seed: [u8; 32] = match &config.response_signing_key {
+            Some(key) => general_purpose::STANDARD
+                .decode(key)
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| anyhow::anyhow!("RESPONSE_SIGNING_KEY must be 32 bytes of base64"))?,
+            None => CryptoService::hmac_sha256(&config.get_encryption_key_bytes()?, b"rustgram:response-signing"),
+        };
+        Ok(Some(Self { key: SigningKey::from_bytes(&seed) }))
+    }
+
+    pub fn public_key(&self) -> String {
+        general_purpose::STANDARD.encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// Base64 signature over a body's SHA-256 digest
+    pub fn sign(&self, body_sha256: &[u8; 32]) -> String {
+        general_purpose::STANDARD.encode(self.key.sign(body_sha256).to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    #[test]
+    fn test_signature_verifies_with_public_key() {
+        let signer = ResponseSigner { key: SigningKey::from_bytes(&[3u8; 32]) };
+        let digest = CryptoService::hash_data(b"image bytes");
+        let signature = general_purpose::STANDARD.decode(signer.sign(&digest)).unwrap();
+        let public_key = general_purpose::STANDARD.decode(signer.public_key()).unwrap();
+
+        let verifying_key = VerifyingKey::from_bytes(&public_key.try_into().unwrap()).unwrap();
+        let signature = Signature::from_bytes(&signature.try_into().unwrap());
+        assert!(verifying_key.verify(&digest, &signature).is_ok());
+        assert!(verifying_key.verify(&CryptoService::hash_data(b"tampered"), &signature).is_err());
+    }
+}
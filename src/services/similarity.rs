@@ -0,0 +1,59 @@
+use image::{imageops::FilterType, DynamicImage};
+
+// Default similarity score above which two images count as near-duplicates
+pub const DEFAULT_THRESHOLD: f32 = 0.85;
+
+/// 64-bit difference hash: each bit says whether a pixel of a 9x8 grayscale thumbnail is
+/// brighter than its right neighbour. Re-encoding, resizing and light edits barely move it.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// Hex form stored in the index
+pub fn encode(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn decode(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
+
+/// Share of matching bits between two hashes, from 0.0 to 1.0
+pub fn similarity(a: u64, b: u64) -> f32 {
+    1.0 - (a ^ b).count_ones() as f32 / 64.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient(width: u32, height: u32, flip: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let x = if flip { width - 1 - x } else { x };
+            let value = ((x * 255 / width + y * 64 / height) % 256) as u8;
+            Rgb([value, value, value])
+        }))
+    }
+
+    #[test]
+    fn test_dhash_survives_resizing() {
+        let original = dhash(&gradient(400, 300, false));
+        let resized = dhash(&gradient(120, 90, false));
+        let different = dhash(&gradient(400, 300, true));
+
+        assert!(similarity(original, resized) >= DEFAULT_THRESHOLD);
+        assert!(similarity(original, different) < DEFAULT_THRESHOLD);
+        assert_eq!(decode(&encode(original)), Some(original));
+    }
+}
@@ -0,0 +1,215 @@
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+};
+
+// Plaintext gathered into each encrypted record of a spooled body
+const SPOOL_RECORD_BYTES: usize = 64 * 1024;
+
+/// Encrypted image data waiting in the upload queue, kept on disk once it passes
+/// SPILL_THRESHOLD_BYTES so a full queue doesn't pin its payloads in RAM
+#[derive(Debug)]
+pub enum Payload {
+    Memory(Bytes),
+    Spilled(SpillFile),
+}
+
+impl Payload {
+    /// Keep `data` in memory, or write it to `dir` when it is larger than `threshold` (0 never spills).
+    /// `data` must already be encrypted, since it is written out as is.
+    pub fn new(data: Vec<u8>, threshold: usize, dir: &Path) -> Result<Self> {
+        if threshold == 0 || data.len() <= threshold {
+            return Ok(Payload::Memory(data.into()));
+        }
+
+        let path = dir.join(format!("rustgram-spill-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &data)
+            .map_err(|e| AppError::InternalError(format!("Failed to spill upload to disk: {}", e)))?;
+        Ok(Payload::Spilled(SpillFile { path, len: data.len() }))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Payload::Memory(data) => data.len(),
+            Payload::Spilled(file) => file.len,
+        }
+    }
+
+    /// The payload's bytes, shared without copying unless they have to be read back from disk
+    pub async fn load(&self) -> Result<Bytes> {
+        match self {
+            Payload::Memory(data) => Ok(data.clone()),
+            Payload::Spilled(file) => tokio::fs::read(&file.path)
+                .await
+                .map(Bytes::from)
+                .map_err(|e| AppError::InternalError(format!("Failed to read spilled upload: {}", e))),
+        }
+    }
+}
+
+/// A request body as it arrives: in memory up to the spill threshold, then appended to a temp file
+/// encrypted under a key that only lives as long as this value, so slow or stalled uploads don't
+/// hold what they sent so far in RAM. This only covers receiving: once complete, the whole body is
+/// read back into memory, where validating and encrypting it for storage needs it and its copies.
+pub struct SpooledBody {
+    memory: Vec<u8>,
+    spool: Option<Spool>,
+    len: usize,
+    threshold: usize,
+    dir: PathBuf,
+}
+
+struct Spool {
+    // Closed before `temp` removes the file
+    file: tokio::fs::File,
+    crypto: CryptoService,
+    pending: Vec<u8>,
+    temp: SpillFile,
+}
+
+impl SpooledBody {
+    /// Spool to `dir` past `threshold` bytes (0 keeps the body in memory)
+    pub fn new(threshold: usize, dir: PathBuf) -> Self {
+        Self { memory: Vec::new(), spool: None, len: 0, threshold, dir }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub async fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        self.len += chunk.len();
+        if self.spool.is_none() && (self.threshold == 0 || self.len <= self.threshold) {
+            self.memory.extend_from_slice(chunk);
+            return Ok(());
+        }
+
+        let spool = match &mut self.spool {
+            Some(spool) => spool,
+            None => {
+                let path = self.dir.join(format!("rustgram-spool-{}.bin", uuid::Uuid::new_v4()));
+                let file = tokio::fs::File::create(&path).await.map_err(spool_error)?;
+                let pending = std::mem::take(&mut self.memory);
+                let crypto = CryptoService::new(&CryptoService::generate_key());
+                self.spool.insert(Spool { file, crypto, pending, temp: SpillFile { path, len: 0 } })
+            }
+        };
+        spool.pending.extend_from_slice(chunk);
+        if spool.pending.len() >= SPOOL_RECORD_BYTES {
+            spool.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// The whole body, decrypted from the temp file if it was spooled. Records are read back one
+    /// at a time, so only the body itself is held in memory.
+    pub async fn into_bytes(self) -> Result<Bytes> {
+        let Some(mut spool) = self.spool else {
+            return Ok(self.memory.into());
+        };
+        spool.flush().await?;
+        spool.file.flush().await.map_err(spool_error)?;
+
+        let file = tokio::fs::File::open(&spool.temp.path).await.map_err(spool_error)?;
+        let mut records = BufReader::new(file);
+        let mut body = Vec::with_capacity(self.len);
+        let mut record = Vec::new();
+        let mut remaining = spool.temp.len;
+        while remaining > 0 {
+            let len = records.read_u32().await.map_err(spool_error)? as usize;
+            if len + 4 > remaining {
+                return Err(AppError::InternalError("Truncated upload spool".to_string()));
+            }
+            record.resize(len, 0);
+            records.read_exact(&mut record).await.map_err(spool_error)?;
+            body.extend_from_slice(&spool.crypto.decrypt_data(&record)?);
+            remaining -= len + 4;
+        }
+        Ok(body.into())
+    }
+}
+
+impl Spool {
+    // Each record is a big-endian length followed by the encrypted chunk
+    async fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let record = self.crypto.encrypt_data(&self.pending)?;
+        self.pending.clear();
+        self.file.write_all(&(record.len() as u32).to_be_bytes()).await.map_err(spool_error)?;
+        self.file.write_all(&record).await.map_err(spool_error)?;
+        self.temp.len += record.len() + 4;
+        Ok(())
+    }
+}
+
+fn spool_error(e: std::io::Error) -> AppError {
+    AppError::InternalError(format!("Failed to spool upload to disk: {}", e))
+}
+
+/// Temp file holding a spilled payload, removed when the job is done with it
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove spill file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spills_above_threshold() {
+        let dir = std::env::temp_dir();
+        let small = Payload::new(vec![1; 4], 8, &dir).unwrap();
+        assert!(matches!(small, Payload::Memory(_)));
+
+        let large = Payload::new(vec![2; 16], 8, &dir).unwrap();
+        let Payload::Spilled(file) = &large else {
+            panic!("expected a spilled payload");
+        };
+        let path = file.path.clone();
+        assert_eq!(large.len(), 16);
+        assert_eq!(large.load().await.unwrap().as_ref(), &[2; 16]);
+
+        drop(large);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_spools_body_past_threshold() {
+        let mut body = SpooledBody::new(8, std::env::temp_dir());
+        body.push(b"abcd").await.unwrap();
+        assert!(body.spool.is_none());
+        let chunk = vec![7u8; SPOOL_RECORD_BYTES + 10];
+        body.push(&chunk).await.unwrap();
+        body.push(b"end").await.unwrap();
+
+        let spool = body.spool.as_ref().unwrap();
+        let path = spool.temp.path.clone();
+        assert!(body.memory.is_empty());
+        // Only ciphertext reaches the disk
+        let on_disk = tokio::fs::read(&path).await.unwrap();
+        assert!(!on_disk.windows(64).any(|window| window.iter().all(|b| *b == 7)));
+
+        let mut expected = b"abcd".to_vec();
+        expected.extend_from_slice(&chunk);
+        expected.extend_from_slice(b"end");
+        assert_eq!(body.len(), expected.len());
+        assert_eq!(body.into_bytes().await.unwrap().as_ref(), expected.as_slice());
+        assert!(!path.exists());
+    }
+}
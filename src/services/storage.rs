@@ -0,0 +1,88 @@
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::{
+    error::{AppError, Result},
+    models::Backend,
+    services::{discord::DiscordService, telegram::TelegramService},
+};
+
+/// A service files are stored in, so uploads, downloads and deletions go to wherever a file lives
+#[derive(Clone)]
+pub enum Storage {
+    Telegram(Arc<TelegramService>),
+    Discord(Arc<DiscordService>),
+}
+
+/// One uploaded object: the message carrying it and the ID its data is fetched by
+pub struct StoredObject {
+    pub file_id: String,
+    pub message_id: i64,
+}
+
+impl Storage {
+    /// The storage holding files of `backend`; Telegram files are in `telegram`, normally the
+    /// storage of the file's tenant
+    pub fn for_backend(
+        backend: Backend,
+        telegram: Arc<TelegramService>,
+        discord: Option<&Arc<DiscordService>>,
+    ) -> Result<Self> {
+        match backend {
+            Backend::Telegram => Ok(Storage::Telegram(telegram)),
+            Backend::Discord => discord.cloned().map(Storage::Discord).ok_or_else(|| {
+                AppError::ConfigError("Image is stored on Discord but DISCORD_BOT_TOKEN is not set".to_string())
+            }),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            Storage::Telegram(_) => Backend::Telegram,
+            Storage::Discord(_) => Backend::Discord,
+        }
+    }
+
+    /// Chat new Telegram uploads go to; None for other backends
+    pub fn telegram_chat(&self) -> Option<i64> {
+        match self {
+            Storage::Telegram(telegram) => Some(telegram.chat_id()),
+            Storage::Discord(_) => None,
+        }
+    }
+
+    pub async fn upload(&self, data: Bytes, filename: &str) -> Result<StoredObject> {
+        match self {
+            Storage::Telegram(telegram) => {
+                let message = telegram.upload_file(data, filename).await?;
+                let file_id = message
+                    .document
+                    .map(|doc| doc.file_id)
+                    .ok_or_else(|| AppError::TelegramError("No document in response".to_string()))?;
+                Ok(StoredObject { file_id, message_id: message.message_id })
+            }
+            Storage::Discord(discord) => {
+                let stored = discord.upload_file(data, filename).await?;
+                Ok(StoredObject { file_id: stored.attachment_id, message_id: stored.message_id })
+            }
+        }
+    }
+
+    pub async fn download(&self, file_id: &str, message_id: i64) -> Result<Bytes> {
+        match self {
+            Storage::Telegram(telegram) => telegram.download_file_by_id(file_id).await,
+            Storage::Discord(discord) => discord.download_file(message_id).await,
+        }
+    }
+
+    /// Delete a stored message; `chat_id` is the Telegram chat holding it, the storage's own
+    /// chat when None, and is ignored by other backends
+    pub async fn delete(&self, chat_id: Option<i64>, message_id: i64) -> Result<()> {
+        match self {
+            Storage::Telegram(telegram) => {
+                telegram.delete_message(chat_id.unwrap_or(telegram.chat_id()), message_id).await
+            }
+            Storage::Discord(discord) => discord.delete_message(message_id).await,
+        }
+    }
+}
@@ -1,59 +1,239 @@
-use bytes::Bytes;
-use reqwest::{multipart, Client};
+use bytes::{Bytes, BytesMut};
+use futures::{stream, StreamExt, TryStreamExt};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use reqwest::{header, multipart, Client, RequestBuilder, StatusCode};
 use crate::{
     error::{AppError, Result},
-    models::{TelegramFile, TelegramMessage, TelegramResponse},
+    models::{unix_timestamp, TelegramFile, TelegramMessage, TelegramResponse},
+    services::{chaos::FaultInjector, chat_migrations::ChatMigrations, metrics::Metrics},
 };
 
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+// Telegram keeps a file path valid for at least an hour; reuse it a little less than that
+const FILE_PATH_TTL: Duration = Duration::from_secs(55 * 60);
+
+struct CachedFilePath {
+    path: String,
+    size: u64,
+    fetched_at: Instant,
+}
+
+/// What a side-effect-free edit attempt revealed about a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageProbe {
+    // Exists and was sent by this bot
+    Ours,
+    Missing,
+    // Exists but belongs to someone else, so it is none of our business
+    NotOurs,
+}
+
 pub struct TelegramService {
     client: Client,
     bot_token: String,
     chat_id: i64,
-    log_chat_id: Option<i64>, // New field for logging
+    log_chat_id: Option<i64>,
+    // Bot API server, api.telegram.org unless a local server (or a fake one in tests) is used
+    api_url: String,
     base_url: String,
+    // Files larger than one chunk are fetched as this many concurrent range requests
+    download_chunk_bytes: u64,
+    download_parallelism: usize,
+    // file_id -> path from getFile, shared with the storage services derived from this one
+    file_paths: Arc<Mutex<HashMap<String, CachedFilePath>>>,
+    // Group chats upgraded to supergroups, followed for every chat a request is sent to
+    migrations: Arc<ChatMigrations>,
+    metrics: Arc<Metrics>,
+    // Unix time of the last call Telegram answered successfully, 0 before the first
+    last_success: Arc<AtomicU64>,
+    // CHAOS_MODE: delays and failures injected ahead of every call
+    faults: Option<FaultInjector>,
 }
 
 impl TelegramService {
     pub fn new(bot_token: String, chat_id: i64, log_chat_id: Option<i64>) -> Self {
         Self {
             client: Client::new(),
-            base_url: format!("https://api.telegram.org/bot{}", bot_token),
+            api_url: DEFAULT_API_URL.to_string(),
+            base_url: format!("{}/bot{}", DEFAULT_API_URL, bot_token),
             bot_token,
             chat_id,
-            log_chat_id, // Initialize new field
+            log_chat_id,
+            download_chunk_bytes: 0,
+            download_parallelism: 1,
+            file_paths: Arc::default(),
+            migrations: Arc::default(),
+            metrics: Arc::default(),
+            last_success: Arc::default(),
+            faults: None,
         }
     }
 
+    /// Talk to the Bot API server at `api_url` instead of api.telegram.org
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self.base_url = format!("{}/bot{}", self.api_url, self.bot_token);
+        self
+    }
+
+    /// Record Telegram latency and errors in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Record and follow chat upgrades in `migrations` instead of a private in-memory map
+    pub fn with_chat_migrations(mut self, migrations: Arc<ChatMigrations>) -> Self {
+        self.migrations = migrations;
+        self
+    }
+
+    /// Delay and fail calls at random as configured by CHAOS_MODE
+    pub fn with_fault_injection(mut self, faults: Option<FaultInjector>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Download files over `chunk_bytes` in ranges, up to `parallelism` at a time (1 disables)
+    pub fn with_parallel_downloads(mut self, chunk_bytes: u64, parallelism: usize) -> Self {
+        self.download_chunk_bytes = chunk_bytes;
+        self.download_parallelism = parallelism;
+        self
+    }
+
+    /// A service storing files in another chat, optionally through another bot; logs still go to the log chat
+    pub fn with_storage(&self, bot_token: Option<&str>, chat_id: i64) -> Self {
+        let bot_token = bot_token.unwrap_or(&self.bot_token).to_string();
+        Self {
+            client: self.client.clone(),
+            base_url: format!("{}/bot{}", self.api_url, bot_token),
+            api_url: self.api_url.clone(),
+            bot_token,
+            chat_id,
+            log_chat_id: self.log_chat_id,
+            download_chunk_bytes: self.download_chunk_bytes,
+            download_parallelism: self.download_parallelism,
+            file_paths: self.file_paths.clone(),
+            migrations: self.migrations.clone(),
+            metrics: self.metrics.clone(),
+            last_success: self.last_success.clone(),
+            faults: self.faults,
+        }
+    }
+
+    /// The chat files are uploaded to, following any upgrade to a supergroup
+    pub fn chat_id(&self) -> i64 {
+        self.migrations.resolve(self.chat_id)
+    }
+
+    /// When Telegram last answered a call successfully, through this service or one derived from it
+    pub fn last_success(&self) -> Option<u64> {
+        match self.last_success.load(Ordering::Relaxed) {
+            0 => None,
+            at => Some(at),
+        }
+    }
+
+    // Time a call and count its failure by class
+    async fn observed<T>(&self, method: &'static str, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let started = Instant::now();
+        let result = match self.inject_fault(method).await {
+            Ok(()) => call.await,
+            Err(e) => Err(e),
+        };
+        self.metrics
+            .record_telegram_call(method, started.elapsed(), result.as_ref().err().map(error_class));
+        result
+    }
+
+    async fn inject_fault(&self, method: &str) -> Result<()> {
+        match &self.faults {
+            Some(faults) => faults.telegram_call(method).await,
+            None => Ok(()),
+        }
+    }
+
+    fn file_url(&self, file_path: &str) -> String {
+        format!("{}/file/bot{}/{}", self.api_url, self.bot_token, file_path)
+    }
+
+    fn record_success(&self) {
+        self.last_success.store(unix_timestamp(), Ordering::Relaxed);
+    }
+
+    /// Send a request addressed to `chat_id`, returning the status and body. When Telegram reports
+    /// the group was upgraded to a supergroup, the move is recorded and the request retried there.
+    async fn send_to_chat(
+        &self,
+        chat_id: i64,
+        build: impl Fn(i64) -> Result<RequestBuilder>,
+    ) -> Result<(StatusCode, Bytes)> {
+        let chat_id = self.migrations.resolve(chat_id);
+        let response = build(chat_id)?.send().await?;
+        let (status, body) = (response.status(), response.bytes().await?);
+        if status != StatusCode::BAD_REQUEST {
+            return Ok((status, body));
+        }
+
+        let migrated_to = serde_json::from_slice::<TelegramResponse<serde_json::Value>>(&body)
+            .ok()
+            .and_then(|r| r.parameters)
+            .and_then(|p| p.migrate_to_chat_id);
+        let Some(migrated_to) = migrated_to else {
+            return Ok((status, body));
+        };
+
+        tracing::warn!(
+            "Chat {} was upgraded to supergroup {}; update the configured chat ID",
+            chat_id,
+            migrated_to
+        );
+        self.migrations.record(chat_id, migrated_to)?;
+        let response = build(migrated_to)?.send().await?;
+        Ok((response.status(), response.bytes().await?))
+    }
+
     /// Upload file to Telegram and return file info
-    pub async fn upload_file(&self, data: &[u8], filename: &str) -> Result<TelegramMessage> {
-        let form = multipart::Form::new()
-            .text("chat_id", self.chat_id.to_string())
-            .part(
-                "document",
-                multipart::Part::bytes(data.to_vec())
-                    .file_name(filename.to_string())
-                    .mime_str("application/octet-stream")
-                    .map_err(|e| AppError::InternalError(e.to_string()))?,
-            );
+    pub async fn upload_file(&self, data: Bytes, filename: &str) -> Result<TelegramMessage> {
+        self.observed("send_document", self.send_document(data, filename)).await
+    }
 
+    async fn send_document(&self, data: Bytes, filename: &str) -> Result<TelegramMessage> {
+        let length = data.len() as u64;
         let url = format!("{}/sendDocument", self.base_url);
-        
-        let response = self
-            .client
-            .post(&url)
-            .multipart(form)
-            .send()
+
+        let (status, body) = self
+            .send_to_chat(self.chat_id, |chat_id| {
+                let form = multipart::Form::new()
+                    .text("chat_id", chat_id.to_string())
+                    .part(
+                        "document",
+                        multipart::Part::stream_with_length(data.clone(), length)
+                            .file_name(filename.to_string())
+                            .mime_str("application/octet-stream")
+                            .map_err(|e| AppError::InternalError(e.to_string()))?,
+                    );
+                Ok(self.client.post(&url).multipart(form))
+            })
             .await?;
 
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
             return Err(AppError::TelegramError(format!(
                 "Upload failed: {}",
-                error_text
+                String::from_utf8_lossy(&body)
             )));
         }
 
-        let telegram_response: TelegramResponse<TelegramMessage> = response.json().await?;
+        let telegram_response: TelegramResponse<TelegramMessage> = serde_json::from_slice(&body)?;
 
         if !telegram_response.ok {
             return Err(AppError::TelegramError(
@@ -61,6 +241,7 @@ impl TelegramService {
             ));
         }
 
+        self.record_success();
         telegram_response
             .result
             .ok_or_else(|| AppError::TelegramError("No result in response".to_string()))
@@ -68,6 +249,10 @@ impl TelegramService {
 
     /// Get file info from Telegram
     pub async fn get_file_info(&self, file_id: &str) -> Result<TelegramFile> {
+        self.observed("get_file", self.request_file_info(file_id)).await
+    }
+
+    async fn request_file_info(&self, file_id: &str) -> Result<TelegramFile> {
         let url = format!("{}/getFile", self.base_url);
         
         let response = self
@@ -77,6 +262,23 @@ impl TelegramService {
             .send()
             .await?;
 
+        if response.status() == StatusCode::BAD_REQUEST {
+            let description = response
+                .json::<TelegramResponse<TelegramFile>>()
+                .await
+                .ok()
+                .and_then(|r| r.description)
+                .unwrap_or_default();
+            // A malformed file_id will never resolve; "wrong file_id" is also sent while a file is
+            // temporarily unreachable, so callers decide over time whether it is gone
+            if is_invalid_file_id(&description) {
+                return Err(AppError::Gone);
+            }
+            if is_unavailable_file_id(&description) {
+                return Err(AppError::FileUnavailable);
+            }
+            return Err(AppError::TelegramError(format!("Failed to get file info: {}", description)));
+        }
         if !response.status().is_success() {
             return Err(AppError::TelegramError("Failed to get file info".to_string()));
         }
@@ -89,6 +291,7 @@ impl TelegramService {
             ));
         }
 
+        self.record_success();
         telegram_response
             .result
             .ok_or_else(|| AppError::TelegramError("No file info in response".to_string()))
@@ -96,57 +299,178 @@ impl TelegramService {
 
     /// Download file from Telegram
     pub async fn download_file(&self, file_path: &str) -> Result<Bytes> {
-        let download_url = format!("https://api.telegram.org/file/bot{}/{}", 
-                                 self.bot_token, file_path);
-        
+        self.observed("download", self.try_download_file(file_path))
+            .await?
+            .ok_or_else(|| AppError::TelegramError("Failed to download file".to_string()))
+    }
+
+    // None when Telegram no longer knows the path, which happens once it expires
+    async fn try_download_file(&self, file_path: &str) -> Result<Option<Bytes>> {
         let response = self
             .client
-            .get(&download_url)
+            .get(self.file_url(file_path))
             .send()
             .await?;
 
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
         if !response.status().is_success() {
             return Err(AppError::TelegramError("Failed to download file".to_string()));
         }
 
         let bytes = response.bytes().await?;
-        Ok(bytes)
+        self.record_success();
+        Ok(Some(bytes))
     }
 
-    /// Download file by file_id (combines get_file_info and download_file)
+    /// Download file by file_id, reusing its file path until Telegram expires it
     pub async fn download_file_by_id(&self, file_id: &str) -> Result<Bytes> {
+        let (file_path, size) = self.resolve_file_path(file_id, true).await?;
+        if let Some(data) = self.observed("download", self.fetch_file(file_id, &file_path, size)).await? {
+            return Ok(data);
+        }
+
+        // The path expired: ask for a fresh one and retry once
+        tracing::info!("File path of {} expired, refreshing", file_id);
+        let (file_path, size) = self.resolve_file_path(file_id, false).await?;
+        self.observed("download", self.fetch_file(file_id, &file_path, size))
+            .await?
+            .ok_or_else(|| AppError::TelegramError("Failed to download file".to_string()))
+    }
+
+    // File path and size of `file_id`, from the cache while it is fresh unless `cached` is false
+    async fn resolve_file_path(&self, file_id: &str, cached: bool) -> Result<(String, u64)> {
+        if cached
+            && let Some(entry) = self.file_paths.lock().unwrap().get(file_id)
+            && entry.fetched_at.elapsed() < FILE_PATH_TTL
+        {
+            return Ok((entry.path.clone(), entry.size));
+        }
+
         let file_info = self.get_file_info(file_id).await?;
-        
         let file_path = file_info
             .file_path
             .ok_or_else(|| AppError::TelegramError("No file path in response".to_string()))?;
+        let size = file_info.file_size.unwrap_or(0).max(0) as u64;
 
-        self.download_file(&file_path).await
+        let mut file_paths = self.file_paths.lock().unwrap();
+        file_paths.retain(|_, entry| entry.fetched_at.elapsed() < FILE_PATH_TTL);
+        file_paths.insert(
+            file_id.to_string(),
+            CachedFilePath { path: file_path.clone(), size, fetched_at: Instant::now() },
+        );
+        Ok((file_path, size))
     }
 
-    /// Delete message (to clean up if needed)
-    pub async fn delete_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
-        let url = format!("{}/deleteMessage", self.base_url);
-        
+    async fn fetch_file(&self, file_id: &str, file_path: &str, size: u64) -> Result<Option<Bytes>> {
+        if self.download_parallelism > 1 && self.download_chunk_bytes > 0 && size > self.download_chunk_bytes {
+            match self.download_ranges(file_path, size).await {
+                Ok(data) => return Ok(Some(data)),
+                // Range support isn't guaranteed, so a failed attempt costs one plain download
+                Err(e) => tracing::warn!("Parallel download of {} failed, retrying whole: {}", file_id, e),
+            }
+        }
+
+        self.try_download_file(file_path).await
+    }
+
+    /// Fetch `size` bytes as concurrent range requests, reassembled in order
+    async fn download_ranges(&self, file_path: &str, size: u64) -> Result<Bytes> {
+        let download_url = self.file_url(file_path);
+
+        let chunks: Vec<Bytes> = stream::iter(chunk_ranges(size, self.download_chunk_bytes))
+            .map(|(start, end)| self.download_range(&download_url, start, end))
+            .buffered(self.download_parallelism)
+            .try_collect()
+            .await?;
+
+        let mut data = BytesMut::with_capacity(size as usize);
+        for chunk in chunks {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+
+    async fn download_range(&self, url: &str, start: u64, end: u64) -> Result<Bytes> {
         let response = self
             .client
-            .post(&url)
-            .form(&[
-                ("chat_id", chat_id.to_string()),
-                ("message_id", message_id.to_string()),
-            ])
+            .get(url)
+            .header(header::RANGE, format!("bytes={}-{}", start, end))
             .send()
             .await?;
 
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(AppError::TelegramError(format!(
+                "Range request answered with {}",
+                response.status()
+            )));
+        }
+
+        let chunk = response.bytes().await?;
+        if chunk.len() as u64 != end - start + 1 {
+            return Err(AppError::TelegramError("Short range response".to_string()));
+        }
+        Ok(chunk)
+    }
+
+    /// Forward a message into the storage chat so its media can be accessed by the bot
+    pub async fn forward_message(&self, from_chat_id: &str, message_id: i64) -> Result<TelegramMessage> {
+        self.inject_fault("forward_message").await?;
+        let url = format!("{}/forwardMessage", self.base_url);
+
+        let (status, body) = self
+            .send_to_chat(self.chat_id, |chat_id| {
+                Ok(self.client.post(&url).form(&[
+                    ("chat_id", chat_id.to_string()),
+                    ("from_chat_id", from_chat_id.to_string()),
+                    ("message_id", message_id.to_string()),
+                ]))
+            })
+            .await?;
+
+        if !status.is_success() {
+            return Err(AppError::TelegramError(format!(
+                "Failed to forward message: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        let telegram_response: TelegramResponse<TelegramMessage> = serde_json::from_slice(&body)?;
+
+        if !telegram_response.ok {
+            return Err(AppError::TelegramError(
+                telegram_response.description.unwrap_or_default(),
+            ));
+        }
+
+        telegram_response
+            .result
+            .ok_or_else(|| AppError::TelegramError("No result in response".to_string()))
+    }
+
+    /// Delete message (to clean up if needed)
+    pub async fn delete_message(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        self.inject_fault("delete_message").await?;
+        let url = format!("{}/deleteMessage", self.base_url);
+
+        let (status, body) = self
+            .send_to_chat(chat_id, |chat_id| {
+                Ok(self.client.post(&url).form(&[
+                    ("chat_id", chat_id.to_string()),
+                    ("message_id", message_id.to_string()),
+                ]))
+            })
+            .await?;
+
+        if !status.is_success() {
             return Err(AppError::TelegramError(format!(
                 "Failed to delete message: {}",
-                error_text
+                String::from_utf8_lossy(&body)
             )));
         }
 
-        let telegram_response: TelegramResponse<bool> = response.json().await?;
+        let telegram_response: TelegramResponse<bool> = serde_json::from_slice(&body)?;
 
         if !telegram_response.ok {
             return Err(AppError::TelegramError(
@@ -157,45 +481,84 @@ impl TelegramService {
         Ok(())
     }
 
+    /// Find out whether a message exists and is ours, since bots can't read chat history. Clearing
+    /// the reply markup changes nothing on our messages, which never carry any, and Telegram says
+    /// which case applies when it refuses.
+    pub async fn probe_message(&self, chat_id: i64, message_id: i64) -> Result<MessageProbe> {
+        self.inject_fault("probe_message").await?;
+        let url = format!("{}/editMessageReplyMarkup", self.base_url);
+
+        let (_, body) = self
+            .send_to_chat(chat_id, |chat_id| {
+                Ok(self.client.post(&url).form(&[
+                    ("chat_id", chat_id.to_string()),
+                    ("message_id", message_id.to_string()),
+                ]))
+            })
+            .await?;
+
+        let telegram_response: TelegramResponse<serde_json::Value> = serde_json::from_slice(&body)?;
+        if telegram_response.ok {
+            return Ok(MessageProbe::Ours);
+        }
+        let description = telegram_response.description.unwrap_or_default();
+        if description.contains("message is not modified") {
+            Ok(MessageProbe::Ours)
+        } else if description.contains("message to edit not found") {
+            Ok(MessageProbe::Missing)
+        } else if description.contains("message can't be edited") {
+            Ok(MessageProbe::NotOurs)
+        } else {
+            Err(AppError::TelegramError(format!("Failed to probe message: {}", description)))
+        }
+    }
+
     /// Send a log message to the configured log chat ID
     pub async fn send_log_message(&self, message: &str) -> Result<()> {
-        if let Some(log_chat_id) = self.log_chat_id {
-            let url = format!("{}/sendMessage", self.base_url);
-            let response = self
-                .client
-                .post(&url)
-                .form(&[
-                    ("chat_id", log_chat_id.to_string()),
+        match self.log_chat_id {
+            Some(log_chat_id) => self.send_message(log_chat_id, message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send a text message to `chat_id`
+    pub async fn send_message(&self, chat_id: i64, message: &str) -> Result<()> {
+        self.inject_fault("send_message").await?;
+        let url = format!("{}/sendMessage", self.base_url);
+        let (status, body) = self
+            .send_to_chat(chat_id, |chat_id| {
+                Ok(self.client.post(&url).form(&[
+                    ("chat_id", chat_id.to_string()),
                     ("text", message.to_string()),
-                ])
-                .send()
-                .await?;
-
-            if !response.status().is_success() {
-                let error_text = response.text().await.unwrap_or_default();
-                return Err(AppError::TelegramError(format!(
-                    "Failed to send log message: {}",
-                    error_text
-                )));
-            }
+                ]))
+            })
+            .await?;
 
-            let telegram_response: TelegramResponse<TelegramMessage> = response.json().await?;
-            if !telegram_response.ok {
-                return Err(AppError::TelegramError(
-                    telegram_response.description.unwrap_or_default(),
-                ));
-            }
+        if !status.is_success() {
+            return Err(AppError::TelegramError(format!(
+                "Failed to send message: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        let telegram_response: TelegramResponse<TelegramMessage> = serde_json::from_slice(&body)?;
+        if !telegram_response.ok {
+            return Err(AppError::TelegramError(
+                telegram_response.description.unwrap_or_default(),
+            ));
         }
         Ok(())
     }
 
     /// Test bot connection
     pub async fn test_connection(&self) -> Result<()> {
+        self.inject_fault("get_me").await?;
         let url = format!("{}/getMe", self.base_url);
         
         let response = self.client.get(&url).send().await?;
 
         if response.status().is_success() {
+            self.record_success();
             Ok(())
         } else {
             Err(AppError::TelegramError("Bot connection test failed".to_string()))
@@ -203,14 +566,77 @@ impl TelegramService {
     }
 }
 
+/// Coarse cause of a failed call, separating Telegram being slow or unreachable from it refusing the request
+fn error_class(error: &AppError) -> &'static str {
+    let message = match error {
+        AppError::Gone => return "file_gone",
+        AppError::FileUnavailable => return "file_unavailable",
+        AppError::TelegramError(message) => message.to_ascii_lowercase(),
+        _ => return "other",
+    };
+    if message.contains("too many requests") || message.contains("retry after") {
+        "rate_limited"
+    } else if message.contains("timed out") || message.contains("timeout") {
+        "timeout"
+    } else if message.contains("error sending request") || message.contains("connect") {
+        "network"
+    } else {
+        "api"
+    }
+}
+
+fn is_invalid_file_id(description: &str) -> bool {
+    description.to_ascii_lowercase().contains("invalid file_id")
+}
+
+fn is_unavailable_file_id(description: &str) -> bool {
+    let description = description.to_ascii_lowercase();
+    description.contains("wrong file_id") || description.contains("temporarily unavailable")
+}
+
+// Inclusive byte ranges of `chunk` bytes covering `size` bytes
+fn chunk_ranges(size: u64, chunk: u64) -> Vec<(u64, u64)> {
+    (0..size)
+        .step_by(chunk as usize)
+        .map(|start| (start, (start + chunk).min(size) - 1))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_error_class() {
+        assert_eq!(error_class(&AppError::Gone), "file_gone");
+        assert_eq!(error_class(&AppError::TelegramError("Upload failed: Too Many Requests: retry after 5".into())), "rate_limited");
+        assert_eq!(error_class(&AppError::TelegramError("operation timed out".into())), "timeout");
+        assert_eq!(error_class(&AppError::TelegramError("Failed to download file".into())), "api");
+    }
+
+    #[test]
+    fn test_file_id_errors() {
+        let unavailable = "Bad Request: wrong file_id or the file is temporarily unavailable";
+        assert!(is_unavailable_file_id(unavailable) && !is_invalid_file_id(unavailable));
+        assert!(is_invalid_file_id("Bad Request: invalid file_id"));
+        assert!(!is_unavailable_file_id("Bad Request: file is too big"));
+    }
+
+    #[test]
+    fn test_chunk_ranges() {
+        assert_eq!(chunk_ranges(10, 4), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(chunk_ranges(8, 4), vec![(0, 3), (4, 7)]);
+    }
+
     #[tokio::test]
     async fn test_telegram_service_creation() {
-        let service = TelegramService::new("test_token".to_string(), 12345);
+        let service = TelegramService::new("test_token".to_string(), 12345, None);
         assert_eq!(service.chat_id, 12345);
         assert!(service.base_url.contains("test_token"));
+
+        let routed = service.with_storage(Some("other_token"), 67890);
+        assert_eq!(routed.chat_id(), 67890);
+        assert!(routed.base_url.contains("other_token"));
+        assert!(service.with_storage(None, 1).base_url.contains("test_token"));
     }
 }
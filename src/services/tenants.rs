@@ -0,0 +1,393 @@
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::unix_timestamp,
+    services::{
+        index::{ImageIndex, QuotaReservation},
+        telegram::TelegramService,
+    },
+};
+
+// An independent application sharing this deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    // SHA-256 hashes of the tenant's API keys; raw keys are only shown once at creation
+    #[serde(default)]
+    pub key_hashes: Vec<String>,
+    pub quota_bytes: Option<u64>,
+    // Overrides the global ALLOWED image types when set
+    pub allowed_types: Option<Vec<String>>,
+    // The tenant's content key, encrypted with the master key; tenants without one use the master key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key: Option<String>,
+    // Storage chat (and optionally bot) for this tenant's uploads instead of the defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    // Decrypted when the store is opened; on disk it is only kept as `wrapped_bot_token`, encrypted with
+    // the master key. Files written before that carry it here in plaintext and are rewritten on open.
+    #[serde(default, skip_serializing)]
+    pub bot_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_bot_token: Option<String>,
+    // Serve every image of this tenant with the configured watermark
+    #[serde(default)]
+    pub watermark: bool,
+    pub created_at: u64,
+}
+
+// Settings chosen when a tenant is created
+#[derive(Debug, Default, Deserialize)]
+pub struct TenantSettings {
+    pub name: Option<String>,
+    pub quota_bytes: Option<u64>,
+    pub allowed_types: Option<Vec<String>>,
+    pub chat_id: Option<i64>,
+    pub bot_token: Option<String>,
+    #[serde(default)]
+    pub watermark: bool,
+}
+
+impl Tenant {
+    /// Unwrap the key this tenant's image data is encrypted with
+    pub fn content_key(&self, master_key: &[u8; 32]) -> Result<[u8; 32]> {
+        let Some(wrapped_key) = &self.wrapped_key else {
+            return Ok(*master_key);
+        };
+
+        let wrapped = general_purpose::STANDARD
+            .decode(wrapped_key)
+            .map_err(|e| AppError::EncryptionError(format!("Invalid wrapped key: {}", e)))?;
+        CryptoService::new(master_key)
+            .decrypt_data(&wrapped)?
+            .try_into()
+            .map_err(|_| AppError::EncryptionError("Tenant key must be 32 bytes".to_string()))
+    }
+
+    /// Telegram service for this tenant's storage chat and bot
+    pub fn storage(&self, telegram: &Arc<TelegramService>) -> Arc<TelegramService> {
+        match (self.chat_id, &self.bot_token) {
+            (None, None) => telegram.clone(),
+            (chat_id, bot_token) => Arc::new(
+                telegram.with_storage(bot_token.as_deref(), chat_id.unwrap_or(telegram.chat_id())),
+            ),
+        }
+    }
+
+    /// Check an upload of `size` bytes against the tenant's type list and storage quota, and hold
+    /// its bytes against the quota until the returned reservation is dropped
+    pub fn reserve_upload(&self, index: &ImageIndex, size: usize, mime_type: &str) -> Result<QuotaReservation> {
+        if let Some(allowed) = &self.allowed_types
+            && !allowed.iter().any(|t| t == mime_type)
+        {
+            return Err(AppError::InvalidFileFormat(format!(
+                "Unsupported type: {}. Allowed: {:?}",
+                mime_type, allowed
+            )));
+        }
+
+        index.reserve(&self.id, size, self.quota_bytes)
+    }
+}
+
+// Public view of a tenant, without key material
+#[derive(Debug, Serialize)]
+pub struct TenantSummary {
+    pub id: String,
+    pub name: String,
+    pub key_count: usize,
+    pub quota_bytes: Option<u64>,
+    pub allowed_types: Option<Vec<String>>,
+    pub chat_id: Option<i64>,
+    // The bot token itself is never returned
+    pub custom_bot: bool,
+    pub watermark: bool,
+    pub created_at: u64,
+}
+
+impl From<&Tenant> for TenantSummary {
+    fn from(tenant: &Tenant) -> Self {
+        Self {
+            id: tenant.id.clone(),
+            name: tenant.name.clone(),
+            key_count: tenant.key_hashes.len(),
+            quota_bytes: tenant.quota_bytes,
+            allowed_types: tenant.allowed_types.clone(),
+            chat_id: tenant.chat_id,
+            custom_bot: tenant.bot_token.is_some(),
+            watermark: tenant.watermark,
+            created_at: tenant.created_at,
+        }
+    }
+}
+
+/// Tenants and their API keys, optionally persisted as JSON
+pub struct TenantStore {
+    tenants: Mutex<HashMap<String, Tenant>>,
+    path: Option<PathBuf>,
+}
+
+impl TenantStore {
+    /// Load the tenants, unwrapping their bot tokens with `master_key`
+    pub fn open(path: Option<PathBuf>, master_key: &[u8; 32]) -> anyhow::Result<Self> {
+        let mut tenants: HashMap<String, Tenant> = match &path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read(path)?;
+                let list: Vec<Tenant> = serde_json::from_slice(&data)?;
+                list.into_iter().map(|tenant| (tenant.id.clone(), tenant)).collect()
+            }
+            _ => HashMap::new(),
+        };
+
+        let mut plaintext_tokens = false;
+        for tenant in tenants.values_mut() {
+            match (&tenant.wrapped_bot_token, &tenant.bot_token) {
+                (Some(wrapped), _) => tenant.bot_token = Some(unwrap_bot_token(master_key, wrapped)?),
+                (None, Some(token)) => {
+                    tenant.wrapped_bot_token = Some(wrap_bot_token(master_key, token)?);
+                    plaintext_tokens = true;
+                }
+                (None, None) => {}
+            }
+        }
+
+        let store = Self {
+            tenants: Mutex::new(tenants),
+            path,
+        };
+        if plaintext_tokens {
+            let tenants = store.lock()?;
+            store.persist(&tenants)?;
+            tracing::info!("Encrypted tenant bot tokens stored in plaintext");
+        }
+        Ok(store)
+    }
+
+    /// Resolve the tenant owning an API key. Without tenants configured, keys are only usage labels;
+    /// once tenants exist, an unknown key is rejected rather than treated as anonymous.
+    pub fn resolve_key(&self, api_key: Option<&str>) -> Result<Option<Tenant>> {
+        let Some(api_key) = api_key else {
+            return Ok(None);
+        };
+
+        let tenants = self.lock()?;
+        if tenants.is_empty() {
+            return Ok(None);
+        }
+
+        let key_hash = hash_key(api_key);
+        tenants
+            .values()
+            .find(|tenant| tenant.key_hashes.contains(&key_hash))
+            .cloned()
+            .map(Some)
+            .ok_or(AppError::Unauthorized)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Tenant>> {
+        Ok(self.lock()?.get(id).cloned())
+    }
+
+    pub fn list(&self) -> Result<Vec<Tenant>> {
+        let mut list: Vec<Tenant> = self.lock()?.values().cloned().collect();
+        list.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(list)
+    }
+
+    /// The tenant owning a stored image; images of a deleted tenant are treated as gone
+    pub fn owner(&self, tenant_id: Option<&str>) -> Result<Option<Tenant>> {
+        match tenant_id {
+            Some(id) => self.get(id)?.ok_or(AppError::NotFound).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Create a tenant and return it with its first API key. The tenant's content key is
+    /// `content_key` when configured, otherwise a random one; it is stored wrapped by `master_key`.
+    pub fn create(
+        &self,
+        id: &str,
+        settings: TenantSettings,
+        master_key: &[u8; 32],
+        content_key: Option<[u8; 32]>,
+    ) -> Result<(Tenant, String)> {
+        if id.is_empty()
+            || id.len() > 32
+            || !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        {
+            return Err(AppError::ValidationError(
+                "Tenant ID must be 1-32 characters of a-z, 0-9, '-' or '_'".to_string(),
+            ));
+        }
+
+        let mut tenants = self.lock()?;
+        if tenants.contains_key(id) {
+            return Err(AppError::ValidationError(format!("Tenant {} already exists", id)));
+        }
+
+        let content_key = content_key.unwrap_or_else(CryptoService::generate_key);
+        let wrapped = CryptoService::new(master_key).encrypt_data(&content_key)?;
+
+        let api_key = generate_api_key();
+        let tenant = Tenant {
+            id: id.to_string(),
+            name: settings.name.unwrap_or_else(|| id.to_string()),
+            key_hashes: vec![hash_key(&api_key)],
+            quota_bytes: settings.quota_bytes,
+            allowed_types: settings.allowed_types,
+            wrapped_key: Some(general_purpose::STANDARD.encode(wrapped)),
+            chat_id: settings.chat_id,
+            wrapped_bot_token: settings.bot_token.as_deref().map(|token| wrap_bot_token(master_key, token)).transpose()?,
+            bot_token: settings.bot_token,
+            watermark: settings.watermark,
+            created_at: unix_timestamp(),
+        };
+
+        tenants.insert(tenant.id.clone(), tenant.clone());
+        self.persist(&tenants)?;
+        Ok((tenant, api_key))
+    }
+
+    /// Issue an additional API key for a tenant
+    pub fn add_key(&self, id: &str) -> Result<String> {
+        let mut tenants = self.lock()?;
+        let tenant = tenants.get_mut(id).ok_or(AppError::NotFound)?;
+
+        let api_key = generate_api_key();
+        tenant.key_hashes.push(hash_key(&api_key));
+
+        self.persist(&tenants)?;
+        Ok(api_key)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<Tenant>> {
+        let mut tenants = self.lock()?;
+        let removed = tenants.remove(id);
+        if removed.is_some() {
+            self.persist(&tenants)?;
+        }
+        Ok(removed)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Tenant>>> {
+        self.tenants
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire tenant lock".to_string()))
+    }
+
+    fn persist(&self, tenants: &HashMap<String, Tenant>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let list: Vec<&Tenant> = tenants.values().collect();
+        let data = serde_json::to_vec(&list)?;
+        let tmp_path = path.with_extension("tmp");
+
+        std::fs::write(&tmp_path, data)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| AppError::InternalError(format!("Failed to persist tenants: {}", e)))
+    }
+}
+
+fn wrap_bot_token(master_key: &[u8; 32], token: &str) -> Result<String> {
+    Ok(general_purpose::STANDARD.encode(CryptoService::new(master_key).encrypt_data(token.as_bytes())?))
+}
+
+fn unwrap_bot_token(master_key: &[u8; 32], wrapped: &str) -> Result<String> {
+    let wrapped = general_purpose::STANDARD
+        .decode(wrapped)
+        .map_err(|e| AppError::EncryptionError(format!("Invalid wrapped bot token: {}", e)))?;
+    String::from_utf8(CryptoService::new(master_key).decrypt_data(&wrapped)?)
+        .map_err(|_| AppError::EncryptionError("Bot token is not UTF-8".to_string()))
+}
+
+fn generate_api_key() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("rg_{}", hex::encode(bytes))
+}
+
+fn hash_key(api_key: &str) -> String {
+    hex::encode(CryptoService::hash_data(api_key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY: [u8; 32] = [7u8; 32];
+
+    fn create(store: &TenantStore, id: &str) -> Result<(Tenant, String)> {
+        store.create(id, TenantSettings::default(), &MASTER_KEY, None)
+    }
+
+    #[test]
+    fn test_resolve_key() {
+        let store = TenantStore::open(None, &MASTER_KEY).unwrap();
+
+        // Without tenants, any key is accepted as anonymous
+        assert!(store.resolve_key(Some("anything")).unwrap().is_none());
+
+        let (tenant, api_key) = create(&store, "acme").unwrap();
+        assert_eq!(store.resolve_key(Some(&api_key)).unwrap().unwrap().id, tenant.id);
+        assert!(matches!(store.resolve_key(Some("wrong")), Err(AppError::Unauthorized)));
+        assert!(store.resolve_key(None).unwrap().is_none());
+
+        let second_key = store.add_key("acme").unwrap();
+        assert_eq!(store.resolve_key(Some(&second_key)).unwrap().unwrap().id, "acme");
+
+        assert!(create(&store, "Bad ID").is_err());
+        assert!(create(&store, "acme").is_err());
+    }
+
+    #[test]
+    fn test_content_keys_are_per_tenant() {
+        let store = TenantStore::open(None, &MASTER_KEY).unwrap();
+        let (acme, _) = create(&store, "acme").unwrap();
+        let (globex, _) = create(&store, "globex").unwrap();
+
+        let acme_key = acme.content_key(&MASTER_KEY).unwrap();
+        assert_ne!(acme_key, MASTER_KEY);
+        assert_ne!(acme_key, globex.content_key(&MASTER_KEY).unwrap());
+        assert!(store.owner(None).unwrap().is_none());
+
+        // Removing a tenant destroys its key
+        store.remove("acme").unwrap();
+        assert!(store.owner(Some("acme")).is_err());
+        assert!(acme.content_key(&[1u8; 32]).is_err());
+    }
+
+    #[test]
+    fn test_bot_tokens_encrypted_at_rest() {
+        let path = std::env::temp_dir().join(format!("rustgram-tenants-{}.json", uuid::Uuid::new_v4()));
+        let token = "123456:secret-bot-token";
+
+        // A file from before tokens were encrypted is rewritten on open
+        let legacy = serde_json::json!([{ "id": "acme", "name": "Acme", "quota_bytes": null, "allowed_types": null, "bot_token": token, "created_at": 1 }]);
+        std::fs::write(&path, legacy.to_string()).unwrap();
+        let store = TenantStore::open(Some(path.clone()), &MASTER_KEY).unwrap();
+        assert_eq!(store.get("acme").unwrap().unwrap().bot_token.as_deref(), Some(token));
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        let settings = TenantSettings { bot_token: Some("654321:other-secret".to_string()), ..Default::default() };
+        store.create("globex", settings, &MASTER_KEY, None).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+        let reopened = TenantStore::open(Some(path.clone()), &MASTER_KEY).unwrap();
+        assert_eq!(reopened.get("acme").unwrap().unwrap().bot_token.as_deref(), Some(token));
+        assert_eq!(reopened.get("globex").unwrap().unwrap().bot_token.as_deref(), Some("654321:other-secret"));
+        assert!(TenantStore::open(Some(path.clone()), &[1u8; 32]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
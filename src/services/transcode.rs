@@ -0,0 +1,128 @@
+use std::{path::Path, process::Stdio, time::Duration};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    services::{index::IndexEntry, manifest, storage::Storage},
+};
+
+// A GIF that takes longer than this is not worth waiting for; the process is killed
+const TRANSCODE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Video formats animated GIFs can be served as with ?format=
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Mp4,
+    Webm,
+}
+
+impl VideoFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "mp4" => Ok(VideoFormat::Mp4),
+            "webm" => Ok(VideoFormat::Webm),
+            _ => Err(AppError::ValidationError("format must be mp4 or webm".to_string())),
+        }
+    }
+
+    /// File extension, also the key of the transcode in the image's index entry
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Webm => "webm",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "video/mp4",
+            VideoFormat::Webm => "video/webm",
+        }
+    }
+
+    fn codec_args(&self) -> &'static [&'static str] {
+        match self {
+            // H.264 in 4:2:0 needs even dimensions; faststart lets playback begin before the download ends
+            VideoFormat::Mp4 => &[
+                "-c:v", "libx264", "-pix_fmt", "yuv420p", "-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2",
+                "-movflags", "+faststart",
+            ],
+            VideoFormat::Webm => &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "35", "-pix_fmt", "yuv420p"],
+        }
+    }
+}
+
+/// Run `ffmpeg` over a GIF, returning the video. ffmpeg can't write MP4 with its index up front to a
+/// pipe, so input and output go through files in `work_dir`, removed afterwards.
+pub async fn transcode(ffmpeg: &str, work_dir: &Path, gif: &[u8], format: VideoFormat) -> Result<Vec<u8>> {
+    let name = Uuid::new_v4();
+    let input = work_dir.join(format!("rustgram-transcode-{}.gif", name));
+    let output = work_dir.join(format!("rustgram-transcode-{}.{}", name, format.extension()));
+
+    let result = async {
+        tokio::fs::write(&input, gif)
+            .await
+            .map_err(|e| AppError::InternalError(format!("Failed to write GIF for ffmpeg: {}", e)))?;
+        let mut command = Command::new(ffmpeg);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+            .arg(&input)
+            .arg("-an")
+            .args(format.codec_args())
+            .arg(&output)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let finished = tokio::time::timeout(TRANSCODE_TIMEOUT, command.output())
+            .await
+            .map_err(|_| AppError::InternalError("ffmpeg timed out".to_string()))?
+            .map_err(|e| AppError::InternalError(format!("Failed to run ffmpeg: {}", e)))?;
+        if !finished.status.success() {
+            let stderr = String::from_utf8_lossy(&finished.stderr);
+            return Err(AppError::InternalError(format!("ffmpeg failed: {}", stderr.trim())));
+        }
+        tokio::fs::read(&output)
+            .await
+            .map_err(|e| AppError::InternalError(format!("Failed to read ffmpeg output: {}", e)))
+    }
+    .await;
+
+    let _ = tokio::fs::remove_file(&input).await;
+    let _ = tokio::fs::remove_file(&output).await;
+    result
+}
+
+/// Delete the stored transcodes of an image being removed, from `storage` holding the image;
+/// a leftover only takes up space
+pub async fn delete_transcodes(storage: &Storage, master_key: &[u8; 32], default_chat_id: i64, entry: &IndexEntry) {
+    for transcode in entry.transcodes.values() {
+        let chat_id = Some(transcode.chat_id.unwrap_or(default_chat_id));
+        manifest::delete_file_chunks(storage, master_key, chat_id, transcode).await;
+        if let Err(e) = storage.delete(chat_id, transcode.message_id).await {
+            tracing::warn!("Could not delete transcode {} of {}: {}", transcode.file_id, entry.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transcode_runs_ffmpeg() {
+        assert_eq!(VideoFormat::parse("webm").unwrap().mime_type(), "video/webm");
+        assert!(VideoFormat::parse("avi").is_err());
+
+        // A missing binary and a failing one are both errors, leaving nothing behind
+        let work_dir = std::env::temp_dir().join(format!("rustgram-transcode-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&work_dir).unwrap();
+        assert!(transcode("/nonexistent/ffmpeg", &work_dir, b"GIF89a", VideoFormat::Mp4).await.is_err());
+        let error = transcode("false", &work_dir, b"GIF89a", VideoFormat::Mp4).await.unwrap_err();
+        assert!(error.to_string().contains("ffmpeg failed"), "{}", error);
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&work_dir).unwrap();
+    }
+}
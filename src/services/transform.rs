@@ -0,0 +1,210 @@
+use image::{imageops::FilterType, DynamicImage};
+
+use crate::{
+    error::{AppError, Result},
+    services::watermark::Watermark,
+};
+
+// A region of the source image, in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    /// Parse "x,y,w,h"
+    fn parse(value: &str) -> Result<Self> {
+        let parts: Vec<u32> = value
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| AppError::ValidationError("crop must be x,y,w,h in pixels".to_string()))?;
+
+        match parts[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Self { x, y, width, height }),
+            _ => Err(AppError::ValidationError("crop must be x,y,w,h with a non-empty size".to_string())),
+        }
+    }
+
+    fn check_bounds(&self, width: u32, height: u32) -> Result<()> {
+        let fits = self.x.checked_add(self.width).is_some_and(|right| right <= width)
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= height);
+        if !fits {
+            return Err(AppError::ValidationError(format!(
+                "crop {},{},{},{} is outside the {}x{} image",
+                self.x, self.y, self.width, self.height, width, height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Edits applied to an image when it is served, in order: crop, rotate, watermark
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transform {
+    // Serve an animated image as a still of its first frame; decoding only yields that frame,
+    // so this just forces the image through re-encoding
+    pub first_frame: bool,
+    pub crop: Option<Crop>,
+    // Clockwise degrees: 90, 180 or 270
+    pub rotate: Option<u16>,
+    pub watermark: bool,
+}
+
+impl Transform {
+    /// Validate query parameters; the crop is checked against the stored dimensions when known
+    pub fn parse(crop: Option<&str>, rotate: Option<&str>, dimensions: Option<(u32, u32)>) -> Result<Self> {
+        let crop = crop.map(Crop::parse).transpose()?;
+        if let (Some(crop), Some((width, height))) = (crop, dimensions) {
+            crop.check_bounds(width, height)?;
+        }
+
+        let rotate = match rotate {
+            None | Some("0") => None,
+            Some("90") => Some(90),
+            Some("180") => Some(180),
+            Some("270") => Some(270),
+            Some(_) => return Err(AppError::ValidationError("rotate must be 90, 180 or 270".to_string())),
+        };
+
+        Ok(Self { crop, rotate, ..Self::default() })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Canonical description, naming the rendition in the cache
+    pub fn variant_name(&self) -> String {
+        let mut parts = Vec::new();
+        if self.first_frame {
+            parts.push("frame=first".to_string());
+        }
+        if let Some(crop) = self.crop {
+            parts.push(format!("crop={},{},{},{}", crop.x, crop.y, crop.width, crop.height));
+        }
+        if let Some(rotate) = self.rotate {
+            parts.push(format!("rotate={}", rotate));
+        }
+        if self.watermark {
+            parts.push("wm".to_string());
+        }
+        parts.join(";")
+    }
+
+    pub fn apply(&self, mut image: DynamicImage, watermark: Option<&Watermark>) -> Result<DynamicImage> {
+        if let Some(crop) = self.crop {
+            // Images without stored dimensions are only checked here
+            crop.check_bounds(image.width(), image.height())?;
+            image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+        }
+
+        image = match self.rotate {
+            Some(90) => image.rotate90(),
+            Some(180) => image.rotate180(),
+            Some(270) => image.rotate270(),
+            _ => image,
+        };
+
+        if let (true, Some(watermark)) = (self.watermark, watermark) {
+            image = watermark.apply(&image);
+        }
+
+        Ok(image)
+    }
+}
+
+// Detail is scored on a copy whose shorter side is this many pixels
+const SMART_CROP_SAMPLE: u32 = 64;
+
+/// Crop to the square with the most detail, measured as the sum of luminance gradients.
+/// The window slides along the longer axis, so the square always spans the shorter one.
+pub fn smart_square_crop(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let side = width.min(height);
+    if width == height {
+        return image.clone();
+    }
+
+    // Gradient energy per column (landscape) or row (portrait) of a small grayscale copy
+    let scale = SMART_CROP_SAMPLE as f32 / side as f32;
+    let sample_width = ((width as f32 * scale).round() as u32).max(1);
+    let sample_height = ((height as f32 * scale).round() as u32).max(1);
+    let gray = image.resize_exact(sample_width, sample_height, FilterType::Triangle).to_luma8();
+
+    let landscape = width > height;
+    let lines = if landscape { sample_width } else { sample_height };
+    let mut energy = vec![0u64; lines as usize];
+    for y in 0..sample_height {
+        for x in 0..sample_width {
+            let value = gray.get_pixel(x, y)[0] as i32;
+            let right = gray.get_pixel((x + 1).min(sample_width - 1), y)[0] as i32;
+            let below = gray.get_pixel(x, (y + 1).min(sample_height - 1))[0] as i32;
+            let line = if landscape { x } else { y };
+            energy[line as usize] += ((value - right).abs() + (value - below).abs()) as u64;
+        }
+    }
+
+    // Best window of the square's size over the energy profile
+    let window = (SMART_CROP_SAMPLE.min(lines)) as usize;
+    let mut best = (0, 0u64);
+    let mut sum: u64 = energy[..window].iter().sum();
+    for start in 0..=(energy.len() - window) {
+        if start > 0 {
+            sum = sum - energy[start - 1] + energy[start + window - 1];
+        }
+        if sum > best.1 {
+            best = (start, sum);
+        }
+    }
+
+    let offset = ((best.0 as f32 / scale).round() as u32).min(width.max(height) - side);
+    if landscape {
+        image.crop_imm(offset, 0, side, side)
+    } else {
+        image.crop_imm(0, offset, side, side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_parse_and_apply() {
+        let transform = Transform::parse(Some("10,20,30,40"), Some("90"), Some((100, 100))).unwrap();
+        assert_eq!(transform.variant_name(), "crop=10,20,30,40;rotate=90");
+
+        let image = DynamicImage::ImageRgb8(RgbImage::new(100, 100));
+        let edited = transform.apply(image, None).unwrap();
+        assert_eq!((edited.width(), edited.height()), (40, 30));
+
+        assert!(Transform::parse(Some("90,0,20,20"), None, Some((100, 100))).is_err());
+        assert!(Transform::parse(Some("1,2,3"), None, None).is_err());
+        assert!(Transform::parse(None, Some("45"), None).is_err());
+        assert!(Transform::parse(None, None, None).unwrap().is_identity());
+
+        let still = Transform { first_frame: true, ..Transform::default() };
+        assert!(!still.is_identity());
+        assert_eq!(still.variant_name(), "frame=first");
+    }
+
+    #[test]
+    fn test_smart_square_crop_finds_detail() {
+        // Flat image with a checkerboard near the right edge
+        let image = RgbImage::from_fn(300, 100, |x, y| {
+            let value = if x >= 220 && (x / 4 + y / 4) % 2 == 0 { 255 } else { 0 };
+            Rgb([value, value, value])
+        });
+
+        let cropped = smart_square_crop(&DynamicImage::ImageRgb8(image));
+        assert_eq!((cropped.width(), cropped.height()), (100, 100));
+        // The crop covers the checkerboard rather than the flat centre
+        let bright = cropped.to_rgb8().pixels().filter(|pixel| pixel.0[0] == 255).count();
+        assert!(bright > 1000);
+    }
+}
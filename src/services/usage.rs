@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    crypto::CryptoService,
+    error::{AppError, Result},
+    models::unix_timestamp,
+};
+
+// Counters for one subject on one day; bytes_stored is the net change for that day
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageCounters {
+    pub uploads: u64,
+    pub deletes: u64,
+    pub downloads: u64,
+    pub bytes_stored: i64,
+    pub bytes_served: u64,
+}
+
+impl UsageCounters {
+    fn add(&mut self, other: &UsageCounters) {
+        self.uploads += other.uploads;
+        self.deletes += other.deletes;
+        self.downloads += other.downloads;
+        self.bytes_stored += other.bytes_stored;
+        self.bytes_served += other.bytes_served;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub subject: String,
+    // Days since the Unix epoch (UTC)
+    pub day: u64,
+    pub counters: UsageCounters,
+}
+
+// Totals for one subject plus its daily breakdown
+#[derive(Debug, Serialize)]
+pub struct UsageSummary {
+    pub subject: String,
+    pub totals: UsageCounters,
+    pub daily: Vec<UsageRecord>,
+}
+
+/// Usage subjects for a request: the client IP, plus the API key when one was presented
+pub fn usage_subjects(ip: IpAddr, api_key: Option<&str>) -> Vec<String> {
+    let mut subjects = vec![format!("ip:{}", ip)];
+    subjects.extend(api_key.map(key_subject));
+    subjects
+}
+
+/// Usage subject of an API key; never keep raw keys in the usage file
+pub fn key_subject(api_key: &str) -> String {
+    format!("key:{}", hex::encode(&CryptoService::hash_data(api_key.as_bytes())[..8]))
+}
+
+/// Usage subject of an anonymous owner token, hashed like API keys
+pub fn owner_subject(owner_token: &str) -> String {
+    format!("owner:{}", hex::encode(&CryptoService::hash_data(owner_token.as_bytes())[..8]))
+}
+
+/// Per-subject daily usage rollups, flushed to disk by a scheduled task
+pub struct UsageStore {
+    records: Mutex<HashMap<(String, u64), UsageCounters>>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl UsageStore {
+    pub fn open(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let records = match &path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read(path)?;
+                let list: Vec<UsageRecord> = serde_json::from_slice(&data)?;
+                list.into_iter()
+                    .map(|record| ((record.subject, record.day), record.counters))
+                    .collect()
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            records: Mutex::new(records),
+            path,
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn record_upload(&self, subjects: &[String], bytes: usize) {
+        self.update(subjects, |c| {
+            c.uploads += 1;
+            c.bytes_stored += bytes as i64;
+        });
+    }
+
+    /// Count the delete for whoever requested it and release the bytes from the original uploader
+    pub fn record_delete(&self, deleter: &[String], owner: &[String], bytes: usize) {
+        self.update(deleter, |c| c.deletes += 1);
+        self.update(owner, |c| c.bytes_stored -= bytes as i64);
+    }
+
+    pub fn record_download(&self, subjects: &[String], bytes: usize) {
+        self.update(subjects, |c| {
+            c.downloads += 1;
+            c.bytes_served += bytes as u64;
+        });
+    }
+
+    /// Usage summaries for every subject (or just `subject`), limited to the last `days` days
+    pub fn summaries(&self, subject: Option<&str>, days: u64) -> Result<Vec<UsageSummary>> {
+        let since = (unix_timestamp() / 86_400).saturating_sub(days.saturating_sub(1));
+        let records = self.lock()?;
+
+        let mut grouped: BTreeMap<&str, Vec<UsageRecord>> = BTreeMap::new();
+        for ((record_subject, day), counters) in records.iter() {
+            if *day < since || subject.is_some_and(|s| s != record_subject) {
+                continue;
+            }
+            grouped.entry(record_subject).or_default().push(UsageRecord {
+                subject: record_subject.clone(),
+                day: *day,
+                counters: counters.clone(),
+            });
+        }
+
+        Ok(grouped
+            .into_iter()
+            .map(|(subject, mut daily)| {
+                daily.sort_by_key(|record| record.day);
+                let mut totals = UsageCounters::default();
+                daily.iter().for_each(|record| totals.add(&record.counters));
+                UsageSummary { subject: subject.to_string(), totals, daily }
+            })
+            .collect())
+    }
+
+    /// Forget every rollup of `subjects`; written out by the next flush
+    pub fn purge_subjects(&self, subjects: &[String]) -> Result<usize> {
+        let mut records = self.lock()?;
+        let before = records.len();
+        records.retain(|(subject, _), _| !subjects.contains(subject));
+        let removed = before - records.len();
+        if removed > 0 {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Ok(removed)
+    }
+
+    /// Drop rollups older than `retention_days` and write pending changes to disk
+    pub fn flush(&self, retention_days: u64) -> Result<()> {
+        let oldest_kept = (unix_timestamp() / 86_400).saturating_sub(retention_days);
+        let mut records = self.lock()?;
+
+        let before = records.len();
+        records.retain(|(_, day), _| *day >= oldest_kept);
+        let pruned = records.len() != before;
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) && !pruned {
+            return Ok(());
+        }
+
+        let list: Vec<UsageRecord> = records
+            .iter()
+            .map(|((subject, day), counters)| UsageRecord {
+                subject: subject.clone(),
+                day: *day,
+                counters: counters.clone(),
+            })
+            .collect();
+        let data = serde_json::to_vec(&list)?;
+        let tmp_path = path.with_extension("tmp");
+
+        std::fs::write(&tmp_path, data)
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| AppError::InternalError(format!("Failed to persist usage: {}", e)))
+    }
+
+    fn update(&self, subjects: &[String], apply: impl Fn(&mut UsageCounters)) {
+        let day = unix_timestamp() / 86_400;
+        // Accounting must never fail a request, so a poisoned lock only skips the update
+        let Ok(mut records) = self.records.lock() else {
+            tracing::error!("Failed to acquire usage lock");
+            return;
+        };
+        for subject in subjects {
+            apply(records.entry((subject.clone(), day)).or_default());
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<(String, u64), UsageCounters>>> {
+        self.records
+            .lock()
+            .map_err(|_| AppError::InternalError("Failed to acquire usage lock".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_rollup() {
+        let store = UsageStore::open(None).unwrap();
+        let uploader = usage_subjects("10.0.0.1".parse().unwrap(), Some("secret"));
+        let deleter = usage_subjects("10.0.0.2".parse().unwrap(), None);
+
+        store.record_upload(&uploader, 100);
+        store.record_upload(&uploader, 50);
+        store.record_download(&deleter, 100);
+        store.record_delete(&deleter, &uploader, 100);
+
+        let summaries = store.summaries(Some("ip:10.0.0.1"), 1).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].totals.uploads, 2);
+        assert_eq!(summaries[0].totals.bytes_stored, 50);
+
+        let summaries = store.summaries(Some("ip:10.0.0.2"), 1).unwrap();
+        assert_eq!(summaries[0].totals.deletes, 1);
+        assert_eq!(summaries[0].totals.bytes_served, 100);
+
+        assert!(uploader[1].starts_with("key:") && !uploader[1].contains("secret"));
+        assert!(owner_subject("secret").starts_with("owner:") && !owner_subject("secret").contains("secret"));
+
+        assert_eq!(store.purge_subjects(&uploader).unwrap(), 2);
+        assert!(store.summaries(Some("ip:10.0.0.1"), 1).unwrap().is_empty());
+        assert_eq!(store.summaries(None, 1).unwrap().len(), 1);
+    }
+}
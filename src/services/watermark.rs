@@ -0,0 +1,119 @@
+use anyhow::Context;
+use image::{imageops::{self, FilterType}, DynamicImage, RgbaImage};
+
+use crate::config::Config;
+
+// Watermarks never cover more than this share of the image width
+const MAX_WIDTH_RATIO: f32 = 0.25;
+
+// Gap between the watermark and the image edge, as a share of the shorter side
+const MARGIN_RATIO: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl Position {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "top-left" => Some(Self::TopLeft),
+            "top-right" => Some(Self::TopRight),
+            "bottom-left" => Some(Self::BottomLeft),
+            "bottom-right" => Some(Self::BottomRight),
+            "center" => Some(Self::Center),
+            _ => None,
+        }
+    }
+}
+
+/// The configured watermark, composited over images when they are served
+pub struct Watermark {
+    mark: RgbaImage,
+    position: Position,
+    opacity: f32,
+}
+
+impl Watermark {
+    /// Load WATERMARK_PATH; `None` when no watermark is configured
+    pub fn load(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &config.watermark_path else {
+            return Ok(None);
+        };
+
+        let mark = image::open(path)
+            .with_context(|| format!("Failed to load watermark from {}", path))?
+            .to_rgba8();
+        let position = Position::parse(&config.watermark_position).with_context(|| {
+            format!("WATERMARK_POSITION must be top-left, top-right, bottom-left, bottom-right or center, got {}", config.watermark_position)
+        })?;
+        if !(0.0..=1.0).contains(&config.watermark_opacity) {
+            anyhow::bail!("WATERMARK_OPACITY must be between 0 and 1");
+        }
+
+        Ok(Some(Self { mark, position, opacity: config.watermark_opacity }))
+    }
+
+    /// Composite the watermark over `image`, shrinking it to fit when the image is small.
+    /// Opaque images stay opaque, so they can still be served as JPEG.
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let mut canvas = image.to_rgba8();
+        let (width, height) = canvas.dimensions();
+
+        let max_width = ((width as f32 * MAX_WIDTH_RATIO) as u32).max(1);
+        let mut mark = if self.mark.width() > max_width {
+            let scaled_height = (self.mark.height() as u64 * max_width as u64 / self.mark.width() as u64).max(1);
+            imageops::resize(&self.mark, max_width, scaled_height as u32, FilterType::Triangle)
+        } else {
+            self.mark.clone()
+        };
+        for pixel in mark.pixels_mut() {
+            pixel.0[3] = (pixel.0[3] as f32 * self.opacity).round() as u8;
+        }
+
+        let margin = (width.min(height) as f32 * MARGIN_RATIO) as i64;
+        let (free_x, free_y) = (width as i64 - mark.width() as i64, height as i64 - mark.height() as i64);
+        let (x, y) = match self.position {
+            Position::TopLeft => (margin, margin),
+            Position::TopRight => (free_x - margin, margin),
+            Position::BottomLeft => (margin, free_y - margin),
+            Position::BottomRight => (free_x - margin, free_y - margin),
+            Position::Center => (free_x / 2, free_y / 2),
+        };
+
+        imageops::overlay(&mut canvas, &mark, x, y);
+        let marked = DynamicImage::ImageRgba8(canvas);
+        if image.color().has_alpha() {
+            marked
+        } else {
+            DynamicImage::ImageRgb8(marked.to_rgb8())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_apply_blends_in_corner() {
+        let watermark = Watermark {
+            mark: RgbaImage::from_pixel(400, 100, Rgba([255, 255, 255, 255])),
+            position: Position::BottomRight,
+            opacity: 0.5,
+        };
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(200, 100, Rgba([0, 0, 0, 255])));
+
+        let marked = watermark.apply(&image).to_rgba8();
+        // Shrunk to a quarter of the width (50x12) and placed 2px from the bottom-right corner
+        let blended = marked.get_pixel(190, 90).0;
+        assert!(blended[0] > 100 && blended[0] < 150);
+        assert_eq!(marked.get_pixel(5, 5).0, [0, 0, 0, 255]);
+        assert_eq!(marked.get_pixel(140, 90).0, [0, 0, 0, 255]);
+    }
+}
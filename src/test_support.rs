@@ -0,0 +1,398 @@
+//! A fake Telegram Bot API and helpers that run the whole router against it, so tests can go
+//! from upload to serving to deletion without network access
+
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::{Client, Response, StatusCode};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use wiremock::{
+    matchers::{method, path_regex},
+    Mock, MockServer, Respond, ResponseTemplate,
+};
+
+use crate::{
+    build_router, build_state, build_telegram,
+    config::Config,
+    middleware::{
+        abuse::{AbuseDetector, AbuseLimits},
+        rate_limit::RateLimitLayer,
+    },
+    services::metrics::Metrics,
+    AppState,
+};
+
+/// Documents held by the fake Bot API: file_id -> (message_id, data)
+type StoredFiles = Arc<Mutex<HashMap<String, (i64, Vec<u8>)>>>;
+
+/// In-process stand-in for the Bot API methods the server uses: sendDocument, getFile, file
+/// downloads, deleteMessage, editMessageReplyMarkup, sendMessage and getMe
+pub struct FakeTelegram {
+    server: MockServer,
+    files: StoredFiles,
+    outage: Arc<AtomicBool>,
+}
+
+impl FakeTelegram {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let files = StoredFiles::default();
+        let next_message_id = Arc::new(AtomicI64::new(1));
+        let outage = Arc::new(AtomicBool::new(false));
+
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/bot[^/]+/sendDocument$"))
+            .respond_with(SendDocument { files: files.clone(), next_message_id: next_message_id.clone() })
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/bot[^/]+/getFile$"))
+            .respond_with(GetFile { files: files.clone(), outage: outage.clone() })
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/file/bot[^/]+/documents/.+$"))
+            .respond_with(DownloadFile(files.clone()))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/bot[^/]+/deleteMessage$"))
+            .respond_with(DeleteMessage(files.clone()))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/bot[^/]+/editMessageReplyMarkup$"))
+            .respond_with(EditReplyMarkup(files.clone()))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/bot[^/]+/sendMessage$"))
+            .respond_with(move |_: &wiremock::Request| {
+                let message_id = next_message_id.fetch_add(1, Ordering::Relaxed);
+                ok(json!({ "message_id": message_id }))
+            })
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/bot[^/]+/getMe$"))
+            .respond_with(ok(json!({ "id": 1, "is_bot": true, "first_name": "RustGram" })))
+            .mount(&server)
+            .await;
+
+        Self { server, files, outage }
+    }
+
+    pub fn url(&self) -> String {
+        self.server.uri()
+    }
+
+    /// Documents uploaded and not deleted since
+    pub fn stored_files(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    /// Answer getFile the way Telegram does while files are temporarily unreachable
+    pub fn set_outage(&self, outage: bool) {
+        self.outage.store(outage, Ordering::Relaxed);
+    }
+}
+
+fn ok(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({ "ok": true, "result": result }))
+}
+
+fn bad_request(description: &str) -> ResponseTemplate {
+    ResponseTemplate::new(400).set_body_json(json!({ "ok": false, "description": description }))
+}
+
+// `key=value&...` form fields; the values the server sends never need unescaping
+fn form_field(request: &wiremock::Request, key: &str) -> Option<String> {
+    String::from_utf8_lossy(&request.body)
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value.to_string())
+}
+
+// Contents of the multipart part named `name`
+fn multipart_part(request: &wiremock::Request, name: &str) -> Option<Vec<u8>> {
+    let content_type = request.headers.get("content-type")?.to_str().ok()?;
+    let delimiter = format!("--{}", content_type.split("boundary=").nth(1)?);
+    let body = &request.body;
+    let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|window| window == needle);
+
+    let header = format!("name=\"{}\"", name);
+    let part_start = find(body, header.as_bytes())?;
+    let data_start = part_start + find(&body[part_start..], b"\r\n\r\n")? + 4;
+    let data_len = find(&body[data_start..], format!("\r\n{}", delimiter).as_bytes())?;
+    Some(body[data_start..data_start + data_len].to_vec())
+}
+
+struct SendDocument {
+    files: StoredFiles,
+    next_message_id: Arc<AtomicI64>,
+}
+
+impl Respond for SendDocument {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let Some(data) = multipart_part(request, "document") else {
+            return bad_request("Bad Request: there is no document in the request");
+        };
+        let message_id = self.next_message_id.fetch_add(1, Ordering::Relaxed);
+        let file_id = format!("file-{}", message_id);
+        let size = data.len();
+        self.files.lock().unwrap().insert(file_id.clone(), (message_id, data));
+        ok(json!({
+            "message_id": message_id,
+            "document": {
+                "file_id": file_id,
+                "file_unique_id": format!("unique-{}", message_id),
+                "file_size": size,
+            },
+        }))
+    }
+}
+
+struct GetFile {
+    files: StoredFiles,
+    outage: Arc<AtomicBool>,
+}
+
+impl Respond for GetFile {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        if self.outage.load(Ordering::Relaxed) {
+            return bad_request("Bad Request: wrong file_id or the file is temporarily unavailable");
+        }
+        let file_id = form_field(request, "file_id").unwrap_or_default();
+        match self.files.lock().unwrap().get(&file_id) {
+            Some((message_id, data)) => ok(json!({
+                "file_id": file_id,
+                "file_unique_id": format!("unique-{}", message_id),
+                "file_size": data.len(),
+                "file_path": format!("documents/{}", file_id),
+            })),
+            None => bad_request("Bad Request: invalid file_id"),
+        }
+    }
+}
+
+struct DownloadFile(StoredFiles);
+
+impl Respond for DownloadFile {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let file_id = request.url.path().rsplit('/').next().unwrap_or_default();
+        match self.0.lock().unwrap().get(file_id) {
+            Some((_, data)) => ResponseTemplate::new(200).set_body_bytes(data.clone()),
+            None => ResponseTemplate::new(404),
+        }
+    }
+}
+
+struct DeleteMessage(StoredFiles);
+
+impl Respond for DeleteMessage {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let message_id = form_field(request, "message_id").and_then(|id| id.parse::<i64>().ok());
+        let mut files = self.0.lock().unwrap();
+        let before = files.len();
+        files.retain(|_, (stored, _)| Some(*stored) != message_id);
+        if files.len() == before {
+            return bad_request("Bad Request: message to delete not found");
+        }
+        ok(json!(true))
+    }
+}
+
+// Documents never carry reply markup, so clearing it is refused as a no-op
+struct EditReplyMarkup(StoredFiles);
+
+impl Respond for EditReplyMarkup {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let message_id = form_field(request, "message_id").and_then(|id| id.parse::<i64>().ok());
+        if self.0.lock().unwrap().values().any(|(stored, _)| Some(*stored) == message_id) {
+            bad_request("Bad Request: message is not modified")
+        } else {
+            bad_request("Bad Request: message to edit not found")
+        }
+    }
+}
+
+/// The full router over in-memory stores, served on a loopback port with the upload worker
+/// running and files stored in a fresh `FakeTelegram`
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    pub telegram: FakeTelegram,
+    pub client: Client,
+    addr: SocketAddr,
+}
+
+impl TestApp {
+    /// `vars` are set on top of a minimal configuration, as if they were in the environment
+    pub async fn start(vars: &[(&str, &str)]) -> Self {
+        let telegram = FakeTelegram::start().await;
+        let mut env: HashMap<String, String> = [
+            ("TELEGRAM_BOT_TOKEN", "test-token".to_string()),
+            ("TELEGRAM_CHAT_ID", "-1001".to_string()),
+            ("TELEGRAM_API_URL", telegram.url()),
+            ("ENCRYPTION_KEY", general_purpose::STANDARD.encode([7u8; 32])),
+            ("ADMIN_SECRET", "admin".to_string()),
+            ("UPLOAD_DELAY_SECS", "0".to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+        env.extend(vars.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+
+        let config = Arc::new(Config::from_vars(&env).expect("test configuration"));
+        let metrics = Arc::new(Metrics::default());
+        let telegram_service = Arc::new(build_telegram(&config, metrics.clone(), None).expect("telegram service"));
+        let state = build_state(config.clone(), telegram_service, metrics, None).expect("app state");
+        // Abuse detection stays off, as it is by default
+        let abuse = AbuseDetector::new(
+            AbuseLimits { ban_secs: 0, max_errors: 0, max_id_probes: 0, max_churn: 0 },
+            state.audit.clone(),
+        );
+        let router = build_router(state.clone(), RateLimitLayer::new(config.rate_limit_per_minute), abuse);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind test server");
+        let addr = listener.local_addr().expect("test server address");
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await
+        });
+        Self { state, telegram, client: Client::new(), addr }
+    }
+
+    /// Absolute URL of `path` on the test server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub async fn get(&self, path: &str) -> Response {
+        self.client.get(self.url(path)).send().await.expect("test server request")
+    }
+
+    /// Poll /job/:id until the job is no longer pending, returning its final status
+    pub async fn wait_for_job(&self, job_id: &str) -> Value {
+        for _ in 0..100 {
+            let response = self.get(&format!("/job/{}", job_id)).await;
+            if response.status() != StatusCode::ACCEPTED {
+                return response.json().await.expect("job status");
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("job {} did not finish", job_id);
+    }
+}
+
+/// A small opaque PNG
+pub fn png_bytes() -> Vec<u8> {
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([200, 40, 40])))
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .expect("encode PNG");
+    png
+}
+
+/// A minimal RGB matrix/TRC profile with sRGB's colorants and the given curve for every channel
+pub fn icc_profile(curve: &[u8]) -> Vec<u8> {
+    let fixed = |v: f32| ((v * 65536.0).round() as i32).to_be_bytes();
+    let xyz = |xyz: [f32; 3]| {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        xyz.iter().for_each(|v| tag.extend_from_slice(&fixed(*v)));
+        tag
+    };
+    let tags: [(&[u8; 4], Vec<u8>); 6] = [
+        (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+        (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+        (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+        (b"rTRC", curve.to_vec()),
+        (b"gTRC", curve.to_vec()),
+        (b"bTRC", curve.to_vec()),
+    ];
+
+    let mut profile = vec![0u8; 128];
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[36..40].copy_from_slice(b"acsp");
+    profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    let mut offset = 128 + 4 + tags.len() * 12;
+    let mut data = Vec::new();
+    for (signature, tag) in &tags {
+        profile.extend_from_slice(*signature);
+        profile.extend_from_slice(&(offset as u32).to_be_bytes());
+        profile.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        offset += tag.len();
+    }
+    profile.extend_from_slice(&data);
+    let size = profile.len() as u32;
+    profile[..4].copy_from_slice(&size.to_be_bytes());
+    profile
+}
+
+// curv with no entries: values are linear light
+pub const LINEAR_ICC_CURVE: &[u8] = b"curv\0\0\0\0\0\0\0\0";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upload_serve_delete_roundtrip() {
+        let app = TestApp::start(&[]).await;
+        let png = png_bytes();
+
+        let response = app
+            .client
+            .put(app.url("/upload?filename=red.png"))
+            .header("content-type", "image/png")
+            .body(png.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let queued: Value = response.json().await.unwrap();
+        let job_id = queued["job_id"].as_str().unwrap();
+
+        let job = app.wait_for_job(job_id).await;
+        assert_eq!(job["status"], "Completed", "{}", job);
+        let id = job["response"]["id"].as_str().unwrap().to_string();
+        let delete_url = job["response"]["delete_url"].as_str().unwrap().to_string();
+        assert_eq!(app.telegram.stored_files(), 1);
+        assert!(app.state.index.get(&id).unwrap().is_some());
+
+        let response = app.get(&format!("/image/{}", id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), png.as_slice());
+
+        let response = app.get(&delete_url).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(app.telegram.stored_files(), 0);
+        assert!(app.state.index.get(&id).unwrap().is_none());
+        assert!(app.get(&format!("/image/{}", id)).await.status().is_client_error());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_carries_request_id() {
+        let app = TestApp::start(&[]).await;
+        let response = app
+            .client
+            .request(reqwest::Method::OPTIONS, app.url("/upload"))
+            .header("origin", "https://example.com")
+            .header("access-control-request-method", "PUT")
+            .header("x-request-id", "preflight-1")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["x-request-id"], "preflight-1");
+    }
+}
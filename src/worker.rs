@@ -1,64 +1,343 @@
-use std::collections::HashMap;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc::Receiver, Notify};
 
 use crate::{
     config::Config,
+    crypto::CryptoService,
     error::AppError,
-    models::FileReference,
-    services::telegram::TelegramService,
+    handlers::{upload::prepare_upload, url_upload::try_fetch_remote_image},
+    models::{unix_timestamp, Backend, FileReference, Visibility},
+    services::{
+        index::{ImageIndex, IndexEntry, QuotaReservation},
+        audit::{AuditAction, AuditEvent, AuditLog},
+        cpu_pool::CpuPool,
+        discord::DiscordService,
+        manifest::{self, StoredFile},
+        ocr,
+        spill::Payload,
+        storage::Storage,
+        telegram::TelegramService,
+        tenants::Tenant,
+        usage::{owner_subject, usage_subjects, UsageStore},
+    },
 };
 
+// How many times the worker tries to download a remote image before giving up
+const REMOTE_FETCH_ATTEMPTS: u32 = 3;
+
 // The job that will be sent to the upload worker
 #[derive(Debug)]
 pub struct UploadJob {
     pub job_id: String,
-    pub encrypted_data: Vec<u8>,
+    pub payload: JobPayload,
+    pub options: UploadOptions,
+    pub client_ip: SocketAddr,
+    // Bytes held against the tenant's quota until the job ends, after its image is indexed
+    pub reservation: Option<QuotaReservation>,
+}
+
+// Per-upload settings chosen by the client, applied when the file reference is created
+#[derive(Debug, Default)]
+pub struct UploadOptions {
+    pub expires_at: Option<u64>,
+    pub api_key: Option<String>,
+    // Resolved from the API key when the upload is queued
+    pub tenant: Option<Tenant>,
+    // Client-chosen job ID of a progress-tracked upload; a random one is used otherwise
+    pub job_id: Option<String>,
+    // Owns the upload when no API key was presented
+    pub owner_token: Option<String>,
+    // The owner token was generated for this upload and has to be returned to the client
+    pub owner_token_issued: bool,
+    // Normalized tags recorded in the index with the image
+    pub tags: Vec<String>,
+    pub visibility: Visibility,
+}
+
+// What the worker has to do before the data can be sent to Telegram
+#[derive(Debug)]
+pub enum JobPayload {
+    // Image already validated and encrypted by the request handler
+    Ready(Box<PreparedUpload>),
+    // Remote image that the worker has to fetch, validate and encrypt itself
+    RemoteUrl(String),
+}
+
+// An encrypted image ready to be uploaded to Telegram
+#[derive(Debug)]
+pub struct PreparedUpload {
+    pub encrypted_data: Payload,
     pub unique_filename: String,
     pub original_size: usize,
     pub mime_type: String,
-    pub client_ip: SocketAddr,
+    pub dimensions: Option<(u32, u32)>,
+    pub metadata: ImageMetadata,
+}
+
+// Derived from the original image at upload and kept in the index
+#[derive(Debug, Clone)]
+pub struct ImageMetadata {
+    // Hex SHA-256 of the exact bytes
+    pub content_hash: String,
+    // Hex dHash of the decoded pixels, matching re-encoded or resized copies
+    pub perceptual_hash: Option<String>,
+    // Most common colors as "#rrggbb", dominant first
+    pub palette: Vec<String>,
+    // Name the client uploaded the file under, offered again on download
+    pub filename: String,
+    // Filled in by the worker when OCR_SERVICE_URL is set
+    pub text: Option<String>,
+}
+
+// The store for finished job results, either the stored reference or the failure reason
+pub type JobStore = Arc<Mutex<HashMap<String, Result<FinishedJob, String>>>>;
+
+/// The image a job ended with
+#[derive(Debug, Clone)]
+pub struct FinishedJob {
+    pub file_ref: FileReference,
+    // The bytes matched an image already stored under a content-addressed ID, which the
+    // client gets to share but not to delete
+    pub existing: bool,
+}
+
+/// A job waiting in the upload queue or being processed, as shown by /admin/queue
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedJob {
+    pub id: String,
+    // "upload" for data sent with the request, "url" for a remote image the worker fetches
+    pub kind: &'static str,
+    // Original size in bytes; unknown for remote images until they are fetched
+    pub size: Option<usize>,
+    pub queued_at: u64,
+}
+
+impl QueuedJob {
+    fn new(job: &UploadJob) -> Self {
+        let (kind, size) = match &job.payload {
+            JobPayload::Ready(prepared) => ("upload", Some(prepared.original_size)),
+            JobPayload::RemoteUrl(_) => ("url", None),
+        };
+        Self { id: job.job_id.clone(), kind, size, queued_at: unix_timestamp() }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RunningJob {
+    job: QueuedJob,
+    started_at: u64,
+    // Fetch attempts so far for remote images; always 1 for uploaded data
+    attempts: u32,
+}
+
+/// A queued or running job with its age, for /admin/queue
+#[derive(Debug, Serialize)]
+pub struct JobView {
+    #[serde(flatten)]
+    pub job: QueuedJob,
+    pub age_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub running_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueSnapshot {
+    pub paused: bool,
+    pub queued: usize,
+    pub jobs: Vec<JobView>,
+    pub current: Option<JobView>,
+}
+
+/// What the upload worker is doing, reported by /health and /admin/queue so a stuck worker
+/// or upload is visible
+#[derive(Default)]
+pub struct WorkerStatus {
+    // Jobs waiting in the queue, oldest first
+    queued: Mutex<VecDeque<QueuedJob>>,
+    alive: AtomicBool,
+    // The job being processed, None while idle
+    current: Mutex<Option<RunningJob>>,
+    // Set by an admin to hold jobs in the queue; the worker finishes its current job first
+    paused: AtomicBool,
+    resumed: Notify,
+}
+
+impl WorkerStatus {
+    /// Note a job entering the queue; `enqueue_failed` undoes it if the send fails
+    pub fn enqueued(&self, job: &UploadJob) {
+        self.queued.lock().unwrap().push_back(QueuedJob::new(job));
+    }
+
+    pub fn enqueue_failed(&self, job_id: &str) {
+        self.queued.lock().unwrap().retain(|job| job.id != job_id);
+    }
+
+    /// Move a job from the queue to the worker
+    fn started(&self, job_id: &str) {
+        let mut queued = self.queued.lock().unwrap();
+        let position = queued.iter().position(|job| job.id == job_id);
+        let job = position.and_then(|position| queued.remove(position));
+        *self.current.lock().unwrap() = job.map(|job| RunningJob { job, started_at: unix_timestamp(), attempts: 1 });
+    }
+
+    fn finished(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+
+    fn set_attempts(&self, attempts: u32) {
+        if let Some(running) = self.current.lock().unwrap().as_mut() {
+            running.attempts = attempts;
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Stop taking jobs off the queue, or start again; returns whether the state changed
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let changed = self.paused.swap(paused, Ordering::AcqRel) != paused;
+        if !paused {
+            self.resumed.notify_waiters();
+        }
+        changed
+    }
+
+    async fn wait_while_paused(&self) {
+        loop {
+            // Registered before the check so a resume in between is not missed
+            let resumed = self.resumed.notified();
+            if !self.is_paused() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+
+    /// Seconds the oldest waiting job has been queued
+    pub fn oldest_job_age(&self) -> Option<u64> {
+        let queued_at = self.queued.lock().unwrap().front()?.queued_at;
+        Some(unix_timestamp().saturating_sub(queued_at))
+    }
+
+    /// Seconds the current job has been running
+    pub fn current_job_age(&self) -> Option<u64> {
+        let started_at = self.current.lock().unwrap().as_ref()?.started_at;
+        Some(unix_timestamp().saturating_sub(started_at))
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        let now = unix_timestamp();
+        let jobs: Vec<JobView> = self
+            .queued
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| JobView {
+                job: job.clone(),
+                age_secs: now.saturating_sub(job.queued_at),
+                running_secs: None,
+                attempts: None,
+            })
+            .collect();
+        let current = self.current.lock().unwrap().clone().map(|running| JobView {
+            age_secs: now.saturating_sub(running.job.queued_at),
+            running_secs: Some(now.saturating_sub(running.started_at)),
+            attempts: Some(running.attempts),
+            job: running.job,
+        });
+        QueueSnapshot { paused: self.is_paused(), queued: jobs.len(), jobs, current }
+    }
 }
 
-// The store for completed job results
-pub type JobStore = Arc<Mutex<HashMap<String, FileReference>>>;
+// Clears the worker's liveness flag however the worker exits, panics included
+struct AliveGuard<'a>(&'a WorkerStatus);
 
+impl Drop for AliveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.alive.store(false, Ordering::Relaxed);
+        self.0.finished();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_upload_worker(
     mut rx: Receiver<UploadJob>,
     job_store: JobStore,
+    index: Arc<ImageIndex>,
+    usage: Arc<UsageStore>,
     telegram_service: Arc<TelegramService>,
+    discord: Option<Arc<DiscordService>>,
+    audit: Arc<AuditLog>,
     config: Arc<Config>,
+    status: Arc<WorkerStatus>,
+    cpu: CpuPool,
+    client: reqwest::Client,
 ) {
     tracing::info!("Upload worker started");
+    status.alive.store(true, Ordering::Relaxed);
+    let _alive = AliveGuard(&status);
 
-    while let Some(job) = rx.recv().await {
+    while let Some(mut job) = rx.recv().await {
+        // Jobs keep their place in the queue while the worker is paused
+        status.wait_while_paused().await;
         tracing::info!("Processing job ID: {}", job.job_id);
+        status.started(&job.job_id);
+
+        let mut subjects = usage_subjects(job.client_ip.ip(), job.options.api_key.as_deref());
+        subjects.extend(job.options.owner_token.as_deref().map(owner_subject));
+        let result = process_job(&mut job, &index, &telegram_service, discord.as_ref(), &config, &client, &status, &cpu)
+            .await;
+        let result = result.and_then(|processed| match processed {
+            Processed::Stored(file_ref, metadata) => {
+                usage.record_upload(&subjects, file_ref.size);
+                record_in_index(&index, &config, file_ref, subjects, Some(metadata), job.options.tags.clone())
+                    .map(|file_ref| FinishedJob { file_ref, existing: false })
+            }
+            Processed::Existing(entry) => {
+                index.add_reuploaders(&entry.id, &subjects)?;
+                Ok(FinishedJob { file_ref: entry.reference, existing: true })
+            }
+        });
+        // The indexed image counts against the tenant's quota now, or the job failed
+        job.reservation = None;
 
-        let result = process_job(&job, &telegram_service, &job_store).await;
-
-        let log_message = match &result {
-            Ok(_) => format!(
-                "✅ Upload Success | Job ID: {} | Size: {} | Type: {} | IP: {}",
-                job.job_id, job.original_size, job.mime_type, job.client_ip
-            ),
-            Err(e) => format!(
-                "❌ Upload Failed | Job ID: {} | Error: {} | IP: {}",
-                job.job_id, e, job.client_ip
-            ),
+        let event = match &result {
+            Ok(FinishedJob { file_ref, .. }) => AuditEvent::new(AuditAction::Upload)
+                .detail(format!("job {}, {} bytes, {}", job.job_id, file_ref.size, file_ref.mime_type)),
+            Err(e) => AuditEvent::new(AuditAction::UploadFailed).detail(format!("job {}: {}", job.job_id, e)),
         };
-        
-        if let Err(e) = telegram_service.send_log_message(&log_message).await {
-            tracing::error!("Failed to send log message for job {}: {}", job.job_id, e);
-        }
+        let event = event.ip(job.client_ip.ip()).key(job.options.api_key.as_deref());
+        audit.record(event);
 
-        if let Err(e) = result {
+        if let Err(e) = &result {
             tracing::error!("Failed to process job ID {}: {}", job.job_id, e);
             // In a real-world scenario, you might want to add the job to a dead-letter queue
             // or implement a retry mechanism with backoff.
         }
 
+        // Store the result in the job store so the status endpoint can report it
+        match job_store.lock() {
+            Ok(mut store) => {
+                store.insert(job.job_id.clone(), result.map_err(|e| e.to_string()));
+            }
+            Err(_) => tracing::error!("Failed to acquire job store lock for job {}", job.job_id),
+        }
+
+        status.finished();
+
         // Apply a delay after each job processing to respect Telegram's rate limits
         tokio::time::sleep(Duration::from_secs(config.upload_delay_secs)).await;
     }
@@ -66,40 +345,326 @@ pub async fn run_upload_worker(
     tracing::info!("Upload worker shutting down");
 }
 
+/// What became of a job's image
+enum Processed {
+    Stored(FileReference, ImageMetadata),
+    // Content-addressed re-upload of an image already stored, which is shared as it is
+    Existing(IndexEntry),
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn process_job(
-    job: &UploadJob,
+    job: &mut UploadJob,
+    index: &ImageIndex,
     telegram_service: &Arc<TelegramService>,
-    job_store: &JobStore,
-) -> Result<(), AppError> {
-    // Upload to Telegram
-    let telegram_message = telegram_service
-        .upload_file(&job.encrypted_data, &job.unique_filename)
-        .await?;
-
-    // Extract file information
-    let file_id = telegram_message
-        .document
-        .as_ref()
-        .map(|doc| doc.file_id.clone())
-        .ok_or_else(|| AppError::TelegramError("No document in response".to_string()))?;
+    discord: Option<&Arc<DiscordService>>,
+    config: &Arc<Config>,
+    client: &reqwest::Client,
+    status: &WorkerStatus,
+    cpu: &CpuPool,
+) -> Result<Processed, AppError> {
+    let fetched;
+    let prepared = match &job.payload {
+        JobPayload::Ready(prepared) => prepared.as_ref(),
+        JobPayload::RemoteUrl(url) => {
+            fetched = prepare_remote_upload(client, url, job.options.tenant.as_ref(), config, status, cpu).await?;
+            // Size and type are only known once the remote image has been fetched
+            if let Some(tenant) = &job.options.tenant {
+                job.reservation = Some(tenant.reserve_upload(index, fetched.original_size, &fetched.mime_type)?);
+            }
+            &fetched
+        }
+    };
+
+    // Checked again here since the worker is the only writer, so queued copies can't slip through
+    reject_duplicate(index, config, prepared, job.options.tenant.as_ref())?;
+
+    let tenant_id = job.options.tenant.as_ref().map(|tenant| tenant.id.as_str());
+    let content_id = if config.content_addressed_ids {
+        let key = config.get_encryption_key_bytes()?;
+        Some(CryptoService::content_id(&key, tenant_id, &prepared.metadata.content_hash))
+    } else {
+        None
+    };
+    if let Some(id) = &content_id
+        && let Some(existing) = index.get(id)?
+        && !existing.broken
+    {
+        tracing::info!("Job ID {} matches stored image {}", job.job_id, id);
+        return Ok(Processed::Existing(existing));
+    }
+
+    let mut metadata = prepared.metadata.clone();
+    if let Some(url) = &config.ocr_service_url {
+        metadata.text = recognize_text(client, url, prepared, job.options.tenant.as_ref(), config).await;
+    }
+
+    // Upload to Telegram, in the tenant's own chat when it has one, unless Discord is configured
+    let telegram = match &job.options.tenant {
+        Some(tenant) => tenant.storage(telegram_service),
+        None => telegram_service.clone(),
+    };
+    let mut storage = Storage::for_backend(config.storage_backend, telegram, discord)?;
+    let encrypted_data = prepared.encrypted_data.load().await?;
+    let master_key = config.get_encryption_key_bytes()?;
+    let filename = &prepared.unique_filename;
+    let mut stored = store_upload(&storage, config, &master_key, encrypted_data.clone(), filename).await;
+    if let Err(e) = &stored
+        && config.discord_overflow
+        && storage.backend() == Backend::Telegram
+        && let Some(discord) = discord
+    {
+        tracing::warn!("Telegram refused job ID {} ({}), storing it on Discord", job.job_id, e);
+        storage = Storage::Discord(discord.clone());
+        stored = store_upload(&storage, config, &master_key, encrypted_data, filename).await;
+    }
+    let stored = stored?;
 
     // Create file reference
-    let file_ref = FileReference::new(
-        file_id,
-        telegram_message.message_id,
-        job.original_size,
-        job.mime_type.clone(),
+    let mut file_ref = FileReference::new(
+        stored.file_id,
+        stored.message_id,
+        prepared.original_size,
+        prepared.mime_type.clone(),
     );
+    file_ref.chunked = stored.chunked;
+    file_ref.backend = storage.backend();
+    file_ref.width = prepared.dimensions.map(|(width, _)| width);
+    file_ref.height = prepared.dimensions.map(|(_, height)| height);
+    file_ref.expires_at = job.options.expires_at;
+    file_ref.visibility = job.options.visibility;
+    file_ref.tenant = job.options.tenant.as_ref().map(|tenant| tenant.id.clone());
+    file_ref.chat_id = storage.telegram_chat().filter(|chat_id| *chat_id != config.telegram_chat_id);
+    file_ref.content_id = content_id;
 
-    // Store the result in the job store
-    {
-        let mut store = job_store.lock().map_err(|_| {
-            AppError::InternalError("Failed to acquire job store lock".to_string())
-        })?;
-        store.insert(job.job_id.clone(), file_ref);
+    tracing::info!("Job ID {} processed successfully", job.job_id);
+
+    Ok(Processed::Stored(file_ref, metadata))
+}
+
+/// Store encrypted upload data, split into chunks past the backend's limit
+pub(crate) async fn store_upload(
+    storage: &Storage,
+    config: &Config,
+    master_key: &[u8; 32],
+    encrypted_data: Bytes,
+    filename: &str,
+) -> Result<StoredFile, AppError> {
+    let chunk_bytes = match storage.backend() {
+        Backend::Telegram => config.storage_chunk_bytes,
+        Backend::Discord => config.discord_chunk_bytes,
+    };
+    manifest::store(storage, master_key, encrypted_data, filename, chunk_bytes).await
+}
+
+/// Run OCR on the original bytes; failures only cost the image its searchable text
+async fn recognize_text(
+    client: &reqwest::Client,
+    url: &str,
+    prepared: &PreparedUpload,
+    tenant: Option<&Tenant>,
+    config: &Config,
+) -> Option<String> {
+    let result = async {
+        let mut key = config.get_encryption_key_bytes()?;
+        if let Some(tenant) = tenant {
+            key = tenant.content_key(&key)?;
+        }
+        let encrypted_data = prepared.encrypted_data.load().await?;
+        let image_data = CryptoService::new(&key).decrypt_data(&encrypted_data)?;
+        ocr::extract_text(client, url, image_data, &prepared.mime_type).await
+    };
+
+    result.await.unwrap_or_else(|e| {
+        tracing::warn!("OCR failed for {}: {}", prepared.unique_filename, e);
+        None
+    })
+}
+
+/// In strict dedup mode, refuse content already stored in the same tenant namespace
+pub(crate) fn reject_duplicate(
+    index: &ImageIndex,
+    config: &Config,
+    prepared: &PreparedUpload,
+    tenant: Option<&Tenant>,
+) -> Result<(), AppError> {
+    if !config.strict_dedup {
+        return Ok(());
     }
 
-    tracing::info!("Job ID {} processed and stored successfully", job.job_id);
+    let tenant_id = tenant.map(|tenant| tenant.id.as_str());
+    match index.find_by_hash(&prepared.metadata.content_hash, tenant_id)? {
+        Some(existing) => Err(AppError::Duplicate { id: existing.id, first_uploaded_at: existing.created_at }),
+        None => Ok(()),
+    }
+}
+
+/// Add a freshly stored file to the index under its public ID
+pub(crate) fn record_in_index(
+    index: &ImageIndex,
+    config: &Config,
+    file_ref: FileReference,
+    uploader: Vec<String>,
+    metadata: Option<ImageMetadata>,
+    tags: Vec<String>,
+) -> Result<FileReference, AppError> {
+    let encryption_key = config.get_encryption_key_bytes()?;
+    let crypto = CryptoService::new(&encryption_key);
 
-    Ok(())
-}
\ No newline at end of file
+    index.insert(IndexEntry {
+        id: crypto.public_id(&file_ref)?,
+        reference: file_ref.clone(),
+        created_at: unix_timestamp(),
+        uploader,
+        reuploaders: Vec::new(),
+        content_hash: metadata.as_ref().map(|m| m.content_hash.clone()),
+        perceptual_hash: metadata.as_ref().and_then(|m| m.perceptual_hash.clone()),
+        palette: metadata.as_ref().map(|m| m.palette.clone()).unwrap_or_default(),
+        filename: metadata.as_ref().map(|m| m.filename.clone()),
+        text: metadata.and_then(|m| m.text),
+        previous_file_ids: Vec::new(),
+        broken: false,
+        unavailable: None,
+        tags,
+        transcodes: Default::default(),
+    })?;
+
+    Ok(file_ref)
+}
+
+/// Download, validate and encrypt a remote image, retrying transport errors and 5xx/429 responses
+/// with backoff
+async fn prepare_remote_upload(
+    client: &reqwest::Client,
+    url: &str,
+    tenant: Option<&Tenant>,
+    config: &Arc<Config>,
+    status: &WorkerStatus,
+    cpu: &CpuPool,
+) -> Result<PreparedUpload, AppError> {
+    let mut attempt = 1;
+    let (image_data, mime_type, filename) = loop {
+        status.set_attempts(attempt);
+        match try_fetch_remote_image(client, url, config, cpu).await {
+            Ok(fetched) => break fetched,
+            Err(e) if e.retryable && attempt < REMOTE_FETCH_ATTEMPTS => {
+                tracing::warn!("Fetch attempt {} for {} failed: {}", attempt, url, e.error);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.error),
+        }
+    };
+
+    prepare_upload(cpu, config, tenant, image_data.bytes, Some(image_data.sha256), &filename, mime_type).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str) -> UploadJob {
+        UploadJob {
+            job_id: id.to_string(),
+            payload: JobPayload::RemoteUrl("https://example.com/a.png".to_string()),
+            options: UploadOptions::default(),
+            client_ip: "127.0.0.1:1".parse().unwrap(),
+            reservation: None,
+        }
+    }
+
+    #[test]
+    fn test_worker_status_tracks_queue() {
+        let status = WorkerStatus::default();
+        status.enqueued(&job("a"));
+        status.enqueued(&job("b"));
+        status.enqueued(&job("c"));
+        status.enqueue_failed("b");
+
+        status.started("a");
+        status.set_attempts(2);
+        let snapshot = status.snapshot();
+        assert_eq!(snapshot.queued, 1);
+        assert_eq!(snapshot.jobs[0].job.id, "c");
+        let current = snapshot.current.unwrap();
+        assert_eq!((current.job.id.as_str(), current.job.kind, current.attempts), ("a", "url", Some(2)));
+
+        status.finished();
+        assert!(status.snapshot().current.is_none());
+        assert!(status.current_job_age().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pause_holds_worker_until_resumed() {
+        let status = Arc::new(WorkerStatus::default());
+        assert!(status.set_paused(true));
+        assert!(!status.set_paused(true));
+
+        let waiter = tokio::spawn({
+            let status = status.clone();
+            async move { status.wait_while_paused().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        assert!(status.set_paused(false));
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reupload_of_stored_bytes_gets_no_delete_link() {
+        use crate::test_support::{png_bytes, TestApp};
+
+        let index_path = std::env::temp_dir().join(format!("rustgram-index-{}.json", uuid::Uuid::new_v4()));
+        let app = TestApp::start(&[
+            ("CONTENT_ADDRESSED_IDS", "true"),
+            ("INDEX_PATH", index_path.to_str().unwrap()),
+        ])
+        .await;
+        let app = &app;
+        let upload = |key: &'static str| {
+            let request = app.client.put(app.url("/upload")).header("content-type", "image/png").header("x-api-key", key);
+            async move {
+                let queued: serde_json::Value = request.body(png_bytes()).send().await.unwrap().json().await.unwrap();
+                app.wait_for_job(queued["job_id"].as_str().unwrap()).await["response"].clone()
+            }
+        };
+
+        let original = upload("alice").await;
+        assert!(original["delete_url"].is_string() && original.get("existing").is_none(), "{}", original);
+        let copy = upload("bob").await;
+        assert_eq!(copy["id"], original["id"]);
+        assert!(copy["delete_url"].is_null() && copy["existing"] == true, "{}", copy);
+
+        let entry = app.state.index.get(original["id"].as_str().unwrap()).unwrap().unwrap();
+        let bob = usage_subjects("127.0.0.1".parse().unwrap(), Some("bob"));
+        assert!(entry.reuploaders.contains(&bob[1]) && !entry.uploader.contains(&bob[1]));
+        assert_eq!(app.telegram.stored_files(), 1);
+        let _ = std::fs::remove_file(index_path);
+    }
+
+    #[tokio::test]
+    async fn test_remote_fetch_fails_4xx_without_retrying() {
+        use crate::test_support::TestApp;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let remote = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(404)).mount(&remote).await;
+        let app = TestApp::start(&[]).await;
+
+        let response = app
+            .client
+            .post(app.url("/upload_from_url/async"))
+            .json(&serde_json::json!({ "url": format!("{}/missing.png", remote.uri()) }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        let queued: serde_json::Value = response.json().await.unwrap();
+
+        // A retry would back off for seconds; the job fails on the first response instead
+        let job = app.wait_for_job(queued["job_id"].as_str().unwrap()).await;
+        assert_eq!(job["status"], "Failed", "{}", job);
+        assert_eq!(remote.received_requests().await.unwrap().len(), 1);
+    }
+}